@@ -0,0 +1,139 @@
+use crate::config::Config;
+use crate::error::{AppError, AppResult};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::server::ResolvesServerCert;
+
+#[cfg(feature = "acme")]
+use {
+    futures_util::StreamExt,
+    rustls_acme::{caches::DirCache, is_tls_alpn_challenge, AcmeConfig, AcmeState},
+    std::time::Duration,
+    tokio::io::AsyncWriteExt,
+    tokio_rustls::rustls::server::Acceptor,
+    tokio_rustls::rustls::{self, ServerConfig},
+    tokio_rustls::LazyConfigAcceptor,
+    tracing::{debug, error, info, warn},
+};
+
+/// Сколько ждать рукопожатия проверки TLS-ALPN-01
+#[cfg(feature = "acme")]
+const CHALLENGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Сертификат POP3S от Let's Encrypt для --acme-domain. Заказ и продление
+/// идут в фоне, а слушатель POP3S берёт сертификат у [`Acme::resolver`]
+/// при каждом рукопожатии, так что новый подхватывается без перезапуска.
+#[cfg(feature = "acme")]
+pub struct Acme {
+    state: AcmeState<std::io::Error>,
+}
+
+/// Без feature acme получать сертификат нечем, и значение не создаётся
+#[cfg(not(feature = "acme"))]
+pub enum Acme {}
+
+#[cfg(feature = "acme")]
+impl Acme {
+    /// Готовит заказ. К кешу и к Let's Encrypt обращается только
+    /// [`Acme::spawn`], уже после сброса привилегий.
+    pub fn new(config: &Config) -> AppResult<Self> {
+        let cache = config
+            .acme_cache
+            .clone()
+            .ok_or_else(|| AppError::Config("--acme-domain требует --acme-cache".to_string()))?;
+        let state = AcmeConfig::new_with_provider(&config.acme_domain, provider())
+            .contact(
+                config
+                    .acme_email
+                    .iter()
+                    .map(|email| format!("mailto:{}", email)),
+            )
+            .cache(DirCache::new(cache))
+            .directory_lets_encrypt(!config.acme_staging)
+            .state();
+        Ok(Acme { state })
+    }
+
+    /// Сертификат для рукопожатий POP3S; до первого получения его нет, и
+    /// рукопожатие не удаётся
+    pub fn resolver(&self) -> Arc<dyn ResolvesServerCert> {
+        self.state.resolver()
+    }
+
+    /// Загружает сертификат из кеша или заказывает новый и продлевает его
+    /// до истечения. На `listeners` отвечает на проверки TLS-ALPN-01.
+    pub fn spawn(self, listeners: Vec<TcpListener>) {
+        let challenge_config = self.state.challenge_rustls_config_with_provider(provider());
+        for listener in listeners {
+            tokio::spawn(answer_challenges(listener, Arc::clone(&challenge_config)));
+        }
+
+        let mut state = self.state;
+        tokio::spawn(async move {
+            while let Some(event) = state.next().await {
+                match event {
+                    Ok(event) => info!("ACME: {:?}", event),
+                    Err(e) => error!("ACME certificate error: {}", e),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(not(feature = "acme"))]
+impl Acme {
+    pub fn new(_config: &Config) -> AppResult<Self> {
+        Err(AppError::Config(
+            "mop3 собран без поддержки ACME (cargo build --features acme)".to_string(),
+        ))
+    }
+
+    pub fn resolver(&self) -> Arc<dyn ResolvesServerCert> {
+        match *self {}
+    }
+
+    pub fn spawn(self, _listeners: Vec<TcpListener>) {
+        match self {}
+    }
+}
+
+#[cfg(feature = "acme")]
+fn provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// Отвечает на проверки TLS-ALPN-01; остальные соединения закрываются
+#[cfg(feature = "acme")]
+async fn answer_challenges(listener: TcpListener, challenge_config: Arc<ServerConfig>) {
+    if let Ok(addr) = listener.local_addr() {
+        info!("ACME challenge listener on: {}", addr);
+    }
+
+    loop {
+        let (stream, peer_addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!("Failed to accept ACME connection: {}", e);
+                continue;
+            }
+        };
+        let challenge_config = Arc::clone(&challenge_config);
+        tokio::spawn(async move {
+            let answer = async {
+                let handshake = LazyConfigAcceptor::new(Acceptor::default(), stream).await?;
+                if !is_tls_alpn_challenge(&handshake.client_hello()) {
+                    debug!("Non-ACME TLS connection from {} closed", peer_addr);
+                    return Ok(());
+                }
+                let mut tls = handshake.into_stream(challenge_config).await?;
+                info!("Answered ACME TLS-ALPN-01 challenge from {}", peer_addr);
+                tls.shutdown().await
+            };
+            match tokio::time::timeout(CHALLENGE_TIMEOUT, answer).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!("ACME challenge from {} failed: {}", peer_addr, e),
+                Err(_) => warn!("ACME challenge from {} timed out", peer_addr),
+            }
+        });
+    }
+}
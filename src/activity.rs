@@ -0,0 +1,208 @@
+use crate::error::{AppError, AppResult};
+use crate::models::Email;
+use crate::pop3::message::{Attachments, Message};
+use crate::sign;
+use crate::state::AppState;
+use chrono::{DateTime, TimeDelta, Utc};
+use mail_builder::headers::raw::Raw;
+use mail_builder::MessageBuilder;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use tracing::{info, warn};
+
+/// Когда отправлен последний отчёт каждому аккаунту и с какими счётчиками
+const REPORTS_TABLE: &str = "activity_reports";
+
+/// Счётчики ведутся с этого момента; после перезапуска они начинаются с нуля
+static STARTED: LazyLock<DateTime<Utc>> = LazyLock::new(Utc::now);
+
+/// Две сессии одного аккаунта не должны получить отчёт дважды
+static REPORT_LOCK: Mutex<()> = Mutex::new(());
+
+static POSTS_FETCHED: AtomicU64 = AtomicU64::new(0);
+static EMAILS_SERVED: AtomicU64 = AtomicU64::new(0);
+static POSTS_PUBLISHED: AtomicU64 = AtomicU64::new(0);
+static ERRORS: AtomicU64 = AtomicU64::new(0);
+static RATE_LIMITED: AtomicU64 = AtomicU64::new(0);
+
+/// Событие, учитываемое в отчёте о работе шлюза
+#[derive(Debug, Clone, Copy)]
+pub enum Counter {
+    /// Новые посты ленты с прошлой загрузки
+    PostsFetched,
+    /// Письма, отданные по RETR
+    EmailsServed,
+    /// Посты, опубликованные через SMTP
+    PostsPublished,
+    /// Неудачные запросы к API: сетевая ошибка или ответ 5xx
+    Errors,
+    /// Ответы 429 от API
+    RateLimited,
+}
+
+/// Значения всех счётчиков на момент отчёта
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+struct Totals {
+    posts_fetched: u64,
+    emails_served: u64,
+    posts_published: u64,
+    errors: u64,
+    rate_limited: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LastReport {
+    sent_at: DateTime<Utc>,
+    /// Запуск шлюза, к которому относятся `totals`
+    started: DateTime<Utc>,
+    totals: Totals,
+}
+
+/// Начинает отсчёт статистики; вызывается при запуске шлюза
+pub fn start() {
+    LazyLock::force(&STARTED);
+}
+
+pub fn count(counter: Counter, n: u64) {
+    let counter = match counter {
+        Counter::PostsFetched => &POSTS_FETCHED,
+        Counter::EmailsServed => &EMAILS_SERVED,
+        Counter::PostsPublished => &POSTS_PUBLISHED,
+        Counter::Errors => &ERRORS,
+        Counter::RateLimited => &RATE_LIMITED,
+    };
+    counter.fetch_add(n, Ordering::Relaxed);
+}
+
+fn totals() -> Totals {
+    Totals {
+        posts_fetched: POSTS_FETCHED.load(Ordering::Relaxed),
+        emails_served: EMAILS_SERVED.load(Ordering::Relaxed),
+        posts_published: POSTS_PUBLISHED.load(Ordering::Relaxed),
+        errors: ERRORS.load(Ordering::Relaxed),
+        rate_limited: RATE_LIMITED.load(Ordering::Relaxed),
+    }
+}
+
+/// Письмо-отчёт для ящика `account_addr`, если с прошлого отчёта прошло
+/// --activity-report дней. Первая загрузка ленты только начинает период.
+pub fn due_report(state: &AppState, account_addr: &str) -> Option<Email> {
+    let days = state.config.activity_report?;
+    let _guard = REPORT_LOCK.lock().ok()?;
+
+    let now = Utc::now();
+    let current = totals();
+    let last: Option<LastReport> = state.store.get(REPORTS_TABLE, account_addr);
+    let record = LastReport {
+        sent_at: now,
+        started: *STARTED,
+        totals: current,
+    };
+
+    let Some(last) = last else {
+        if let Err(e) = state.store.insert(REPORTS_TABLE, account_addr, &record) {
+            warn!(
+                "Cannot start activity report period for {}: {}",
+                account_addr, e
+            );
+        }
+        return None;
+    };
+    if now - last.sent_at < TimeDelta::days(days as i64) {
+        return None;
+    }
+
+    // Без записи о новом отчёте он приходил бы при каждой загрузке ленты
+    if let Err(e) = state.store.insert(REPORTS_TABLE, account_addr, &record) {
+        warn!("Cannot record activity report for {}: {}", account_addr, e);
+        return None;
+    }
+
+    let restarted = last.started != *STARTED;
+    let (since, baseline) = if restarted {
+        (*STARTED, Totals::default())
+    } else {
+        (last.sent_at, last.totals)
+    };
+    let period = Totals {
+        posts_fetched: current.posts_fetched.saturating_sub(baseline.posts_fetched),
+        emails_served: current.emails_served.saturating_sub(baseline.emails_served),
+        posts_published: current
+            .posts_published
+            .saturating_sub(baseline.posts_published),
+        errors: current.errors.saturating_sub(baseline.errors),
+        rate_limited: current.rate_limited.saturating_sub(baseline.rate_limited),
+    };
+
+    match build_report(state, account_addr, since, now, &period, restarted) {
+        Ok(message) => {
+            info!("Delivering activity report to {}", account_addr);
+            Some(message)
+        }
+        Err(e) => {
+            warn!("Cannot build activity report for {}: {}", account_addr, e);
+            None
+        }
+    }
+}
+
+fn build_report(
+    state: &AppState,
+    account_addr: &str,
+    since: DateTime<Utc>,
+    now: DateTime<Utc>,
+    period: &Totals,
+    restarted: bool,
+) -> AppResult<Email> {
+    let config = &state.config;
+    let charset = config.charset;
+    let time_format = "%Y-%m-%d %H:%M UTC";
+
+    let mut text = format!(
+        "Отчёт о работе шлюза mop3 {}\n\
+         Период: {} - {}\n",
+        env!("CARGO_PKG_VERSION"),
+        since.format(time_format),
+        now.format(time_format),
+    );
+    if restarted {
+        text.push_str("Шлюз перезапускался: счётчики ведутся с момента запуска.\n");
+    }
+    text.push_str(&format!(
+        "\n\
+         Новых постов в ленте:      {}\n\
+         Отдано писем по POP3:      {}\n\
+         Опубликовано постов:       {}\n\
+         Ошибок запросов к API:     {}\n\
+         Ответов 429 (лимит API):   {}\n",
+        period.posts_fetched,
+        period.emails_served,
+        period.posts_published,
+        period.errors,
+        period.rate_limited,
+    ));
+
+    let subject = format!("mop3: отчёт о работе с {}", since.format("%Y-%m-%d"));
+    let gateway = format!("mop3@{}", config.gateway_domain);
+    let id = format!("report-{}", now.timestamp());
+    let mut message = MessageBuilder::new()
+        .to(account_addr)
+        .date(now.timestamp())
+        .message_id(format!("{}@{}", id, config.gateway_domain))
+        .header("From", Raw::new(charset.encode_address("mop3", &gateway)));
+    if charset.is_utf8() {
+        message = message.subject(subject.as_str());
+    } else {
+        message = message.header("Subject", Raw::new(charset.encode_header(&subject)));
+    }
+
+    let email_string = message
+        .body(charset.text_part("text/plain", &text, config.transfer_encoding))
+        .write_to_string()
+        .map_err(|e| AppError::ServerError(format!("Failed to build email: {}", e)))?;
+
+    let body = Message::assemble(email_string, Attachments::default())?;
+    let body = sign::sign(state, body)?;
+    Ok(Email::new(id, subject, body))
+}
@@ -1,29 +1,142 @@
-use crate::config::Config;
+use crate::api::{escape_html, retry, status_error, text_to_html};
+use crate::config::{ApiMode, Config};
 use crate::error::{AppError, AppResult};
-use crate::models::{Credentials, Post};
+use crate::models::{
+    BlueskyEmbed, BlueskyFeedViewPost, BlueskyPostThread, BlueskyPostView, BlueskyPosts,
+    BlueskyProfile, BlueskyQuote, BlueskyReason, BlueskyReplyRecord, BlueskyThreadNode,
+    BlueskyThreadPost, BlueskyTimeline, ContactList, Credentials, MediaAttachment, MediaMeta,
+    MediaType, NormalizedPost, Notification, NotificationKind, PostAuthor, ReplyInfo, SavedFeed,
+    SearchResults, Secret,
+};
 use async_trait::async_trait;
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
 use serde_json::Value;
-use std::time::Duration;
+use std::collections::HashMap;
 use tracing::{debug, error, info, warn};
 
-const USER_AGENT: &str = "mop3/0.2";
-const TIMEOUT_SECS: u64 = 30;
 const BLUESKY_API_URL: &str = "https://bsky.social/xrpc";
 
+/// Сколько URI принимает app.bsky.feed.getPosts за один запрос
+const GET_POSTS_LIMIT: usize = 25;
+
+/// Столько аккаунтов отдаёт одна страница getFollowers и getFollows (максимум API)
+const FOLLOWS_PAGE_LIMIT: &str = "100";
+
+/// Списки подписок длиннее этого числа страниц обрезаются
+const MAX_FOLLOWS_PAGES: usize = 100;
+
+/// Глубина ответов и предков в getPostThread
+const THREAD_DEPTH: &str = "100";
+
+/// Ответ com.atproto.server.createSession
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Session {
+    access_jwt: Secret,
+}
+
+/// Ответ com.atproto.repo.createRecord
+#[derive(Deserialize)]
+struct CreatedRecord {
+    uri: String,
+}
+
+/// Ответ app.bsky.notification.listNotifications
+#[derive(Deserialize)]
+struct Notifications {
+    notifications: Vec<BlueskyNotification>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlueskyNotification {
+    uri: String,
+    author: BlueskyProfile,
+    /// like, repost, follow, mention, reply, quote...
+    reason: String,
+    /// Пост пользователя, который отметили или репостнули
+    #[serde(default)]
+    reason_subject: Option<String>,
+    indexed_at: String,
+}
+
+/// Ответ app.bsky.actor.getPreferences: нужны только сохранённые ленты
+#[derive(Deserialize)]
+struct Preferences {
+    preferences: Vec<Value>,
+}
+
+/// Элемент app.bsky.actor.defs#savedFeedsPrefV2
+#[derive(Deserialize)]
+struct SavedFeedItem {
+    #[serde(rename = "type")]
+    kind: String,
+    value: String,
+    #[serde(default)]
+    pinned: bool,
+}
+
+/// Ответ app.bsky.feed.getFeedGenerators
+#[derive(Deserialize)]
+struct FeedGenerators {
+    feeds: Vec<FeedGenerator>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FeedGenerator {
+    uri: String,
+    display_name: String,
+}
+
+/// Страница app.bsky.graph.getFollowers или app.bsky.graph.getFollows
+#[derive(Deserialize)]
+struct Follows {
+    #[serde(alias = "followers", alias = "follows")]
+    accounts: Vec<BlueskyProfile>,
+    #[serde(default)]
+    cursor: Option<String>,
+}
+
+/// Ответ com.atproto.identity.resolveHandle
+#[derive(Deserialize)]
+struct ResolvedHandle {
+    did: String,
+}
+
+/// Ответ app.bsky.actor.searchActors
+#[derive(Deserialize)]
+struct Actors {
+    actors: Vec<BlueskyProfile>,
+}
+
+/// Ответ com.atproto.repo.uploadBlob
+#[derive(Deserialize)]
+struct UploadedBlob {
+    blob: Blob,
+}
+
+#[derive(Deserialize)]
+struct Blob {
+    #[serde(rename = "ref")]
+    reference: BlobLink,
+}
+
+#[derive(Deserialize)]
+struct BlobLink {
+    #[serde(rename = "$link")]
+    link: String,
+}
+
 pub struct BlueskyClient {
     http_client: Client,
     config: Config,
 }
 
 impl BlueskyClient {
-    pub fn new(config: Config) -> Self {
-        let http_client = Client::builder()
-            .timeout(Duration::from_secs(TIMEOUT_SECS))
-            .user_agent(USER_AGENT)
-            .build()
-            .unwrap_or_else(|_| Client::new());
-
+    pub fn new(config: Config, http_client: Client) -> Self {
         BlueskyClient {
             http_client,
             config,
@@ -31,48 +144,112 @@ impl BlueskyClient {
     }
 
     /// Создаёт сессию и получает access token
-    async fn create_session(&self, cred: &Credentials) -> AppResult<String> {
+    async fn create_session(&self, cred: &Credentials) -> AppResult<Secret> {
         debug!("Creating Bluesky session for: {}", cred.username);
 
-        let response = self
-            .http_client
-            .post(format!(
-                "{}/com.atproto.server.createSession",
-                BLUESKY_API_URL
-            ))
-            .json(&serde_json::json!({
-                "identifier": &cred.username,
-                "password": &cred.password,
-            }))
-            .send()
-            .await
-            .map_err(|e| {
-                error!("Failed to create Bluesky session: {}", e);
-                if e.is_timeout() {
-                    AppError::Timeout
-                } else {
-                    AppError::NetworkError(e)
-                }
-            })?;
+        let response = retry::send(
+            self.http_client
+                .post(format!(
+                    "{}/com.atproto.server.createSession",
+                    BLUESKY_API_URL
+                ))
+                .json(&serde_json::json!({
+                    "identifier": &cred.username,
+                    "password": &cred.password,
+                })),
+            self.config.api_retries,
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to create Bluesky session: {}", e);
+            if e.is_timeout() {
+                AppError::Timeout
+            } else {
+                AppError::NetworkError(e)
+            }
+        })?;
 
-        if !response.status().is_success() {
+        // Неверный пароль приложения createSession отклоняет с кодом 400 или 401
+        let status = response.status();
+        if status == StatusCode::BAD_REQUEST {
             error!("Invalid Bluesky credentials");
             return Err(AppError::InvalidCredentials);
         }
+        if !status.is_success() {
+            error!("Cannot create Bluesky session: {}", status);
+            return Err(status_error(&response, "Cannot create session"));
+        }
 
-        let session: Value = response.json().await.map_err(|e| {
+        let session: Session = response.json().await.map_err(|e| {
             error!("Failed to parse session response: {}", e);
             AppError::NetworkError(e)
         })?;
 
-        let access_token = session["accessJwt"]
-            .as_str()
-            .ok_or(AppError::ApiError(
-                "No access token in response".to_string(),
-            ))?
-            .to_string();
+        Ok(session.access_jwt)
+    }
 
-        Ok(access_token)
+    /// Запрос XRPC метода чтения с разбором ответа
+    async fn get_json<T: DeserializeOwned>(
+        &self,
+        token: &Secret,
+        method: &str,
+        query: &[(&str, &str)],
+        what: &str,
+    ) -> AppResult<T> {
+        debug!("{}: {}", what, method);
+
+        let response = retry::send(
+            self.http_client
+                .get(format!("{}/{}", BLUESKY_API_URL, method))
+                .bearer_auth(&**token)
+                .query(query),
+            self.config.api_retries,
+        )
+        .await
+        .map_err(|e| {
+            error!("{} failed: {}", what, e);
+            if e.is_timeout() {
+                AppError::Timeout
+            } else {
+                AppError::NetworkError(e)
+            }
+        })?;
+
+        if !response.status().is_success() {
+            error!(
+                "Bluesky API returned status: {} for {}",
+                response.status(),
+                method
+            );
+            return Err(status_error(&response, what));
+        }
+
+        response.json().await.map_err(|e| {
+            error!("Failed to parse {} response: {}", method, e);
+            AppError::NetworkError(e)
+        })
+    }
+
+    /// Загружает посты по AT URI; getPosts принимает не больше 25 URI за раз
+    async fn fetch_posts(&self, token: &Secret, uris: &[&str]) -> AppResult<Vec<BlueskyPostView>> {
+        let mut posts = Vec::with_capacity(uris.len());
+        for chunk in uris.chunks(GET_POSTS_LIMIT) {
+            let query: Vec<(&str, &str)> = chunk.iter().map(|uri| ("uris", *uri)).collect();
+            let batch: BlueskyPosts = self
+                .get_json(token, "app.bsky.feed.getPosts", &query, "Fetching posts")
+                .await?;
+            posts.extend(batch.posts);
+        }
+        Ok(posts)
+    }
+
+    /// Загружает пост по AT URI, например родителя для ответа
+    async fn fetch_post(&self, token: &Secret, uri: &str) -> AppResult<BlueskyPostView> {
+        self.fetch_posts(token, &[uri])
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| AppError::ApiError(format!("Post {} not found", uri)))
     }
 }
 
@@ -92,46 +269,67 @@ impl super::SocialNetworkApi for BlueskyClient {
         &self,
         cred: &Credentials,
         limit: u32,
-        since_id: &str,
-    ) -> AppResult<Vec<Post>> {
+        _since_id: &str,
+    ) -> AppResult<Vec<NormalizedPost>> {
         debug!("Fetching Bluesky timeline (limit: {})", limit);
 
         // Получаем access token
         let token = self.create_session(cred).await?;
 
         // Запрашиваем timeline
-        let response = self
-            .http_client
-            .get(format!("{}/app.bsky.feed.getTimeline", BLUESKY_API_URL))
-            .header("Authorization", format!("Bearer {}", token))
-            .query(&[("limit", limit.to_string())])
-            .send()
-            .await
-            .map_err(|e| {
-                error!("Failed to fetch Bluesky timeline: {}", e);
-                if e.is_timeout() {
-                    AppError::Timeout
-                } else {
-                    AppError::NetworkError(e)
-                }
-            })?;
+        let response = retry::send(
+            self.http_client
+                .get(format!("{}/app.bsky.feed.getTimeline", BLUESKY_API_URL))
+                .bearer_auth(&*token)
+                .query(&[("limit", limit.to_string())]),
+            self.config.api_retries,
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to fetch Bluesky timeline: {}", e);
+            if e.is_timeout() {
+                AppError::Timeout
+            } else {
+                AppError::NetworkError(e)
+            }
+        })?;
 
         if !response.status().is_success() {
             error!("Bluesky API returned status: {}", response.status());
-            return Err(AppError::ApiError("Failed to fetch timeline".to_string()));
+            return Err(status_error(&response, "Failed to fetch timeline"));
         }
 
-        let data: Value = response.json().await.map_err(|e| {
-            error!("Failed to parse timeline JSON: {}", e);
+        let json = response.text().await.map_err(|e| {
+            error!("Failed to get timeline JSON: {}", e);
             AppError::NetworkError(e)
         })?;
-
-        // TODO: Парсить посты в Vec<Post::Bluesky>
-        // Пока возвращаем пустой вектор
-        warn!("Bluesky timeline parsing not fully implemented yet");
-
-        info!("Fetched Bluesky timeline successfully");
-        Ok(vec![])
+        let parse_error = |e| {
+            error!("Failed to parse timeline JSON: {}", e);
+            AppError::JsonError(e)
+        };
+        let timeline: BlueskyTimeline = serde_json::from_str(&json).map_err(parse_error)?;
+
+        // С --debug исходный JSON прикладывается к письму
+        let mut raw = if self.config.debug {
+            let value: Value = serde_json::from_str(&json).map_err(parse_error)?;
+            value["feed"].as_array().cloned().unwrap_or_default()
+        } else {
+            Vec::new()
+        }
+        .into_iter();
+
+        let posts: Vec<NormalizedPost> = timeline
+            .feed
+            .into_iter()
+            .map(|item| {
+                let mut post = normalize(item);
+                post.raw = raw.next();
+                post
+            })
+            .collect();
+
+        info!("Fetched {} posts from Bluesky timeline", posts.len());
+        Ok(posts)
     }
 
     async fn post_status(
@@ -139,9 +337,15 @@ impl super::SocialNetworkApi for BlueskyClient {
         cred: &Credentials,
         status: String,
         in_reply_to_id: Option<String>,
-        media_ids: Vec<String>,
+        _media_ids: Vec<String>,
+        spoiler_text: Option<String>,
     ) -> AppResult<String> {
         debug!("Posting to Bluesky (reply_to: {:?})", in_reply_to_id);
+        // Самометки Bluesky покрывают только графику и контент для взрослых,
+        // текстового предупреждения в записи нет
+        if let Some(cw) = spoiler_text {
+            debug!("Bluesky has no content warnings, ignoring CW '{}'", cw);
+        }
 
         // Получаем access token
         let token = self.create_session(cred).await?;
@@ -153,51 +357,54 @@ impl super::SocialNetworkApi for BlueskyClient {
             "createdAt": chrono::Utc::now().to_rfc3339(),
         });
 
-        // Добавляем reply, если есть
+        // Ответ ссылается на родителя и корень треда по URI и CID
         if let Some(reply_to) = in_reply_to_id {
-            record["reply"] = serde_json::json!({
-                "parent": { "uri": reply_to },
-                "root": { "uri": reply_to }
-            });
+            let parent = self.fetch_post(&token, &reply_to).await?;
+            let reply = BlueskyReplyRecord {
+                root: parent
+                    .record
+                    .reply
+                    .as_ref()
+                    .map_or_else(|| parent.strong_ref(), |reply| reply.root.clone()),
+                parent: parent.strong_ref(),
+            };
+            record["reply"] = serde_json::to_value(reply)?;
         }
 
-        let response = self
-            .http_client
-            .post(format!("{}/com.atproto.repo.createRecord", BLUESKY_API_URL))
-            .header("Authorization", format!("Bearer {}", token))
-            .json(&serde_json::json!({
-                "repo": &cred.username,
-                "collection": "app.bsky.feed.post",
-                "record": record,
-            }))
-            .send()
-            .await
-            .map_err(|e| {
-                error!("Failed to post to Bluesky: {}", e);
-                if e.is_timeout() {
-                    AppError::Timeout
-                } else {
-                    AppError::ApiError(format!("Post failed: {}", e))
-                }
-            })?;
+        let response = retry::send(
+            self.http_client
+                .post(format!("{}/com.atproto.repo.createRecord", BLUESKY_API_URL))
+                .bearer_auth(&*token)
+                .json(&serde_json::json!({
+                    "repo": &cred.username,
+                    "collection": "app.bsky.feed.post",
+                    "record": record,
+                })),
+            self.config.api_retries,
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to post to Bluesky: {}", e);
+            if e.is_timeout() {
+                AppError::Timeout
+            } else {
+                AppError::ApiError(format!("Post failed: {}", e))
+            }
+        })?;
 
         if !response.status().is_success() {
             error!(
                 "Bluesky API returned status: {} for post",
                 response.status()
             );
-            return Err(AppError::ApiError("Failed to post".to_string()));
+            return Err(status_error(&response, "Failed to post"));
         }
 
-        let result: Value = response.json().await.map_err(|e| {
+        let created: CreatedRecord = response.json().await.map_err(|e| {
             error!("Failed to parse post response: {}", e);
             AppError::NetworkError(e)
         })?;
-
-        let uri = result["uri"]
-            .as_str()
-            .ok_or(AppError::ApiError("No URI in response".to_string()))?
-            .to_string();
+        let uri = created.uri;
 
         info!("Successfully posted to Bluesky: {}", uri);
         Ok(uri)
@@ -209,6 +416,8 @@ impl super::SocialNetworkApi for BlueskyClient {
         data: Vec<u8>,
         filename: String,
         mime: String,
+        // Alt текст Bluesky хранится в записи поста, а не в blob
+        _description: Option<String>,
     ) -> AppResult<String> {
         debug!("Uploading media to Bluesky: {} ({})", filename, mime);
 
@@ -216,41 +425,578 @@ impl super::SocialNetworkApi for BlueskyClient {
         let token = self.create_session(cred).await?;
 
         // Загружаем blob
-        let response = self
-            .http_client
-            .post(format!("{}/com.atproto.repo.uploadBlob", BLUESKY_API_URL))
-            .header("Authorization", format!("Bearer {}", token))
-            .header("Content-Type", mime)
-            .body(data)
-            .send()
-            .await
-            .map_err(|e| {
-                error!("Failed to upload media to Bluesky: {}", e);
-                if e.is_timeout() {
-                    AppError::Timeout
-                } else {
-                    AppError::ApiError(format!("Upload failed: {}", e))
-                }
-            })?;
+        let response = retry::send(
+            self.http_client
+                .post(format!("{}/com.atproto.repo.uploadBlob", BLUESKY_API_URL))
+                .bearer_auth(&*token)
+                .header("Content-Type", mime)
+                .body(data),
+            self.config.api_retries,
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to upload media to Bluesky: {}", e);
+            if e.is_timeout() {
+                AppError::Timeout
+            } else {
+                AppError::ApiError(format!("Upload failed: {}", e))
+            }
+        })?;
 
         if !response.status().is_success() {
             error!("Media upload returned status: {}", response.status());
-            return Err(AppError::ApiError("Upload failed".to_string()));
+            return Err(status_error(&response, "Upload failed"));
         }
 
-        let result: Value = response.json().await.map_err(|e| {
+        let uploaded: UploadedBlob = response.json().await.map_err(|e| {
             error!("Failed to parse upload response: {}", e);
             AppError::NetworkError(e)
         })?;
-
-        let blob_ref = result["blob"]["ref"]["$link"]
-            .as_str()
-            .ok_or(AppError::ApiError(
-                "No blob reference in response".to_string(),
-            ))?
-            .to_string();
+        let blob_ref = uploaded.blob.reference.link;
 
         info!("Successfully uploaded media to Bluesky: {}", blob_ref);
         Ok(blob_ref)
     }
+
+    async fn get_notifications(
+        &self,
+        cred: &Credentials,
+        limit: u32,
+    ) -> AppResult<Vec<Notification>> {
+        let token = self.create_session(cred).await?;
+        let limit = limit.to_string();
+        let list: Notifications = self
+            .get_json(
+                &token,
+                "app.bsky.notification.listNotifications",
+                &[("limit", &limit)],
+                "Fetching notifications",
+            )
+            .await?;
+
+        // Уведомление содержит только URI поста, сами посты грузятся одним запросом
+        let post_uri = |notification: &BlueskyNotification| match notification.reason.as_str() {
+            "mention" | "reply" | "quote" => Some(notification.uri.clone()),
+            "like" | "repost" => notification.reason_subject.clone(),
+            _ => None,
+        };
+        let uris: Vec<String> = list.notifications.iter().filter_map(post_uri).collect();
+        let uris: Vec<&str> = uris.iter().map(String::as_str).collect();
+        let mut posts: HashMap<String, BlueskyPostView> = self
+            .fetch_posts(&token, &uris)
+            .await?
+            .into_iter()
+            .map(|post| (post.uri.clone(), post))
+            .collect();
+
+        Ok(list
+            .notifications
+            .into_iter()
+            .map(|notification| Notification {
+                post: post_uri(&notification)
+                    .and_then(|uri| posts.remove(&uri))
+                    .map(|view| normalize_post(view, None)),
+                kind: match notification.reason.as_str() {
+                    "mention" => NotificationKind::Mention,
+                    "reply" => NotificationKind::Reply,
+                    "repost" => NotificationKind::Boost,
+                    "like" => NotificationKind::Favourite,
+                    "follow" => NotificationKind::Follow,
+                    _ => NotificationKind::Other(notification.reason),
+                },
+                // У подписки и отметки нет своего поста, URI уникален и для них
+                id: notification.uri,
+                created_at: notification.indexed_at,
+                account: normalize_profile(notification.author),
+            })
+            .collect())
+    }
+
+    async fn search(
+        &self,
+        cred: &Credentials,
+        query: &str,
+        limit: u32,
+    ) -> AppResult<SearchResults> {
+        let token = self.create_session(cred).await?;
+        let limit = limit.to_string();
+        let query = [("q", query), ("limit", limit.as_str())];
+
+        let actors: Actors = self
+            .get_json(
+                &token,
+                "app.bsky.actor.searchActors",
+                &query,
+                "Searching accounts",
+            )
+            .await?;
+        let posts: BlueskyPosts = self
+            .get_json(
+                &token,
+                "app.bsky.feed.searchPosts",
+                &query,
+                "Searching posts",
+            )
+            .await?;
+
+        Ok(SearchResults {
+            accounts: actors.actors.into_iter().map(normalize_profile).collect(),
+            posts: posts
+                .posts
+                .into_iter()
+                .map(|view| normalize_post(view, None))
+                .collect(),
+            tags: Vec::new(),
+        })
+    }
+
+    async fn saved_feeds(&self, cred: &Credentials) -> AppResult<Vec<SavedFeed>> {
+        let token = self.create_session(cred).await?;
+        let preferences: Preferences = self
+            .get_json(
+                &token,
+                "app.bsky.actor.getPreferences",
+                &[],
+                "Fetching preferences",
+            )
+            .await?;
+
+        // savedFeedsPrefV2 заменил savedFeedsPref, но старые аккаунты
+        // могут хранить только его
+        let mut saved: Vec<(String, bool)> = Vec::new();
+        for preference in &preferences.preferences {
+            match preference["$type"].as_str() {
+                Some("app.bsky.actor.defs#savedFeedsPrefV2") => {
+                    let items: Vec<SavedFeedItem> =
+                        serde_json::from_value(preference["items"].clone())?;
+                    saved = items
+                        .into_iter()
+                        .filter(|item| item.kind == "feed")
+                        .map(|item| (item.value, item.pinned))
+                        .collect();
+                    break;
+                }
+                Some("app.bsky.actor.defs#savedFeedsPref") => {
+                    let uris = |key: &str| -> Vec<String> {
+                        serde_json::from_value(preference[key].clone()).unwrap_or_default()
+                    };
+                    let pinned = uris("pinned");
+                    saved = uris("saved")
+                        .into_iter()
+                        .map(|uri| {
+                            let is_pinned = pinned.contains(&uri);
+                            (uri, is_pinned)
+                        })
+                        .collect();
+                }
+                _ => {}
+            }
+        }
+        if saved.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let query: Vec<(&str, &str)> = saved
+            .iter()
+            .map(|(uri, _)| ("feeds", uri.as_str()))
+            .collect();
+        let generators: FeedGenerators = self
+            .get_json(
+                &token,
+                "app.bsky.feed.getFeedGenerators",
+                &query,
+                "Fetching feed names",
+            )
+            .await?;
+
+        Ok(saved
+            .into_iter()
+            .filter_map(|(uri, pinned)| {
+                let generator = generators.feeds.iter().find(|g| g.uri == uri)?;
+                Some(SavedFeed {
+                    name: generator.display_name.clone(),
+                    id: uri,
+                    pinned,
+                })
+            })
+            .collect())
+    }
+
+    async fn get_feed(
+        &self,
+        cred: &Credentials,
+        feed_id: &str,
+        limit: u32,
+    ) -> AppResult<Vec<NormalizedPost>> {
+        let token = self.create_session(cred).await?;
+        let limit = limit.to_string();
+        let feed: BlueskyTimeline = self
+            .get_json(
+                &token,
+                "app.bsky.feed.getFeed",
+                &[("feed", feed_id), ("limit", &limit)],
+                "Fetching feed",
+            )
+            .await?;
+
+        let posts: Vec<NormalizedPost> = feed.feed.into_iter().map(normalize).collect();
+        info!(
+            "Fetched {} posts from Bluesky feed {}",
+            posts.len(),
+            feed_id
+        );
+        Ok(posts)
+    }
+
+    async fn get_contacts(
+        &self,
+        cred: &Credentials,
+        list: ContactList,
+    ) -> AppResult<Vec<PostAuthor>> {
+        let token = self.create_session(cred).await?;
+        let method = match list {
+            ContactList::Followers => "app.bsky.graph.getFollowers",
+            ContactList::Following => "app.bsky.graph.getFollows",
+        };
+
+        let mut accounts = Vec::new();
+        let mut cursor: Option<String> = None;
+        for page in 0..MAX_FOLLOWS_PAGES {
+            let mut query = vec![
+                ("actor", cred.username.as_str()),
+                ("limit", FOLLOWS_PAGE_LIMIT),
+            ];
+            if let Some(cursor) = &cursor {
+                query.push(("cursor", cursor));
+            } else if page > 0 {
+                break;
+            }
+
+            let follows: Follows = self
+                .get_json(&token, method, &query, "Fetching contacts")
+                .await?;
+            accounts.extend(follows.accounts.into_iter().map(normalize_profile));
+            cursor = follows.cursor;
+        }
+
+        if cursor.is_some() {
+            warn!(
+                "Fetching contacts: list truncated at {} accounts",
+                accounts.len()
+            );
+        }
+        info!("Fetched {} {} accounts", accounts.len(), list.name());
+        Ok(accounts)
+    }
+
+    async fn get_post(&self, cred: &Credentials, post_id: &str) -> AppResult<NormalizedPost> {
+        let token = self.create_session(cred).await?;
+        let view = self.fetch_post(&token, post_id).await?;
+        Ok(normalize_post(view, None))
+    }
+
+    async fn resolve_post_url(&self, cred: &Credentials, url: &str) -> AppResult<String> {
+        if url.starts_with("at://") {
+            return Ok(url.to_string());
+        }
+        // https://bsky.app/profile/<хэндл или DID>/post/<rkey>
+        let Some((actor, rkey)) = url
+            .split_once("/profile/")
+            .and_then(|(_, path)| path.split_once("/post/"))
+        else {
+            return Err(AppError::ApiError(format!(
+                "{} is not a Bluesky post link",
+                url
+            )));
+        };
+        let rkey = rkey.split(['/', '?', '#']).next().unwrap_or_default();
+
+        let did = if actor.starts_with("did:") {
+            actor.to_string()
+        } else {
+            let token = self.create_session(cred).await?;
+            let resolved: ResolvedHandle = self
+                .get_json(
+                    &token,
+                    "com.atproto.identity.resolveHandle",
+                    &[("handle", actor)],
+                    "Resolving handle",
+                )
+                .await?;
+            resolved.did
+        };
+        Ok(format!("at://{}/app.bsky.feed.post/{}", did, rkey))
+    }
+
+    async fn get_thread(
+        &self,
+        cred: &Credentials,
+        post_id: &str,
+    ) -> AppResult<Vec<NormalizedPost>> {
+        let token = self.create_session(cred).await?;
+        let response: BlueskyPostThread = self
+            .get_json(
+                &token,
+                "app.bsky.feed.getPostThread",
+                &[
+                    ("uri", post_id),
+                    ("depth", THREAD_DEPTH),
+                    ("parentHeight", THREAD_DEPTH),
+                ],
+                "Fetching thread",
+            )
+            .await?;
+        let BlueskyThreadNode::Post {
+            post,
+            parent,
+            replies,
+        } = response.thread
+        else {
+            return Err(AppError::ApiError(format!(
+                "Post {} is not available",
+                post_id
+            )));
+        };
+
+        // Предки приходят цепочкой от родителя к корню
+        let mut ancestors = Vec::new();
+        let mut node = parent;
+        while let Some(BlueskyThreadNode::Post { post, parent, .. }) = node.map(|node| *node) {
+            ancestors.push(*post);
+            node = parent;
+        }
+
+        let mut thread = Vec::new();
+        let parent_dids: Vec<Option<String>> = ancestors
+            .iter()
+            .skip(1)
+            .map(|parent| Some(parent.author.did.clone()))
+            .chain(std::iter::once(None))
+            .collect();
+        for (view, parent_did) in ancestors.iter().zip(parent_dids).rev() {
+            thread.push(normalize_post(view.clone(), parent_did));
+        }
+
+        let parent_did = ancestors.first().map(|parent| parent.author.did.clone());
+        let did = post.author.did.clone();
+        thread.push(normalize_post(*post, parent_did));
+        flatten_replies(replies, &did, &mut thread);
+        Ok(thread)
+    }
+
+    async fn delete_post(&self, cred: &Credentials, post_id: &str) -> AppResult<()> {
+        // at://<did>/app.bsky.feed.post/<rkey>
+        let Some((collection, rkey)) = post_id
+            .strip_prefix("at://")
+            .and_then(|path| path.split_once('/'))
+            .and_then(|(_, path)| path.split_once('/'))
+        else {
+            return Err(AppError::ApiError(format!(
+                "{} is not a Bluesky post URI",
+                post_id
+            )));
+        };
+
+        debug!("Deleting Bluesky post {}", post_id);
+
+        let token = self.create_session(cred).await?;
+        let response = retry::send(
+            self.http_client
+                .post(format!("{}/com.atproto.repo.deleteRecord", BLUESKY_API_URL))
+                .bearer_auth(&*token)
+                .json(&serde_json::json!({
+                    "repo": &cred.username,
+                    "collection": collection,
+                    "rkey": rkey,
+                })),
+            self.config.api_retries,
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to delete Bluesky post: {}", e);
+            if e.is_timeout() {
+                AppError::Timeout
+            } else {
+                AppError::NetworkError(e)
+            }
+        })?;
+
+        if !response.status().is_success() {
+            error!(
+                "Bluesky API returned status: {} for delete",
+                response.status()
+            );
+            return Err(status_error(&response, "Failed to delete"));
+        }
+
+        info!("Deleted Bluesky post {}", post_id);
+        Ok(())
+    }
+
+    fn max_post_length(&self) -> usize {
+        300
+    }
+}
+
+/// Переводит пост ленты Bluesky в общий вид; репост становится бустом
+fn normalize(item: BlueskyFeedViewPost) -> NormalizedPost {
+    // Автор родителя известен, только если лента прислала его целиком
+    let parent_author = item.reply.and_then(|reply| match reply.parent {
+        BlueskyThreadPost::Post(parent) => Some(parent.author.did),
+        BlueskyThreadPost::Unavailable => None,
+    });
+    let post = normalize_post(item.post, parent_author);
+
+    match item.reason {
+        Some(BlueskyReason::Repost { by, indexed_at }) => NormalizedPost {
+            // У репоста нет своего URI, а ID ленты должен быть уникальным
+            id: format!("{}#repost-{}", post.id, by.did),
+            html: String::new(),
+            created_at: indexed_at,
+            edited_at: None,
+            url: None,
+            uri: None,
+            title: None,
+            visibility: None,
+            language: None,
+            author: normalize_profile(by),
+            reblog: Some(Box::new(post)),
+            reply: None,
+            attachments: Vec::new(),
+            mentions: Vec::new(),
+            tags: Vec::new(),
+            replies_count: 0,
+            boosts_count: 0,
+            favourites_count: 0,
+            application: None,
+            backend: ApiMode::Bluesky,
+            raw: None,
+        },
+        _ => post,
+    }
+}
+
+/// Ответы треда в порядке обхода: каждый ответ, затем ответы на него
+fn flatten_replies(
+    replies: Vec<BlueskyThreadNode>,
+    parent_did: &str,
+    thread: &mut Vec<NormalizedPost>,
+) {
+    for reply in replies {
+        if let BlueskyThreadNode::Post { post, replies, .. } = reply {
+            let did = post.author.did.clone();
+            thread.push(normalize_post(*post, Some(parent_did.to_string())));
+            flatten_replies(replies, &did, thread);
+        }
+    }
+}
+
+fn normalize_post(view: BlueskyPostView, parent_author: Option<String>) -> NormalizedPost {
+    let mut html = text_to_html(&view.record.text);
+    let mut attachments = Vec::new();
+    if let Some(embed) = view.embed {
+        add_embed(embed, &mut html, &mut attachments);
+    }
+
+    let rkey = view.uri.rsplit('/').next().unwrap_or_default();
+    let url = format!(
+        "https://bsky.app/profile/{}/post/{}",
+        view.author.handle, rkey
+    );
+    let reply = view.record.reply.map(|reply| ReplyInfo {
+        id: reply.parent.uri,
+        account_id: parent_author,
+    });
+
+    NormalizedPost {
+        id: view.uri,
+        html,
+        created_at: view.record.created_at,
+        edited_at: None,
+        url: Some(url),
+        uri: None,
+        title: None,
+        visibility: None,
+        language: view.record.langs.into_iter().next(),
+        author: normalize_profile(view.author),
+        reblog: None,
+        reply,
+        attachments,
+        mentions: Vec::new(),
+        tags: Vec::new(),
+        replies_count: view.reply_count,
+        boosts_count: view.repost_count,
+        favourites_count: view.like_count,
+        application: None,
+        backend: ApiMode::Bluesky,
+        raw: None,
+    }
+}
+
+fn normalize_profile(profile: BlueskyProfile) -> PostAuthor {
+    PostAuthor {
+        id: profile.did,
+        username: profile.handle.clone(),
+        display_name: profile.display_name.unwrap_or_default(),
+        url: Some(format!("https://bsky.app/profile/{}", profile.handle)),
+        acct: profile.handle,
+        avatar: profile.avatar,
+        moved_to: None,
+    }
+}
+
+/// Картинки и видео становятся вложениями, карточка ссылки и цитата -
+/// частью текста
+fn add_embed(embed: BlueskyEmbed, html: &mut String, attachments: &mut Vec<MediaAttachment>) {
+    match embed {
+        BlueskyEmbed::Images { images } => {
+            attachments.extend(images.into_iter().map(|image| MediaAttachment {
+                id: image.fullsize.clone(),
+                kind: MediaType::Image,
+                url: Some(image.fullsize),
+                preview_url: Some(image.thumb),
+                description: Some(image.alt).filter(|alt| !alt.is_empty()),
+                blurhash: None,
+                meta: MediaMeta::default(),
+            }));
+        }
+        BlueskyEmbed::Video(video) => attachments.push(MediaAttachment {
+            id: video.playlist.clone(),
+            kind: MediaType::Video,
+            url: Some(video.playlist),
+            preview_url: video.thumbnail,
+            description: video.alt,
+            blurhash: None,
+            meta: MediaMeta::default(),
+        }),
+        BlueskyEmbed::External { external } => {
+            let title = if external.title.is_empty() {
+                &external.uri
+            } else {
+                &external.title
+            };
+            html.push_str(&format!(
+                "<p><a href=\"{}\">{}</a><br>{}</p>",
+                escape_html(&external.uri),
+                escape_html(title),
+                escape_html(&external.description)
+            ));
+        }
+        BlueskyEmbed::Record { record } => add_quote(record, html),
+        BlueskyEmbed::RecordWithMedia { record, media } => {
+            add_quote(record.record, html);
+            add_embed(*media, html, attachments);
+        }
+        BlueskyEmbed::Unknown => {}
+    }
+}
+
+fn add_quote(quote: BlueskyQuote, html: &mut String) {
+    if let BlueskyQuote::Post(quoted) = quote {
+        html.push_str(&format!(
+            "<blockquote><p>@{}:</p>{}</blockquote>",
+            escape_html(&quoted.author.handle),
+            text_to_html(&quoted.value.text)
+        ));
+    }
 }
@@ -0,0 +1,130 @@
+use crate::api::retry;
+use bytes::Bytes;
+use reqwest::header::{
+    HeaderMap, AUTHORIZATION, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, ETAG,
+    IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+};
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tracing::debug;
+
+/// Сколько ответов API держать в памяти
+const CACHE_CAPACITY: usize = 64;
+
+/// Ответы крупнее этого не запоминаются
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// Ответ API, пришедший с валидатором ETag или Last-Modified
+#[derive(Clone)]
+struct Cached {
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+/// Условные GET запросы к API. Ответ с валидатором запоминается, а при
+/// повторе того же запроса отправляются If-None-Match и If-Modified-Since:
+/// если на сервере ничего не изменилось, он отвечает 304 без тела, и
+/// вызывающий получает запомненный ответ, как будто пришёл полный.
+/// Ключ - URL вместе с хешем Authorization, чтобы ответы аккаунтов не
+/// смешивались, а токен не хранился в кеше открытым текстом.
+#[derive(Default)]
+pub struct HttpCache {
+    entries: Mutex<HashMap<String, Cached>>,
+    retries: u32,
+}
+
+impl HttpCache {
+    /// `retries` - сколько раз повторять запрос при временном сбое
+    pub fn new(retries: u32) -> Self {
+        HttpCache {
+            entries: Mutex::default(),
+            retries,
+        }
+    }
+
+    /// Отправляет запрос с повторами; GET - с валидаторами запомненного ответа
+    pub async fn send(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        let (client, request) = request.build_split();
+        let mut request = request?;
+        if request.method() != Method::GET {
+            return retry::execute(&client, request, self.retries).await;
+        }
+
+        let auth: String = request
+            .headers()
+            .get(AUTHORIZATION)
+            .map(|v| Sha256::digest(v.as_bytes()))
+            .unwrap_or_default()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let key = format!("{} {}", request.url(), auth);
+
+        let cached = self
+            .entries
+            .lock()
+            .ok()
+            .and_then(|entries| entries.get(&key).cloned());
+        if let Some(cached) = &cached {
+            if let Some(etag) = cached.headers.get(ETAG) {
+                request.headers_mut().insert(IF_NONE_MATCH, etag.clone());
+            }
+            if let Some(modified) = cached.headers.get(LAST_MODIFIED) {
+                request
+                    .headers_mut()
+                    .insert(IF_MODIFIED_SINCE, modified.clone());
+            }
+        }
+
+        let response = retry::execute(&client, request, self.retries).await?;
+        match cached {
+            Some(cached) if response.status() == StatusCode::NOT_MODIFIED => {
+                debug!("Not modified, using cached response: {}", response.url());
+                Ok(rebuild(cached))
+            }
+            _ => self.remember(key, response).await,
+        }
+    }
+
+    /// Запоминает успешный ответ с валидатором и отдаёт его дальше
+    async fn remember(&self, key: String, response: Response) -> reqwest::Result<Response> {
+        let headers = response.headers();
+        let cacheable = response.status() == StatusCode::OK
+            && (headers.contains_key(ETAG) || headers.contains_key(LAST_MODIFIED))
+            && !headers
+                .get(CACHE_CONTROL)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v.contains("no-store"));
+        if !cacheable {
+            if let Ok(mut entries) = self.entries.lock() {
+                entries.remove(&key);
+            }
+            return Ok(response);
+        }
+
+        let cached = Cached {
+            headers: headers.clone(),
+            body: response.bytes().await?,
+        };
+        if cached.body.len() <= MAX_BODY_BYTES {
+            if let Ok(mut entries) = self.entries.lock() {
+                if entries.len() >= CACHE_CAPACITY {
+                    entries.clear();
+                }
+                entries.insert(key, cached.clone());
+            }
+        }
+        Ok(rebuild(cached))
+    }
+}
+
+/// Собирает ответ из запомненных заголовков и уже распакованного тела
+fn rebuild(cached: Cached) -> Response {
+    let mut response = http::Response::new(cached.body);
+    *response.headers_mut() = cached.headers;
+    response.headers_mut().remove(CONTENT_ENCODING);
+    response.headers_mut().remove(CONTENT_LENGTH);
+    Response::from(response)
+}
@@ -1,31 +1,110 @@
-use crate::config::Config;
+use crate::api::http_cache::HttpCache;
+use crate::api::{escape_html, retry, status_error, text_to_html};
+use crate::config::{ApiMode, Config};
 use crate::error::{AppError, AppResult};
-use crate::models::{Credentials, MastodonAccount, MastodonStatus, Post};
+use crate::models::{
+    ContactList, Conversation, Credentials, MastodonAccount, MastodonStatus, MastodonTag,
+    MediaAttachment, NormalizedPost, Notification, NotificationKind, PostAuthor, PostMention,
+    ReplyInfo, SearchResults,
+};
 use async_trait::async_trait;
-use reqwest::Client;
+use reqwest::header::LINK;
+use reqwest::{Client, Response};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
 use serde_json::Value;
-use std::time::Duration;
-use tracing::{debug, error, info};
+use std::collections::HashSet;
+use tracing::{debug, error, info, warn};
+
+/// Столько символов занимает в посте любая ссылка
+const URL_LENGTH: usize = 23;
+
+/// Столько аккаунтов отдаёт одна страница списка подписок (максимум API)
+const ACCOUNTS_PAGE_LIMIT: u32 = 80;
+
+/// Списки подписок длиннее этого числа страниц обрезаются
+const MAX_ACCOUNT_PAGES: usize = 100;
+
+/// Элемент /api/v1/notifications
+#[derive(Deserialize)]
+struct MastodonNotification {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    created_at: String,
+    account: MastodonAccount,
+    #[serde(default)]
+    status: Option<MastodonStatus>,
+}
+
+/// Элемент /api/v1/conversations
+#[derive(Deserialize)]
+struct MastodonConversation {
+    id: String,
+    #[serde(default)]
+    unread: bool,
+    accounts: Vec<MastodonAccount>,
+    #[serde(default)]
+    last_status: Option<MastodonStatus>,
+}
+
+/// Элемент /api/v1/scheduled_statuses
+#[derive(Deserialize)]
+struct MastodonScheduled {
+    id: String,
+    scheduled_at: String,
+    params: ScheduledParams,
+    #[serde(default)]
+    media_attachments: Vec<MediaAttachment>,
+}
 
-const USER_AGENT: &str = "mop3/0.2";
-const TIMEOUT_SECS: u64 = 30;
+/// Параметры, с которыми пост будет опубликован
+#[derive(Deserialize)]
+struct ScheduledParams {
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    spoiler_text: Option<String>,
+    #[serde(default)]
+    visibility: Option<String>,
+    #[serde(default)]
+    language: Option<String>,
+    #[serde(default)]
+    in_reply_to_id: Option<String>,
+}
+
+/// Ответ /api/v1/statuses/:id/context
+#[derive(Deserialize)]
+struct MastodonContext {
+    #[serde(default)]
+    ancestors: Vec<MastodonStatus>,
+    #[serde(default)]
+    descendants: Vec<MastodonStatus>,
+}
+
+/// Ответ /api/v2/search
+#[derive(Deserialize)]
+struct MastodonSearch {
+    #[serde(default)]
+    accounts: Vec<MastodonAccount>,
+    #[serde(default)]
+    statuses: Vec<MastodonStatus>,
+    #[serde(default)]
+    hashtags: Vec<MastodonTag>,
+}
 
 #[derive(Default)]
 pub struct MastodonClient {
     http_client: Client,
+    responses: HttpCache,
     config: Config,
 }
 
 impl MastodonClient {
-    pub fn new(config: Config) -> Self {
-        let http_client = Client::builder()
-            .timeout(Duration::from_secs(TIMEOUT_SECS))
-            .user_agent(USER_AGENT)
-            .build()
-            .unwrap_or_else(|_| Client::new());
-
+    pub fn new(config: Config, http_client: Client) -> Self {
         MastodonClient {
             http_client,
+            responses: HttpCache::new(config.api_retries),
             config,
         }
     }
@@ -47,8 +126,95 @@ impl MastodonClient {
         Ok((domain, url))
     }
 
-    fn get_auth_header(token: &str) -> String {
-        format!("Bearer {}", token)
+    /// GET запрос к API инстанции пользователя с разбором ответа
+    async fn get_json<T: DeserializeOwned>(
+        &self,
+        cred: &Credentials,
+        path: &str,
+        query: &[(&str, &str)],
+        what: &str,
+    ) -> AppResult<T> {
+        let (_, url) = Self::parse_account(&cred.username)?;
+
+        debug!("{}: GET {}", what, path);
+
+        let response = self
+            .responses
+            .send(
+                self.http_client
+                    .get(format!("{}{}", url, path))
+                    .bearer_auth(&*cred.password)
+                    .query(query),
+            )
+            .await
+            .map_err(|e| {
+                error!("{} failed: {}", what, e);
+                if e.is_timeout() {
+                    AppError::Timeout
+                } else {
+                    AppError::NetworkError(e)
+                }
+            })?;
+
+        if !response.status().is_success() {
+            error!("API returned status: {} for {}", response.status(), path);
+            return Err(status_error(&response, what));
+        }
+
+        response.json().await.map_err(|e| {
+            error!("Failed to parse {} response: {}", path, e);
+            AppError::NetworkError(e)
+        })
+    }
+
+    /// Постраничный список аккаунтов: следующая страница берётся из
+    /// заголовка Link, пока их не больше MAX_ACCOUNT_PAGES
+    async fn get_account_pages(
+        &self,
+        cred: &Credentials,
+        path: &str,
+        what: &str,
+    ) -> AppResult<Vec<MastodonAccount>> {
+        let (_, url) = Self::parse_account(&cred.username)?;
+        let mut next = Some(format!("{}{}?limit={}", url, path, ACCOUNTS_PAGE_LIMIT));
+        let mut accounts = Vec::new();
+
+        for _ in 0..MAX_ACCOUNT_PAGES {
+            let Some(page) = next.take() else {
+                break;
+            };
+            debug!("{}: GET {}", what, page);
+
+            let response = self
+                .responses
+                .send(self.http_client.get(&page).bearer_auth(&*cred.password))
+                .await
+                .map_err(|e| {
+                    error!("{} failed: {}", what, e);
+                    if e.is_timeout() {
+                        AppError::Timeout
+                    } else {
+                        AppError::NetworkError(e)
+                    }
+                })?;
+
+            if !response.status().is_success() {
+                error!("API returned status: {} for {}", response.status(), path);
+                return Err(status_error(&response, what));
+            }
+
+            next = next_page(&response);
+            let batch: Vec<MastodonAccount> = response.json().await.map_err(|e| {
+                error!("Failed to parse {} response: {}", path, e);
+                AppError::NetworkError(e)
+            })?;
+            accounts.extend(batch);
+        }
+
+        if next.is_some() {
+            warn!("{}: list truncated at {} accounts", what, accounts.len());
+        }
+        Ok(accounts)
     }
 }
 
@@ -60,10 +226,12 @@ impl super::SocialNetworkApi for MastodonClient {
         debug!("Verifying Mastodon credentials for domain: {}", domain);
 
         let response = self
-            .http_client
-            .get(format!("{}/api/v1/accounts/verify_credentials", url))
-            .header("Authorization", Self::get_auth_header(&cred.password))
-            .send()
+            .responses
+            .send(
+                self.http_client
+                    .get(format!("{}/api/v1/accounts/verify_credentials", url))
+                    .bearer_auth(&*cred.password),
+            )
             .await
             .map_err(|e| {
                 error!("Failed to verify credentials: {}", e);
@@ -76,10 +244,11 @@ impl super::SocialNetworkApi for MastodonClient {
 
         if !response.status().is_success() {
             error!(
-                "Invalid credentials for Mastodon account: {}",
-                cred.username
+                "Cannot verify Mastodon account {}: {}",
+                cred.username,
+                response.status()
             );
-            return Err(AppError::InvalidCredentials);
+            return Err(status_error(&response, "Cannot verify account"));
         }
 
         let account: MastodonAccount = response.json().await.map_err(|e| {
@@ -99,7 +268,7 @@ impl super::SocialNetworkApi for MastodonClient {
         cred: &Credentials,
         limit: u32,
         since_id: &str,
-    ) -> AppResult<Vec<Post>> {
+    ) -> AppResult<Vec<NormalizedPost>> {
         let (_, url) = Self::parse_account(&cred.username)?;
         let since_query = if !since_id.is_empty() {
             format!("&since_id={}", since_id)
@@ -115,10 +284,8 @@ impl super::SocialNetworkApi for MastodonClient {
         debug!("Fetching Mastodon timeline from: {}", endpoint);
 
         let response = self
-            .http_client
-            .get(&endpoint)
-            .header("Authorization", Self::get_auth_header(&cred.password))
-            .send()
+            .responses
+            .send(self.http_client.get(&endpoint).bearer_auth(&*cred.password))
             .await
             .map_err(|e| {
                 error!("Failed to fetch timeline 1: {}", e);
@@ -131,7 +298,7 @@ impl super::SocialNetworkApi for MastodonClient {
 
         if !response.status().is_success() {
             error!("API returned status: {}", response.status());
-            return Err(AppError::ApiError("Failed to fetch timeline 2".to_string()));
+            return Err(status_error(&response, "Failed to fetch timeline"));
         }
 
         let json: String = response.text().await.map_err(|e| {
@@ -140,19 +307,121 @@ impl super::SocialNetworkApi for MastodonClient {
         })?;
 
         debug!("Timeline JSON: {:?}", &json);
-        let timeline: Vec<MastodonStatus> = serde_json::from_str(&json).map_err(|e| {
+        let parse_error = |e| {
             error!("Failed to parse timeline JSON: {}", e);
             AppError::JsonError(e)
-        })?;
+        };
+        let values: Vec<Value> = serde_json::from_str(&json).map_err(parse_error)?;
+        let posts = values
+            .into_iter()
+            .map(|value| {
+                // С --debug исходный JSON прикладывается к письму
+                let raw = self.config.debug.then(|| value.clone());
+                let status: MastodonStatus = serde_json::from_value(value)?;
+                let mut post = normalize(status);
+                post.raw = raw;
+                Ok(post)
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(parse_error)?;
+
+        info!("Fetched {} posts from Mastodon timeline", posts.len());
+        Ok(posts)
+    }
 
-        info!("Fetched {} posts from Mastodon timeline", timeline.len());
+    async fn followed_accounts(
+        &self,
+        cred: &Credentials,
+        account_ids: &[String],
+    ) -> AppResult<HashSet<String>> {
+        let (_, url) = Self::parse_account(&cred.username)?;
 
-        let posts = timeline
-            .into_iter()
-            .map(|status| Post::Mastodon(status))
-            .collect();
+        let own: MastodonAccount = self
+            .responses
+            .send(
+                self.http_client
+                    .get(format!("{}/api/v1/accounts/verify_credentials", url))
+                    .bearer_auth(&*cred.password),
+            )
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(AppError::NetworkError)?
+            .json()
+            .await
+            .map_err(AppError::NetworkError)?;
 
-        Ok(posts)
+        let mut followed = HashSet::from([own.id]);
+        if account_ids.is_empty() {
+            return Ok(followed);
+        }
+
+        let query: Vec<(&str, &str)> = account_ids.iter().map(|id| ("id[]", id.as_str())).collect();
+
+        debug!("Fetching relationships for {} accounts", account_ids.len());
+
+        let relationships: Vec<Value> = self
+            .responses
+            .send(
+                self.http_client
+                    .get(format!("{}/api/v1/accounts/relationships", url))
+                    .bearer_auth(&*cred.password)
+                    .query(&query),
+            )
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| {
+                error!("Failed to fetch relationships: {}", e);
+                AppError::NetworkError(e)
+            })?
+            .json()
+            .await
+            .map_err(AppError::NetworkError)?;
+
+        followed.extend(
+            relationships
+                .iter()
+                .filter(|relationship| relationship["following"].as_bool() == Some(true))
+                .filter_map(|relationship| relationship["id"].as_str())
+                .map(str::to_string),
+        );
+
+        Ok(followed)
+    }
+
+    async fn ancestor_ids(&self, cred: &Credentials, post_id: &str) -> AppResult<Vec<String>> {
+        let (_, url) = Self::parse_account(&cred.username)?;
+
+        debug!("Fetching thread context for status {}", post_id);
+
+        let context: Value = self
+            .responses
+            .send(
+                self.http_client
+                    .get(format!("{}/api/v1/statuses/{}/context", url, post_id))
+                    .bearer_auth(&*cred.password),
+            )
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| {
+                error!("Failed to fetch thread context: {}", e);
+                AppError::NetworkError(e)
+            })?
+            .json()
+            .await
+            .map_err(AppError::NetworkError)?;
+
+        let ancestors = context["ancestors"]
+            .as_array()
+            .map(|ancestors| {
+                ancestors
+                    .iter()
+                    .filter_map(|status| status["id"].as_str())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(ancestors)
     }
 
     async fn post_status(
@@ -161,6 +430,7 @@ impl super::SocialNetworkApi for MastodonClient {
         status: String,
         in_reply_to_id: Option<String>,
         media_ids: Vec<String>,
+        spoiler_text: Option<String>,
     ) -> AppResult<String> {
         let (_, url) = Self::parse_account(&cred.username)?;
 
@@ -174,6 +444,11 @@ impl super::SocialNetworkApi for MastodonClient {
             body["in_reply_to_id"] = Value::String(id);
         }
 
+        if let Some(cw) = spoiler_text {
+            body["spoiler_text"] = Value::String(cw);
+            body["sensitive"] = Value::Bool(true);
+        }
+
         if !media_ids.is_empty() {
             body["media_ids"] = Value::Array(
                 media_ids
@@ -183,25 +458,26 @@ impl super::SocialNetworkApi for MastodonClient {
             );
         }
 
-        let response = self
-            .http_client
-            .post(format!("{}/api/v1/statuses", url))
-            .header("Authorization", Self::get_auth_header(&cred.password))
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| {
-                error!("Failed to post status: {}", e);
-                if e.is_timeout() {
-                    AppError::Timeout
-                } else {
-                    AppError::ApiError(format!("Post failed: {}", e))
-                }
-            })?;
+        let response = retry::send(
+            self.http_client
+                .post(format!("{}/api/v1/statuses", url))
+                .bearer_auth(&*cred.password)
+                .json(&body),
+            self.config.api_retries,
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to post status: {}", e);
+            if e.is_timeout() {
+                AppError::Timeout
+            } else {
+                AppError::ApiError(format!("Post failed: {}", e))
+            }
+        })?;
 
         if !response.status().is_success() {
             error!("API returned status: {} for post", response.status());
-            return Err(AppError::ApiError("Failed to post".to_string()));
+            return Err(status_error(&response, "Failed to post"));
         }
 
         let result: Value = response.json().await.map_err(|e| {
@@ -224,6 +500,7 @@ impl super::SocialNetworkApi for MastodonClient {
         data: Vec<u8>,
         filename: String,
         mime: String,
+        description: Option<String>,
     ) -> AppResult<String> {
         let (_, url) = Self::parse_account(&cred.username)?;
 
@@ -234,27 +511,31 @@ impl super::SocialNetworkApi for MastodonClient {
             .mime_str(&mime)
             .map_err(|e| AppError::ApiError(format!("Invalid MIME type: {}", e)))?;
 
-        let form = reqwest::multipart::Form::new().part("file", part);
+        let mut form = reqwest::multipart::Form::new().part("file", part);
+        if let Some(description) = description {
+            form = form.text("description", description);
+        }
 
-        let response = self
-            .http_client
-            .post(format!("{}/api/v2/media", url))
-            .header("Authorization", Self::get_auth_header(&cred.password))
-            .multipart(form)
-            .send()
-            .await
-            .map_err(|e| {
-                error!("Failed to upload media: {}", e);
-                if e.is_timeout() {
-                    AppError::Timeout
-                } else {
-                    AppError::ApiError(format!("Upload failed: {}", e))
-                }
-            })?;
+        let response = retry::send(
+            self.http_client
+                .post(format!("{}/api/v2/media", url))
+                .bearer_auth(&*cred.password)
+                .multipart(form),
+            self.config.api_retries,
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to upload media: {}", e);
+            if e.is_timeout() {
+                AppError::Timeout
+            } else {
+                AppError::ApiError(format!("Upload failed: {}", e))
+            }
+        })?;
 
         if !response.status().is_success() {
             error!("Media upload returned status: {}", response.status());
-            return Err(AppError::ApiError("Upload failed".to_string()));
+            return Err(status_error(&response, "Upload failed"));
         }
 
         let result: Value = response.json().await.map_err(|e| {
@@ -270,4 +551,359 @@ impl super::SocialNetworkApi for MastodonClient {
         info!("Successfully uploaded media: {}", media_id);
         Ok(media_id)
     }
+
+    async fn get_notifications(
+        &self,
+        cred: &Credentials,
+        limit: u32,
+    ) -> AppResult<Vec<Notification>> {
+        let limit = limit.to_string();
+        let notifications: Vec<MastodonNotification> = self
+            .get_json(
+                cred,
+                "/api/v1/notifications",
+                &[("limit", &limit)],
+                "Fetching notifications",
+            )
+            .await?;
+
+        Ok(notifications
+            .into_iter()
+            .map(|notification| Notification {
+                id: notification.id,
+                kind: match notification.kind.as_str() {
+                    "mention" => NotificationKind::Mention,
+                    "reblog" => NotificationKind::Boost,
+                    "favourite" => NotificationKind::Favourite,
+                    "follow" => NotificationKind::Follow,
+                    _ => NotificationKind::Other(notification.kind),
+                },
+                created_at: notification.created_at,
+                account: normalize_account(notification.account),
+                post: notification.status.map(normalize),
+            })
+            .collect())
+    }
+
+    async fn get_conversations(
+        &self,
+        cred: &Credentials,
+        limit: u32,
+    ) -> AppResult<Vec<Conversation>> {
+        let limit = limit.to_string();
+        let conversations: Vec<MastodonConversation> = self
+            .get_json(
+                cred,
+                "/api/v1/conversations",
+                &[("limit", &limit)],
+                "Fetching conversations",
+            )
+            .await?;
+
+        Ok(conversations
+            .into_iter()
+            .map(|conversation| Conversation {
+                id: conversation.id,
+                accounts: conversation
+                    .accounts
+                    .into_iter()
+                    .map(normalize_account)
+                    .collect(),
+                last_post: conversation.last_status.map(normalize),
+                unread: conversation.unread,
+            })
+            .collect())
+    }
+
+    async fn search(
+        &self,
+        cred: &Credentials,
+        query: &str,
+        limit: u32,
+    ) -> AppResult<SearchResults> {
+        let limit = limit.to_string();
+        // resolve=true загружает на инстанцию посты и аккаунты по URL и адресу
+        let results: MastodonSearch = self
+            .get_json(
+                cred,
+                "/api/v2/search",
+                &[("q", query), ("resolve", "true"), ("limit", &limit)],
+                "Search",
+            )
+            .await?;
+
+        Ok(SearchResults {
+            accounts: results
+                .accounts
+                .into_iter()
+                .map(normalize_account)
+                .collect(),
+            posts: results.statuses.into_iter().map(normalize).collect(),
+            tags: results.hashtags.into_iter().map(|tag| tag.name).collect(),
+        })
+    }
+
+    async fn get_post(&self, cred: &Credentials, post_id: &str) -> AppResult<NormalizedPost> {
+        let status: MastodonStatus = self
+            .get_json(
+                cred,
+                &format!("/api/v1/statuses/{}", post_id),
+                &[],
+                "Fetching status",
+            )
+            .await?;
+        Ok(normalize(status))
+    }
+
+    async fn get_thread(
+        &self,
+        cred: &Credentials,
+        post_id: &str,
+    ) -> AppResult<Vec<NormalizedPost>> {
+        let post = self.get_post(cred, post_id).await?;
+        let context: MastodonContext = self
+            .get_json(
+                cred,
+                &format!("/api/v1/statuses/{}/context", post_id),
+                &[],
+                "Fetching thread",
+            )
+            .await?;
+
+        let mut thread: Vec<NormalizedPost> =
+            context.ancestors.into_iter().map(normalize).collect();
+        thread.push(post);
+        thread.extend(context.descendants.into_iter().map(normalize));
+        Ok(thread)
+    }
+
+    async fn get_contacts(
+        &self,
+        cred: &Credentials,
+        list: ContactList,
+    ) -> AppResult<Vec<PostAuthor>> {
+        let own: MastodonAccount = self
+            .get_json(
+                cred,
+                "/api/v1/accounts/verify_credentials",
+                &[],
+                "Verifying account",
+            )
+            .await?;
+
+        let path = format!("/api/v1/accounts/{}/{}", own.id, list.name());
+        let accounts = self
+            .get_account_pages(cred, &path, "Fetching contacts")
+            .await?;
+
+        info!("Fetched {} {} accounts", accounts.len(), list.name());
+        Ok(accounts.into_iter().map(normalize_account).collect())
+    }
+
+    async fn get_scheduled_posts(&self, cred: &Credentials) -> AppResult<Vec<NormalizedPost>> {
+        let own: MastodonAccount = self
+            .get_json(
+                cred,
+                "/api/v1/accounts/verify_credentials",
+                &[],
+                "Verifying account",
+            )
+            .await?;
+        let mut scheduled: Vec<MastodonScheduled> = self
+            .get_json(
+                cred,
+                "/api/v1/scheduled_statuses",
+                &[("limit", "40")],
+                "Fetching scheduled statuses",
+            )
+            .await?;
+
+        // Ящик упорядочен от новых писем к старым: самый дальний пост первым
+        scheduled.sort_by(|a, b| b.scheduled_at.cmp(&a.scheduled_at));
+        let author = normalize_account(own);
+        Ok(scheduled
+            .into_iter()
+            .map(|scheduled| normalize_scheduled(scheduled, author.clone()))
+            .collect())
+    }
+
+    async fn cancel_scheduled_post(&self, cred: &Credentials, id: &str) -> AppResult<()> {
+        let (_, url) = Self::parse_account(&cred.username)?;
+
+        debug!("Cancelling Mastodon scheduled status {}", id);
+
+        let response = retry::send(
+            self.http_client
+                .delete(format!("{}/api/v1/scheduled_statuses/{}", url, id))
+                .bearer_auth(&*cred.password),
+            self.config.api_retries,
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to cancel scheduled status: {}", e);
+            if e.is_timeout() {
+                AppError::Timeout
+            } else {
+                AppError::NetworkError(e)
+            }
+        })?;
+
+        if !response.status().is_success() {
+            error!("API returned status: {} for cancel", response.status());
+            return Err(status_error(&response, "Failed to cancel scheduled status"));
+        }
+
+        info!("Cancelled Mastodon scheduled status {}", id);
+        Ok(())
+    }
+
+    async fn delete_post(&self, cred: &Credentials, post_id: &str) -> AppResult<()> {
+        let (_, url) = Self::parse_account(&cred.username)?;
+
+        debug!("Deleting Mastodon status {}", post_id);
+
+        let response = retry::send(
+            self.http_client
+                .delete(format!("{}/api/v1/statuses/{}", url, post_id))
+                .bearer_auth(&*cred.password),
+            self.config.api_retries,
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to delete status: {}", e);
+            if e.is_timeout() {
+                AppError::Timeout
+            } else {
+                AppError::NetworkError(e)
+            }
+        })?;
+
+        if !response.status().is_success() {
+            error!("API returned status: {} for delete", response.status());
+            return Err(status_error(&response, "Failed to delete"));
+        }
+
+        info!("Deleted Mastodon status {}", post_id);
+        Ok(())
+    }
+
+    /// Mastodon считает любую ссылку за 23 символа, а у упоминания
+    /// @user@instance - только часть @user
+    fn post_length(&self, text: &str) -> usize {
+        text.split_inclusive(char::is_whitespace)
+            .map(|word| {
+                let token = word.trim_end();
+                let spaces = word[token.len()..].chars().count();
+                let token_len = if token.starts_with("https://") || token.starts_with("http://") {
+                    URL_LENGTH
+                } else if let Some((user, _)) = token
+                    .strip_prefix('@')
+                    .and_then(|mention| mention.split_once('@'))
+                {
+                    user.chars().count() + 1
+                } else {
+                    token.chars().count()
+                };
+                token_len + spaces
+            })
+            .sum()
+    }
+}
+
+/// Переводит пост Mastodon в общий вид
+fn normalize(status: MastodonStatus) -> NormalizedPost {
+    let reply = status.in_reply_to_id.map(|id| ReplyInfo {
+        id,
+        account_id: status.in_reply_to_account_id,
+    });
+
+    NormalizedPost {
+        id: status.id,
+        html: status.content,
+        created_at: status.created_at,
+        edited_at: status.edited_at,
+        url: status.url,
+        uri: status.uri,
+        title: None,
+        visibility: status.visibility,
+        language: status.language,
+        author: normalize_account(status.account),
+        reblog: status.reblog.map(|reblog| Box::new(normalize(*reblog))),
+        reply,
+        attachments: status.media_attachments,
+        mentions: status
+            .mentions
+            .into_iter()
+            .map(|mention| PostMention {
+                acct: mention.acct,
+                url: mention.url,
+            })
+            .collect(),
+        tags: status.tags.into_iter().map(|tag| tag.name).collect(),
+        replies_count: status.replies_count,
+        boosts_count: status.reblogs_count,
+        favourites_count: status.favourites_count,
+        application: status.application.map(|application| application.name),
+        backend: ApiMode::Mastodon,
+        raw: None,
+    }
+}
+
+/// Отложенный пост как пост ленты: дата письма - время публикации
+fn normalize_scheduled(scheduled: MastodonScheduled, author: PostAuthor) -> NormalizedPost {
+    let params = scheduled.params;
+    let mut html = text_to_html(&params.text);
+    if let Some(spoiler) = params.spoiler_text.filter(|s| !s.is_empty()) {
+        html = format!("<p>CW: {}</p>{}", escape_html(&spoiler), html);
+    }
+
+    NormalizedPost {
+        id: scheduled.id,
+        html,
+        created_at: scheduled.scheduled_at,
+        edited_at: None,
+        url: None,
+        uri: None,
+        title: None,
+        visibility: params.visibility,
+        language: params.language,
+        author,
+        reblog: None,
+        reply: params.in_reply_to_id.map(|id| ReplyInfo {
+            id,
+            account_id: None,
+        }),
+        attachments: scheduled.media_attachments,
+        mentions: Vec::new(),
+        tags: Vec::new(),
+        replies_count: 0,
+        boosts_count: 0,
+        favourites_count: 0,
+        application: None,
+        backend: ApiMode::Mastodon,
+        raw: None,
+    }
+}
+
+/// URL следующей страницы из заголовка Link: <...>; rel="next", <...>; rel="prev"
+fn next_page(response: &Response) -> Option<String> {
+    let link = response.headers().get(LINK)?.to_str().ok()?;
+    link.split(',').find_map(|part| {
+        let (url, params) = part.split_once(';')?;
+        params
+            .contains("rel=\"next\"")
+            .then(|| url.trim().trim_matches(['<', '>']).to_string())
+    })
+}
+
+fn normalize_account(account: MastodonAccount) -> PostAuthor {
+    PostAuthor {
+        id: account.id,
+        acct: account.acct,
+        username: account.username,
+        display_name: account.display_name,
+        avatar: account.avatar,
+        url: account.url,
+        moved_to: account.moved.map(|moved| moved.acct),
+    }
 }
@@ -1,10 +1,26 @@
 pub mod bluesky;
+pub mod http_cache;
 pub mod mastodon;
+pub mod news;
+pub mod retry;
 
 use crate::config::{ApiMode, Config};
-use crate::error::AppResult;
-use crate::models::Credentials;
+use crate::error::{AppError, AppResult};
+use crate::models::{
+    ContactList, Conversation, Credentials, NormalizedPost, Notification, PostAuthor, SavedFeed,
+    SearchResults,
+};
 use async_trait::async_trait;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, Proxy, Response, StatusCode};
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Сколько держать простаивающее соединение в пуле HTTP клиента
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Интервал TCP keep-alive для соединений из пула
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
 
 /// Абстрактный интерфейс к социальным сетям (полностью асинхронный)
 #[async_trait]
@@ -18,15 +34,17 @@ pub trait SocialNetworkApi: Send + Sync {
         cred: &Credentials,
         limit: u32,
         since_id: &str,
-    ) -> AppResult<Vec<crate::models::Post>>;
+    ) -> AppResult<Vec<NormalizedPost>>;
 
-    /// Отправляет новый пост
+    /// Отправляет новый пост. С `spoiler_text` пост скрывается за
+    /// предупреждением о содержимом (CW) и помечается чувствительным.
     async fn post_status(
         &self,
         cred: &Credentials,
         status: String,
         in_reply_to_id: Option<String>,
         media_ids: Vec<String>,
+        spoiler_text: Option<String>,
     ) -> AppResult<String>;
 
     /// Загружает медиа файл
@@ -36,13 +54,207 @@ pub trait SocialNetworkApi: Send + Sync {
         data: Vec<u8>,
         filename: String,
         mime: String,
+        description: Option<String>,
     ) -> AppResult<String>;
+
+    /// Возвращает те аккаунты из `account_ids`, на которые подписан пользователь,
+    /// включая его собственный. Бэкенды без подписок считают подписанными всех.
+    async fn followed_accounts(
+        &self,
+        _cred: &Credentials,
+        account_ids: &[String],
+    ) -> AppResult<HashSet<String>> {
+        Ok(account_ids.iter().cloned().collect())
+    }
+
+    /// Возвращает ID предков поста в треде, начиная с корня.
+    /// Бэкенды без такой возможности возвращают пустой список.
+    async fn ancestor_ids(&self, _cred: &Credentials, _post_id: &str) -> AppResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// Уведомления, от новых к старым
+    async fn get_notifications(
+        &self,
+        _cred: &Credentials,
+        _limit: u32,
+    ) -> AppResult<Vec<Notification>> {
+        Err(AppError::Unsupported("Notifications"))
+    }
+
+    /// Личные переписки, от недавних к давним
+    async fn get_conversations(
+        &self,
+        _cred: &Credentials,
+        _limit: u32,
+    ) -> AppResult<Vec<Conversation>> {
+        Err(AppError::Unsupported("Conversations"))
+    }
+
+    /// Ищет аккаунты, посты и хэштеги. URL поста или адрес аккаунта
+    /// с другого сервера бэкенд по возможности загружает к себе.
+    async fn search(
+        &self,
+        _cred: &Credentials,
+        _query: &str,
+        _limit: u32,
+    ) -> AppResult<SearchResults> {
+        Err(AppError::Unsupported("Search"))
+    }
+
+    /// Загружает пост по ID
+    async fn get_post(&self, _cred: &Credentials, _post_id: &str) -> AppResult<NormalizedPost> {
+        Err(AppError::Unsupported("Fetching a post"))
+    }
+
+    /// ID поста по ссылке на него. Пост с другого сервера бэкенд по
+    /// возможности загружает к себе.
+    async fn resolve_post_url(&self, cred: &Credentials, url: &str) -> AppResult<String> {
+        self.search(cred, url, 1)
+            .await?
+            .posts
+            .into_iter()
+            .next()
+            .map(|post| post.id)
+            .ok_or_else(|| AppError::ApiError(format!("No post found at {}", url)))
+    }
+
+    /// Тред поста целиком: предки от корня, сам пост и ответы на него
+    async fn get_thread(
+        &self,
+        _cred: &Credentials,
+        _post_id: &str,
+    ) -> AppResult<Vec<NormalizedPost>> {
+        Err(AppError::Unsupported("Fetching threads"))
+    }
+
+    /// Удаляет собственный пост пользователя
+    async fn delete_post(&self, _cred: &Credentials, _post_id: &str) -> AppResult<()> {
+        Err(AppError::Unsupported("Deleting a post"))
+    }
+
+    /// Ленты, сохранённые пользователем в настройках; каждая доступна
+    /// отдельным ящиком. Бэкенды без таких лент возвращают пустой список.
+    async fn saved_feeds(&self, _cred: &Credentials) -> AppResult<Vec<SavedFeed>> {
+        Ok(Vec::new())
+    }
+
+    /// Посты сохранённой ленты `feed_id`, от новых к старым
+    async fn get_feed(
+        &self,
+        _cred: &Credentials,
+        _feed_id: &str,
+        _limit: u32,
+    ) -> AppResult<Vec<NormalizedPost>> {
+        Err(AppError::Unsupported("Saved feeds"))
+    }
+
+    /// Отложенные посты пользователя, от ближайших к дальним. ID - ID
+    /// отложенного поста, а не будущего опубликованного.
+    async fn get_scheduled_posts(&self, _cred: &Credentials) -> AppResult<Vec<NormalizedPost>> {
+        Err(AppError::Unsupported("Scheduled posts"))
+    }
+
+    /// Отменяет отложенный пост
+    async fn cancel_scheduled_post(&self, _cred: &Credentials, _id: &str) -> AppResult<()> {
+        Err(AppError::Unsupported("Scheduled posts"))
+    }
+
+    /// Подписчики пользователя или его подписки, целиком со всех страниц
+    async fn get_contacts(
+        &self,
+        _cred: &Credentials,
+        _list: ContactList,
+    ) -> AppResult<Vec<PostAuthor>> {
+        Err(AppError::Unsupported("Contact lists"))
+    }
+
+    /// Максимальная длина поста в символах
+    fn max_post_length(&self) -> usize {
+        500
+    }
+
+    /// Длина текста так, как её считает бэкенд при проверке лимита
+    fn post_length(&self, text: &str) -> usize {
+        text.chars().count()
+    }
 }
 
 /// Фабрика для создания API клиента на основе конфигурации
-pub fn create_api_client(config: &Config) -> AppResult<Box<dyn SocialNetworkApi>> {
+pub fn create_api_client(config: &Config, http_client: Client) -> Box<dyn SocialNetworkApi> {
     match config.api_mode {
-        ApiMode::Mastodon => Ok(Box::new(mastodon::MastodonClient::new(config.clone()))),
-        ApiMode::Bluesky => Ok(Box::new(bluesky::BlueskyClient::new(config.clone()))),
+        ApiMode::Mastodon => Box::new(mastodon::MastodonClient::new(config.clone(), http_client)),
+        ApiMode::Bluesky => Box::new(bluesky::BlueskyClient::new(config.clone(), http_client)),
+        ApiMode::HackerNews | ApiMode::Lobsters => {
+            Box::new(news::NewsClient::new(config.clone(), http_client))
+        }
     }
 }
+
+/// Простой текст поста в HTML: абзацы по пустой строке, переносы - <br>
+pub fn text_to_html(text: &str) -> String {
+    escape_html(text)
+        .split("\n\n")
+        .map(|paragraph| format!("<p>{}</p>", paragraph.replace('\n', "<br>")))
+        .collect()
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Ошибка по коду неуспешного ответа API. Отказ в доступе, лимит запросов
+/// и сбой сервера различаются: клиент получает по ним разные ответы, а
+/// блокировка подбора паролей учитывает только отказ в доступе.
+pub fn status_error(response: &Response, what: &str) -> AppError {
+    match response.status() {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => AppError::InvalidCredentials,
+        StatusCode::TOO_MANY_REQUESTS => AppError::RateLimited(retry_after(response)),
+        status if status.is_server_error() => {
+            AppError::ApiUnavailable(format!("{} ({})", what, status))
+        }
+        status => AppError::ApiError(format!("{} ({})", what, status)),
+    }
+}
+
+/// Пауза из заголовка Retry-After; поддерживается только форма в секундах
+fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Создаёт HTTP клиент для исходящих запросов (API и загрузка медиа).
+/// Клиент один на приложение: соединения переиспользуются через пул с
+/// keep-alive, а с HTTPS инстанциями по ALPN согласуется HTTP/2, так что
+/// вложения ящика загружаются по одному соединению без лишних TLS рукопожатий.
+/// Ошибка прокси не приводит к прямому соединению: запросы в обход
+/// --outbound-proxy недопустимы, например, при работе через Tor.
+pub fn http_client(config: &Config) -> AppResult<Client> {
+    let mut builder = Client::builder()
+        .timeout(Duration::from_secs(config.api_timeout))
+        .connect_timeout(Duration::from_secs(config.api_connect_timeout))
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+        .tcp_keepalive(TCP_KEEPALIVE)
+        .http2_adaptive_window(true)
+        .user_agent(config.user_agent.as_str());
+
+    if let Some(proxy) = &config.outbound_proxy {
+        let proxy = Proxy::all(proxy)
+            .map_err(|e| AppError::Config(format!("Некорректный --outbound-proxy: {}", e)))?;
+        builder = builder.proxy(proxy);
+    }
+
+    builder
+        .build()
+        .map_err(|e| AppError::Config(format!("Не удалось создать HTTP клиент: {}", e)))
+}
@@ -0,0 +1,412 @@
+use crate::api::{escape_html, retry, status_error, SocialNetworkApi};
+use crate::config::{ApiMode, Config};
+use crate::error::{AppError, AppResult};
+use crate::models::{Credentials, NormalizedPost, PostAuthor};
+use async_trait::async_trait;
+use chrono::DateTime;
+use futures_util::future::join_all;
+use reqwest::Client;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::{debug, error, info, warn};
+
+const HACKER_NEWS_API_URL: &str = "https://hacker-news.firebaseio.com/v0";
+const HACKER_NEWS_URL: &str = "https://news.ycombinator.com";
+const LOBSTERS_URL: &str = "https://lobste.rs";
+
+/// Сколько комментариев верхнего уровня показывать под статьёй
+const TOP_COMMENTS: usize = 3;
+
+/// Элемент Hacker News: статья или комментарий
+#[derive(Debug, Deserialize)]
+struct HnItem {
+    id: u64,
+    #[serde(default)]
+    by: Option<String>,
+    #[serde(default)]
+    time: i64,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    url: Option<String>,
+    /// Текст Ask HN или комментария в HTML
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    score: u64,
+    #[serde(default)]
+    descendants: u64,
+    /// Ответы в порядке, в котором их показывает сайт
+    #[serde(default)]
+    kids: Vec<u64>,
+    #[serde(default)]
+    deleted: bool,
+    #[serde(default)]
+    dead: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct LobstersStory {
+    short_id: String,
+    title: String,
+    #[serde(default)]
+    url: String,
+    comments_url: String,
+    created_at: String,
+    #[serde(default)]
+    score: i64,
+    #[serde(default)]
+    comment_count: u64,
+    #[serde(default)]
+    description: String,
+    /// Имя пользователя; старые версии сайта отдают объект профиля
+    submitter_user: Value,
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Есть только в ответе со страницей статьи
+    #[serde(default)]
+    comments: Vec<LobstersComment>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LobstersComment {
+    #[serde(default)]
+    comment: String,
+    commenting_user: Value,
+    #[serde(default)]
+    parent_comment: Option<String>,
+    #[serde(default)]
+    is_deleted: bool,
+}
+
+/// Комментарий под статьёй в письме
+struct Comment {
+    author: String,
+    html: String,
+}
+
+/// Бэкенд только для чтения: первая страница Hacker News или Lobsters
+/// вместо ленты. Учётная запись не нужна, вход принимается с любым паролем.
+pub struct NewsClient {
+    http_client: Client,
+    config: Config,
+}
+
+impl NewsClient {
+    pub fn new(config: Config, http_client: Client) -> Self {
+        NewsClient {
+            http_client,
+            config,
+        }
+    }
+
+    /// Сайт, первую страницу которого показывает шлюз
+    fn host(&self) -> &'static str {
+        match self.config.api_mode {
+            ApiMode::Lobsters => "lobste.rs",
+            _ => "news.ycombinator.com",
+        }
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, url: &str, what: &str) -> AppResult<T> {
+        debug!("{}: GET {}", what, url);
+
+        let response = retry::send(self.http_client.get(url), self.config.api_retries)
+            .await
+            .map_err(|e| {
+                error!("{}: {}", what, e);
+                if e.is_timeout() {
+                    AppError::Timeout
+                } else {
+                    AppError::NetworkError(e)
+                }
+            })?;
+        if !response.status().is_success() {
+            error!("{} returned status: {}", url, response.status());
+            return Err(status_error(&response, what));
+        }
+        response.json().await.map_err(|e| {
+            error!("{}: cannot parse response: {}", what, e);
+            AppError::NetworkError(e)
+        })
+    }
+
+    async fn hacker_news(&self, limit: u32) -> AppResult<Vec<NormalizedPost>> {
+        let ids: Vec<u64> = self
+            .get_json(
+                &format!("{}/topstories.json", HACKER_NEWS_API_URL),
+                "Failed to fetch Hacker News front page",
+            )
+            .await?;
+
+        let stories = join_all(ids.iter().take(limit as usize).map(|id| self.hn_item(*id))).await;
+        let mut posts = Vec::with_capacity(stories.len());
+        for story in stories {
+            let story = match story {
+                Ok(Some(story)) => story,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("Skipping Hacker News story: {}", e);
+                    continue;
+                }
+            };
+            let kids = story
+                .kids
+                .iter()
+                .take(TOP_COMMENTS)
+                .map(|id| self.hn_item(*id));
+            let comments = join_all(kids)
+                .await
+                .into_iter()
+                .filter_map(|comment| comment.ok().flatten())
+                .filter_map(|comment| {
+                    Some(Comment {
+                        author: comment.by?,
+                        html: comment.text?,
+                    })
+                })
+                .collect();
+            posts.push(hn_post(story, comments));
+        }
+        Ok(posts)
+    }
+
+    /// Элемент по ID; удалённые и скрытые модерацией пропускаются
+    async fn hn_item(&self, id: u64) -> AppResult<Option<HnItem>> {
+        let item: Option<HnItem> = self
+            .get_json(
+                &format!("{}/item/{}.json", HACKER_NEWS_API_URL, id),
+                "Failed to fetch Hacker News item",
+            )
+            .await?;
+        Ok(item.filter(|item| !item.deleted && !item.dead))
+    }
+
+    async fn lobsters(&self, limit: u32) -> AppResult<Vec<NormalizedPost>> {
+        let stories: Vec<LobstersStory> = self
+            .get_json(
+                &format!("{}/hottest.json", LOBSTERS_URL),
+                "Failed to fetch Lobsters front page",
+            )
+            .await?;
+
+        let pages = stories.into_iter().take(limit as usize).map(|story| async {
+            // Комментарии есть только на странице статьи
+            let page: AppResult<LobstersStory> = self
+                .get_json(
+                    &format!("{}/s/{}.json", LOBSTERS_URL, story.short_id),
+                    "Failed to fetch Lobsters story",
+                )
+                .await;
+            match page {
+                Ok(page) => page,
+                Err(e) => {
+                    warn!("Showing {} without comments: {}", story.short_id, e);
+                    story
+                }
+            }
+        });
+        Ok(join_all(pages)
+            .await
+            .into_iter()
+            .map(lobsters_post)
+            .collect())
+    }
+}
+
+#[async_trait]
+impl SocialNetworkApi for NewsClient {
+    async fn verify_credentials(&self, cred: &Credentials) -> AppResult<String> {
+        // Первая страница общая для всех: имя нужно только для адреса ящика
+        let account_addr = if cred.username.contains('@') {
+            cred.username.clone()
+        } else {
+            format!("{}@{}", cred.username, self.host())
+        };
+        info!("News reader signed in: {}", account_addr);
+        Ok(account_addr)
+    }
+
+    async fn get_timeline(
+        &self,
+        _cred: &Credentials,
+        limit: u32,
+        _since_id: &str,
+    ) -> AppResult<Vec<NormalizedPost>> {
+        debug!("Fetching {} front page (limit: {})", self.host(), limit);
+
+        let posts = match self.config.api_mode {
+            ApiMode::Lobsters => self.lobsters(limit).await?,
+            _ => self.hacker_news(limit).await?,
+        };
+        info!("Fetched {} stories from {}", posts.len(), self.host());
+        Ok(posts)
+    }
+
+    async fn post_status(
+        &self,
+        _cred: &Credentials,
+        _status: String,
+        _in_reply_to_id: Option<String>,
+        _media_ids: Vec<String>,
+        _spoiler_text: Option<String>,
+    ) -> AppResult<String> {
+        Err(AppError::Unsupported("Posting"))
+    }
+
+    async fn upload_media(
+        &self,
+        _cred: &Credentials,
+        _data: Vec<u8>,
+        _filename: String,
+        _mime: String,
+        _description: Option<String>,
+    ) -> AppResult<String> {
+        Err(AppError::Unsupported("Media upload"))
+    }
+}
+
+fn hn_post(story: HnItem, comments: Vec<Comment>) -> NormalizedPost {
+    let discussion = format!("{}/item?id={}", HACKER_NEWS_URL, story.id);
+    let title = story.title.unwrap_or_default();
+    // У Ask HN нет внешней ссылки: статья - само обсуждение
+    let link = story.url.unwrap_or_else(|| discussion.clone());
+    let by = story.by.unwrap_or_default();
+
+    NormalizedPost {
+        id: story.id.to_string(),
+        html: story_html(
+            &title,
+            &link,
+            story.text.as_deref().unwrap_or_default(),
+            &discussion,
+            &comments,
+        ),
+        created_at: DateTime::from_timestamp(story.time, 0)
+            .unwrap_or_default()
+            .to_rfc3339(),
+        edited_at: None,
+        url: Some(discussion),
+        uri: None,
+        title: Some(title),
+        visibility: None,
+        language: None,
+        author: author(by, "news.ycombinator.com", HACKER_NEWS_URL, "user?id="),
+        reblog: None,
+        reply: None,
+        attachments: Vec::new(),
+        mentions: Vec::new(),
+        tags: Vec::new(),
+        replies_count: story.descendants,
+        boosts_count: 0,
+        favourites_count: story.score,
+        application: None,
+        backend: ApiMode::HackerNews,
+        raw: None,
+    }
+}
+
+fn lobsters_post(story: LobstersStory) -> NormalizedPost {
+    let link = if story.url.is_empty() {
+        story.comments_url.clone()
+    } else {
+        story.url.clone()
+    };
+    let comments: Vec<Comment> = story
+        .comments
+        .iter()
+        .filter(|comment| comment.parent_comment.is_none() && !comment.is_deleted)
+        .take(TOP_COMMENTS)
+        .map(|comment| Comment {
+            author: lobsters_user(&comment.commenting_user),
+            html: comment.comment.clone(),
+        })
+        .collect();
+
+    NormalizedPost {
+        html: story_html(
+            &story.title,
+            &link,
+            &story.description,
+            &story.comments_url,
+            &comments,
+        ),
+        id: story.short_id,
+        created_at: story.created_at,
+        edited_at: None,
+        url: Some(story.comments_url),
+        uri: None,
+        title: Some(story.title),
+        visibility: None,
+        language: None,
+        author: author(
+            lobsters_user(&story.submitter_user),
+            "lobste.rs",
+            LOBSTERS_URL,
+            "~",
+        ),
+        reblog: None,
+        reply: None,
+        attachments: Vec::new(),
+        mentions: Vec::new(),
+        tags: story.tags,
+        replies_count: story.comment_count,
+        boosts_count: 0,
+        favourites_count: story.score.max(0) as u64,
+        application: None,
+        backend: ApiMode::Lobsters,
+        raw: None,
+    }
+}
+
+fn lobsters_user(user: &Value) -> String {
+    user.as_str()
+        .or_else(|| user["username"].as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Автор статьи; профиль - `site` + `profile_path` + имя
+fn author(name: String, host: &str, site: &str, profile_path: &str) -> PostAuthor {
+    PostAuthor {
+        id: name.clone(),
+        acct: format!("{}@{}", name, host),
+        display_name: name.clone(),
+        url: Some(format!("{}/{}{}", site, profile_path, name)),
+        username: name,
+        avatar: None,
+        moved_to: None,
+    }
+}
+
+/// Письмо статьи: ссылка, текст, если он есть, ссылка на обсуждение и
+/// первые комментарии
+fn story_html(
+    title: &str,
+    link: &str,
+    text: &str,
+    discussion: &str,
+    comments: &[Comment],
+) -> String {
+    let mut html = format!(
+        "<p><a href=\"{}\">{}</a></p>",
+        escape_html(link),
+        escape_html(title)
+    );
+    if !text.trim().is_empty() {
+        html.push_str(&format!("<p>{}</p>", text));
+    }
+    html.push_str(&format!(
+        "<p>Discussion: <a href=\"{0}\">{0}</a></p>",
+        escape_html(discussion)
+    ));
+    for comment in comments {
+        html.push_str(&format!(
+            "<p>{} wrote:</p><blockquote><p>{}</p></blockquote>",
+            escape_html(&comment.author),
+            comment.html
+        ));
+    }
+    html
+}
@@ -0,0 +1,152 @@
+use crate::activity::{self, Counter};
+use crate::error::AppError;
+use reqwest::{Client, Method, Request, RequestBuilder, Response, StatusCode};
+use std::collections::hash_map::RandomState;
+use std::error::Error as _;
+use std::hash::{BuildHasher, Hasher};
+use std::io::ErrorKind;
+use std::time::Duration;
+use tracing::{field, info_span, warn, Instrument};
+
+/// Пауза перед первым повтором; каждая следующая вдвое длиннее
+const BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Пауза между повторами не превышает этого значения
+const MAX_DELAY: Duration = Duration::from_secs(8);
+
+/// Отправляет запрос, повторяя его при временных сбоях (--api-retries)
+pub async fn send(request: RequestBuilder, retries: u32) -> reqwest::Result<Response> {
+    let (client, request) = request.build_split();
+    execute(&client, request?, retries).await
+}
+
+/// Выполняет запрос в спане `api_request` с методом, URL, кодом ответа и
+/// числом попыток; длительность спана - задержка запроса вместе с повторами
+pub async fn execute(client: &Client, request: Request, retries: u32) -> reqwest::Result<Response> {
+    let span = info_span!(
+        "api_request",
+        otel.kind = "client",
+        http.request.method = %request.method(),
+        url.full = %request.url(),
+        http.response.status_code = field::Empty,
+        attempts = field::Empty,
+        error.type = field::Empty,
+    );
+
+    let mut attempts = 0;
+    let result = execute_with_retries(client, request, retries, &mut attempts)
+        .instrument(span.clone())
+        .await;
+
+    span.record("attempts", attempts);
+    match &result {
+        Ok(response) => {
+            span.record("http.response.status_code", response.status().as_u16());
+            if response.status().is_server_error() {
+                span.record("error.type", response.status().as_str());
+                activity::count(Counter::Errors, 1);
+            } else if response.status() == StatusCode::TOO_MANY_REQUESTS {
+                activity::count(Counter::RateLimited, 1);
+            }
+        }
+        Err(e) => {
+            span.record("error.type", e.to_string());
+            activity::count(Counter::Errors, 1);
+        }
+    }
+    result
+}
+
+/// Выполняет запрос с повторами при таймауте, сбросе соединения и ответе,
+/// который `AppError::is_retryable` считает временным. Неидемпотентные
+/// запросы (публикация поста) повторяются только если соединение не удалось
+/// установить или API ответил 429: иначе пост мог бы выйти дважды.
+/// Пауза из Retry-After длиннее MAX_DELAY не выжидается - ответ 429
+/// возвращается сразу. Запрос с потоковым телом (загрузка медиа) не повторяется.
+async fn execute_with_retries(
+    client: &Client,
+    request: Request,
+    retries: u32,
+    attempts: &mut u32,
+) -> reqwest::Result<Response> {
+    let idempotent = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS
+    );
+
+    let mut attempt = 0;
+    let mut request = request;
+    loop {
+        let retry = (attempt < retries).then(|| request.try_clone()).flatten();
+        let url = request.url().to_string();
+
+        let result = client.execute(request).await;
+        *attempts = attempt + 1;
+        let (transient, retry_after) = match &result {
+            Ok(response) if response.status().is_success() => (false, None),
+            Ok(response) => {
+                let error = super::status_error(response, "");
+                let rejected = matches!(error, AppError::RateLimited(_));
+                (
+                    error.is_retryable() && (idempotent || rejected),
+                    error.retry_after(),
+                )
+            }
+            Err(e) => (
+                e.is_connect() || (idempotent && (e.is_timeout() || is_reset(e))),
+                None,
+            ),
+        };
+        let delay = retry_after.unwrap_or_else(|| backoff(attempt));
+
+        match retry {
+            Some(next) if transient && delay <= MAX_DELAY => {
+                match &result {
+                    Ok(response) => warn!(
+                        "API returned {} for {}, retrying in {:?}",
+                        response.status(),
+                        url,
+                        delay
+                    ),
+                    Err(e) => warn!(
+                        "API request to {} failed: {}, retrying in {:?}",
+                        url, e, delay
+                    ),
+                }
+                tokio::time::sleep(delay).await;
+                request = next;
+                attempt += 1;
+            }
+            _ => return result,
+        }
+    }
+}
+
+/// Экспоненциальная пауза со случайным разбросом от половины до полной,
+/// чтобы повторы нескольких сессий не приходили на сервер одновременно
+fn backoff(attempt: u32) -> Duration {
+    let delay = BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_DELAY);
+    let random = RandomState::new().build_hasher().finish();
+    let jitter = (random % 1000) as f64 / 2000.0 + 0.5;
+    delay.mul_f64(jitter)
+}
+
+/// Соединение оборвалось по ходу запроса
+fn is_reset(e: &reqwest::Error) -> bool {
+    let mut source = e.source();
+    while let Some(error) = source {
+        if let Some(io) = error.downcast_ref::<std::io::Error>() {
+            return matches!(
+                io.kind(),
+                ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::UnexpectedEof
+            );
+        }
+        source = error.source();
+    }
+    false
+}
@@ -0,0 +1,107 @@
+use crate::config::Config;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use tracing::warn;
+
+/// Записи из разных соединений не должны перемешиваться в одной строке
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Итог попытки входа
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoginResult {
+    Success,
+    Failure,
+    /// Вход отклонён без проверки: адрес или пользователь заблокирован
+    Locked,
+}
+
+/// Событие журнала аудита
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum Event<'a> {
+    Login {
+        protocol: &'a str,
+        user: &'a str,
+        result: LoginResult,
+    },
+    Publish {
+        user: &'a str,
+        from: &'a str,
+        message_id: Option<&'a str>,
+        post_ids: &'a [String],
+        dry_run: bool,
+    },
+    Delete {
+        user: &'a str,
+        from: &'a str,
+        post_id: &'a str,
+        dry_run: bool,
+    },
+    Cancel {
+        user: &'a str,
+        from: &'a str,
+        scheduled_id: &'a str,
+        dry_run: bool,
+    },
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    time: DateTime<Utc>,
+    ip: IpAddr,
+    #[serde(flatten)]
+    event: Event<'a>,
+}
+
+/// Дописывает событие строкой JSON в --audit-log. Файл открывается только на
+/// дозапись и заново для каждой записи, поэтому его можно ротировать снаружи.
+pub fn record(config: &Config, ip: IpAddr, event: Event) {
+    let Some(path) = &config.audit_log else {
+        return;
+    };
+
+    let entry = Entry {
+        time: Utc::now(),
+        ip: ip.to_canonical(),
+        event,
+    };
+    let mut line = match serde_json::to_vec(&entry) {
+        Ok(line) => line,
+        Err(e) => {
+            warn!("Cannot serialize audit entry: {}", e);
+            return;
+        }
+    };
+    line.push(b'\n');
+
+    let mut options = OpenOptions::new();
+    options.append(true).create(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let _guard = WRITE_LOCK.lock();
+    if let Err(e) = options
+        .open(path)
+        .and_then(|mut file| file.write_all(&line))
+    {
+        warn!("Cannot write audit log {}: {}", path.display(), e);
+    }
+}
+
+/// Попытка входа по POP3 или SMTP
+pub fn login(config: &Config, ip: IpAddr, protocol: &str, user: &str, result: LoginResult) {
+    record(
+        config,
+        ip,
+        Event::Login {
+            protocol,
+            user,
+            result,
+        },
+    );
+}
@@ -0,0 +1,158 @@
+use crate::acme::Acme;
+use crate::api;
+use crate::config::Config;
+use crate::error::AppError;
+use crate::models::Credentials;
+use crate::net;
+use std::fmt;
+use tracing::{info, warn};
+
+/// Результат одной проверки
+enum Status {
+    Ok,
+    Fail,
+    Skip,
+}
+
+/// Отчёт самопроверки при запуске
+pub struct Report {
+    entries: Vec<(Status, String)>,
+}
+
+impl Report {
+    /// Пройдена ли самопроверка (пропущенные проверки ошибкой не считаются)
+    pub fn is_ok(&self) -> bool {
+        !self
+            .entries
+            .iter()
+            .any(|(status, _)| matches!(status, Status::Fail))
+    }
+
+    /// Пишет отчёт в лог: ошибки - предупреждениями
+    pub fn log(&self) {
+        for (status, message) in &self.entries {
+            match status {
+                Status::Fail => warn!("Self-check failed: {}", message),
+                Status::Ok | Status::Skip => info!("Self-check: {}", message),
+            }
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (status, message) in &self.entries {
+            let label = match status {
+                Status::Ok => "[ OK ]",
+                Status::Fail => "[FAIL]",
+                Status::Skip => "[SKIP]",
+            };
+            writeln!(f, "{} {}", label, message)?;
+        }
+        Ok(())
+    }
+}
+
+/// Проверяет порты, сертификат POP3S, доступность инстанции и токен.
+/// Сокеты закрываются сразу после проверки, серверы открывают их заново.
+pub async fn run_self_check(config: &Config) -> Report {
+    let mut entries = Vec::new();
+
+    if config.nopop3 {
+        entries.push((Status::Skip, "POP3 disabled via --nopop3".to_string()));
+    } else {
+        entries.push(check_port("POP3", config, config.pop3port).await);
+    }
+    // С --nopop3 POP3S тоже не открывается
+    if config.pop3s() {
+        entries.push(check_port("POP3S", config, config.pop3s_port).await);
+        if config.acme() {
+            entries.push(check_port("ACME", config, config.acme_port).await);
+        }
+        entries.push(check_tls(config));
+    }
+    if config.nosmtp {
+        entries.push((Status::Skip, "SMTP disabled via --nosmtp".to_string()));
+    } else {
+        entries.push(check_port("SMTP", config, config.smtp_port).await);
+    }
+
+    entries.push(check_api(config).await);
+
+    Report { entries }
+}
+
+async fn check_port(name: &str, config: &Config, port: u16) -> (Status, String) {
+    match net::bind_listeners(&config.address, port).await {
+        Ok(listeners) => {
+            let addrs: Vec<String> = listeners
+                .iter()
+                .filter_map(|listener| listener.local_addr().ok())
+                .map(|addr| addr.to_string())
+                .collect();
+            (
+                Status::Ok,
+                format!("{} can listen on {}", name, addrs.join(", ")),
+            )
+        }
+        Err(e) => (Status::Fail, format!("{} cannot listen: {}", name, e)),
+    }
+}
+
+/// Загружает сертификат, ключ и CA клиентов POP3S: --check запускается с
+/// теми же правами, что и шлюз до сброса привилегий. Сертификат ACME здесь
+/// не заказывается.
+fn check_tls(config: &Config) -> (Status, String) {
+    let acme = match config.acme().then(|| Acme::new(config)).transpose() {
+        Ok(acme) => acme,
+        Err(e) => return (Status::Fail, format!("POP3S certificate: {}", e)),
+    };
+    match net::pop3s_acceptor(config, acme.as_ref()) {
+        Ok(_) if acme.is_some() => (
+            Status::Skip,
+            format!(
+                "POP3S certificate for {} is obtained via ACME after startup",
+                config.acme_domain.join(", ")
+            ),
+        ),
+        Ok(_) => (Status::Ok, "POP3S certificate loaded".to_string()),
+        Err(e) => (Status::Fail, format!("POP3S certificate: {}", e)),
+    }
+}
+
+async fn check_api(config: &Config) -> (Status, String) {
+    let (Some(account), Some(token)) = (&config.account, &config.token) else {
+        return (
+            Status::Skip,
+            "API credentials not checked: --account and --token are not both set".to_string(),
+        );
+    };
+
+    let api_client = match api::http_client(config) {
+        Ok(http_client) => api::create_api_client(config, http_client),
+        Err(e) => return (Status::Fail, format!("API client: {}", e)),
+    };
+
+    let cred = Credentials {
+        username: account.clone(),
+        password: token.clone(),
+    };
+
+    match api_client.verify_credentials(&cred).await {
+        Ok(username) => (
+            Status::Ok,
+            format!("{:?} token accepted for {}", config.api_mode, username),
+        ),
+        Err(AppError::InvalidCredentials) => (
+            Status::Fail,
+            format!(
+                "Instance for {} is reachable, but the token was rejected",
+                account
+            ),
+        ),
+        Err(e) => (
+            Status::Fail,
+            format!("Instance for {} is unreachable: {}", account, e),
+        ),
+    }
+}
@@ -1,21 +1,286 @@
-use clap::{Parser, ValueEnum};
+use crate::error::{AppError, AppResult};
+use crate::models::Secret;
+use crate::password;
+use crate::pop3::charset::{Charset, TransferEncoding};
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
 
-#[derive(Default, Debug, Clone, Copy, Serialize, Deserialize, ValueEnum)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
 pub enum ApiMode {
     #[default]
     #[value(name = "mastodon")]
     Mastodon,
     #[value(name = "bluesky")]
     Bluesky,
+    /// Первая страница Hacker News, только чтение
+    #[value(name = "hackernews")]
+    HackerNews,
+    /// Первая страница Lobsters, только чтение
+    #[value(name = "lobsters")]
+    Lobsters,
 }
 
-#[derive(Default, Parser, Debug, Clone)]
+impl ApiMode {
+    /// Имя бэкенда для заголовка X-Mop3-Backend и подписи
+    pub fn name(self) -> &'static str {
+        match self {
+            ApiMode::Mastodon => "mastodon",
+            ApiMode::Bluesky => "bluesky",
+            ApiMode::HackerNews => "hackernews",
+            ApiMode::Lobsters => "lobsters",
+        }
+    }
+
+    /// Бэкенд умеет публиковать посты
+    pub fn can_post(self) -> bool {
+        !matches!(self, ApiMode::HackerNews | ApiMode::Lobsters)
+    }
+}
+
+/// Параметры, которые задаются только в файле конфигурации
+const FILE_ONLY_KEYS: [&str; 4] = [
+    "crosspost",
+    "crosspost_routes",
+    "cw_rules",
+    "tls_client_accounts",
+];
+
+/// Аккаунт другого бэкенда для кросспостинга через SMTP. Задаётся только в
+/// файле конфигурации таблицами `[[crosspost]]`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrosspostAccount {
+    /// Бэкенд аккаунта; его имя указывается в X-Mop3-Backends и crosspost_routes
+    pub api_mode: ApiMode,
+    pub account: String,
+    pub token: Secret,
+}
+
+/// Правило предупреждения о содержимом (CW) для публикуемых постов.
+/// Задаётся только в файле конфигурации таблицами `[[cw_rules]]`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CwRule {
+    /// Регулярное выражение для текста письма, без учёта регистра
+    pub pattern: String,
+    /// Текст предупреждения
+    pub cw: String,
+}
+
+impl CwRule {
+    pub fn regex(&self) -> Result<fancy_regex::Regex, String> {
+        fancy_regex::Regex::new(&format!("(?i){}", self.pattern)).map_err(|e| e.to_string())
+    }
+}
+
+/// Ротация лог файла по времени
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    #[default]
+    #[value(name = "never")]
+    Never,
+    #[value(name = "hourly")]
+    Hourly,
+    #[value(name = "daily")]
+    Daily,
+}
+
+/// Шифрование соединения с SMTP сервером провайдера
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum RelayTls {
+    /// STARTTLS после приветствия, обычно порт 587
+    #[default]
+    #[value(name = "starttls")]
+    Starttls,
+    /// TLS с первого байта, обычно порт 465
+    #[value(name = "tls")]
+    Tls,
+    /// Без шифрования: только для сервера в локальной сети
+    #[value(name = "none")]
+    None,
+}
+
+/// Как показывать аватар автора в письме
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum AvatarMode {
+    #[default]
+    #[value(name = "none")]
+    None,
+    /// Картинка 48x48, встроенная в письмо
+    #[value(name = "inline")]
+    Inline,
+    /// Заголовок Face: PNG 48x48 в base64
+    #[value(name = "face")]
+    Face,
+}
+
+/// Формат, в который перекодируются изображения, непонятные старым клиентам
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageTranscode {
+    #[value(name = "jpeg")]
+    Jpeg,
+    #[value(name = "png")]
+    Png,
+}
+
+/// Формат адреса From в сгенерированных письмах
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum FromFormat {
+    /// Адрес автора в федиверсе: user@instance
+    #[default]
+    #[value(name = "acct")]
+    Acct,
+    /// Адрес шлюза user=instance@gateway-domain: ответ на него уходит через SMTP mop3
+    #[value(name = "gateway")]
+    Gateway,
+    /// Только имя автора, без адреса (RFC 5322 group: "Имя:;")
+    #[value(name = "name")]
+    Name,
+}
+
+/// Сервис машинного перевода постов
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TranslateApi {
+    #[default]
+    #[value(name = "libretranslate")]
+    LibreTranslate,
+    #[value(name = "deepl")]
+    Deepl,
+}
+
+/// Готовые наборы настроек для старых почтовых клиентов
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientProfile {
+    /// MS-DOS клиенты (Pegasus, Minuet): CP437, 80 колонок, без вложений
+    #[value(name = "dos")]
+    Dos,
+    /// Windows 3.1 (Eudora 1.x, Pegasus for Windows)
+    #[value(name = "win31")]
+    Win31,
+    /// Mac OS 7 (Eudora 1.5, Claris Emailer)
+    #[value(name = "macos7")]
+    Macos7,
+}
+
+impl ClientProfile {
+    /// Значения параметров профиля в формате файла конфигурации
+    fn preset(self) -> AppResult<toml::Table> {
+        let preset = match self {
+            ClientProfile::Dos => {
+                r#"
+                charset = "cp437"
+                line_width = 78
+                attachment = false
+                inline = false
+                html = false
+                max_message_size = 65536
+                "#
+            }
+            ClientProfile::Win31 => {
+                r#"
+                charset = "windows-1252"
+                line_width = 76
+                attachment = true
+                html = false
+                max_message_size = 524288
+                "#
+            }
+            ClientProfile::Macos7 => {
+                r#"
+                charset = "macintosh"
+                line_width = 72
+                attachment = true
+                html = false
+                max_message_size = 1048576
+                "#
+            }
+        };
+
+        toml::from_str(preset).map_err(|e| AppError::Config(format!("Ошибка профиля: {}", e)))
+    }
+}
+
+/// Служебные команды вместо запуска шлюза
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Управление пользователями --users-file
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Выгрузить подписчиков и подписки аккаунта --account в stdout
+    ExportContacts {
+        /// Формат выгрузки
+        #[arg(long, value_enum, default_value = "csv")]
+        format: ContactFormat,
+    },
+}
+
+/// Формат выгрузки контактов для адресной книги
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ContactFormat {
+    #[value(name = "csv")]
+    Csv,
+    #[value(name = "vcard")]
+    Vcard,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum UserCommand {
+    /// Добавить или обновить пользователя. Пароль шлюза читается из первой
+    /// строки stdin, токен API аккаунта - из второй (без неё - общий --token)
+    Add {
+        /// Логин для входа по POP3 и SMTP
+        login: String,
+        /// Аккаунт социальной сети (user@example.com)
+        #[arg(long)]
+        account: String,
+    },
+    /// Удалить пользователя
+    Remove {
+        /// Логин пользователя
+        login: String,
+    },
+}
+
+#[derive(Default, Parser, Debug, Clone, Serialize, Deserialize)]
 #[command(name = "MOP3")]
 #[command(author = "Dabe Vlohn")]
 #[command(version = "0.2.0")]
 #[command(about = "Mastodon/Bluesky to POP3/SMTP gateway")]
 pub struct Config {
+    /// Путь к файлу конфигурации (TOML). Ключи совпадают с именами параметров,
+    /// значения из CLI и env имеют приоритет. Перечитывается по SIGHUP.
+    /// env: MOP3_CONFIG
+    #[arg(long, env = "MOP3_CONFIG")]
+    #[serde(skip)]
+    pub config: Option<PathBuf>,
+
+    /// Только выполнить самопроверку (токен, доступность инстанции, порты)
+    /// и завершиться; код возврата 1 при ошибках
+    #[arg(long)]
+    #[serde(skip)]
+    pub check: bool,
+
+    /// Прочитать пароль из stdin, напечатать его хеш для --gateway-password
+    /// и завершиться
+    #[arg(long)]
+    #[serde(skip)]
+    pub hash_password: bool,
+
+    #[command(subcommand)]
+    #[serde(skip)]
+    pub command: Option<Command>,
+
     /// Mastodon/Bluesky аккаунт (user@example.com)
     /// Также задаётся через env: MOP3_ACCOUNT
     #[arg(long, env = "MOP3_ACCOUNT")]
@@ -24,13 +289,62 @@ pub struct Config {
     /// Токен авторизации для API
     /// Задаётся через env: MOP3_TOKEN (ОБЯЗАТЕЛЕН для SMTP)
     #[arg(long, env = "MOP3_TOKEN")]
-    pub token: Option<String>,
+    pub token: Option<Secret>,
+
+    /// Файл с токеном авторизации, чтобы токен не светился в `ps` и окружении
+    /// env: MOP3_TOKEN_FILE
+    #[arg(long, env = "MOP3_TOKEN_FILE", conflicts_with = "token")]
+    pub token_file: Option<PathBuf>,
+
+    /// Брать токен из хранилища секретов ОС (сервис "mop3", пользователь = аккаунт)
+    /// Требует сборки с feature `keyring`. env: MOP3_KEYRING
+    #[arg(long, env = "MOP3_KEYRING")]
+    pub keyring: bool,
+
+    /// Хеш пароля шлюза (из --hash-password) для входа по POP3 и SMTP.
+    /// Клиент вводит этот пароль, а токен API из --token остаётся на сервере.
+    /// env: MOP3_GATEWAY_PASSWORD
+    #[arg(long, env = "MOP3_GATEWAY_PASSWORD")]
+    pub gateway_password: Option<String>,
 
-    /// IP адрес для прослушивания
+    /// Файл пользователей шлюза для нескольких аккаунтов: логин, хеш пароля,
+    /// аккаунт и его токен. Управляется через `mop3 user add|remove`.
+    /// env: MOP3_USERS_FILE
+    #[arg(long, env = "MOP3_USERS_FILE", conflicts_with = "gateway_password")]
+    pub users_file: Option<PathBuf>,
+
+    /// Адреса для прослушивания (IPv4, IPv6 или имя хоста).
+    /// Можно указать несколько раз или через запятую: 127.0.0.1,::1
     /// По умолчанию: 127.0.0.1
     /// env: MOP3_ADDRESS
-    #[arg(long, env = "MOP3_ADDRESS", default_value = "127.0.0.1")]
-    pub address: String,
+    #[arg(
+        long,
+        env = "MOP3_ADDRESS",
+        value_delimiter = ',',
+        default_value = "127.0.0.1"
+    )]
+    pub address: Vec<String>,
+
+    /// Принимать соединения только из этих сетей (CIDR): 192.168.0.0/16.
+    /// Пусто - из любых. env: MOP3_ALLOW_FROM
+    #[arg(long, env = "MOP3_ALLOW_FROM", value_delimiter = ',')]
+    pub allow_from: Vec<IpNet>,
+
+    /// Отклонять соединения из этих сетей (CIDR), даже разрешённых
+    /// --allow-from. env: MOP3_DENY_FROM
+    #[arg(long, env = "MOP3_DENY_FROM", value_delimiter = ',')]
+    pub deny_from: Vec<IpNet>,
+
+    /// Сколько неудачных входов по POP3/SMTP с одного адреса или для одного
+    /// пользователя допускается до блокировки; 0 - не блокировать.
+    /// env: MOP3_LOGIN_MAX_FAILURES
+    #[arg(long, env = "MOP3_LOGIN_MAX_FAILURES", default_value = "5")]
+    pub login_max_failures: u32,
+
+    /// На сколько секунд блокировать вход после превышения лимита неудач;
+    /// за это же время неудачи и считаются. env: MOP3_LOGIN_BAN_TIME
+    #[arg(long, env = "MOP3_LOGIN_BAN_TIME", default_value = "900")]
+    pub login_ban_time: u64,
 
     /// POP3 порт (по умолчанию: 110)
     /// env: MOP3_POP3_PORT
@@ -42,6 +356,70 @@ pub struct Config {
     #[arg(long, env = "MOP3_SMTP_PORT", default_value = "25")]
     pub smtp_port: u16,
 
+    /// Порт POP3S (TLS с первого байта), открывается при заданных --tls-cert
+    /// и --tls-key или --acme-domain. env: MOP3_POP3S_PORT
+    #[arg(long, env = "MOP3_POP3S_PORT", default_value = "995")]
+    pub pop3s_port: u16,
+
+    /// Сертификат POP3S в PEM, вместе с промежуточными. Читается при запуске
+    /// до сброса привилегий, по SIGHUP не перечитывается. env: MOP3_TLS_CERT
+    #[arg(long, env = "MOP3_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// Закрытый ключ --tls-cert в PEM (PKCS#8, PKCS#1 или SEC1).
+    /// env: MOP3_TLS_KEY
+    #[arg(long, env = "MOP3_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Требовать на POP3S клиентский сертификат, выданный этим CA (PEM).
+    /// Сертификат заменяет пароль: его CN сопоставляется логину в
+    /// `[tls_client_accounts]`. env: MOP3_TLS_CLIENT_CA
+    #[arg(long, env = "MOP3_TLS_CLIENT_CA")]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Логины POP3 по CN клиентских сертификатов --tls-client-ca.
+    /// Только в файле конфигурации: `[tls_client_accounts]`
+    #[arg(skip)]
+    #[serde(default)]
+    pub tls_client_accounts: BTreeMap<String, String>,
+
+    /// Получать сертификат POP3S для этих имён у Let's Encrypt (ACME) и
+    /// продлевать его без перезапуска; вместо --tls-cert. Требует сборку с
+    /// feature acme. env: MOP3_ACME_DOMAIN
+    #[arg(long, env = "MOP3_ACME_DOMAIN", value_delimiter = ',')]
+    pub acme_domain: Vec<String>,
+
+    /// Адрес для уведомлений Let's Encrypt об истечении сертификата.
+    /// env: MOP3_ACME_EMAIL
+    #[arg(long, env = "MOP3_ACME_EMAIL")]
+    pub acme_email: Option<String>,
+
+    /// Каталог для ключа аккаунта ACME и полученного сертификата; должен
+    /// быть доступен на запись --user. env: MOP3_ACME_CACHE
+    #[arg(long, env = "MOP3_ACME_CACHE")]
+    pub acme_cache: Option<PathBuf>,
+
+    /// Порт, на котором Let's Encrypt проверяет домен (TLS-ALPN-01). Сам
+    /// Let's Encrypt приходит на 443, другой порт - только за пробросом.
+    /// env: MOP3_ACME_PORT
+    #[arg(long, env = "MOP3_ACME_PORT", default_value = "443")]
+    pub acme_port: u16,
+
+    /// Тестовый сервер Let's Encrypt: без лимитов, но сертификату не
+    /// доверяют клиенты. env: MOP3_ACME_STAGING
+    #[arg(long, env = "MOP3_ACME_STAGING")]
+    pub acme_staging: bool,
+
+    /// После открытия портов сменить пользователя на этого (Unix), чтобы
+    /// слушать 110 и 25 от root, но не работать от root. env: MOP3_USER
+    #[arg(long, env = "MOP3_USER")]
+    pub user: Option<String>,
+
+    /// Группа после открытия портов (Unix). По умолчанию - основная
+    /// группа --user. env: MOP3_GROUP
+    #[arg(long, env = "MOP3_GROUP")]
+    pub group: Option<String>,
+
     /// Режим API: mastodon или bluesky
     /// env: MOP3_API_MODE
     #[arg(long, env = "MOP3_API_MODE", value_enum, default_value = "mastodon")]
@@ -51,6 +429,11 @@ pub struct Config {
     #[arg(long, env = "MOP3_NO_SMTP")]
     pub nosmtp: bool,
 
+    /// Отключить POP3 сервер: лента только доставляется через
+    /// --deliver-maildir или --deliver-lmtp. env: MOP3_NO_POP3
+    #[arg(long, env = "MOP3_NO_POP3")]
+    pub nopop3: bool,
+
     /// Преобразовывать Unicode в ASCII
     #[arg(long, env = "MOP3_ASCII")]
     pub ascii: bool,
@@ -63,34 +446,1021 @@ pub struct Config {
     #[arg(long, env = "MOP3_INLINE")]
     pub inline: bool,
 
+    /// Перекодировать WebP-вложения в jpeg или png (AVIF и HEIC не поддерживаются)
+    /// env: MOP3_TRANSCODE_IMAGES
+    #[arg(long, env = "MOP3_TRANSCODE_IMAGES", value_enum)]
+    pub transcode_images: Option<ImageTranscode>,
+
+    /// Уменьшать изображения, у которых ширина или высота больше N пикселей
+    /// env: MOP3_MAX_IMAGE_DIMENSION
+    #[arg(long, env = "MOP3_MAX_IMAGE_DIMENSION")]
+    pub max_image_dimension: Option<u32>,
+
+    /// Пережимать изображения больше N байт (JPEG с понижением качества и размера)
+    /// env: MOP3_MAX_IMAGE_BYTES
+    #[arg(long, env = "MOP3_MAX_IMAGE_BYTES")]
+    pub max_image_bytes: Option<usize>,
+
+    /// Таймаут загрузки одного вложения в секундах; вложения грузятся параллельно
+    /// env: MOP3_MEDIA_TIMEOUT
+    #[arg(long, env = "MOP3_MEDIA_TIMEOUT", default_value = "20")]
+    pub media_timeout: u64,
+
+    /// Каталог дискового кеша медиа и аватаров, чтобы не загружать их
+    /// при каждой проверке почты. env: MOP3_MEDIA_CACHE
+    #[arg(long, env = "MOP3_MEDIA_CACHE")]
+    pub media_cache: Option<PathBuf>,
+
+    /// Предельный размер кеша медиа в байтах; сверх него удаляются файлы,
+    /// которые дольше всех не использовались. env: MOP3_MEDIA_CACHE_SIZE
+    #[arg(long, env = "MOP3_MEDIA_CACHE_SIZE", default_value = "104857600")]
+    pub media_cache_size: u64,
+
+    /// Формат From: acct (user@instance), gateway (адрес шлюза) или name (только имя)
+    /// env: MOP3_FROM_FORMAT
+    #[arg(long, env = "MOP3_FROM_FORMAT", value_enum, default_value = "acct")]
+    pub from_format: FromFormat,
+
+    /// Домен адресов шлюза для --from-format gateway
+    /// env: MOP3_GATEWAY_DOMAIN
+    #[arg(long, env = "MOP3_GATEWAY_DOMAIN", default_value = "mop3.local")]
+    pub gateway_domain: String,
+
+    /// Заменять упоминания в тексте письма на адреса шлюза
+    /// env: MOP3_MENTION_ADDRESSES
+    #[arg(long, env = "MOP3_MENTION_ADDRESSES")]
+    pub mention_addresses: bool,
+
+    /// Добавлять упомянутые аккаунты в Cc адресами шлюза
+    /// env: MOP3_CC_MENTIONS
+    #[arg(long, env = "MOP3_CC_MENTIONS")]
+    pub cc_mentions: bool,
+
+    /// Аватар автора: none, inline (картинка в письме) или face (заголовок Face)
+    /// env: MOP3_AVATARS
+    #[arg(long, env = "MOP3_AVATARS", value_enum, default_value = "none")]
+    pub avatars: AvatarMode,
+
     /// Отправлять HTML вместо простого текста
     #[arg(long, env = "MOP3_HTML")]
     pub html: bool,
 
-    /// Debug режим: выводить JSON ответов
+    /// Отправлять multipart/alternative: текст и исходный HTML, клиент выбирает сам
+    /// env: MOP3_ALTERNATIVE
+    #[arg(long, env = "MOP3_ALTERNATIVE")]
+    pub alternative: bool,
+
+    /// Заменять ссылки в тексте письма номерами [1], [2] со списком ссылок в
+    /// конце. env: MOP3_LINK_FOOTNOTES
+    #[arg(long, env = "MOP3_LINK_FOOTNOTES")]
+    pub link_footnotes: bool,
+
+    /// Debug режим: логировать JSON постов и прикладывать его к письмам
     #[arg(long, env = "MOP3_DEBUG")]
     pub debug: bool,
 
+    /// Не показывать бусты в почтовом ящике
+    /// env: MOP3_NO_BOOSTS
+    #[arg(long, env = "MOP3_NO_BOOSTS")]
+    pub no_boosts: bool,
+
+    /// Склеивать идущие подряд посты одного треда в одно письмо
+    /// env: MOP3_COLLAPSE_THREADS
+    #[arg(long, env = "MOP3_COLLAPSE_THREADS")]
+    pub collapse_threads: bool,
+
+    /// Показывать бусты письмами от автора оригинала со строкой "Boosted by"
+    /// env: MOP3_UNWRAP_BOOSTS
+    #[arg(long, env = "MOP3_UNWRAP_BOOSTS")]
+    pub unwrap_boosts: bool,
+
+    /// Не показывать ответы людям, на которых нет подписки
+    /// env: MOP3_NO_REPLIES
+    #[arg(long, env = "MOP3_NO_REPLIES")]
+    pub no_replies: bool,
+
+    /// Скрывать посты, содержащие эти слова (без учёта регистра, целые слова).
+    /// Можно указать несколько раз или через запятую.
+    /// env: MOP3_MUTED_WORDS
+    #[arg(long, env = "MOP3_MUTED_WORDS", value_delimiter = ',')]
+    pub muted_words: Vec<String>,
+
+    /// Скрывать посты и бусты этих аккаунтов (user или user@instance)
+    /// env: MOP3_MUTED_ACCOUNTS
+    #[arg(long, env = "MOP3_MUTED_ACCOUNTS", value_delimiter = ',')]
+    pub muted_accounts: Vec<String>,
+
+    /// Не запрашивать у API цепочку предков ответа: References будет содержать
+    /// только родительский пост. env: MOP3_NO_THREAD_LOOKUP
+    #[arg(long, env = "MOP3_NO_THREAD_LOOKUP")]
+    pub no_thread_lookup: bool,
+
+    /// Обновлять ленту в фоне раз в указанное число секунд: POP3 сессии
+    /// получают готовый ящик без ожидания API. Требует --account и токен
+    /// env: MOP3_PREFETCH
+    #[arg(long, env = "MOP3_PREFETCH")]
+    pub prefetch: Option<u64>,
+
+    /// Собирать все письма сразу после входа, чтобы STAT и LIST отвечали
+    /// точным размером, а не оценкой. env: MOP3_EXACT_SIZES
+    #[arg(long, env = "MOP3_EXACT_SIZES")]
+    pub exact_sizes: bool,
+
+    /// Доставлять новые письма ленты --account в локальный Maildir
+    /// env: MOP3_DELIVER_MAILDIR
+    #[arg(long, env = "MOP3_DELIVER_MAILDIR")]
+    pub deliver_maildir: Option<PathBuf>,
+
+    /// Доставлять новые письма ленты --account по LMTP: host:port или путь
+    /// к unix сокету (Dovecot: /var/run/dovecot/lmtp). env: MOP3_DELIVER_LMTP
+    #[arg(long, env = "MOP3_DELIVER_LMTP")]
+    pub deliver_lmtp: Option<String>,
+
+    /// Получатель RCPT TO для --deliver-lmtp; по умолчанию адрес аккаунта
+    /// env: MOP3_DELIVER_TO
+    #[arg(long, env = "MOP3_DELIVER_TO")]
+    pub deliver_to: Option<String>,
+
+    /// Пересылать новые письма ленты --account через SMTP сервер
+    /// провайдера (host:port) на --relay-to. env: MOP3_RELAY_SMTP
+    #[arg(long, env = "MOP3_RELAY_SMTP")]
+    pub relay_smtp: Option<String>,
+
+    /// Шифрование соединения с --relay-smtp: starttls, tls или none
+    /// env: MOP3_RELAY_TLS
+    #[arg(long, env = "MOP3_RELAY_TLS", value_enum, default_value = "starttls")]
+    pub relay_tls: RelayTls,
+
+    /// Логин AUTH PLAIN на --relay-smtp. env: MOP3_RELAY_USER
+    #[arg(long, env = "MOP3_RELAY_USER")]
+    pub relay_user: Option<String>,
+
+    /// Пароль AUTH PLAIN на --relay-smtp. env: MOP3_RELAY_PASSWORD
+    #[arg(long, env = "MOP3_RELAY_PASSWORD")]
+    pub relay_password: Option<Secret>,
+
+    /// Ящик, в который пересылается лента. env: MOP3_RELAY_TO
+    #[arg(long, env = "MOP3_RELAY_TO")]
+    pub relay_to: Option<String>,
+
+    /// Отправитель MAIL FROM для --relay-smtp; по умолчанию
+    /// mop3@--gateway-domain. env: MOP3_RELAY_FROM
+    #[arg(long, env = "MOP3_RELAY_FROM")]
+    pub relay_from: Option<String>,
+
+    /// Как часто доставлять ленту, секунд. env: MOP3_DELIVER_INTERVAL
+    #[arg(long, env = "MOP3_DELIVER_INTERVAL", default_value = "300")]
+    pub deliver_interval: u64,
+
+    /// Загрузить ленту сразу после запуска, чтобы первая POP3 сессия после
+    /// перезапуска не ждала API. Требует --account и токен
+    /// env: MOP3_WARM_CACHE
+    #[arg(long, env = "MOP3_WARM_CACHE")]
+    pub warm_cache: bool,
+
+    /// Раз в указанное число дней класть в ящик письмо-отчёт о работе шлюза:
+    /// новые посты, отданные и опубликованные письма, ошибки API и ответы 429.
+    /// env: MOP3_ACTIVITY_REPORT
+    #[arg(long, env = "MOP3_ACTIVITY_REPORT")]
+    pub activity_report: Option<u64>,
+
+    /// Один раз сообщать письмом о каждом авторе ленты, переехавшем на
+    /// другой аккаунт
+    /// env: MOP3_MOVED_NOTICES
+    #[arg(long, env = "MOP3_MOVED_NOTICES")]
+    pub moved_notices: bool,
+
+    /// Прикладывать к постам-событиям Mobilizon и Friendica файл .ics;
+    /// время и место запрашиваются у сервера автора
+    /// env: MOP3_EVENT_INVITES
+    #[arg(long, env = "MOP3_EVENT_INVITES")]
+    pub event_invites: bool,
+
     /// Включать URL оригинального поста в письмо
     #[arg(long, env = "MOP3_URL")]
     pub url: bool,
 
-    /// Прокси для ссылок (например: http://frogfind.com/read.php?a=)
+    /// Добавлять подпись со счётчиками ответов, бустов, избранного и приложением
+    /// env: MOP3_STATS
+    #[arg(long, env = "MOP3_STATS")]
+    pub stats: bool,
+
+    /// Добавлять хэштеги поста в заголовок X-Mop3-Tags
+    /// env: MOP3_TAGS_HEADER
+    #[arg(long, env = "MOP3_TAGS_HEADER")]
+    pub tags_header: bool,
+
+    /// Подписывать письма шлюза S/MIME сертификатом из PEM файла (можно с
+    /// промежуточными сертификатами). Требует --sign-key и сборку с feature
+    /// smime. env: MOP3_SIGN_CERT
+    #[arg(long, env = "MOP3_SIGN_CERT")]
+    pub sign_cert: Option<PathBuf>,
+
+    /// Закрытый ключ сертификата --sign-cert в PEM. env: MOP3_SIGN_KEY
+    #[arg(long, env = "MOP3_SIGN_KEY")]
+    pub sign_key: Option<PathBuf>,
+
+    /// Шаблон подписи письма; переменные: {url}, {id}, {author}, {acct},
+    /// {backend}, {tags}, {boosted}, {moved}, {media}, {stats}. Перевод строки - \n
+    /// env: MOP3_FOOTER
+    #[arg(long, env = "MOP3_FOOTER")]
+    pub footer: Option<String>,
+
+    /// Переводить посты на другом языке на этот язык (например: ru, en)
+    /// env: MOP3_TRANSLATE_TO
+    #[arg(long, env = "MOP3_TRANSLATE_TO")]
+    pub translate_to: Option<String>,
+
+    /// Сервис перевода: libretranslate или deepl
+    /// env: MOP3_TRANSLATE_API
+    #[arg(
+        long,
+        env = "MOP3_TRANSLATE_API",
+        value_enum,
+        default_value = "libretranslate"
+    )]
+    pub translate_api: TranslateApi,
+
+    /// Адрес сервиса перевода (для DeepL по умолчанию https://api-free.deepl.com)
+    /// env: MOP3_TRANSLATE_URL
+    #[arg(long, env = "MOP3_TRANSLATE_URL")]
+    pub translate_url: Option<String>,
+
+    /// API ключ сервиса перевода
+    /// env: MOP3_TRANSLATE_KEY
+    #[arg(long, env = "MOP3_TRANSLATE_KEY")]
+    pub translate_key: Option<Secret>,
+
+    /// Прокси для ссылок письма: в тексте, на медиа и на оригинал поста
+    /// (например: http://frogfind.com/read.php?a=)
     #[arg(long, env = "MOP3_PROXY")]
     pub proxy: Option<String>,
+
+    /// Прокси для исходящих запросов к API и загрузки медиа
+    /// (socks5h://127.0.0.1:9050 для Tor, http://..., https://...)
+    #[arg(long, env = "MOP3_OUTBOUND_PROXY")]
+    pub outbound_proxy: Option<String>,
+
+    /// User-Agent для запросов к API
+    /// env: MOP3_USER_AGENT
+    #[arg(long, env = "MOP3_USER_AGENT", default_value = "mop3/0.2")]
+    pub user_agent: String,
+
+    /// Таймаут запроса к API в секундах
+    /// env: MOP3_API_TIMEOUT
+    #[arg(long, env = "MOP3_API_TIMEOUT", default_value = "30")]
+    pub api_timeout: u64,
+
+    /// Таймаут установки соединения с API в секундах
+    /// env: MOP3_API_CONNECT_TIMEOUT
+    #[arg(long, env = "MOP3_API_CONNECT_TIMEOUT", default_value = "10")]
+    pub api_connect_timeout: u64,
+
+    /// Сколько раз повторять запрос к API при таймауте, обрыве соединения
+    /// или ответе 5xx; паузы между попытками растут. env: MOP3_API_RETRIES
+    #[arg(long, env = "MOP3_API_RETRIES", default_value = "2")]
+    pub api_retries: u32,
+
+    /// Не публиковать посты из SMTP: письмо проходит разбор, проверку и
+    /// разбиение, а вызов API только логируется. env: MOP3_DRY_RUN
+    #[arg(long, env = "MOP3_DRY_RUN")]
+    pub dry_run: bool,
+
+    /// Не публиковать письмо сразу: в ящик приходит превью постов с кодом,
+    /// и пост уходит, когда на превью ответят. env: MOP3_CONFIRM_POSTS
+    #[arg(long, env = "MOP3_CONFIRM_POSTS")]
+    pub confirm_posts: bool,
+
+    /// Не удалять EXIF (в том числе GPS) из фото перед публикацией
+    /// env: MOP3_KEEP_EXIF
+    #[arg(long, env = "MOP3_KEEP_EXIF")]
+    pub keep_exif: bool,
+
+    /// Хэштеги, которые добавляются в конец каждого поста из SMTP, если их
+    /// ещё нет в тексте: "#retrocomputing #mop3". env: MOP3_APPEND_TAGS
+    #[arg(long, env = "MOP3_APPEND_TAGS", value_delimiter = ',')]
+    pub append_tags: Vec<String>,
+
+    /// Не больше стольких постов из SMTP за час на аккаунт; лишние письма
+    /// получают временный отказ 451. env: MOP3_MAX_POSTS_PER_HOUR
+    #[arg(long, env = "MOP3_MAX_POSTS_PER_HOUR")]
+    pub max_posts_per_hour: Option<u32>,
+
+    /// То же за сутки. env: MOP3_MAX_POSTS_PER_DAY
+    #[arg(long, env = "MOP3_MAX_POSTS_PER_DAY")]
+    pub max_posts_per_day: Option<u32>,
+
+    /// Отклонять письма с картинками без alt текста (Content-Description
+    /// вложения или строка `alt:` в теле). env: MOP3_REQUIRE_ALT_TEXT
+    #[arg(long, env = "MOP3_REQUIRE_ALT_TEXT")]
+    pub require_alt_text: bool,
+
+    /// Аккаунты других бэкендов, в которые письмо публикуется вместе с
+    /// основным. Только в файле конфигурации: `[[crosspost]]`
+    #[arg(skip)]
+    #[serde(default)]
+    pub crosspost: Vec<CrosspostAccount>,
+
+    /// Правила, которые скрывают публикуемый пост за предупреждением, если
+    /// текст письма подходит под выражение. Только в файле конфигурации:
+    /// `[[cw_rules]]`
+    #[arg(skip)]
+    #[serde(default)]
+    pub cw_rules: Vec<CwRule>,
+
+    /// Бэкенды по домену MAIL FROM, если в письме нет X-Mop3-Backends.
+    /// Только в файле конфигурации: `[crosspost_routes]`
+    #[arg(skip)]
+    #[serde(default)]
+    pub crosspost_routes: BTreeMap<String, Vec<String>>,
+
+    /// Кодировка писем: utf-8, iso-8859-1, koi8-r, cp437, shift_jis, ...
+    /// Непредставимые символы транслитерируются. env: MOP3_CHARSET
+    #[arg(long, env = "MOP3_CHARSET", value_enum, default_value = "utf-8")]
+    pub charset: Charset,
+
+    /// Content-Transfer-Encoding тела письма: auto, quoted-printable, base64
+    /// или 7bit (с транслитерацией в ASCII). env: MOP3_TRANSFER_ENCODING
+    #[arg(
+        long,
+        env = "MOP3_TRANSFER_ENCODING",
+        value_enum,
+        default_value = "auto"
+    )]
+    pub transfer_encoding: TransferEncoding,
+
+    /// Файл состояния шлюза (ID последних постов, опубликованные письма),
+    /// переживающего перезапуск. Без него состояние хранится в памяти
+    /// env: MOP3_STATE_FILE
+    #[arg(long, env = "MOP3_STATE_FILE")]
+    pub state_file: Option<PathBuf>,
+
+    /// Журнал аудита: входы по POP3/SMTP, опубликованные, удалённые и
+    /// отменённые посты с адресами клиентов, по строке JSON на событие.
+    /// Только дозапись.
+    /// env: MOP3_AUDIT_LOG
+    #[arg(long, env = "MOP3_AUDIT_LOG")]
+    pub audit_log: Option<PathBuf>,
+
+    /// URL, на который шлюз отправляет POST с JSON о каждом новом упоминании
+    /// и личном сообщении из уведомлений API, проверяемых при загрузке ленты
+    /// (например, тема ntfy). env: MOP3_WEBHOOK_URL
+    #[arg(long, env = "MOP3_WEBHOOK_URL")]
+    pub webhook_url: Option<String>,
+
+    /// Дублировать логи в файл (в дополнение к stderr)
+    /// env: MOP3_LOG_FILE
+    #[arg(long, env = "MOP3_LOG_FILE")]
+    pub log_file: Option<PathBuf>,
+
+    /// Ротировать лог файл при превышении размера в байтах (0 - без ограничения)
+    /// env: MOP3_LOG_MAX_SIZE
+    #[arg(long, env = "MOP3_LOG_MAX_SIZE", default_value = "10485760")]
+    pub log_max_size: u64,
+
+    /// Ротировать лог файл по времени: never, hourly, daily
+    /// env: MOP3_LOG_ROTATION
+    #[arg(long, env = "MOP3_LOG_ROTATION", value_enum, default_value = "never")]
+    pub log_rotation: LogRotation,
+
+    /// Сколько старых лог файлов хранить
+    /// env: MOP3_LOG_KEEP
+    #[arg(long, env = "MOP3_LOG_KEEP", default_value = "5")]
+    pub log_keep: usize,
+
+    /// Коллектор OpenTelemetry для спанов сессий и запросов к API
+    /// (OTLP/HTTP, например http://localhost:4318). env: MOP3_OTLP_ENDPOINT
+    #[arg(long, env = "MOP3_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+
+    /// Профиль старого клиента: dos, win31, macos7.
+    /// Задаёт значения остальных параметров, если они не указаны явно.
+    /// env: MOP3_PROFILE
+    #[arg(long, env = "MOP3_PROFILE", value_enum)]
+    pub profile: Option<ClientProfile>,
+
+    /// Переносить строки текста писем по ширине (в символах)
+    /// env: MOP3_LINE_WIDTH
+    #[arg(long, env = "MOP3_LINE_WIDTH")]
+    pub line_width: Option<usize>,
+
+    /// Максимальный размер письма в байтах: вложения сверх лимита
+    /// заменяются ссылками. env: MOP3_MAX_MESSAGE_SIZE
+    #[arg(long, env = "MOP3_MAX_MESSAGE_SIZE")]
+    pub max_message_size: Option<usize>,
+
+    /// Максимальный размер загружаемого вложения в байтах: загрузка крупнее
+    /// прерывается, и в письме остаётся ссылка. env: MOP3_MAX_ATTACHMENT_SIZE
+    #[arg(long, env = "MOP3_MAX_ATTACHMENT_SIZE")]
+    pub max_attachment_size: Option<usize>,
+
+    /// Максимальный размер ящика в байтах: самые старые посты сверх лимита
+    /// не попадают в ящик. env: MOP3_MAX_MAILBOX_SIZE
+    #[arg(long, env = "MOP3_MAX_MAILBOX_SIZE")]
+    pub max_mailbox_size: Option<usize>,
+
+    /// Ограничить скорость отправки писем POP3 клиенту, в килобитах в
+    /// секунду: 56 - как у модема. env: MOP3_THROTTLE_KBPS
+    #[arg(long, env = "MOP3_THROTTLE_KBPS")]
+    pub throttle_kbps: Option<u64>,
 }
 
 impl Config {
-    /// Валидирует конфигурацию при запуске
-    pub fn validate(&self) -> crate::error::AppResult<()> {
-        if !self.nosmtp && self.token.is_none() {
-            return Err("SMTP требует токен. Предоставьте --token или используйте --nosmtp".into());
+    /// Собирает конфигурацию из CLI, env, файла конфигурации и профиля клиента.
+    /// Приоритет: CLI/env > файл > профиль > значения по умолчанию.
+    pub fn load() -> AppResult<Config> {
+        let matches = Config::command().get_matches();
+        let config =
+            Config::from_arg_matches(&matches).map_err(|e| AppError::Config(e.to_string()))?;
+
+        let is_explicit = |key: &str| {
+            !FILE_ONLY_KEYS.contains(&key)
+                && matches!(
+                    matches.value_source(key),
+                    Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+                )
+        };
+
+        let file = match &config.config {
+            Some(path) => Self::read_file(path)?,
+            None => toml::Table::new(),
+        };
+        let mut config = config.overlay(&file, is_explicit)?;
+
+        if let Some(profile) = config.profile {
+            let is_fixed = |key: &str| is_explicit(key) || file.contains_key(key);
+            // --attachment и --inline взаимоисключающие: явный выбор любого из них
+            // отменяет политику вложений профиля целиком
+            let attachment_fixed = is_fixed("attachment") || is_fixed("inline");
+
+            config = config.overlay(&profile.preset()?, |key| match key {
+                "attachment" | "inline" => attachment_fixed,
+                _ => is_fixed(key),
+            })?;
+        }
+
+        config.resolve_token()?;
+
+        Ok(config)
+    }
+
+    /// Подставляет токен из файла или хранилища секретов, если он не задан напрямую
+    fn resolve_token(&mut self) -> AppResult<()> {
+        if self.token.is_some() {
+            return Ok(());
+        }
+
+        if let Some(path) = &self.token_file {
+            let token = Zeroizing::new(std::fs::read_to_string(path).map_err(|e| {
+                AppError::Config(format!("Не удалось прочитать {}: {}", path.display(), e))
+            })?);
+            let token = token.trim();
+            if token.is_empty() {
+                return Err(AppError::Config(format!(
+                    "Файл токена {} пуст",
+                    path.display()
+                )));
+            }
+            self.token = Some(token.into());
+        } else if self.keyring {
+            self.token = Some(self.token_from_keyring()?.into());
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "keyring")]
+    fn token_from_keyring(&self) -> AppResult<String> {
+        let account = self.account.as_deref().ok_or_else(|| {
+            AppError::Config("--keyring требует --account для поиска токена".to_string())
+        })?;
+
+        keyring::Entry::new("mop3", account)
+            .and_then(|entry| entry.get_password())
+            .map_err(|e| {
+                AppError::Config(format!("Токен для {} не найден в keyring: {}", account, e))
+            })
+    }
+
+    #[cfg(not(feature = "keyring"))]
+    fn token_from_keyring(&self) -> AppResult<String> {
+        Err(AppError::Config(
+            "mop3 собран без поддержки keyring (cargo build --features keyring)".to_string(),
+        ))
+    }
+
+    /// Читает файл конфигурации и проверяет, что все ключи известны
+    fn read_file(path: &Path) -> AppResult<toml::Table> {
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            AppError::Config(format!("Не удалось прочитать {}: {}", path.display(), e))
+        })?;
+        let file: toml::Table = toml::from_str(&text)
+            .map_err(|e| AppError::Config(format!("Ошибка разбора {}: {}", path.display(), e)))?;
+
+        let command = Config::command();
+        if let Some(key) = file.keys().find(|key| {
+            !FILE_ONLY_KEYS.contains(&key.as_str())
+                && !command
+                    .get_arguments()
+                    .any(|arg| arg.get_id() == key.as_str())
+        }) {
+            return Err(AppError::Config(format!(
+                "Неизвестный параметр '{}' в {}",
+                key,
+                path.display()
+            )));
+        }
+
+        Ok(file)
+    }
+
+    /// Накладывает слой значений на конфигурацию, пропуская ключи, для которых `is_fixed`
+    fn overlay(self, layer: &toml::Table, is_fixed: impl Fn(&str) -> bool) -> AppResult<Config> {
+        let mut merged = toml::Table::try_from(&self)
+            .map_err(|e| AppError::Config(format!("Ошибка сериализации конфигурации: {}", e)))?;
+
+        for (key, value) in layer {
+            if !is_fixed(key) {
+                merged.insert(key.clone(), value.clone());
+            }
+        }
+
+        let mut config: Config = merged
+            .try_into()
+            .map_err(|e| AppError::Config(format!("Некорректное значение параметра: {}", e)))?;
+        config.config = self.config;
+        config.check = self.check;
+        config.hash_password = self.hash_password;
+        config.command = self.command;
+
+        Ok(config)
+    }
+
+    /// Открывается ли слушатель POP3S: нужен сертификат, и POP3 не отключён
+    pub fn pop3s(&self) -> bool {
+        !self.nopop3 && (self.tls_cert.is_some() || self.acme())
+    }
+
+    /// Получает ли шлюз сертификат POP3S по ACME
+    pub fn acme(&self) -> bool {
+        !self.acme_domain.is_empty()
+    }
+
+    /// Лента доставляется сама: в Maildir, по LMTP или через SMTP провайдера
+    pub fn delivers(&self) -> bool {
+        self.deliver_maildir.is_some() || self.deliver_lmtp.is_some() || self.relay_smtp.is_some()
+    }
+
+    /// Валидирует конфигурацию при запуске, собирая все найденные проблемы сразу,
+    /// чтобы ошибки не всплывали позже при bind или первом запросе к API
+    pub fn validate(&self) -> AppResult<()> {
+        let mut problems = Vec::new();
+
+        // С --users-file токены хранятся у пользователей
+        if !self.nosmtp && self.token.is_none() && self.users_file.is_none() {
+            problems.push(
+                "SMTP требует токен. Предоставьте --token, --token-file, --keyring, \
+                 --users-file или используйте --nosmtp"
+                    .to_string(),
+            );
+        }
+
+        if let Some(hash) = &self.gateway_password {
+            if !password::is_valid_hash(hash) {
+                problems.push(
+                    "--gateway-password должен быть хешем, полученным через --hash-password"
+                        .to_string(),
+                );
+            }
+            if self.account.is_none() || self.token.is_none() {
+                problems.push(
+                    "--gateway-password требует --account и токен: клиент больше не передаёт токен"
+                        .to_string(),
+                );
+            }
+        }
+
+        if !self.nosmtp && !self.nopop3 && self.pop3port == self.smtp_port {
+            problems.push(format!(
+                "POP3 и SMTP не могут слушать один порт {}. Измените --pop3port или --smtp-port",
+                self.pop3port
+            ));
+        }
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            problems.push("--tls-cert и --tls-key задаются вместе".to_string());
+        }
+        // Права на чтение проверяет --check: ключ может быть доступен только
+        // root, а конфигурация перепроверяется и после сброса привилегий
+        for (flag, path) in [
+            ("--tls-cert", &self.tls_cert),
+            ("--tls-key", &self.tls_key),
+            ("--tls-client-ca", &self.tls_client_ca),
+        ] {
+            if let Some(path) = path {
+                if !path.is_file() {
+                    problems.push(format!("{} {}: файл не найден", flag, path.display()));
+                }
+            }
+        }
+        if self.pop3s() {
+            if self.pop3s_port == self.pop3port {
+                problems.push(format!(
+                    "POP3 и POP3S не могут слушать один порт {}. Измените --pop3s-port",
+                    self.pop3s_port
+                ));
+            }
+            if !self.nosmtp && self.pop3s_port == self.smtp_port {
+                problems.push(format!(
+                    "POP3S и SMTP не могут слушать один порт {}. Измените --pop3s-port",
+                    self.pop3s_port
+                ));
+            }
+        }
+        if self.tls_client_ca.is_some() {
+            if !self.pop3s() {
+                problems.push(
+                    "--tls-client-ca требует POP3S: --tls-cert или --acme-domain".to_string(),
+                );
+            }
+            if self.tls_client_accounts.is_empty() {
+                problems.push(
+                    "--tls-client-ca требует таблицу [tls_client_accounts] в файле конфигурации"
+                        .to_string(),
+                );
+            }
+            // Пароль не передаётся, поэтому токен должен быть у шлюза
+            if self.users_file.is_none() && (self.account.is_none() || self.token.is_none()) {
+                problems
+                    .push("--tls-client-ca без --users-file требует --account и токен".to_string());
+            }
+        }
+        if self.acme() {
+            if self.tls_cert.is_some() {
+                problems.push("--acme-domain и --tls-cert взаимоисключающие".to_string());
+            }
+            // Без кеша сертификат заказывается при каждом запуске, а Let's
+            // Encrypt ограничивает число заказов на домен
+            if self.acme_cache.is_none() {
+                problems.push("--acme-domain требует --acme-cache".to_string());
+            }
+            let taken = [
+                (!self.nopop3, self.pop3port),
+                (self.pop3s(), self.pop3s_port),
+                (!self.nosmtp, self.smtp_port),
+            ];
+            if taken
+                .iter()
+                .any(|&(open, port)| open && port == self.acme_port)
+            {
+                problems.push(format!(
+                    "Порт ACME {} уже занят POP3, POP3S или SMTP. Измените --acme-port",
+                    self.acme_port
+                ));
+            }
+        }
+
+        for address in &self.address {
+            if let Err(problem) = validate_address(address) {
+                problems.push(problem);
+            }
+        }
+
+        if let Some(proxy) = &self.proxy {
+            if let Err(problem) = validate_proxy(proxy) {
+                problems.push(problem);
+            }
+        }
+
+        if let Some(proxy) = &self.outbound_proxy {
+            if let Err(problem) = validate_outbound_proxy(proxy) {
+                problems.push(problem);
+            }
+        }
+
+        if let Some(endpoint) = &self.otlp_endpoint {
+            match reqwest::Url::parse(endpoint) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") && url.host().is_some() => {}
+                _ => problems.push(format!(
+                    "Некорректный --otlp-endpoint '{}': ожидается http(s) URL коллектора \
+                     (пример: http://localhost:4318)",
+                    endpoint
+                )),
+            }
+        }
+
+        if let Some(url) = &self.webhook_url {
+            match reqwest::Url::parse(url) {
+                Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+                _ => problems.push(format!(
+                    "Некорректный --webhook-url '{}': ожидается http(s) URL \
+                     (пример: https://ntfy.sh/my-topic)",
+                    url
+                )),
+            }
+        }
+
+        if let Err(problem) = validate_address(&self.gateway_domain) {
+            problems.push(problem.replace("--address", "--gateway-domain"));
+        }
+
+        if self.translate_to.is_some() {
+            match self.translate_api {
+                TranslateApi::LibreTranslate if self.translate_url.is_none() => problems
+                    .push("--translate-to с LibreTranslate требует --translate-url".to_string()),
+                TranslateApi::Deepl if self.translate_key.is_none() => {
+                    problems.push("--translate-to с DeepL требует --translate-key".to_string())
+                }
+                _ => {}
+            }
+        }
+
+        let mut backends = vec![self.api_mode];
+        for account in &self.crosspost {
+            if backends.contains(&account.api_mode) {
+                problems.push(format!(
+                    "Бэкенд {} уже используется: в [[crosspost]] допускается один аккаунт \
+                     на бэкенд, отличный от --api-mode",
+                    account.api_mode.name()
+                ));
+            } else if !account.api_mode.can_post() {
+                problems.push(format!(
+                    "Бэкенд {} в [[crosspost]] не умеет публиковать посты",
+                    account.api_mode.name()
+                ));
+            }
+            backends.push(account.api_mode);
+        }
+        for (domain, names) in &self.crosspost_routes {
+            for name in names {
+                if !backends.iter().any(|backend| backend.name() == name) {
+                    problems.push(format!(
+                        "crosspost_routes: бэкенд {} для домена {} не задан ни --api-mode, \
+                         ни в [[crosspost]]",
+                        name, domain
+                    ));
+                }
+            }
+        }
+
+        for rule in &self.cw_rules {
+            if let Err(e) = rule.regex() {
+                problems.push(format!(
+                    "cw_rules: некорректное выражение '{}': {}",
+                    rule.pattern, e
+                ));
+            }
+            if rule.cw.trim().is_empty() {
+                problems.push(format!(
+                    "cw_rules: пустое предупреждение для '{}'",
+                    rule.pattern
+                ));
+            }
+        }
+
+        if self.user_agent.trim().is_empty() {
+            problems.push("--user-agent не может быть пустым".to_string());
+        }
+
+        if self.max_posts_per_hour == Some(0) || self.max_posts_per_day == Some(0) {
+            problems.push(
+                "--max-posts-per-hour и --max-posts-per-day должны быть больше 0; \
+                 без ограничения не задавайте их"
+                    .to_string(),
+            );
+        }
+
+        if self.activity_report == Some(0) {
+            problems.push("--activity-report должен быть больше 0 дней".to_string());
+        }
+
+        let delivers = self.delivers();
+        if delivers {
+            if self.deliver_interval == 0 {
+                problems.push("--deliver-interval должен быть больше 0 секунд".to_string());
+            }
+            if self.account.is_none() || self.token.is_none() {
+                problems.push(
+                    "--deliver-maildir, --deliver-lmtp и --relay-smtp требуют --account и токен"
+                        .to_string(),
+                );
+            }
+        }
+        if let Some(address) = self
+            .deliver_lmtp
+            .as_ref()
+            .filter(|address| !address.starts_with('/'))
+        {
+            if !is_host_port(address) {
+                problems.push(format!(
+                    "Некорректный --deliver-lmtp '{}': ожидается host:port или путь к сокету \
+                     (пример: 127.0.0.1:24)",
+                    address
+                ));
+            }
+        }
+        if let Some(address) = &self.relay_smtp {
+            if !is_host_port(address) {
+                problems.push(format!(
+                    "Некорректный --relay-smtp '{}': ожидается host:port \
+                     (пример: smtp.example.com:587)",
+                    address
+                ));
+            }
+            if self.relay_to.is_none() {
+                problems.push("--relay-smtp требует --relay-to".to_string());
+            }
+            if self.relay_user.is_some() != self.relay_password.is_some() {
+                problems.push("--relay-user и --relay-password задаются вместе".to_string());
+            }
+            if self.relay_user.is_some() && self.relay_tls == RelayTls::None {
+                problems.push(
+                    "Пароль --relay-password не отправляется без шифрования: \
+                     уберите --relay-tls none"
+                        .to_string(),
+                );
+            }
+        }
+        if self.sign_cert.is_some() != self.sign_key.is_some() {
+            problems.push("--sign-cert и --sign-key задаются вместе".to_string());
+        }
+        if self.nopop3 && self.nosmtp && !delivers {
+            problems.push(
+                "--nopop3 и --nosmtp вместе имеют смысл только с --deliver-maildir, \
+                 --deliver-lmtp или --relay-smtp"
+                    .to_string(),
+            );
+        }
+
+        if let Some(interval) = self.prefetch {
+            if interval == 0 {
+                problems.push("--prefetch должен быть больше 0 секунд".to_string());
+            }
+            if self.account.is_none() || self.token.is_none() {
+                problems.push(
+                    "--prefetch требует --account и токен, чтобы загружать ленту без входа по POP3"
+                        .to_string(),
+                );
+            }
+        }
+
+        if self.warm_cache && (self.account.is_none() || self.token.is_none()) {
+            problems.push(
+                "--warm-cache требует --account и токен, чтобы загрузить ленту без входа по POP3"
+                    .to_string(),
+            );
+        }
+
+        if self.login_max_failures > 0 && self.login_ban_time == 0 {
+            problems.push(
+                "--login-ban-time должен быть больше 0 секунд; чтобы отключить блокировку, \
+                 задайте --login-max-failures 0"
+                    .to_string(),
+            );
+        }
+
+        if !cfg!(unix) && (self.user.is_some() || self.group.is_some()) {
+            problems.push("--user и --group поддерживаются только на Unix".to_string());
+        }
+
+        if self.api_timeout == 0 || self.api_connect_timeout == 0 {
+            problems.push(
+                "--api-timeout и --api-connect-timeout должны быть больше 0 секунд".to_string(),
+            );
+        }
+
+        if self.media_timeout == 0 {
+            problems.push("--media-timeout должен быть больше 0 секунд".to_string());
+        }
+
+        if self.media_cache.is_some() && self.media_cache_size == 0 {
+            problems.push(
+                "--media-cache-size должен быть больше 0; уберите --media-cache, чтобы отключить кеш"
+                    .to_string(),
+            );
         }
 
         if self.attachment && self.inline {
-            return Err("Нельзя использовать одновременно --attachment и --inline".into());
+            problems.push("Нельзя использовать одновременно --attachment и --inline".to_string());
+        }
+
+        if self.html && self.alternative {
+            problems.push("Нельзя использовать одновременно --html и --alternative".to_string());
+        }
+
+        if self.html && self.link_footnotes {
+            problems.push("--link-footnotes работает только без --html".to_string());
+        }
+
+        if self.line_width.is_some_and(|width| width < 20) {
+            problems.push("--line-width должен быть не меньше 20 символов".to_string());
+        }
+
+        if self.max_image_dimension.is_some_and(|max| max < 16) {
+            problems.push("--max-image-dimension должен быть не меньше 16 пикселей".to_string());
+        }
+
+        if self.max_image_bytes.is_some_and(|max| max < 1024) {
+            problems.push("--max-image-bytes должен быть не меньше 1024 байт".to_string());
+        }
+
+        for (flag, limit) in [
+            ("--max-message-size", self.max_message_size),
+            ("--max-attachment-size", self.max_attachment_size),
+            ("--max-mailbox-size", self.max_mailbox_size),
+        ] {
+            if limit == Some(0) {
+                problems.push(format!(
+                    "{} должен быть больше 0; уберите параметр, чтобы снять лимит",
+                    flag
+                ));
+            }
+        }
+
+        if self.throttle_kbps == Some(0) {
+            problems.push(
+                "--throttle-kbps должен быть больше 0; уберите параметр, чтобы снять лимит"
+                    .to_string(),
+            );
         }
 
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::Config(format!(
+                "\n  - {}",
+                problems.join("\n  - ")
+            )))
+        }
+    }
+}
+
+/// Адрес host:port исходящего соединения
+fn is_host_port(address: &str) -> bool {
+    address
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+}
+
+/// Проверяет адрес прослушивания: IP литерал (IPv6 - в том числе в скобках) или имя хоста
+fn validate_address(address: &str) -> Result<(), String> {
+    let host = address.trim_start_matches('[').trim_end_matches(']');
+    if host.parse::<std::net::IpAddr>().is_ok() {
+        return Ok(());
+    }
+
+    let is_hostname = !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+
+    if is_hostname {
         Ok(())
+    } else {
+        Err(format!(
+            "Некорректный адрес '{}' в --address: ожидается IP (127.0.0.1, ::1) или имя хоста",
+            address
+        ))
+    }
+}
+
+/// Проверяет, что прокси для ссылок - http(s) URL, к которому можно дописать ссылку
+fn validate_proxy(proxy: &str) -> Result<(), String> {
+    match reqwest::Url::parse(proxy) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.host().is_some() => Ok(()),
+        Ok(url) => Err(format!(
+            "Некорректный --proxy '{}': схема должна быть http или https, а не '{}'",
+            proxy,
+            url.scheme()
+        )),
+        Err(e) => Err(format!(
+            "Некорректный --proxy '{}': {} (пример: http://frogfind.com/read.php?a=)",
+            proxy, e
+        )),
+    }
+}
+
+/// Проверяет прокси для исходящих соединений: http(s) или SOCKS5
+fn validate_outbound_proxy(proxy: &str) -> Result<(), String> {
+    match reqwest::Url::parse(proxy) {
+        Ok(url)
+            if matches!(url.scheme(), "http" | "https" | "socks5" | "socks5h")
+                && url.host().is_some() =>
+        {
+            Ok(())
+        }
+        Ok(url) => Err(format!(
+            "Некорректный --outbound-proxy '{}': поддерживаются http, https, socks5 и socks5h, а не '{}'",
+            proxy,
+            url.scheme()
+        )),
+        Err(e) => Err(format!(
+            "Некорректный --outbound-proxy '{}': {} (пример: socks5h://127.0.0.1:9050)",
+            proxy, e
+        )),
     }
 }
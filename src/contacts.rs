@@ -0,0 +1,198 @@
+use crate::api::{self, SocialNetworkApi};
+use crate::config::{Config, ContactFormat};
+use crate::error::{AppError, AppResult};
+use crate::models::{ContactList, Credentials, PostAuthor};
+use crate::outbox;
+use crate::pop3::charset::base64_part;
+use crate::pop3::converter::{full_acct, gateway_address};
+use crate::state::AppState;
+use chrono::Utc;
+use mail_builder::headers::content_type::ContentType;
+use mail_builder::headers::raw::Raw;
+use mail_builder::mime::MimePart;
+use mail_builder::MessageBuilder;
+use tracing::info;
+
+/// Аккаунт из списков подписок и списки, в которых он есть
+struct Contact {
+    author: PostAuthor,
+    lists: Vec<ContactList>,
+}
+
+/// Подписчики и подписки одним списком: взаимная подписка - один контакт
+async fn fetch(api_client: &dyn SocialNetworkApi, cred: &Credentials) -> AppResult<Vec<Contact>> {
+    let mut contacts: Vec<Contact> = Vec::new();
+    for list in [ContactList::Followers, ContactList::Following] {
+        for author in api_client.get_contacts(cred, list).await? {
+            match contacts.iter_mut().find(|c| c.author.id == author.id) {
+                Some(contact) => contact.lists.push(list),
+                None => contacts.push(Contact {
+                    author,
+                    lists: vec![list],
+                }),
+            }
+        }
+    }
+    Ok(contacts)
+}
+
+/// Адрес шлюза контакта: письмо на него становится упоминанием
+fn email(contact: &Contact, account_addr: &str, config: &Config) -> String {
+    gateway_address(&full_acct(&contact.author.acct, account_addr), config)
+}
+
+fn display_name(contact: &Contact) -> &str {
+    if contact.author.display_name.is_empty() {
+        &contact.author.acct
+    } else {
+        &contact.author.display_name
+    }
+}
+
+fn lists(contact: &Contact) -> String {
+    let names: Vec<&str> = contact.lists.iter().map(|list| list.name()).collect();
+    names.join(", ")
+}
+
+/// CSV с заголовками, которые адресные книги Thunderbird и Outlook
+/// сопоставляют своим полям без ручной настройки
+fn to_csv(contacts: &[Contact], account_addr: &str, config: &Config) -> String {
+    let mut csv = String::from("Name,E-mail Address,Nickname,Web Page,Notes\r\n");
+    for contact in contacts {
+        let fields = [
+            display_name(contact),
+            &email(contact, account_addr, config),
+            &full_acct(&contact.author.acct, account_addr),
+            contact.author.url.as_deref().unwrap_or_default(),
+            &lists(contact),
+        ];
+        let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// vCard 3.0, по карточке на контакт
+fn to_vcard(contacts: &[Contact], account_addr: &str, config: &Config) -> String {
+    let mut vcard = String::new();
+    for contact in contacts {
+        vcard.push_str("BEGIN:VCARD\r\nVERSION:3.0\r\n");
+        vcard.push_str(&format!("FN:{}\r\n", vcard_text(display_name(contact))));
+        vcard.push_str(&format!(
+            "NICKNAME:{}\r\n",
+            vcard_text(&full_acct(&contact.author.acct, account_addr))
+        ));
+        vcard.push_str(&format!(
+            "EMAIL;TYPE=INTERNET:{}\r\n",
+            email(contact, account_addr, config)
+        ));
+        if let Some(url) = &contact.author.url {
+            vcard.push_str(&format!("URL:{}\r\n", url));
+        }
+        vcard.push_str(&format!("NOTE:{}\r\n", vcard_text(&lists(contact))));
+        vcard.push_str("END:VCARD\r\n");
+    }
+    vcard
+}
+
+fn vcard_text(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace(';', "\\;")
+        .replace('\n', "\\n")
+        .replace('\r', "")
+}
+
+/// Команда export@: выгружает контакты и ставит письмо с CSV и vCard
+/// в очередь ящика аккаунта
+pub async fn deliver_export(state: &AppState, cred: &Credentials) -> AppResult<()> {
+    let config = &state.config;
+    let account_addr = state.api_client.verify_credentials(cred).await?;
+    let contacts = fetch(state.api_client.as_ref(), cred).await?;
+    let count = |list| contacts.iter().filter(|c| c.lists.contains(&list)).count();
+
+    let text = format!(
+        "Подписчиков: {}\n\
+         Подписок: {}\n\
+         \n\
+         Во вложении те же контакты в CSV и vCard для импорта в адресную книгу.\n\
+         Адрес контакта - адрес шлюза: письмо на него станет упоминанием.\n",
+        count(ContactList::Followers),
+        count(ContactList::Following),
+    );
+
+    let now = Utc::now();
+    let charset = config.charset;
+    let subject = format!("mop3: контакты {}", account_addr);
+    let gateway = format!("mop3@{}", config.gateway_domain);
+    let id = format!("contacts-{}", now.timestamp());
+    let mut message = MessageBuilder::new()
+        .to(account_addr.as_str())
+        .date(now.timestamp())
+        .message_id(format!("{}@{}", id, config.gateway_domain))
+        .header("From", Raw::new(charset.encode_address("mop3", &gateway)));
+    if charset.is_utf8() {
+        message = message.subject(subject.as_str());
+    } else {
+        message = message.header("Subject", Raw::new(charset.encode_header(&subject)));
+    }
+
+    let csv = to_csv(&contacts, &account_addr, config);
+    let vcard = to_vcard(&contacts, &account_addr, config);
+    let email_string = message
+        .body(MimePart::new(
+            "multipart/mixed",
+            vec![
+                charset.text_part("text/plain", &text, config.transfer_encoding),
+                base64_part(utf8("text/csv"), csv.as_bytes()).attachment("contacts.csv"),
+                base64_part(utf8("text/vcard"), vcard.as_bytes()).attachment("contacts.vcf"),
+            ],
+        ))
+        .write_to_string()
+        .map_err(|e| AppError::ServerError(format!("Failed to build email: {}", e)))?;
+
+    outbox::push(state, &account_addr, id, subject, email_string)?;
+    info!(
+        "Queued contacts export for {}: {} accounts",
+        account_addr,
+        contacts.len()
+    );
+    Ok(())
+}
+
+fn utf8(content_type: &'static str) -> ContentType<'static> {
+    ContentType::new(content_type).attribute("charset", "utf-8")
+}
+
+/// Выполняет `mop3 export-contacts` для --account и --token
+pub async fn run_command(config: &Config, format: ContactFormat) -> AppResult<()> {
+    let (Some(account), Some(token)) = (&config.account, &config.token) else {
+        return Err(AppError::Config(
+            "Для выгрузки контактов задайте --account и --token".to_string(),
+        ));
+    };
+    let cred = Credentials {
+        username: account.clone(),
+        password: token.clone(),
+    };
+
+    let api_client = api::create_api_client(config, api::http_client(config)?);
+    let account_addr = api_client.verify_credentials(&cred).await?;
+    let contacts = fetch(api_client.as_ref(), &cred).await?;
+
+    match format {
+        ContactFormat::Csv => print!("{}", to_csv(&contacts, &account_addr, config)),
+        ContactFormat::Vcard => print!("{}", to_vcard(&contacts, &account_addr, config)),
+    }
+    Ok(())
+}
@@ -0,0 +1,183 @@
+use crate::error::AppResult;
+use crate::models::{Credentials, Email};
+use crate::pop3::folder::Folder;
+use crate::pop3::server;
+use crate::relay::{self, expect};
+use crate::state::{AppState, SharedState};
+use chrono::Utc;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tracing::{debug, info, warn};
+
+/// UIDL уже доставленных писем: аккаунт -> UIDL, от старых к новым
+const DELIVERED_TABLE: &str = "delivered";
+
+/// Сколько UIDL помнить: с запасом больше ленты, которую отдаёт API
+const DELIVERED_CAPACITY: usize = 1000;
+
+/// Счётчик для уникальных имён файлов Maildir внутри одной секунды
+static MAILDIR_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Запускает доставку ленты аккаунта из конфигурации в --deliver-maildir,
+/// по --deliver-lmtp и/или через --relay-smtp раз в --deliver-interval. Как и фоновая загрузка,
+/// после SIGHUP задача сразу берёт новую конфигурацию.
+pub fn spawn_delivery(mut shared_state: SharedState) {
+    tokio::spawn(async move {
+        loop {
+            let state = Arc::clone(&shared_state.borrow_and_update());
+
+            if state.config.delivers() {
+                let mut delay = Duration::from_secs(state.config.deliver_interval);
+                if let Err(e) = deliver_new(&state).await {
+                    warn!("Delivery failed: {}", e);
+                    delay = delay.max(e.retry_after().unwrap_or_default());
+                }
+
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => continue,
+                    changed = shared_state.changed() => if changed.is_err() { return },
+                }
+            } else if shared_state.changed().await.is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/// Загружает ленту и доставляет письма, которых ещё не доставляли, от
+/// старых к новым. Письмо считается доставленным, только когда его приняли
+/// все назначения, так что после сбоя оно уйдёт снова.
+async fn deliver_new(state: &AppState) -> AppResult<()> {
+    let config = &state.config;
+    let cred = Credentials {
+        username: config.account.clone().unwrap_or_default(),
+        password: config.token.clone().unwrap_or_default(),
+    };
+    let account_addr = state.api_client.verify_credentials(&cred).await?;
+    let mailbox = server::fetch_mailbox(state, &cred, &account_addr, &Folder::Timeline).await?;
+
+    let mut delivered: Vec<String> = state
+        .store
+        .get(DELIVERED_TABLE, &account_addr)
+        .unwrap_or_default();
+    let pending: Vec<usize> = (0..mailbox.len())
+        .rev()
+        .filter(|&index| !delivered.iter().any(|uidl| *uidl == mailbox.uidl(index)))
+        .collect();
+    if pending.is_empty() {
+        debug!("Nothing new to deliver for {}", account_addr);
+        return Ok(());
+    }
+
+    let recipient = config.deliver_to.as_deref().unwrap_or(&account_addr);
+    let mut count = 0;
+    let result = async {
+        for index in pending {
+            let email = mailbox.email(index, state).await?;
+            deliver(state, email, recipient).await?;
+            delivered.push(email.uidl.clone());
+            count += 1;
+        }
+        Ok(())
+    }
+    .await;
+
+    let excess = delivered.len().saturating_sub(DELIVERED_CAPACITY);
+    delivered.drain(..excess);
+    if let Err(e) = state
+        .store
+        .insert(DELIVERED_TABLE, &account_addr, &delivered)
+    {
+        warn!(
+            "Cannot record delivered messages of {}: {}",
+            account_addr, e
+        );
+    }
+    info!("Delivered {} messages for {}", count, account_addr);
+    result
+}
+
+async fn deliver(state: &AppState, email: &Email, recipient: &str) -> AppResult<()> {
+    let config = &state.config;
+    if let Some(maildir) = &config.deliver_maildir {
+        deliver_maildir(maildir, email).await?;
+    }
+    if let Some(address) = &config.deliver_lmtp {
+        // Unix сокет Dovecot задаётся путём, TCP - адресом host:port
+        if address.starts_with('/') {
+            #[cfg(unix)]
+            {
+                let stream = tokio::net::UnixStream::connect(address).await?;
+                deliver_lmtp(stream, email, recipient).await?;
+            }
+            #[cfg(not(unix))]
+            return Err(crate::error::AppError::Config(
+                "LMTP over a unix socket is not supported on this platform".to_string(),
+            ));
+        } else {
+            let stream = TcpStream::connect(address).await?;
+            deliver_lmtp(stream, email, recipient).await?;
+        }
+    }
+    relay::send(config, email).await?;
+    Ok(())
+}
+
+/// Кладёт письмо в new/ через tmp/, как требует формат Maildir: читатель
+/// никогда не видит недописанный файл. Строки разделяются LF.
+async fn deliver_maildir(maildir: &Path, email: &Email) -> AppResult<()> {
+    for dir in ["tmp", "new", "cur"] {
+        tokio::fs::create_dir_all(maildir.join(dir)).await?;
+    }
+
+    let mut text = Vec::with_capacity(email.size);
+    email.body.write_to(&mut text).await?;
+    let text = String::from_utf8_lossy(&text).replace("\r\n", "\n");
+
+    let name = format!(
+        "{}.M{}P{}Q{}.mop3",
+        Utc::now().timestamp(),
+        Utc::now().timestamp_subsec_micros(),
+        std::process::id(),
+        MAILDIR_COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    let tmp = maildir.join("tmp").join(&name);
+    tokio::fs::write(&tmp, text).await?;
+    tokio::fs::rename(&tmp, maildir.join("new").join(&name)).await?;
+    debug!("Delivered {} to {}", email.id, maildir.display());
+    Ok(())
+}
+
+/// Доставляет письмо одной сессией LMTP (RFC 2033)
+async fn deliver_lmtp<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    email: &Email,
+    recipient: &str,
+) -> AppResult<()> {
+    let mut stream = BufReader::new(stream);
+    expect(&mut stream, '2').await?;
+    for command in [
+        "LHLO mop3".to_string(),
+        "MAIL FROM:<>".to_string(),
+        format!("RCPT TO:<{}>", recipient),
+    ] {
+        stream
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .await?;
+        expect(&mut stream, '2').await?;
+    }
+    stream.write_all(b"DATA\r\n").await?;
+    expect(&mut stream, '3').await?;
+    email.body.write_stuffed(&mut stream).await?;
+    stream.write_all(b".\r\n").await?;
+    // LMTP отвечает на DATA по строке на каждого получателя, он у нас один
+    expect(&mut stream, '2').await?;
+    stream.write_all(b"QUIT\r\n").await?;
+    stream.flush().await?;
+    debug!("Delivered {} over LMTP to {}", email.id, recipient);
+    Ok(())
+}
@@ -1,3 +1,5 @@
+use reqwest::StatusCode;
+use std::time::Duration;
 use thiserror::Error;
 
 pub type AppResult<T> = Result<T, AppError>;
@@ -25,24 +27,127 @@ pub enum AppError {
     #[error("Timeout waiting for server response")]
     Timeout,
 
+    #[error("API rate limit exceeded")]
+    RateLimited(Option<Duration>),
+
+    #[error("API server unavailable: {0}")]
+    ApiUnavailable(String),
+
     #[error("Invalid email format: {0}")]
     InvalidEmail(String),
 
+    #[error("Media error: {0}")]
+    Media(String),
+
+    #[error("Post limit reached: {0}")]
+    PostLimit(String),
+
+    #[error("Alt text required: {0}")]
+    MissingAltText(String),
+
+    #[error("Not supported by this backend: {0}")]
+    Unsupported(&'static str),
+
+    #[error("Mailbox is locked by another session")]
+    MailboxInUse,
+
+    #[error("Client disconnected: {0}")]
+    Disconnected(&'static str),
+
     #[error("Server error: {0}")]
     ServerError(String),
-
-    #[error("{0}")]
-    Custom(String),
 }
 
-impl From<String> for AppError {
-    fn from(s: String) -> Self {
-        AppError::Custom(s)
+impl AppError {
+    /// Сбой временный: тот же запрос стоит повторить позже
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            AppError::Timeout
+            | AppError::RateLimited(_)
+            | AppError::ApiUnavailable(_)
+            | AppError::PostLimit(_)
+            | AppError::MailboxInUse
+            | AppError::IoError(_) => true,
+            AppError::NetworkError(e) => match e.status() {
+                Some(status) => status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error(),
+                None => !e.is_builder() && !e.is_decode(),
+            },
+            AppError::Config(_)
+            | AppError::ApiError(_)
+            | AppError::JsonError(_)
+            | AppError::InvalidCredentials
+            | AppError::InvalidEmail(_)
+            | AppError::MissingAltText(_)
+            | AppError::Media(_)
+            | AppError::Unsupported(_)
+            | AppError::Disconnected(_)
+            | AppError::ServerError(_) => false,
+        }
+    }
+
+    /// API отказал в доступе: неверный логин или токен
+    pub fn is_auth(&self) -> bool {
+        matches!(self, AppError::InvalidCredentials)
+    }
+
+    /// Через сколько API разрешил повторить запрос (Retry-After)
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            AppError::RateLimited(retry_after) => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// Текст ответа -ERR с кодом RFC 2449/3206: `[AUTH]` - клиенту нужно
+    /// исправить логин или пароль, `[IN-USE]` - ящик открыт другой сессией,
+    /// `[SYS/TEMP]` - повторить проверку почты позже, `[SYS/PERM]` - без
+    /// администратора шлюза не обойтись
+    pub fn pop3_reply(&self) -> String {
+        let code = if self.is_auth() {
+            "AUTH"
+        } else if matches!(self, AppError::MailboxInUse) {
+            "IN-USE"
+        } else if self.is_retryable() {
+            "SYS/TEMP"
+        } else {
+            "SYS/PERM"
+        };
+        format!("[{}] {}", code, self.reply_text())
+    }
+
+    /// Ответ SMTP на неудачную отправку: на 4xx клиент повторит отправку
+    /// сам, 5xx означает, что письмо в таком виде не опубликовать
+    pub fn smtp_reply(&self) -> String {
+        match self {
+            AppError::InvalidEmail(e) => format!("554 Message rejected: {}", e),
+            AppError::MissingAltText(e) => format!("550 Alt text required: {}", e),
+            AppError::InvalidCredentials => "554 API rejected the access token".to_string(),
+            AppError::ApiError(e) => format!("554 API rejected the post: {}", e),
+            e if e.is_retryable() => format!("451 {}", e.reply_text()),
+            e => format!("554 {}", e.reply_text()),
+        }
     }
-}
 
-impl From<&str> for AppError {
-    fn from(s: &str) -> Self {
-        AppError::Custom(s.to_string())
+    /// Причина ошибки для клиента, без подробностей из лога
+    fn reply_text(&self) -> String {
+        match self {
+            AppError::InvalidCredentials => "Invalid credentials".to_string(),
+            AppError::Timeout => "API server timed out, try again later".to_string(),
+            AppError::RateLimited(_) => "API rate limit reached, try again later".to_string(),
+            AppError::ApiUnavailable(_) => "API server unavailable, try again later".to_string(),
+            AppError::NetworkError(_) => "Cannot reach API server".to_string(),
+            AppError::ApiError(e) => format!("API error: {}", e),
+            AppError::JsonError(_) => "Unexpected API response".to_string(),
+            AppError::Config(_) => "Gateway configuration error".to_string(),
+            AppError::InvalidEmail(e) => format!("Invalid message: {}", e),
+            AppError::Media(_) => "Cannot fetch media".to_string(),
+            AppError::PostLimit(e) => format!("Post limit reached: {}", e),
+            AppError::MissingAltText(e) => format!("Alt text required: {}", e),
+            AppError::Unsupported(what) => format!("{} is not supported by this backend", what),
+            AppError::MailboxInUse => "Mailbox is in use by another session".to_string(),
+            AppError::IoError(_) | AppError::Disconnected(_) | AppError::ServerError(_) => {
+                "Internal gateway error".to_string()
+            }
+        }
     }
 }
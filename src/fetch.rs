@@ -0,0 +1,54 @@
+use crate::error::{AppError, AppResult};
+use crate::models::Credentials;
+use crate::outbox;
+use crate::pop3::converter::{self, Threads};
+use crate::pop3::edited;
+use crate::state::AppState;
+use tracing::info;
+
+/// Первая ссылка http(s) в тексте письма, без скобок и знаков препинания,
+/// которыми клиенты и люди её окружают
+fn find_url(text: &str) -> Option<&str> {
+    text.split_whitespace()
+        .map(|word| word.trim_matches(['<', '>', '(', ')', '[', ']', '"', '\'', ',', '.', ';']))
+        .find(|word| word.starts_with("https://") || word.starts_with("http://"))
+}
+
+/// Команда fetch@: загружает тред поста по ссылке из письма и ставит его
+/// письмами в очередь ящика. Письма связаны References, как в ленте, так
+/// что клиент покажет их одним тредом.
+pub async fn deliver_thread(state: &AppState, cred: &Credentials, text: &str) -> AppResult<()> {
+    let url = find_url(text).ok_or_else(|| {
+        AppError::InvalidEmail("Put a link to the post into the message body".to_string())
+    })?;
+    let api_client = state.api_client.as_ref();
+    let account_addr = api_client.verify_credentials(cred).await?;
+    let post_id = api_client.resolve_post_url(cred, url).await?;
+    let mut posts = api_client.get_thread(cred, &post_id).await?;
+    edited::mark(state, &account_addr, &mut posts);
+
+    let threads = Threads::default();
+    let drafts = converter::prepare_posts(posts, &state.config);
+    for draft in &drafts {
+        let email =
+            converter::convert_draft(draft, None, &threads, state, cred, &account_addr).await?;
+        let mut text = Vec::with_capacity(email.size);
+        email.body.write_to(&mut text).await?;
+        // Тот же пост может прийти и в ленте: UIDL в ящике должны различаться
+        outbox::push(
+            state,
+            &account_addr,
+            format!("fetched-{}", email.id),
+            email.subject,
+            String::from_utf8_lossy(&text).into_owned(),
+        )?;
+    }
+
+    info!(
+        "Queued thread of {} for {}: {} messages",
+        url,
+        account_addr,
+        drafts.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,288 @@
+//! Шлюз из Mastodon и Bluesky в POP3/SMTP.
+//!
+//! Программа `mop3` - тонкая обёртка над этой библиотекой. Другие проекты
+//! могут встроить шлюз целиком или использовать его части по отдельности:
+//!
+//! - [`Pop3Server`] и [`SmtpServer`] - серверы, обслуживающие открытые сокеты
+//!   с общим состоянием [`AppState`];
+//! - [`api`] - клиенты Mastodon и Bluesky за трейтом [`SocialNetworkApi`],
+//!   приводящие посты к общему виду [`models::NormalizedPost`];
+//! - [`pop3::converter`] - сборка писем RFC 822 из постов.
+//!
+//! ```no_run
+//! use mop3::{AppState, Config, Pop3Server};
+//! use mop3::state::store::Store;
+//! use std::sync::Arc;
+//! use tokio::sync::watch;
+//!
+//! # async fn example() -> mop3::AppResult<()> {
+//! let config = Config::load()?;
+//! let store = Arc::new(Store::open(None)?);
+//! let (_state_tx, state_rx) = watch::channel(Arc::new(AppState::new(config, store)?));
+//! Pop3Server::bind(state_rx).await?.run().await
+//! # }
+//! ```
+
+use std::process::ExitCode;
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+mod acme;
+mod activity;
+pub mod api;
+mod audit;
+mod check;
+pub mod config;
+mod contacts;
+mod deliver;
+pub mod error;
+mod fetch;
+mod lockout;
+mod logging;
+pub mod models;
+mod net;
+mod outbox;
+mod password;
+pub mod pop3;
+mod privileges;
+mod relay;
+mod sign;
+pub mod smtp;
+pub mod state;
+mod telemetry;
+mod users;
+mod webhook;
+
+pub use api::bluesky::BlueskyClient;
+pub use api::mastodon::MastodonClient;
+pub use api::SocialNetworkApi;
+pub use config::Config;
+pub use error::{AppError, AppResult};
+pub use pop3::server::Pop3Server;
+pub use smtp::server::SmtpServer;
+pub use state::{AppState, SharedState};
+
+use acme::Acme;
+use state::store::Store;
+
+/// Полный цикл работы программы `mop3` с уже загруженной конфигурацией:
+/// служебные команды (`--hash-password`, `mop3 user`, `mop3 export-contacts`,
+/// `--check`), логирование, открытие сокетов, сброс привилегий и оба сервера
+/// с перезагрузкой по SIGHUP. Возвращается только после служебной команды
+/// (при `--check` с кодом выхода по результату самопроверки) или с ошибкой;
+/// процесс завершает вызывающий.
+/// Встраивающим шлюз проектам обычно удобнее собрать [`AppState`] и запустить
+/// [`Pop3Server`] и [`SmtpServer`] самостоятельно.
+pub async fn run(config: Config) -> AppResult<ExitCode> {
+    // Хеш пароля шлюза печатается до проверки остальной конфигурации
+    if config.hash_password {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        println!("{}", password::hash(line.trim_end_matches(['\r', '\n']))?);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    match &config.command {
+        Some(config::Command::User(command)) => {
+            users::run_command(&config, command)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(config::Command::ExportContacts { format }) => {
+            contacts::run_command(&config, *format).await?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        None => {}
+    }
+
+    // Инициализируем логирование
+    logging::init_tracing(&config)?;
+
+    // Валидируем конфигурацию
+    if let Err(e) = config.validate() {
+        error!("{}", e);
+        return Err(e);
+    }
+
+    // Самопроверка: при --check только отчёт, иначе предупреждения в лог
+    let report = check::run_self_check(&config).await;
+    if config.check {
+        print!("{}", report);
+        return Ok(if report.is_ok() {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::FAILURE
+        });
+    }
+    report.log();
+
+    info!(
+        "Starting MOP3 gateway - API Mode: {:?}, Listen: {}, POP3 port: {}",
+        config.api_mode,
+        config.address.join(", "),
+        config.pop3port
+    );
+
+    // Порты открываются до сброса привилегий: 110 и 25 требуют root.
+    // Файл состояния и кеш медиа создаются уже от имени --user.
+    let (nopop3, nosmtp) = (config.nopop3, config.nosmtp);
+    let pop3_listeners = if nopop3 {
+        Vec::new()
+    } else {
+        net::bind_listeners(&config.address, config.pop3port).await?
+    };
+    // Ключ POP3S обычно доступен только root: читается здесь же и
+    // не перечитывается по SIGHUP. Сертификат ACME заказывается уже после
+    // сброса привилегий, но порт его проверки тоже привилегированный.
+    let acme = if config.pop3s() && config.acme() {
+        Some((
+            Acme::new(&config)?,
+            net::bind_listeners(&config.address, config.acme_port).await?,
+        ))
+    } else {
+        None
+    };
+    let pop3s = if config.pop3s() {
+        Some((
+            net::bind_listeners(&config.address, config.pop3s_port).await?,
+            net::pop3s_acceptor(&config, acme.as_ref().map(|(acme, _)| acme))?,
+        ))
+    } else {
+        None
+    };
+    let smtp_listeners = if nosmtp {
+        Vec::new()
+    } else {
+        net::bind_listeners(&config.address, config.smtp_port).await?
+    };
+    privileges::drop_privileges(&config)?;
+    if let Some((acme, listeners)) = acme {
+        acme.spawn(listeners);
+    }
+
+    // Счётчики отчёта о работе (--activity-report) ведутся с запуска
+    activity::start();
+
+    // Общее состояние для обоих серверов, пересоздаётся по SIGHUP
+    let store = Arc::new(Store::open(config.state_file.as_deref())?);
+    let (state_tx, state_rx) = watch::channel(Arc::new(AppState::new(config, store)?));
+    spawn_config_reloader(state_tx);
+    pop3::prefetch::spawn_prefetch(state_rx.clone());
+    deliver::spawn_delivery(state_rx.clone());
+
+    // Запускаем POP3 сервер (если не отключен)
+    let pop3_handle: Option<JoinHandle<AppResult<()>>> = if nopop3 {
+        warn!("POP3 server disabled via --nopop3 flag");
+        None
+    } else {
+        let mut pop3_server = Pop3Server::new(state_rx.clone(), pop3_listeners);
+        if let Some((listeners, acceptor)) = pop3s {
+            pop3_server = pop3_server.with_tls_listeners(listeners, acceptor);
+        }
+        Some(tokio::spawn(pop3_server.run()))
+    };
+
+    // Запускаем SMTP сервер (если не отключен)
+    let smtp_handle: Option<JoinHandle<AppResult<()>>> = if nosmtp {
+        warn!("SMTP server disabled via --nosmtp flag");
+        None
+    } else {
+        let smtp_server = SmtpServer::new(state_rx.clone(), smtp_listeners);
+        Some(tokio::spawn(smtp_server.run()))
+    };
+
+    // Ждём завершения обоих серверов (они работают в бесконечном цикле).
+    // Без обоих серверов работает только доставка, и ждать нечего.
+    tokio::select! {
+        res = async {
+            match pop3_handle {
+                Some(handle) => handle.await,
+                None => std::future::pending().await,
+            }
+        } => {
+            error!("POP3 server terminated: {:?}", res);
+            Err(AppError::ServerError("POP3 server terminated".to_string()))
+        }
+        res = async {
+            match smtp_handle {
+                Some(handle) => handle.await,
+                None => std::future::pending().await,
+            }
+        } => {
+            error!("SMTP server terminated: {:?}", res);
+            Err(AppError::ServerError("SMTP server terminated".to_string()))
+        }
+    }
+}
+
+/// Перечитывает конфигурацию по SIGHUP, пересоздаёт клиенты и рассылает
+/// новое состояние серверам. Активные соединения продолжают работать со
+/// своим снимком.
+#[cfg(unix)]
+fn spawn_config_reloader(state_tx: watch::Sender<Arc<AppState>>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            warn!(
+                "Cannot install SIGHUP handler, config reload disabled: {}",
+                e
+            );
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            info!("SIGHUP received, reloading configuration");
+
+            let store = Arc::clone(&state_tx.borrow().store);
+            let new_state = Config::load()
+                .and_then(|c| c.validate().map(|_| c))
+                .and_then(|c| AppState::new(c, store));
+            let new_state = match new_state {
+                Ok(new_state) => new_state,
+                Err(e) => {
+                    error!("Config reload failed, keeping current configuration: {}", e);
+                    continue;
+                }
+            };
+
+            let new_config = &new_state.config;
+            let current = state_tx.borrow().config.clone();
+            if new_config.address != current.address
+                || new_config.pop3port != current.pop3port
+                || new_config.pop3s() != current.pop3s()
+                || new_config.pop3s_port != current.pop3s_port
+                || new_config.tls_cert != current.tls_cert
+                || new_config.tls_key != current.tls_key
+                || new_config.tls_client_ca != current.tls_client_ca
+                || new_config.acme_domain != current.acme_domain
+                || new_config.acme_cache != current.acme_cache
+                || new_config.acme_port != current.acme_port
+                || new_config.smtp_port != current.smtp_port
+                || new_config.nosmtp != current.nosmtp
+                || new_config.nopop3 != current.nopop3
+                || new_config.log_file != current.log_file
+                || new_config.otlp_endpoint != current.otlp_endpoint
+                || new_config.state_file != current.state_file
+                || new_config.user != current.user
+                || new_config.group != current.group
+            {
+                warn!(
+                    "Listener, TLS, log, state file or user settings changed; these require a restart"
+                );
+            }
+
+            state_tx.send_replace(Arc::new(new_state));
+            info!("Configuration reloaded");
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn spawn_config_reloader(_state_tx: watch::Sender<Arc<AppState>>) {
+    tracing::debug!("Config reload on SIGHUP is not supported on this platform");
+}
@@ -0,0 +1,130 @@
+use crate::config::Config;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Больше стольких записей о попытках не держим: устаревшие вычищаются,
+/// а если их не хватает, вытесняются самые старые
+const MAX_TRACKED: usize = 10_000;
+
+/// Вытеснение освобождает сразу десятую часть, чтобы не сортировать
+/// записи при каждой неудаче во время перебора с многих адресов
+const EVICT_TO: usize = MAX_TRACKED - MAX_TRACKED / 10;
+
+/// Неудачные попытки входа одного адреса или пользователя
+struct Attempts {
+    failures: u32,
+    since: Instant,
+    banned_until: Option<Instant>,
+}
+
+/// Попытки по ключу "ip ..." и "user ..."; общие для POP3 и SMTP и
+/// переживают перезагрузку конфигурации
+static ATTEMPTS: LazyLock<Mutex<HashMap<String, Attempts>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Сколько ещё длится бан адреса или пользователя, если он есть
+pub fn banned(ip: IpAddr, username: Option<&str>) -> Option<Duration> {
+    let attempts = ATTEMPTS.lock().ok()?;
+    let now = Instant::now();
+    keys(ip, username)
+        .iter()
+        .filter_map(|key| attempts.get(key)?.banned_until)
+        .filter(|until| *until > now)
+        .map(|until| until - now)
+        .max()
+}
+
+/// Учитывает неудачный вход. После --login-max-failures неудач за
+/// --login-ban-time адрес и пользователь блокируются на то же время.
+pub fn record_failure(config: &Config, ip: IpAddr, username: &str, protocol: &str) {
+    if config.login_max_failures == 0 {
+        return;
+    }
+    let Ok(mut attempts) = ATTEMPTS.lock() else {
+        return;
+    };
+
+    let now = Instant::now();
+    let ban_time = Duration::from_secs(config.login_ban_time);
+    if attempts.len() >= MAX_TRACKED {
+        attempts.retain(|_, entry| {
+            entry.banned_until.is_some_and(|until| until > now) || now - entry.since < ban_time
+        });
+    }
+    if attempts.len() >= MAX_TRACKED {
+        evict_oldest(&mut attempts, now);
+    }
+
+    warn!(
+        target: "security",
+        "{} login failed for user {} from {}", protocol, username, ip
+    );
+
+    for key in keys(ip, Some(username)) {
+        let entry = attempts.entry(key.clone()).or_insert(Attempts {
+            failures: 0,
+            since: now,
+            banned_until: None,
+        });
+        // Счёт начинается заново, когда прошлые неудачи или бан устарели
+        if now - entry.since >= ban_time && entry.banned_until.is_none_or(|until| until <= now) {
+            *entry = Attempts {
+                failures: 0,
+                since: now,
+                banned_until: None,
+            };
+        }
+
+        entry.failures += 1;
+        if entry.failures >= config.login_max_failures && entry.banned_until.is_none() {
+            entry.banned_until = Some(now + ban_time);
+            warn!(
+                target: "security",
+                "Banning {} for {}s after {} failed logins",
+                key, config.login_ban_time, entry.failures
+            );
+        }
+    }
+}
+
+/// Успешный вход сбрасывает счётчик неудач пользователя и адреса
+pub fn record_success(ip: IpAddr, username: &str) {
+    if let Ok(mut attempts) = ATTEMPTS.lock() {
+        for key in keys(ip, Some(username)) {
+            if attempts
+                .get(&key)
+                .is_some_and(|entry| entry.banned_until.is_none())
+            {
+                attempts.remove(&key);
+            }
+        }
+    }
+}
+
+/// Оставляет `EVICT_TO` записей: первыми уходят самые старые счётчики без
+/// бана, затем самые старые баны
+fn evict_oldest(attempts: &mut HashMap<String, Attempts>, now: Instant) {
+    let mut entries: Vec<(bool, Instant, String)> = attempts
+        .iter()
+        .map(|(key, entry)| {
+            let banned = entry.banned_until.is_some_and(|until| until > now);
+            (banned, entry.since, key.clone())
+        })
+        .collect();
+    entries.sort_unstable();
+    let excess = attempts.len().saturating_sub(EVICT_TO);
+    for (_, _, key) in entries.into_iter().take(excess) {
+        attempts.remove(&key);
+    }
+}
+
+fn keys(ip: IpAddr, username: Option<&str>) -> Vec<String> {
+    let mut keys = vec![format!("ip {}", ip.to_canonical())];
+    if let Some(username) = username {
+        keys.push(format!("user {}", username.to_lowercase()));
+    }
+    keys
+}
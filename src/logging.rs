@@ -0,0 +1,177 @@
+use crate::config::{Config, LogRotation};
+use crate::error::{AppError, AppResult};
+use crate::telemetry;
+use chrono::{DateTime, Utc};
+use std::collections::hash_map::RandomState;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::info;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter};
+
+/// Инициализирует систему логирования: stderr и, если задан, файл с ротацией;
+/// с --otlp-endpoint спаны ещё и отправляются в OpenTelemetry
+pub fn init_tracing(config: &Config) -> AppResult<()> {
+    let env_filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new("info"))
+        .unwrap_or_else(|_| EnvFilter::new("info"));
+
+    let stderr_layer = fmt::layer()
+        .with_writer(io::stderr)
+        .with_target(true)
+        .with_thread_ids(true)
+        .with_line_number(true)
+        .with_file(true);
+
+    let file_layer = match &config.log_file {
+        Some(path) => {
+            let file = RotatingFile::open(
+                path.clone(),
+                (config.log_max_size > 0).then_some(config.log_max_size),
+                config.log_rotation,
+                config.log_keep,
+            )
+            .map_err(|e| {
+                AppError::Config(format!("Не удалось открыть лог {}: {}", path.display(), e))
+            })?;
+
+            Some(
+                fmt::layer()
+                    .with_writer(Mutex::new(file))
+                    .with_ansi(false)
+                    .with_target(true)
+                    .with_thread_ids(true)
+                    .with_line_number(true)
+                    .with_file(true),
+            )
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(env_filter)
+        .with(stderr_layer)
+        .with(file_layer)
+        .with(telemetry::layer(config))
+        .init();
+
+    info!("Tracing initialized");
+    Ok(())
+}
+
+/// Короткий случайный ID сессии POP3/SMTP для спана соединения и ответов
+/// с ошибкой: по нему строки одной сессии находятся среди параллельных
+pub fn session_id() -> String {
+    let random = RandomState::new().build_hasher().finish();
+    format!("{:08x}", random as u32)
+}
+
+/// Лог файл с ротацией по размеру и/или времени.
+///
+/// При ротации `mop3.log` становится `mop3.log.1`, старые файлы сдвигаются,
+/// самый старый сверх `keep` удаляется.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: Option<u64>,
+    rotation: LogRotation,
+    keep: usize,
+    period: String,
+}
+
+impl RotatingFile {
+    pub fn open(
+        path: PathBuf,
+        max_size: Option<u64>,
+        rotation: LogRotation,
+        keep: usize,
+    ) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let metadata = file.metadata()?;
+
+        // Период определяется по времени последнего изменения, чтобы
+        // вчерашний лог ротировался и после перезапуска
+        let modified: DateTime<Utc> = metadata
+            .modified()
+            .map(DateTime::from)
+            .unwrap_or_else(|_| Utc::now());
+
+        Ok(RotatingFile {
+            path,
+            file,
+            size: metadata.len(),
+            max_size,
+            rotation,
+            keep,
+            period: rotation.period(modified),
+        })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        if self.keep == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            let _ = fs::remove_file(numbered(&self.path, self.keep));
+            for n in (1..self.keep).rev() {
+                let from = numbered(&self.path, n);
+                if from.exists() {
+                    fs::rename(&from, numbered(&self.path, n + 1))?;
+                }
+            }
+            fs::rename(&self.path, numbered(&self.path, 1))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let period = self.rotation.period(Utc::now());
+        let size_exceeded = self
+            .max_size
+            .is_some_and(|max| self.size > 0 && self.size + buf.len() as u64 > max);
+
+        if period != self.period || size_exceeded {
+            self.rotate()?;
+            self.period = period;
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl LogRotation {
+    /// Идентификатор текущего периода; смена значения означает ротацию
+    fn period(self, now: DateTime<Utc>) -> String {
+        match self {
+            LogRotation::Never => String::new(),
+            LogRotation::Hourly => now.format("%Y-%m-%d-%H").to_string(),
+            LogRotation::Daily => now.format("%Y-%m-%d").to_string(),
+        }
+    }
+}
+
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
@@ -1,9 +1,48 @@
+use crate::config::ApiMode;
+use crate::pop3::message::Message;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Deref;
+use zeroize::Zeroizing;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Токен или пароль: память обнуляется при освобождении, а в Debug и
+/// логи попадает только заглушка
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(Zeroizing<String>);
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Secret(Zeroizing::new(value))
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Secret::from(value.to_string())
+    }
+}
+
+impl Deref for Secret {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Credentials {
     pub username: String,
-    pub password: String,
+    pub password: Secret,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -17,15 +56,31 @@ pub struct Status {
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct MastodonAccount {
+    #[serde(default)]
+    pub id: String,
     pub display_name: String,
     pub username: String,
     pub acct: String,
+    #[serde(default)]
+    pub avatar: Option<String>,
+    /// Страница профиля
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Новый аккаунт, если владелец переехал
+    #[serde(default)]
+    pub moved: Option<Box<MastodonAccount>>,
 }
 
+/// Автор поста Bluesky (app.bsky.actor.defs#profileViewBasic)
 #[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct BlueskyProfile {
-    pub display_name: Option<String>,
+    pub did: String,
     pub handle: String,
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub avatar: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -33,6 +88,8 @@ pub struct Attachment {
     pub filename: String,
     pub content_type: String,
     pub data: Vec<u8>,
+    /// Alt текст из Content-Description или строки `alt:` письма
+    pub description: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -40,31 +97,510 @@ pub struct MastodonStatus {
     pub id: String,
     pub content: String,
     pub created_at: String,
+    #[serde(default)]
+    pub edited_at: Option<String>,
     pub url: Option<String>,
+    #[serde(default)]
+    pub uri: Option<String>,
+    #[serde(default)]
+    pub visibility: Option<String>,
+    #[serde(default)]
+    pub language: Option<String>,
     pub reblog: Option<Box<MastodonStatus>>,
     pub in_reply_to_id: Option<String>,
-    pub media_attachments: Vec<serde_json::Value>,
+    pub in_reply_to_account_id: Option<String>,
+    pub media_attachments: Vec<MediaAttachment>,
     pub account: MastodonAccount,
+    #[serde(default)]
+    pub mentions: Vec<MastodonMention>,
+    #[serde(default)]
+    pub tags: Vec<MastodonTag>,
+    #[serde(default)]
+    pub replies_count: u64,
+    #[serde(default)]
+    pub reblogs_count: u64,
+    #[serde(default)]
+    pub favourites_count: u64,
+    #[serde(default)]
+    pub application: Option<MastodonApplication>,
+}
+
+/// Вложение поста. У медиа с другой инстанции, которое ещё не скопировано
+/// на свою, `url` бывает пустым.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MediaAttachment {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: MediaType,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub preview_url: Option<String>,
+    /// Альтернативный текст от автора
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub blurhash: Option<String>,
+    #[serde(default)]
+    pub meta: MediaMeta,
+}
+
+/// Тип вложения по Mastodon API; это не MIME тип
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaType {
+    Image,
+    Gifv,
+    Video,
+    Audio,
+    #[serde(other)]
+    Unknown,
+}
+
+impl MediaType {
+    /// Видео, анимация или аудио: такие вложения не прикладываются целиком
+    pub fn is_playable(self) -> bool {
+        matches!(self, MediaType::Gifv | MediaType::Video | MediaType::Audio)
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MediaMeta {
+    #[serde(default)]
+    pub original: Option<MediaOriginal>,
+}
+
+/// Параметры оригинала вложения; длительность есть у видео и аудио
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MediaOriginal {
+    #[serde(default)]
+    pub duration: Option<f64>,
+}
+
+/// Упоминание аккаунта в посте
+#[derive(Debug, Clone, Deserialize)]
+pub struct MastodonMention {
+    pub acct: String,
+    pub url: String,
+}
+
+/// Хэштег поста
+#[derive(Debug, Clone, Deserialize)]
+pub struct MastodonTag {
+    pub name: String,
 }
 
+/// Приложение, из которого опубликован пост
 #[derive(Debug, Clone, Deserialize)]
-pub struct BlueskyPost {
+pub struct MastodonApplication {
+    pub name: String,
+}
+
+/// Ответ app.bsky.feed.getTimeline
+#[derive(Debug, Deserialize)]
+pub struct BlueskyTimeline {
+    pub feed: Vec<BlueskyFeedViewPost>,
+}
+
+/// Ответ app.bsky.feed.getPosts
+#[derive(Debug, Deserialize)]
+pub struct BlueskyPosts {
+    pub posts: Vec<BlueskyPostView>,
+}
+
+/// Пост ленты Bluesky (app.bsky.feed.defs#feedViewPost): сам пост,
+/// контекст ответа и причина появления в ленте (репост)
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlueskyFeedViewPost {
+    pub post: BlueskyPostView,
+    #[serde(default)]
+    pub reply: Option<BlueskyReplyRef>,
+    #[serde(default)]
+    pub reason: Option<BlueskyReason>,
+}
+
+/// Пост Bluesky с автором, счётчиками и встроенным содержимым
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlueskyPostView {
     pub uri: String,
+    pub cid: String,
+    pub author: BlueskyProfile,
+    pub record: BlueskyRecord,
+    #[serde(default)]
+    pub embed: Option<BlueskyEmbed>,
+    #[serde(default)]
+    pub reply_count: u64,
+    #[serde(default)]
+    pub repost_count: u64,
+    #[serde(default)]
+    pub like_count: u64,
+    pub indexed_at: String,
+}
+
+impl BlueskyPostView {
+    pub fn strong_ref(&self) -> BlueskyStrongRef {
+        BlueskyStrongRef {
+            uri: self.uri.clone(),
+            cid: self.cid.clone(),
+        }
+    }
+}
+
+/// Запись поста (app.bsky.feed.post), как её создал автор
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlueskyRecord {
+    #[serde(default)]
     pub text: String,
     pub created_at: String,
-    pub reply: Option<serde_json::Value>,
+    #[serde(default)]
+    pub reply: Option<BlueskyReplyRecord>,
+    #[serde(default)]
+    pub langs: Vec<String>,
+}
+
+/// Ссылка на запись по URI и CID (com.atproto.repo.strongRef)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlueskyStrongRef {
+    pub uri: String,
+    pub cid: String,
+}
+
+/// Корень треда и родитель в записи ответа
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlueskyReplyRecord {
+    pub root: BlueskyStrongRef,
+    pub parent: BlueskyStrongRef,
+}
+
+/// Корень и родитель ответа в ленте, уже с авторами и текстом
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlueskyReplyRef {
+    pub root: BlueskyThreadPost,
+    pub parent: BlueskyThreadPost,
+}
+
+/// Пост треда; удалённый или заблокированный приходит без содержимого
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "$type")]
+pub enum BlueskyThreadPost {
+    #[serde(rename = "app.bsky.feed.defs#postView")]
+    Post(Box<BlueskyPostView>),
+    #[serde(other)]
+    Unavailable,
+}
+
+/// Ответ app.bsky.feed.getPostThread
+#[derive(Debug, Deserialize)]
+pub struct BlueskyPostThread {
+    pub thread: BlueskyThreadNode,
+}
+
+/// Узел треда getPostThread: пост с родителем и ответами. Удалённый или
+/// заблокированный пост приходит без содержимого.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "$type")]
+pub enum BlueskyThreadNode {
+    #[serde(rename = "app.bsky.feed.defs#threadViewPost")]
+    Post {
+        post: Box<BlueskyPostView>,
+        #[serde(default)]
+        parent: Option<Box<BlueskyThreadNode>>,
+        #[serde(default)]
+        replies: Vec<BlueskyThreadNode>,
+    },
+    #[serde(other)]
+    Unavailable,
+}
+
+/// Почему пост попал в ленту
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "$type")]
+pub enum BlueskyReason {
+    #[serde(rename = "app.bsky.feed.defs#reasonRepost")]
+    Repost {
+        by: BlueskyProfile,
+        #[serde(rename = "indexedAt")]
+        indexed_at: String,
+    },
+    #[serde(other)]
+    Other,
 }
 
+/// Встроенное в пост содержимое (app.bsky.embed.*#view)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "$type")]
+pub enum BlueskyEmbed {
+    #[serde(rename = "app.bsky.embed.images#view")]
+    Images { images: Vec<BlueskyImage> },
+    #[serde(rename = "app.bsky.embed.video#view")]
+    Video(BlueskyVideo),
+    #[serde(rename = "app.bsky.embed.external#view")]
+    External { external: BlueskyExternal },
+    /// Цитата другого поста
+    #[serde(rename = "app.bsky.embed.record#view")]
+    Record { record: BlueskyQuote },
+    #[serde(rename = "app.bsky.embed.recordWithMedia#view")]
+    RecordWithMedia {
+        record: BlueskyRecordView,
+        media: Box<BlueskyEmbed>,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlueskyImage {
+    pub thumb: String,
+    pub fullsize: String,
+    #[serde(default)]
+    pub alt: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlueskyVideo {
+    /// HLS плейлист
+    pub playlist: String,
+    #[serde(default)]
+    pub thumbnail: Option<String>,
+    #[serde(default)]
+    pub alt: Option<String>,
+}
+
+/// Карточка ссылки
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlueskyExternal {
+    pub uri: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub thumb: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlueskyRecordView {
+    pub record: BlueskyQuote,
+}
+
+/// Цитируемый пост; удалённый или заблокированный приходит без содержимого
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "$type")]
+pub enum BlueskyQuote {
+    #[serde(rename = "app.bsky.embed.record#viewRecord")]
+    Post(Box<BlueskyQuotedPost>),
+    #[serde(other)]
+    Unavailable,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlueskyQuotedPost {
+    pub uri: String,
+    pub cid: String,
+    pub author: BlueskyProfile,
+    pub value: BlueskyRecord,
+    pub indexed_at: String,
+}
+
+/// Пост ленты в общем для всех бэкендов виде: клиент API переводит в него
+/// свои модели, а фильтры и сборка писем работают только с ним
+#[derive(Debug, Clone)]
+pub struct NormalizedPost {
+    /// ID поста в API: по нему строятся Message-ID и ответы
+    pub id: String,
+    /// Текст поста в HTML
+    pub html: String,
+    /// Время публикации в RFC 3339
+    pub created_at: String,
+    pub edited_at: Option<String>,
+    /// Ссылка на пост в веб-интерфейсе
+    pub url: Option<String>,
+    /// ID объекта ActivityPub; у постов Bluesky его нет
+    pub uri: Option<String>,
+    /// Заголовок статьи новостного сайта: становится темой письма
+    pub title: Option<String>,
+    pub visibility: Option<String>,
+    pub language: Option<String>,
+    pub author: PostAuthor,
+    /// Оригинал, если пост - буст (репост)
+    pub reblog: Option<Box<NormalizedPost>>,
+    pub reply: Option<ReplyInfo>,
+    pub attachments: Vec<MediaAttachment>,
+    pub mentions: Vec<PostMention>,
+    /// Хэштеги без #
+    pub tags: Vec<String>,
+    pub replies_count: u64,
+    pub boosts_count: u64,
+    pub favourites_count: u64,
+    /// Приложение, из которого опубликован пост
+    pub application: Option<String>,
+    /// Бэкенд, из ленты которого пришёл пост
+    pub backend: ApiMode,
+    /// Исходный JSON поста, сохраняется только с --debug
+    pub raw: Option<serde_json::Value>,
+}
+
+/// Автор поста. `acct` - адрес в формате бэкенда: user@instance или user
+/// для локальных аккаунтов Mastodon, хэндл для Bluesky
+#[derive(Debug, Clone)]
+pub struct PostAuthor {
+    pub id: String,
+    pub acct: String,
+    pub username: String,
+    pub display_name: String,
+    pub avatar: Option<String>,
+    /// Страница профиля
+    pub url: Option<String>,
+    /// acct нового аккаунта, если автор переехал
+    pub moved_to: Option<String>,
+}
+
+/// На какой пост и чей отвечает пост
+#[derive(Debug, Clone)]
+pub struct ReplyInfo {
+    pub id: String,
+    pub account_id: Option<String>,
+}
+
+/// Упомянутый в посте аккаунт и ссылка на него в HTML
+#[derive(Debug, Clone)]
+pub struct PostMention {
+    pub acct: String,
+    pub url: String,
+}
+
+/// Уведомление аккаунта в общем для всех бэкендов виде
 #[derive(Debug, Clone)]
-pub enum Post {
-    Mastodon(MastodonStatus),
-    Bluesky(BlueskyPost),
+pub struct Notification {
+    pub id: String,
+    pub kind: NotificationKind,
+    pub created_at: String,
+    /// Кто упомянул, ответил, подписался или отметил пост
+    pub account: PostAuthor,
+    /// Пост уведомления: упоминание, ответ или отмеченный пост пользователя
+    pub post: Option<NormalizedPost>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NotificationKind {
+    Mention,
+    Reply,
+    Boost,
+    Favourite,
+    Follow,
+    /// Тип, для которого нет общего варианта, как его называет бэкенд
+    Other(String),
 }
 
+/// Личная переписка (директ)
 #[derive(Debug, Clone)]
+pub struct Conversation {
+    pub id: String,
+    /// Собеседники без самого пользователя
+    pub accounts: Vec<PostAuthor>,
+    pub last_post: Option<NormalizedPost>,
+    pub unread: bool,
+}
+
+/// Результаты поиска по аккаунтам, постам и хэштегам
+#[derive(Debug, Clone, Default)]
+pub struct SearchResults {
+    pub accounts: Vec<PostAuthor>,
+    pub posts: Vec<NormalizedPost>,
+    pub tags: Vec<String>,
+}
+
+/// Лента, сохранённая пользователем в настройках бэкенда
+#[derive(Debug, Clone)]
+pub struct SavedFeed {
+    /// Идентификатор ленты в API (AT URI генератора для Bluesky)
+    pub id: String,
+    /// Название ленты
+    pub name: String,
+    /// Закреплена ли лента
+    pub pinned: bool,
+}
+
+impl SavedFeed {
+    /// Имя ящика ленты в суффиксе логина: название в нижнем регистре,
+    /// всё кроме букв и цифр заменено дефисами
+    pub fn folder_name(&self) -> String {
+        let slug: String = self
+            .name
+            .to_lowercase()
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '-' })
+            .collect();
+        slug.split('-')
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("-")
+    }
+}
+
+/// Список аккаунтов пользователя для экспорта контактов
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContactList {
+    /// Подписчики пользователя
+    Followers,
+    /// Аккаунты, на которые подписан пользователь
+    Following,
+}
+
+impl ContactList {
+    pub fn name(self) -> &'static str {
+        match self {
+            ContactList::Followers => "followers",
+            ContactList::Following => "following",
+        }
+    }
+}
+
+/// Самый длинный UIDL, который допускает RFC 1939
+const MAX_UIDL_LEN: usize = 70;
+
+/// UIDL письма с ID `id`. ID поста Mastodon годится как есть, а at:// URI
+/// Bluesky бывает длиннее 70 символов: такой ID заменяется хешем SHA-256.
+/// Результат зависит только от ID, так что UIDL поста одинаков в каждой
+/// сессии и после перезапуска.
+pub fn uidl(id: &str) -> Cow<'_, str> {
+    let valid =
+        (1..=MAX_UIDL_LEN).contains(&id.len()) && id.bytes().all(|b| (0x21..=0x7e).contains(&b));
+    if valid {
+        return Cow::Borrowed(id);
+    }
+    let hash: String = Sha256::digest(id.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Cow::Owned(hash)
+}
+
+/// Собранное письмо ящика: пост ленты или служебное письмо шлюза
 pub struct Email {
+    /// ID поста или служебного письма
     pub id: String,
-    pub domain: String,
-    pub content: String,
+    /// Идентификатор письма в ответе UIDL
+    pub uidl: String,
+    /// Тема письма до кодирования заголовка
     pub subject: String,
+    /// Размер письма в октетах, как его увидит клиент
+    pub size: usize,
+    pub body: Message,
+}
+
+impl Email {
+    /// Письмо с UIDL по его ID, см. [`uidl`]
+    pub fn new(id: String, subject: String, body: Message) -> Self {
+        Email {
+            uidl: uidl(&id).into_owned(),
+            size: body.size(),
+            id,
+            subject,
+            body,
+        }
+    }
 }
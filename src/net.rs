@@ -0,0 +1,234 @@
+use crate::acme::Acme;
+use crate::config::Config;
+use crate::error::{AppError, AppResult};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpListener;
+use tokio::time::Sleep;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::{WantsServerCert, WebPkiClientVerifier};
+use tokio_rustls::rustls::{self, ConfigBuilder, RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Разрешено ли соединение с этого адреса по --allow-from и --deny-from.
+/// IPv4 клиенты на IPv6 сокете приходят как ::ffff:a.b.c.d и сверяются
+/// по своему IPv4 адресу.
+pub fn is_peer_allowed(config: &Config, ip: IpAddr) -> bool {
+    let ip = ip.to_canonical();
+    let allowed =
+        config.allow_from.is_empty() || config.allow_from.iter().any(|net| net.contains(&ip));
+    allowed && !config.deny_from.iter().any(|net| net.contains(&ip))
+}
+
+/// TLS слушателя POP3S: сертификат от ACME, если он настроен, иначе из
+/// --tls-cert и --tls-key
+pub fn pop3s_acceptor(config: &Config, acme: Option<&Acme>) -> AppResult<TlsAcceptor> {
+    let builder = tls_server_config(config)?;
+    let server_config = match (acme, &config.tls_cert, &config.tls_key) {
+        (Some(acme), _, _) => builder.with_cert_resolver(acme.resolver()),
+        (None, Some(cert), Some(key)) => {
+            let (chain, private_key) = load_certificate(cert, key)?;
+            builder.with_single_cert(chain, private_key).map_err(|e| {
+                AppError::Config(format!(
+                    "Ключ {} не подходит к сертификату {}: {}",
+                    key.display(),
+                    cert.display(),
+                    e
+                ))
+            })?
+        }
+        _ => {
+            return Err(AppError::Config(
+                "POP3S требует --tls-cert и --tls-key или --acme-domain".to_string(),
+            ))
+        }
+    };
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// Протоколы и шифры TLS по умолчанию на ring. С --tls-client-ca
+/// соединение без клиентского сертификата этого CA не устанавливается.
+fn tls_server_config(config: &Config) -> AppResult<ConfigBuilder<ServerConfig, WantsServerCert>> {
+    let tls_error = |e: rustls::Error| AppError::Config(format!("Не удалось настроить TLS: {}", e));
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .map_err(tls_error)?;
+    let Some(ca_path) = &config.tls_client_ca else {
+        return Ok(builder.with_no_client_auth());
+    };
+
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(ca_path).map_err(|e| pem_error(ca_path, e))? {
+        roots
+            .add(cert.map_err(|e| pem_error(ca_path, e))?)
+            .map_err(|e| {
+                AppError::Config(format!("Некорректный CA {}: {}", ca_path.display(), e))
+            })?;
+    }
+    let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+        .build()
+        .map_err(|e| AppError::Config(format!("Некорректный CA {}: {}", ca_path.display(), e)))?;
+    Ok(builder.with_client_cert_verifier(verifier))
+}
+
+/// CN субъекта клиентского сертификата
+pub fn certificate_cn(cert: &CertificateDer<'_>) -> Option<String> {
+    let (_, cert) = X509Certificate::from_der(cert).ok()?;
+    let cn = cert.subject().iter_common_name().next()?;
+    cn.as_str().ok().map(str::to_string)
+}
+
+/// Цепочка сертификатов и ключ в PEM
+fn load_certificate(
+    cert_path: &Path,
+    key_path: &Path,
+) -> AppResult<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| pem_error(cert_path, e))?;
+    if certs.is_empty() {
+        return Err(AppError::Config(format!(
+            "В {} нет сертификата",
+            cert_path.display()
+        )));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| pem_error(key_path, e))?;
+    Ok((certs, key))
+}
+
+fn pem_error(path: &Path, e: rustls::pki_types::pem::Error) -> AppError {
+    AppError::Config(format!("Не удалось прочитать {}: {}", path.display(), e))
+}
+
+/// Разрешает список адресов (IPv4, IPv6 литералы или имена хостов) в адреса сокетов
+pub async fn resolve_bind_addrs(addresses: &[String], port: u16) -> AppResult<Vec<SocketAddr>> {
+    let mut addrs = Vec::new();
+
+    for address in addresses {
+        // Разрешаем запись IPv6 в квадратных скобках: [::1]
+        let host = address.trim_start_matches('[').trim_end_matches(']');
+
+        match host.parse::<IpAddr>() {
+            Ok(ip) => addrs.push(SocketAddr::new(ip, port)),
+            Err(_) => {
+                let resolved = tokio::net::lookup_host((host, port)).await.map_err(|e| {
+                    AppError::Config(format!("Не удалось разрешить адрес '{}': {}", address, e))
+                })?;
+                addrs.extend(resolved);
+            }
+        }
+    }
+
+    addrs.sort();
+    addrs.dedup();
+    Ok(addrs)
+}
+
+/// Открывает по одному слушающему сокету на каждый адрес.
+///
+/// IPv6 сокет работает в dual-stack режиме, если в списке нет IPv4 адресов;
+/// иначе он ограничивается IPv6, чтобы не конфликтовать с IPv4 сокетами на том же порту.
+pub async fn bind_listeners(addresses: &[String], port: u16) -> AppResult<Vec<TcpListener>> {
+    let addrs = resolve_bind_addrs(addresses, port).await?;
+    let has_ipv4 = addrs.iter().any(SocketAddr::is_ipv4);
+
+    let mut listeners = Vec::with_capacity(addrs.len());
+    for addr in addrs {
+        let listener = bind_listener(addr, has_ipv4)
+            .map_err(|e| AppError::ServerError(format!("Failed to bind {}: {}", addr, e)))?;
+        listeners.push(listener);
+    }
+
+    Ok(listeners)
+}
+
+fn bind_listener(addr: SocketAddr, only_v6: bool) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+    if addr.is_ipv6() {
+        socket.set_only_v6(only_v6)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+/// Поток с ограничением скорости записи (--throttle-kbps). После каждой
+/// записи следующая ждёт столько, сколько заняла бы передача записанного
+/// на заданной скорости; запись дробится на куски по 100 мс, чтобы большое
+/// вложение шло равномерно, а не рывками. Чтение не ограничивается.
+pub struct Throttled<S> {
+    inner: S,
+    /// Байт в секунду; без лимита поток пропускает данные как есть
+    rate: Option<u64>,
+    pause: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> Throttled<S> {
+    pub fn new(inner: S, kbps: Option<u64>) -> Self {
+        Throttled {
+            inner,
+            rate: kbps.map(|kbps| (kbps * 1000 / 8).max(1)),
+            pause: None,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Throttled<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Throttled<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let Some(rate) = this.rate else {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        };
+
+        if let Some(pause) = &mut this.pause {
+            ready!(pause.as_mut().poll(cx));
+            this.pause = None;
+        }
+
+        let chunk = buf.len().min((rate / 10).max(1) as usize);
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..chunk]))?;
+        let delay = Duration::from_secs_f64(written as f64 / rate as f64);
+        this.pause = Some(Box::pin(tokio::time::sleep(delay)));
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
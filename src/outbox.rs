@@ -0,0 +1,68 @@
+use crate::error::AppResult;
+use crate::models::Email;
+use crate::pop3::message::{Attachments, Message};
+use crate::sign;
+use crate::state::AppState;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use tracing::warn;
+
+/// Служебные письма шлюза, ждущие загрузки ящика: аккаунт -> письма
+const OUTBOX_TABLE: &str = "outbox";
+
+/// Очередь читается и перезаписывается целиком
+static OUTBOX_LOCK: Mutex<()> = Mutex::new(());
+
+/// Письмо в очереди. Хранится готовым текстом RFC 822, так что с
+/// --state-file очередь переживает перезапуск шлюза.
+#[derive(Debug, Serialize, Deserialize)]
+struct Queued {
+    id: String,
+    subject: String,
+    text: String,
+}
+
+/// Ставит письмо в очередь ящика `account_addr`: оно придёт при следующей
+/// загрузке ленты, например ответ на команду, присланную по SMTP
+pub fn push(
+    state: &AppState,
+    account_addr: &str,
+    id: String,
+    subject: String,
+    text: String,
+) -> AppResult<()> {
+    let _guard = OUTBOX_LOCK.lock();
+    let mut queue: Vec<Queued> = state
+        .store
+        .get(OUTBOX_TABLE, account_addr)
+        .unwrap_or_default();
+    queue.push(Queued { id, subject, text });
+    state.store.insert(OUTBOX_TABLE, account_addr, &queue)
+}
+
+/// Забирает письма из очереди ящика `account_addr`, от старых к новым
+pub fn take(state: &AppState, account_addr: &str) -> Vec<Email> {
+    let _guard = OUTBOX_LOCK.lock();
+    let queue: Vec<Queued> = match state.store.take(OUTBOX_TABLE, account_addr) {
+        Ok(queue) => queue.unwrap_or_default(),
+        Err(e) => {
+            warn!("Cannot read outbox for {}: {}", account_addr, e);
+            return Vec::new();
+        }
+    };
+
+    queue
+        .into_iter()
+        .filter_map(|queued| {
+            match Message::assemble(queued.text, Attachments::default())
+                .and_then(|body| sign::sign(state, body))
+            {
+                Ok(body) => Some(Email::new(queued.id, queued.subject, body)),
+                Err(e) => {
+                    warn!("Dropping queued message {}: {}", queued.id, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,115 @@
+use crate::error::{AppError, AppResult};
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+use zeroize::Zeroizing;
+
+/// Префикс хеша --gateway-password
+const SCHEME: &str = "pbkdf2-sha256";
+
+/// Число итераций PBKDF2 для новых хешей
+const ITERATIONS: u32 = 100_000;
+
+/// Размер соли новых хешей, байт
+const SALT_LEN: usize = 16;
+
+/// Хеш argon2id с параметрами по умолчанию, которым сверяется пароль
+/// неизвестного логина: отказ занимает столько же времени, сколько
+/// неверный пароль существующего пользователя
+const DUMMY_ARGON2: &str = "$argon2id$v=19$m=19456,t=2,p=1$3WMuVL805H96WmxAi8MnWw$\
+                            ihxkV0ly1buAuJ60rfsQUcsGtnByFLR6cn2IGsdDpuE";
+
+/// Хеш пароля шлюза для конфигурации: pbkdf2-sha256$итерации$соль$хеш
+pub fn hash(password: &str) -> AppResult<String> {
+    let mut salt = [0u8; SALT_LEN];
+    getrandom::fill(&mut salt)
+        .map_err(|e| AppError::ServerError(format!("Cannot generate salt: {}", e)))?;
+    let derived = pbkdf2(password.as_bytes(), &salt, ITERATIONS);
+    Ok(format!(
+        "{}${}${}${}",
+        SCHEME,
+        ITERATIONS,
+        to_hex(&salt),
+        to_hex(&derived)
+    ))
+}
+
+/// Хеш пароля пользователя --users-file: строка PHC argon2id
+pub fn hash_argon2(password: &str) -> AppResult<String> {
+    Argon2::default()
+        .hash_password(password.as_bytes())
+        .map(|hash| hash.to_string())
+        .map_err(|e| AppError::ServerError(format!("Cannot hash password: {}", e)))
+}
+
+/// Проверяет, что строка - хеш в формате `hash`
+pub fn is_valid_hash(encoded: &str) -> bool {
+    parse(encoded).is_some()
+}
+
+/// Проверяет, что строка - хеш в формате `hash_argon2`
+pub fn is_argon2_hash(encoded: &str) -> bool {
+    PasswordHash::new(encoded).is_ok_and(|hash| hash.algorithm.as_str().starts_with("argon2"))
+}
+
+/// Сверяет пароль с хешем `hash` или `hash_argon2` за время, не
+/// зависящее от совпадения
+pub fn verify(password: &str, encoded: &str) -> bool {
+    if encoded.starts_with("$argon2") {
+        return Argon2::default()
+            .verify_password(password.as_bytes(), encoded)
+            .is_ok();
+    }
+    let Some((iterations, salt, expected)) = parse(encoded) else {
+        return false;
+    };
+    let derived = pbkdf2(password.as_bytes(), &salt, iterations);
+    derived.ct_eq(expected.as_slice()).into()
+}
+
+fn parse(encoded: &str) -> Option<(u32, Vec<u8>, Vec<u8>)> {
+    let mut fields = encoded.split('$');
+    if fields.next()? != SCHEME {
+        return None;
+    }
+    let iterations = fields.next()?.parse().ok().filter(|&n| n > 0)?;
+    let salt = from_hex(fields.next()?)?;
+    let derived = from_hex(fields.next()?).filter(|derived| derived.len() == 32)?;
+    fields
+        .next()
+        .is_none()
+        .then_some((iterations, salt, derived))
+}
+
+/// PBKDF2-HMAC-SHA256 с одним блоком результата (32 байта)
+fn pbkdf2(password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    pbkdf2::pbkdf2_hmac_array::<Sha256, 32>(password, salt, iterations)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// `verify` в blocking пуле: PBKDF2 и argon2 намеренно медленные
+pub async fn check(password: &str, encoded: &str) -> bool {
+    let (password, encoded) = (Zeroizing::new(password.to_string()), encoded.to_string());
+    tokio::task::spawn_blocking(move || verify(&password, &encoded))
+        .await
+        .unwrap_or(false)
+}
+
+/// Сверяет пароль с заглушкой argon2, чтобы вход под неизвестным логином
+/// тратил то же время, что и под существующим
+pub async fn check_dummy(password: &str) {
+    check(password, DUMMY_ARGON2).await;
+}
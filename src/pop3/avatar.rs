@@ -0,0 +1,79 @@
+use crate::pop3::media::download_media;
+use crate::state::AppState;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use mail_builder::encoders::Base64Encoder;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::{Arc, LazyLock, Mutex};
+use tracing::debug;
+
+/// Сторона аватара в пикселях (требование заголовка Face)
+const AVATAR_SIZE: u32 = 48;
+
+/// Максимальный размер PNG для заголовка Face
+const FACE_MAX_BYTES: usize = 725;
+
+/// Сколько аватаров держать в памяти
+const CACHE_CAPACITY: usize = 1024;
+
+/// Готовый PNG аватара; `None` - загрузка не удалась
+type CachedAvatar = Option<Arc<Vec<u8>>>;
+
+/// Аватары по URL; неудачные загрузки тоже кешируются, чтобы не повторять их
+/// при каждой проверке почты
+static CACHE: LazyLock<Mutex<HashMap<String, CachedAvatar>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Загружает аватар и уменьшает его до PNG 48x48
+pub async fn fetch_avatar(url: &str, state: &AppState) -> CachedAvatar {
+    if let Some(cached) = CACHE.lock().ok()?.get(url) {
+        return cached.clone();
+    }
+
+    let avatar = match download_media(url, state).await {
+        Ok((data, _)) => resize(&data).map(Arc::new),
+        Err(e) => {
+            debug!("Cannot download avatar {}: {}", url, e);
+            None
+        }
+    };
+
+    let mut cache = CACHE.lock().ok()?;
+    if cache.len() >= CACHE_CAPACITY {
+        cache.clear();
+    }
+    cache.insert(url.to_string(), avatar.clone());
+    avatar
+}
+
+/// Значение заголовка Face, если аватар укладывается в лимит размера
+pub fn face_header(png: &[u8]) -> Option<String> {
+    let png = if png.len() > FACE_MAX_BYTES {
+        // Полноцветный аватар часто не помещается: пробуем оттенки серого
+        let gray = image::load_from_memory(png).ok()?.grayscale();
+        encode_png(&gray).filter(|gray| gray.len() <= FACE_MAX_BYTES)?
+    } else {
+        png.to_vec()
+    };
+
+    Base64Encoder::new()
+        .encode(&png)
+        .ok()
+        .map(|encoded| String::from_utf8_lossy(&encoded).into_owned())
+}
+
+fn resize(data: &[u8]) -> Option<Vec<u8>> {
+    let image = image::load_from_memory(data)
+        .map_err(|e| debug!("Cannot decode avatar: {}", e))
+        .ok()?;
+    encode_png(&image.resize_to_fill(AVATAR_SIZE, AVATAR_SIZE, FilterType::Triangle))
+}
+
+fn encode_png(image: &DynamicImage) -> Option<Vec<u8>> {
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .ok()?;
+    Some(png)
+}
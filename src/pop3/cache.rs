@@ -0,0 +1,225 @@
+use crate::error::{AppError, AppResult};
+use reqwest::header::{
+    HeaderMap, CACHE_CONTROL, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+};
+use reqwest::RequestBuilder;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+/// Дисковый кеш загруженных медиа (--media-cache). Файл назван по SHA-256
+/// от URL и хранит первой строкой MIME тип и валидаторы HTTP через табуляцию,
+/// за ней - содержимое.
+/// При превышении --media-cache-size удаляются файлы, которые дольше всех
+/// не читались: время изменения файла обновляется при каждом попадании.
+pub struct MediaCache {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl MediaCache {
+    pub fn open(dir: &Path, max_bytes: u64) -> AppResult<Self> {
+        std::fs::create_dir_all(dir).map_err(|e| {
+            AppError::Config(format!(
+                "Не удалось создать --media-cache {}: {}",
+                dir.display(),
+                e
+            ))
+        })?;
+
+        Ok(MediaCache {
+            dir: dir.to_path_buf(),
+            max_bytes,
+        })
+    }
+
+    /// Медиа из кеша; свежесть проверяет вызывающий по `validators`
+    pub async fn get(&self, url: &str) -> Option<CachedMedia> {
+        let path = self.path(url);
+        let mut data = tokio::fs::read(&path).await.ok()?;
+
+        let newline = data.iter().position(|&b| b == b'\n')?;
+        let header = String::from_utf8(data[..newline].to_vec()).ok()?;
+        data.drain(..=newline);
+
+        // Файлы без валидаторов (записанные до их появления) - просто MIME тип
+        let mut fields = header.split('\t').map(str::to_string);
+        let mime = fields.next().unwrap_or_default();
+        let mut field = || fields.next().filter(|field| !field.is_empty());
+        let validators = Validators {
+            etag: field(),
+            last_modified: field(),
+            fresh_until: field().and_then(|until| until.parse().ok()),
+        };
+
+        // Отмечаем использование для вытеснения давно не нужных файлов
+        if let Err(e) = std::fs::File::options()
+            .append(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()))
+        {
+            debug!("Cannot touch cached media {}: {}", path.display(), e);
+        }
+
+        debug!("Media cache hit: {}", url);
+        Some(CachedMedia {
+            data,
+            mime,
+            validators,
+        })
+    }
+
+    /// Сохраняет медиа и вытесняет старые файлы, если кеш переполнен
+    pub async fn insert(&self, url: &str, data: &[u8], mime: &str, validators: &Validators) {
+        let path = self.path(url);
+        let tmp = path.with_extension("tmp");
+
+        let header = format!(
+            "{}\t{}\t{}\t{}\n",
+            mime,
+            validators.etag.as_deref().unwrap_or_default(),
+            validators.last_modified.as_deref().unwrap_or_default(),
+            validators
+                .fresh_until
+                .map(|until| until.to_string())
+                .unwrap_or_default()
+        );
+        let mut contents = Vec::with_capacity(header.len() + data.len());
+        contents.extend_from_slice(header.as_bytes());
+        contents.extend_from_slice(data);
+
+        let written = match tokio::fs::write(&tmp, &contents).await {
+            Ok(()) => tokio::fs::rename(&tmp, &path).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            warn!("Cannot write media cache {}: {}", path.display(), e);
+            return;
+        }
+
+        if let Err(e) = self.evict().await {
+            warn!("Media cache eviction failed: {}", e);
+        }
+    }
+
+    async fn evict(&self) -> std::io::Result<()> {
+        let mut files = Vec::new();
+        let mut total = 0;
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            // Недописанные файлы других загрузок не трогаем
+            if metadata.is_file() && entry.path().extension().is_none() {
+                total += metadata.len();
+                files.push((metadata.modified()?, metadata.len(), entry.path()));
+            }
+        }
+
+        if total <= self.max_bytes {
+            return Ok(());
+        }
+
+        files.sort();
+        for (_, size, path) in files {
+            if total <= self.max_bytes {
+                break;
+            }
+            if tokio::fs::remove_file(&path).await.is_ok() {
+                debug!("Evicted cached media {}", path.display());
+                total -= size;
+            }
+        }
+        Ok(())
+    }
+
+    fn path(&self, url: &str) -> PathBuf {
+        let hash: String = Sha256::digest(url.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        self.dir.join(hash)
+    }
+}
+
+/// Медиа из кеша вместе с валидаторами для повторной проверки
+pub struct CachedMedia {
+    pub data: Vec<u8>,
+    pub mime: String,
+    pub validators: Validators,
+}
+
+/// Валидаторы HTTP ответа: по ним устаревшее медиа перепроверяется условным
+/// запросом, и неизменившийся файл стоит ответа 304 вместо полной загрузки
+#[derive(Default, Clone, PartialEq)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// До какого момента (секунды Unix) медиа отдаётся без проверки
+    pub fresh_until: Option<u64>,
+}
+
+impl Validators {
+    /// Валидаторы и срок свежести из заголовков ответа (Cache-Control: max-age)
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        // Табуляция и переводы строк сломали бы строку заголовка в файле кеша
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .filter(|v| !v.is_empty() && !v.contains(['\t', '\n']))
+                .map(str::to_string)
+        };
+
+        let cache_control = header(CACHE_CONTROL).unwrap_or_default();
+        let max_age = cache_control
+            .split(',')
+            .find_map(|directive| match directive.trim() {
+                "no-cache" => Some(0),
+                directive => directive.strip_prefix("max-age=")?.parse().ok(),
+            });
+
+        Validators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            fresh_until: max_age.map(|age: u64| now().saturating_add(age)),
+        }
+    }
+
+    /// Можно ли отдать медиа без запроса. Без срока свежести перепроверяется
+    /// каждый раз, а без валидаторов перепроверить нечем - такое медиа считается
+    /// неизменным, как и раньше.
+    pub fn is_fresh(&self) -> bool {
+        match self.fresh_until {
+            Some(until) => now() < until,
+            None => self.etag.is_none() && self.last_modified.is_none(),
+        }
+    }
+
+    /// Добавляет к запросу If-None-Match и If-Modified-Since
+    pub fn apply(&self, mut request: RequestBuilder) -> RequestBuilder {
+        if let Some(etag) = &self.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(modified) = &self.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, modified);
+        }
+        request
+    }
+
+    /// Валидаторы после ответа 304: сервер может прислать новые или только срок
+    pub fn refresh(&self, newer: Validators) -> Self {
+        Validators {
+            etag: newer.etag.or_else(|| self.etag.clone()),
+            last_modified: newer.last_modified.or_else(|| self.last_modified.clone()),
+            fresh_until: newer.fresh_until,
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,350 @@
+use clap::ValueEnum;
+use deunicode::deunicode_char;
+use encoding_rs::{EncoderResult, Encoding};
+use mail_builder::encoders::{Base64Encoder, QuotedPrintableEncoder};
+use mail_builder::headers::content_type::ContentType;
+use mail_builder::headers::raw::Raw;
+use mail_builder::mime::{BodyPart, MimePart};
+use serde::{Deserialize, Serialize};
+
+/// Верхняя половина кодовой страницы IBM 437 (байты 0x80..=0xFF)
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»\
+░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀\
+αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+/// Максимальная длина закодированных байт в одном encoded-word (RFC 2047)
+const ENCODED_WORD_BYTES: usize = 45;
+
+/// Длина строки, после которой 7bit тело переносится принудительно (RFC 5322: 998)
+const SEVEN_BIT_LINE: usize = 900;
+
+/// Content-Transfer-Encoding текстовых частей письма
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+pub enum TransferEncoding {
+    /// mail-builder выбирает 7bit, quoted-printable или base64 сам
+    #[default]
+    #[value(name = "auto")]
+    #[serde(rename = "auto")]
+    Auto,
+    #[value(name = "quoted-printable")]
+    #[serde(rename = "quoted-printable")]
+    QuotedPrintable,
+    #[value(name = "base64")]
+    #[serde(rename = "base64")]
+    Base64,
+    /// Текст транслитерируется в ASCII и отдаётся без кодирования
+    #[value(name = "7bit")]
+    #[serde(rename = "7bit")]
+    SevenBit,
+}
+
+/// Кодировка, в которой отдаются сгенерированные письма
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+pub enum Charset {
+    #[default]
+    #[value(name = "utf-8")]
+    #[serde(rename = "utf-8")]
+    Utf8,
+    #[value(name = "iso-8859-1")]
+    #[serde(rename = "iso-8859-1")]
+    Iso8859_1,
+    #[value(name = "iso-8859-2")]
+    #[serde(rename = "iso-8859-2")]
+    Iso8859_2,
+    #[value(name = "iso-8859-5")]
+    #[serde(rename = "iso-8859-5")]
+    Iso8859_5,
+    #[value(name = "iso-8859-15")]
+    #[serde(rename = "iso-8859-15")]
+    Iso8859_15,
+    #[value(name = "windows-1251")]
+    #[serde(rename = "windows-1251")]
+    Windows1251,
+    #[value(name = "windows-1252")]
+    #[serde(rename = "windows-1252")]
+    Windows1252,
+    #[value(name = "koi8-r")]
+    #[serde(rename = "koi8-r")]
+    Koi8R,
+    #[value(name = "koi8-u")]
+    #[serde(rename = "koi8-u")]
+    Koi8U,
+    #[value(name = "cp437")]
+    #[serde(rename = "cp437")]
+    Cp437,
+    #[value(name = "cp866")]
+    #[serde(rename = "cp866")]
+    Cp866,
+    #[value(name = "macintosh")]
+    #[serde(rename = "macintosh")]
+    Macintosh,
+    #[value(name = "shift_jis")]
+    #[serde(rename = "shift_jis")]
+    ShiftJis,
+    #[value(name = "euc-jp")]
+    #[serde(rename = "euc-jp")]
+    EucJp,
+    #[value(name = "iso-2022-jp")]
+    #[serde(rename = "iso-2022-jp")]
+    Iso2022Jp,
+    #[value(name = "big5")]
+    #[serde(rename = "big5")]
+    Big5,
+    #[value(name = "euc-kr")]
+    #[serde(rename = "euc-kr")]
+    EucKr,
+}
+
+impl Charset {
+    pub fn is_utf8(self) -> bool {
+        self == Charset::Utf8
+    }
+
+    /// Имя кодировки для MIME заголовков (IANA)
+    pub fn mime_name(self) -> &'static str {
+        match self {
+            Charset::Utf8 => "utf-8",
+            Charset::Iso8859_1 => "iso-8859-1",
+            Charset::Iso8859_2 => "iso-8859-2",
+            Charset::Iso8859_5 => "iso-8859-5",
+            Charset::Iso8859_15 => "iso-8859-15",
+            Charset::Windows1251 => "windows-1251",
+            Charset::Windows1252 => "windows-1252",
+            Charset::Koi8R => "koi8-r",
+            Charset::Koi8U => "koi8-u",
+            Charset::Cp437 => "IBM437",
+            Charset::Cp866 => "IBM866",
+            Charset::Macintosh => "macintosh",
+            Charset::ShiftJis => "Shift_JIS",
+            Charset::EucJp => "EUC-JP",
+            Charset::Iso2022Jp => "ISO-2022-JP",
+            Charset::Big5 => "Big5",
+            Charset::EucKr => "EUC-KR",
+        }
+    }
+
+    /// Таблица encoding_rs; ISO-8859-1 и CP437 кодируются вручную,
+    /// так как в WHATWG их нет (iso-8859-1 там означает windows-1252)
+    fn encoding(self) -> Option<&'static Encoding> {
+        match self {
+            Charset::Utf8 => Some(encoding_rs::UTF_8),
+            Charset::Iso8859_1 | Charset::Cp437 => None,
+            Charset::Iso8859_2 => Some(encoding_rs::ISO_8859_2),
+            Charset::Iso8859_5 => Some(encoding_rs::ISO_8859_5),
+            Charset::Iso8859_15 => Some(encoding_rs::ISO_8859_15),
+            Charset::Windows1251 => Some(encoding_rs::WINDOWS_1251),
+            Charset::Windows1252 => Some(encoding_rs::WINDOWS_1252),
+            Charset::Koi8R => Some(encoding_rs::KOI8_R),
+            Charset::Koi8U => Some(encoding_rs::KOI8_U),
+            Charset::Cp866 => Some(encoding_rs::IBM866),
+            Charset::Macintosh => Some(encoding_rs::MACINTOSH),
+            Charset::ShiftJis => Some(encoding_rs::SHIFT_JIS),
+            Charset::EucJp => Some(encoding_rs::EUC_JP),
+            Charset::Iso2022Jp => Some(encoding_rs::ISO_2022_JP),
+            Charset::Big5 => Some(encoding_rs::BIG5),
+            Charset::EucKr => Some(encoding_rs::EUC_KR),
+        }
+    }
+
+    /// Перекодирует текст. Непредставимые символы транслитерируются,
+    /// а если это невозможно — заменяются на `?`
+    pub fn encode(self, text: &str) -> Vec<u8> {
+        match self.encoding() {
+            Some(encoding) => encode_with(encoding, text),
+            None => {
+                let mut output = Vec::with_capacity(text.len());
+                for ch in text.chars() {
+                    match self.encode_single_byte(ch) {
+                        Some(byte) => output.push(byte),
+                        None => output.extend(
+                            fallback(ch)
+                                .chars()
+                                .map(|c| self.encode_single_byte(c).unwrap_or(b'?')),
+                        ),
+                    }
+                }
+                output
+            }
+        }
+    }
+
+    fn encode_single_byte(self, ch: char) -> Option<u8> {
+        if ch.is_ascii() {
+            return Some(ch as u8);
+        }
+
+        match self {
+            Charset::Iso8859_1 => u8::try_from(u32::from(ch)).ok(),
+            Charset::Cp437 => CP437_HIGH
+                .chars()
+                .position(|c| c == ch)
+                .map(|pos| 0x80 + pos as u8),
+            _ => None,
+        }
+    }
+
+    /// Создаёт text/* часть письма в выбранной кодировке
+    pub fn text_part(
+        self,
+        content_type: &'static str,
+        text: &str,
+        transfer: TransferEncoding,
+    ) -> MimePart<'static> {
+        let typed = ContentType::new(content_type).attribute("charset", self.mime_name());
+
+        match transfer {
+            TransferEncoding::Auto => {
+                MimePart::new(typed, BodyPart::Binary(self.encode(text).into()))
+            }
+            TransferEncoding::QuotedPrintable => {
+                // Запись в Vec не завершается ошибкой
+                let encoded = QuotedPrintableEncoder::new()
+                    .preserve_line_breaks()
+                    .encode(&self.encode(text))
+                    .unwrap_or_default();
+                // С явным Content-Transfer-Encoding mail-builder пишет тело как есть
+                MimePart::new(typed, BodyPart::Binary(encoded.into()))
+                    .transfer_encoding("quoted-printable")
+            }
+            TransferEncoding::Base64 => {
+                // Для Content-Type в виде Raw mail-builder всегда выбирает base64
+                let raw = format!("{}; charset=\"{}\"", content_type, self.mime_name());
+                MimePart {
+                    headers: vec![("Content-Type".into(), Raw::new(raw).into())],
+                    contents: BodyPart::Binary(self.encode(text).into()),
+                }
+            }
+            TransferEncoding::SevenBit => {
+                MimePart::new(typed, BodyPart::Text(seven_bit(text).into()))
+            }
+        }
+    }
+
+    /// Кодирует значение заголовка в encoded-words (RFC 2047) выбранной кодировки
+    pub fn encode_header(self, value: &str) -> String {
+        let value: String = value.chars().filter(|c| !c.is_control()).collect();
+        if value.is_ascii() {
+            return value;
+        }
+
+        let mut words = Vec::new();
+        let mut chunk = String::new();
+        for ch in value.chars() {
+            chunk.push(ch);
+            if self.encode(&chunk).len() >= ENCODED_WORD_BYTES {
+                words.push(self.encoded_word(&chunk));
+                chunk.clear();
+            }
+        }
+        if !chunk.is_empty() {
+            words.push(self.encoded_word(&chunk));
+        }
+
+        words.join(" ")
+    }
+
+    /// Формирует адрес `Имя <addr>` с именем в выбранной кодировке
+    pub fn encode_address(self, name: &str, addr: &str) -> String {
+        let name = self.encode_header(name);
+        if name.is_empty() {
+            format!("<{}>", addr)
+        } else if name.starts_with("=?") {
+            format!("{} <{}>", name, addr)
+        } else {
+            format!("\"{}\" <{}>", name.replace(['"', '\\'], ""), addr)
+        }
+    }
+
+    fn encoded_word(self, text: &str) -> String {
+        let encoded = Base64Encoder::new()
+            .encode(&self.encode(text))
+            .unwrap_or_default();
+        format!(
+            "=?{}?B?{}?=",
+            self.mime_name(),
+            String::from_utf8_lossy(&encoded)
+        )
+    }
+}
+
+/// Вложение в base64 независимо от типа. Текстовые вложения (text/calendar,
+/// text/csv) иначе уходят как 7bit, а строки длиннее 998 байт или голые CR
+/// в них испортили бы письмо.
+pub fn base64_part(content_type: ContentType<'static>, data: &[u8]) -> MimePart<'static> {
+    // Запись в Vec не завершается ошибкой
+    let encoded = Base64Encoder::new()
+        .wrap_lines()
+        .encode(data)
+        .unwrap_or_default();
+    MimePart::new(content_type, BodyPart::Binary(encoded.into())).transfer_encoding("base64")
+}
+
+fn encode_with(encoding: &'static Encoding, text: &str) -> Vec<u8> {
+    let mut encoder = encoding.new_encoder();
+    let mut output = Vec::with_capacity(text.len() * 2 + 16);
+    let mut input = text;
+
+    loop {
+        let reserve = encoder
+            .max_buffer_length_from_utf8_without_replacement(input.len())
+            .unwrap_or(input.len() * 4 + 16);
+        output.reserve(reserve);
+
+        let (result, read) =
+            encoder.encode_from_utf8_to_vec_without_replacement(input, &mut output, true);
+        input = &input[read..];
+
+        match result {
+            EncoderResult::InputEmpty => break,
+            EncoderResult::OutputFull => continue,
+            EncoderResult::Unmappable(ch) => {
+                // Транслитерация всегда даёт ASCII, который есть во всех кодировках
+                let replacement = fallback(ch);
+                output.reserve(replacement.len() + 8);
+                let (_, _) = encoder.encode_from_utf8_to_vec_without_replacement(
+                    replacement,
+                    &mut output,
+                    false,
+                );
+            }
+        }
+    }
+
+    output
+}
+
+fn fallback(ch: char) -> &'static str {
+    match deunicode_char(ch) {
+        Some(s) if !s.is_empty() => s,
+        _ => "?",
+    }
+}
+
+/// Приводит текст к виду, который mail-builder гарантированно отдаст как 7bit:
+/// только ASCII, без пробелов в конце строк и без слишком длинных строк
+fn seven_bit(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+
+    for line in text.lines() {
+        let line: String = line
+            .chars()
+            .map(|ch| {
+                if ch.is_ascii() {
+                    ch.to_string()
+                } else {
+                    fallback(ch).to_string()
+                }
+            })
+            .collect();
+        let chars: Vec<char> = line.trim_end().chars().collect();
+        for piece in chars.chunks(SEVEN_BIT_LINE) {
+            output.extend(piece);
+            output.push('\n');
+        }
+        if chars.is_empty() {
+            output.push('\n');
+        }
+    }
+
+    output.trim_end().to_string()
+}
@@ -0,0 +1,1041 @@
+use crate::api::SocialNetworkApi;
+use crate::config::{AvatarMode, Config, FromFormat};
+use crate::error::{AppError, AppResult};
+use crate::models::{Credentials, Email, MediaAttachment, NormalizedPost, PostAuthor};
+use crate::pop3::charset::base64_part;
+use crate::pop3::message::{Attachments, Message};
+use crate::pop3::{avatar, emoji, event, media, translate};
+use crate::sign;
+use crate::state::AppState;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use fancy_regex::Regex;
+use mail_builder::headers::address::Address;
+use mail_builder::headers::content_type::ContentType;
+use mail_builder::headers::raw::Raw;
+use mail_builder::mime::MimePart;
+use mail_builder::MessageBuilder;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use tracing::{debug, info, warn};
+
+/// Оценка заголовков письма до сборки
+const HEADERS_ESTIMATE: usize = 1024;
+
+/// Оценка размера одного вложения в base64 до его загрузки
+const ATTACHMENT_ESTIMATE: usize = 64 * 1024;
+
+/// Цепочки References по ID родителя: ответы в один тред запрашиваются один раз
+pub type Threads = Mutex<HashMap<String, Vec<String>>>;
+
+/// Пост ленты, готовый к сборке письма: бусты и треды уже свёрнуты
+pub struct Draft {
+    post: NormalizedPost,
+    /// Кто ещё бустил пост, кроме автора письма
+    boosted_by: Vec<String>,
+}
+
+impl Draft {
+    pub fn id(&self) -> &str {
+        &self.post.id
+    }
+
+    /// ID будущего письма, из которого строится UIDL, см. [`email_id`]
+    pub fn email_id(&self) -> String {
+        email_id(&self.post)
+    }
+
+    /// URL превью вложений, которые попадут в письмо (у буста - оригинала)
+    pub fn preview_urls(&self) -> Vec<String> {
+        self.post
+            .reblog
+            .as_deref()
+            .unwrap_or(&self.post)
+            .attachments
+            .iter()
+            .filter_map(|attachment| attachment.preview_url.clone())
+            .collect()
+    }
+
+    /// Примерный размер письма до сборки, для STAT и LIST
+    pub fn estimated_size(&self, config: &Config) -> usize {
+        let original = self.post.reblog.as_deref().unwrap_or(&self.post);
+        let copies = if config.alternative { 2 } else { 1 };
+        let mut size = HEADERS_ESTIMATE + original.html.len() * copies;
+        if config.attachment || config.inline {
+            size += self.preview_urls().len() * ATTACHMENT_ESTIMATE;
+        }
+        config.max_message_size.map_or(size, |max| size.min(max))
+    }
+}
+
+/// Готовит посты ленты к сборке писем: сворачивает бусты и треды
+pub fn prepare_posts(posts: Vec<NormalizedPost>, config: &Config) -> Vec<Draft> {
+    let (mut posts, boosters) = collapse_boosts(posts);
+    if config.collapse_threads {
+        posts = collapse_threads(posts);
+    }
+
+    posts
+        .into_iter()
+        .map(|post| {
+            // С --unwrap-boosts буст превращается в письмо от автора оригинала
+            let post = match post.reblog {
+                Some(mut reblog) if config.unwrap_boosts => {
+                    reblog.raw = post.raw;
+                    *reblog
+                }
+                _ => post,
+            };
+            let original = post.reblog.as_deref().unwrap_or(&post);
+            let boosted_by = boosters
+                .get(&original.id)
+                .into_iter()
+                .flatten()
+                .filter(|booster| post.reblog.is_none() || **booster != post.author.acct)
+                .cloned()
+                .collect();
+            Draft { post, boosted_by }
+        })
+        .collect()
+}
+
+/// Собирает RFC822 письмо поста. Медиа берётся из `downloads`, если оно уже
+/// загружено для всего ящика, иначе загружается здесь.
+pub async fn convert_draft(
+    draft: &Draft,
+    downloads: Option<&media::Downloads>,
+    threads: &Threads,
+    state: &AppState,
+    cred: &Credentials,
+    account_addr: &str,
+) -> AppResult<Email> {
+    let config = &state.config;
+    let references = thread_references(
+        &draft.post,
+        state.api_client.as_ref(),
+        cred,
+        config,
+        threads,
+    )
+    .await;
+
+    let own_downloads;
+    let downloads = match downloads {
+        Some(downloads) => downloads,
+        None => {
+            own_downloads = if config.attachment || config.inline {
+                media::download_all(draft.preview_urls(), state).await
+            } else {
+                media::Downloads::new()
+            };
+            &own_downloads
+        }
+    };
+
+    let boosted_by: Vec<&str> = draft.boosted_by.iter().map(String::as_str).collect();
+    convert_post_to_email(
+        &draft.post,
+        &references,
+        &boosted_by,
+        downloads,
+        account_addr,
+        state,
+    )
+    .await
+}
+
+/// Оставляет по одному письму на каждый оригинальный пост: если оригинал есть
+/// в ленте, его бусты убираются, иначе остаётся самый свежий буст.
+/// Возвращает также acct всех бустеров по ID оригинала.
+fn collapse_boosts(
+    posts: Vec<NormalizedPost>,
+) -> (Vec<NormalizedPost>, HashMap<String, Vec<String>>) {
+    let mut boosters: HashMap<String, Vec<String>> = HashMap::new();
+    let mut originals = HashSet::new();
+    for post in &posts {
+        match &post.reblog {
+            Some(reblog) => {
+                let accts = boosters.entry(reblog.id.clone()).or_default();
+                if !accts.contains(&post.author.acct) {
+                    accts.push(post.author.acct.clone());
+                }
+            }
+            None => {
+                originals.insert(post.id.clone());
+            }
+        }
+    }
+
+    let total = posts.len();
+    let mut seen = HashSet::new();
+    let posts: Vec<NormalizedPost> = posts
+        .into_iter()
+        .filter(|post| match &post.reblog {
+            Some(reblog) => !originals.contains(&reblog.id) && seen.insert(reblog.id.clone()),
+            None => true,
+        })
+        .collect();
+
+    if posts.len() != total {
+        debug!("Collapsed {} duplicate boosts", total - posts.len());
+    }
+    (posts, boosters)
+}
+
+/// Склеивает идущие подряд посты одного разговора (ответ прямо над своим
+/// родителем) в один пост с частями в хронологическом порядке. Письмо
+/// получает Message-ID и заголовки первого поста цепочки.
+fn collapse_threads(posts: Vec<NormalizedPost>) -> Vec<NormalizedPost> {
+    let mut collapsed = Vec::with_capacity(posts.len());
+    let mut chain: Vec<NormalizedPost> = Vec::new();
+
+    for post in posts {
+        if post.reblog.is_some() {
+            collapsed.extend(merge_chain(std::mem::take(&mut chain)));
+            collapsed.push(post);
+            continue;
+        }
+
+        let continues = chain.last().is_some_and(|newer| {
+            newer.reply.as_ref().map(|reply| reply.id.as_str()) == Some(post.id.as_str())
+        });
+        if !continues {
+            collapsed.extend(merge_chain(std::mem::take(&mut chain)));
+        }
+        chain.push(post);
+    }
+    collapsed.extend(merge_chain(chain));
+    collapsed
+}
+
+fn merge_chain(mut chain: Vec<NormalizedPost>) -> Option<NormalizedPost> {
+    // Лента идёт от новых к старым, части письма - от старых к новым
+    chain.reverse();
+    let mut parts = chain.into_iter();
+    let mut merged = parts.next()?;
+
+    let total = parts.len() + 1;
+    if total > 1 {
+        debug!("Collapsing thread of {} posts from {}", total, merged.id);
+    }
+    for (n, part) in parts.enumerate() {
+        let author = if part.author.acct == merged.author.acct {
+            String::new()
+        } else {
+            format!(" @{}", part.author.acct)
+        };
+        merged.html = format!(
+            "{}\n<p>---- {}/{}{} ----</p>\n{}",
+            merged.html,
+            n + 2,
+            total,
+            author,
+            part.html
+        );
+        merged.attachments.extend(part.attachments);
+        for tag in part.tags {
+            if !merged.tags.contains(&tag) {
+                merged.tags.push(tag);
+            }
+        }
+        for mention in part.mentions {
+            if !merged
+                .mentions
+                .iter()
+                .any(|known| known.acct == mention.acct)
+            {
+                merged.mentions.push(mention);
+            }
+        }
+    }
+
+    Some(merged)
+}
+
+/// Возвращает ID постов для References ответа: от корня треда до родителя.
+/// Если API недоступно или --no-thread-lookup, цепочка состоит из родителя.
+async fn thread_references(
+    post: &NormalizedPost,
+    api_client: &dyn SocialNetworkApi,
+    cred: &Credentials,
+    config: &Config,
+    threads: &Threads,
+) -> Vec<String> {
+    let Some(parent_id) = post.reply.as_ref().map(|reply| &reply.id) else {
+        return Vec::new();
+    };
+    if let Some(references) = threads.lock().ok().and_then(|t| t.get(parent_id).cloned()) {
+        return references;
+    }
+
+    let mut references = if config.no_thread_lookup {
+        Vec::new()
+    } else {
+        api_client
+            .ancestor_ids(cred, &post.id)
+            .await
+            .unwrap_or_else(|e| {
+                warn!(
+                    "Cannot fetch thread of {}, using parent only: {}",
+                    post.id, e
+                );
+                Vec::new()
+            })
+    };
+    if references.last() != Some(parent_id) {
+        references.push(parent_id.clone());
+    }
+
+    if let Ok(mut threads) = threads.lock() {
+        threads.insert(parent_id.clone(), references.clone());
+    }
+    references
+}
+
+/// ID письма поста. Правка получает собственный ID со временем правки,
+/// чтобы клиент, уже забравший исходную версию, загрузил её новым письмом.
+fn email_id(post: &NormalizedPost) -> String {
+    let original = post.reblog.as_deref().unwrap_or(post);
+    match original.edited_at.as_deref() {
+        Some(edited_at) => format!("{}.edited-{}", post.id, parse_timestamp(edited_at)),
+        None => post.id.clone(),
+    }
+}
+
+/// Конвертирует один пост в RFC822 письмо
+async fn convert_post_to_email(
+    post: &NormalizedPost,
+    references: &[String],
+    boosted_by: &[&str],
+    downloads: &media::Downloads,
+    account_addr: &str,
+    state: &AppState,
+) -> AppResult<Email> {
+    let (config, http_client) = (&state.config, &state.http_client);
+    let subject: String;
+    let attachments: Vec<MediaAttachment>;
+    let mut content: String;
+
+    // Определяем тему письма
+    if let Some(reblog) = &post.reblog {
+        subject = format!("mop3 Boost from {}", post.author.display_name);
+        content = reblog.html.to_string();
+        attachments = reblog.attachments.clone();
+    } else {
+        subject = post
+            .title
+            .clone()
+            .unwrap_or_else(|| "mop3 Post".to_string());
+        content = post.html.clone();
+        attachments = post.attachments.clone();
+    };
+
+    // Машинный перевод с сохранением оригинала под ним
+    let language = post.reblog.as_deref().unwrap_or(post).language.as_deref();
+    content = translate::translate_post(&content, language, http_client, config).await;
+
+    // Исходный HTML для multipart/alternative
+    let mut html_content = config.alternative.then(|| content.clone());
+
+    // Упомянутые аккаунты и их адреса шлюза
+    let original = post.reblog.as_deref().unwrap_or(post);
+    let mentions: Vec<(String, String)> = original
+        .mentions
+        .iter()
+        .map(|mention| {
+            let acct = full_acct(&mention.acct, account_addr);
+            let address = gateway_address(&acct, config);
+            (acct, address)
+        })
+        .collect();
+
+    // Ссылки упоминаний в HTML ведут на адреса шлюза
+    if config.mention_addresses {
+        for (mention, (_, address)) in original.mentions.iter().zip(&mentions) {
+            let href = format!("href=\"{}\"", mention.url);
+            let mailto = format!("href=\"mailto:{}\"", address);
+            content = content.replace(&href, &mailto);
+            html_content = html_content.map(|html| html.replace(&href, &mailto));
+        }
+    }
+
+    // Удаляем HTML теги если нужно конвертировать в текст
+    if !config.html {
+        content = html_to_text(&content);
+        if config.mention_addresses {
+            content = rewrite_mentions(&content, &mentions);
+        }
+    }
+
+    // Применяем ASCII преобразование если нужно (эмодзи - шорткодами)
+    if config.ascii {
+        content = emoji::to_ascii(&content);
+        html_content = html_content.map(|html| emoji::to_ascii(&html));
+    }
+
+    // Применяем proxy для ссылок если нужно
+    if config.link_footnotes && !config.html {
+        content = footnote_links(&content, config.proxy.as_deref().unwrap_or_default());
+    } else if let Some(proxy) = &config.proxy {
+        content = apply_proxy_to_links(&content, proxy);
+    } else {
+        content = apply_proxy_to_links(&content, "");
+    }
+    if let (Some(proxy), Some(html)) = (&config.proxy, html_content.as_mut()) {
+        *html = html.replace("href=\"http", &format!("href=\"{}http", proxy));
+    }
+
+    // Парсим дату
+    let created_at = parse_timestamp(&post.created_at);
+
+    // Отредактированный пост приходит новым письмом с датой правки,
+    // отвечающим на письмо с исходной версией
+    let edited_at = original.edited_at.as_deref().map(parse_timestamp);
+    let (subject, date) = match edited_at {
+        Some(edited_at) => (format!("[edited] {}", subject), edited_at),
+        None => (subject, created_at),
+    };
+    let id = email_id(post);
+
+    // Создаём сообщение
+    let mut message = MessageBuilder::new()
+        .to(account_addr)
+        .date(date)
+        .message_id(message_id(&id, account_addr));
+
+    // Заголовки с текстом кодируются в выбранной кодировке
+    let charset = config.charset;
+    message = message.header(
+        "From",
+        Raw::new(from_address(&post.author, account_addr, config)),
+    );
+    if charset.is_utf8() {
+        message = message.subject(subject.as_str());
+    } else {
+        message = message.header("Subject", Raw::new(charset.encode_header(&subject)));
+    }
+
+    // Добавляем reply if header если это ответ
+    let mut references: Vec<String> = references
+        .iter()
+        .map(|id| message_id(id, account_addr))
+        .collect();
+    if edited_at.is_some() {
+        message = message
+            .in_reply_to(message_id(&post.id, account_addr))
+            .header(
+                "X-Mop3-Edited",
+                Raw::new(original.edited_at.clone().unwrap_or_default()),
+            );
+        references.push(message_id(&post.id, account_addr));
+    } else if let Some(reply) = &post.reply {
+        message = message.in_reply_to(message_id(&reply.id, account_addr));
+    }
+    if !references.is_empty() {
+        message = message.references(references);
+    }
+
+    // Ответ уходит на адрес с ID поста: так он попадёт в нужный тред, даже
+    // если клиент потеряет In-Reply-To. Адрес автора из From остаётся рядом,
+    // чтобы ответ по-прежнему его упоминал.
+    let mut reply_to = vec![Address::new_address(
+        None::<String>,
+        reply_address(&original.id, config),
+    )];
+    let author = full_acct(&post.author.acct, account_addr);
+    if config.from_format == FromFormat::Gateway
+        || (config.from_format == FromFormat::Acct && !author.contains('@'))
+    {
+        reply_to.push(Address::new_address(
+            None::<String>,
+            gateway_address(&author, config),
+        ));
+    }
+    message = message.reply_to(Address::new_list(reply_to));
+
+    // Упомянутые в Cc: "ответить всем" упомянет их в ответе
+    if config.cc_mentions {
+        let own = account_addr.to_lowercase();
+        let cc: Vec<Address> = mentions
+            .iter()
+            .filter(|(acct, _)| acct.to_lowercase() != own)
+            .map(|(_, address)| Address::new_address(None::<String>, address.clone()))
+            .collect();
+        if !cc.is_empty() {
+            message = message.cc(Address::new_list(cc));
+        }
+    }
+
+    // Метаданные поста для procmail/sieve
+    message = message
+        .header("X-Mop3-Post-Id", Raw::new(post.id.clone()))
+        .header("X-Mop3-Backend", Raw::new(post.backend.name()));
+    let metadata = [
+        ("X-Mop3-Post-Url", &original.url),
+        ("X-Mop3-Visibility", &post.visibility),
+        ("X-Mop3-Language", &original.language),
+    ];
+    if config.tags_header && !original.tags.is_empty() {
+        let tags = charset.encode_header(&original.tags.join(", "));
+        message = message.header("X-Mop3-Tags", Raw::new(tags));
+    }
+    if let Some(moved_to) = &original.author.moved_to {
+        let moved_to = full_acct(moved_to, account_addr);
+        message = message.header("X-Mop3-Moved-To", Raw::new(moved_to));
+    }
+    for (name, value) in metadata {
+        if let Some(value) = value
+            .as_deref()
+            .filter(|value| value.is_ascii() && !value.contains(['\r', '\n']))
+        {
+            message = message.header(name, Raw::new(value.to_string()));
+        }
+    }
+
+    // Остаток лимита размера письма, доступный для вложений
+    let text_size = content.len() + html_content.as_ref().map_or(0, String::len);
+    let mut size_budget = config
+        .max_message_size
+        .map(|max| max.saturating_sub(text_size));
+    let mut media_links = Vec::new();
+
+    // Обрабатываем медиа вложения; их данные подставляются при отдаче письма
+    let mut parts = Vec::new();
+    let mut streamed = Attachments::default();
+
+    // Аватар автора: заголовок Face или картинка перед остальными вложениями
+    if let Some(avatar_url) = post
+        .author
+        .avatar
+        .as_deref()
+        .filter(|_| config.avatars != AvatarMode::None)
+    {
+        if let Some(png) = avatar::fetch_avatar(avatar_url, state).await {
+            match config.avatars {
+                AvatarMode::Face => match avatar::face_header(&png) {
+                    Some(face) => message = message.header("Face", Raw::new(fold(&face))),
+                    None => debug!("Avatar {} is too large for Face header", avatar_url),
+                },
+                AvatarMode::Inline => {
+                    let encoded_size = png.len().div_ceil(3) * 4;
+                    if size_budget.is_none_or(|budget| encoded_size <= budget) {
+                        size_budget = size_budget.map(|budget| budget - encoded_size);
+                        parts.push(
+                            MimePart::new("image/png", png.to_vec())
+                                .inline()
+                                .cid("avatar.png"),
+                        );
+                    }
+                }
+                AvatarMode::None => {}
+            }
+        }
+    }
+    // Видео и аудио не прикладываются целиком: только превью и ссылка с длительностью
+    let attach = config.attachment || config.inline;
+    for attachment in attachments {
+        let label = media::link_label(&attachment);
+        let playable = attachment.kind.is_playable();
+        let url = attachment.url;
+
+        if let Some(preview_url) = attachment.preview_url.filter(|_| attach) {
+            // Медиа уже загружено в convert_posts_to_emails
+            if let Some((data, mime)) = downloads.get(&preview_url).cloned().flatten() {
+                // Вложение кодируется в base64: 4 байта на каждые 3
+                let encoded_size = data.len().div_ceil(3) * 4;
+                if let Some(budget) = size_budget.as_mut() {
+                    if encoded_size > *budget {
+                        debug!(
+                            "Skipping attachment {} ({} bytes): message size limit reached",
+                            preview_url, encoded_size
+                        );
+                        if let Some(url) = url {
+                            media_links.push((label, url));
+                        }
+                        continue;
+                    }
+                    *budget -= encoded_size;
+                }
+
+                let filename = media::file_name(&preview_url, &mime);
+                let part = MimePart::new(mime, streamed.placeholder(data));
+                if config.attachment {
+                    parts.push(part.attachment(filename));
+                } else if config.inline {
+                    parts.push(part.inline().cid(filename));
+                }
+            }
+        }
+        // Добавляем ссылку на оригинальный аттачмент
+        if let Some(url) = url.filter(|_| attach || playable) {
+            media_links.push((label, url));
+        }
+    }
+
+    // Событие Mobilizon или Friendica - приглашением для календаря
+    if config.event_invites {
+        if let Some(event) = event::fetch_event(original, state).await {
+            let content_type = ContentType::new("text/calendar")
+                .attribute("method", "PUBLISH")
+                .attribute("charset", "utf-8");
+            let ics = event::to_ics(&event, original);
+            parts.push(base64_part(content_type, ics.as_bytes()).attachment("event.ics"));
+        }
+    }
+
+    // Исходный JSON поста для диагностики конвертации
+    if let Some(raw) = post.raw.as_ref().filter(|_| config.debug) {
+        let json = serde_json::to_string_pretty(raw).unwrap_or_default();
+        info!("Post {} JSON: {}", post.id, json);
+        parts.push(
+            MimePart::new("application/json", json.into_bytes())
+                .attachment(format!("status-{}.json", post.id)),
+        );
+    }
+
+    // Подпись письма: бустеры, ссылки на медиа, URL поста и статистика
+    let footer = render_footer(post, boosted_by, &media_links, account_addr, config);
+    if !footer.is_empty() {
+        if config.html {
+            content.push_str(&footer_to_html(&footer));
+        } else {
+            content = format!("{}\n{}\n", content, footer);
+        }
+        if let Some(html) = html_content.as_mut() {
+            html.push_str(&footer_to_html(&footer));
+        }
+    }
+
+    // Добавляем тело
+    let content_type = if config.html {
+        "text/html"
+    } else {
+        if let Some(width) = config.line_width {
+            content = wrap_text(&content, width);
+        }
+        "text/plain"
+    };
+    let mut body = charset.text_part(content_type, &content, config.transfer_encoding);
+    if let Some(html) = html_content {
+        let html = charset.text_part("text/html", &html, config.transfer_encoding);
+        body = MimePart::new("multipart/alternative", vec![body, html]);
+    }
+
+    message = if parts.is_empty() {
+        message.body(body)
+    } else {
+        parts.insert(0, body);
+        message.body(MimePart::new("multipart/mixed", parts))
+    };
+
+    // Сериализуем в RFC822
+    let email_string = message
+        .write_to_string()
+        .map_err(|e| AppError::ServerError(format!("Failed to build email: {}", e)))?;
+
+    let body = Message::assemble(email_string, streamed)?;
+    let body = sign::sign(state, body)?;
+    Ok(Email::new(id, subject, body))
+}
+
+/// Собирает подпись письма по шаблону --footer. Без шаблона подпись состоит
+/// из строк хэштегов, бустеров, переезда автора и медиа, URL поста с --url
+/// и статистики с --stats.
+fn render_footer(
+    post: &NormalizedPost,
+    boosted_by: &[&str],
+    media_links: &[(String, String)],
+    account_addr: &str,
+    config: &Config,
+) -> String {
+    let original = post.reblog.as_deref().unwrap_or(post);
+    let url = original
+        .url
+        .as_deref()
+        .map(|url| proxied(url, config))
+        .unwrap_or_default();
+
+    let boosted = if boosted_by.is_empty() {
+        String::new()
+    } else if post.reblog.is_some() {
+        format!("> Also boosted by: @{}", boosted_by.join(", @"))
+    } else {
+        format!("> Boosted by: @{}", boosted_by.join(", @"))
+    };
+    let tags = if original.tags.is_empty() {
+        String::new()
+    } else {
+        let names: Vec<String> = original
+            .tags
+            .iter()
+            .map(|tag| format!("#{}", tag))
+            .collect();
+        format!("Tags: {}", names.join(" "))
+    };
+    let moved = match &original.author.moved_to {
+        Some(moved_to) => {
+            let moved_to = full_acct(moved_to, account_addr);
+            format!(
+                "> @{} moved to @{} <{}>",
+                full_acct(&original.author.acct, account_addr),
+                moved_to,
+                gateway_address(&moved_to, config)
+            )
+        }
+        None => String::new(),
+    };
+    let media = media_links
+        .iter()
+        .map(|(label, url)| format!("> {}: {}", label, proxied(url, config)))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let stats = stats_footer(original);
+
+    let Some(template) = &config.footer else {
+        let mut lines = vec![tags, boosted, moved, media];
+        if config.url && !url.is_empty() {
+            lines.push(format!("> Original: {}", url));
+        }
+        if config.stats {
+            lines.push(format!("-- \n{}", stats));
+        }
+        lines.retain(|line| !line.is_empty());
+        return lines.join("\n");
+    };
+
+    template
+        .replace("\\n", "\n")
+        .replace("{url}", &url)
+        .replace("{id}", &original.id)
+        .replace("{author}", &original.author.display_name)
+        .replace("{acct}", &original.author.acct)
+        .replace("{backend}", original.backend.name())
+        .replace("{tags}", &tags)
+        .replace("{boosted}", &boosted)
+        .replace("{moved}", &moved)
+        .replace("{media}", &media)
+        .replace("{stats}", &stats)
+        .trim_end()
+        .to_string()
+}
+
+/// Подпись для HTML части: текст экранируется, ссылки становятся активными
+fn footer_to_html(footer: &str) -> String {
+    let escaped = footer
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    let linked = match Regex::new(r"https?://[^\s<>&]+") {
+        Ok(re) => re
+            .replace_all(&escaped, |caps: &fancy_regex::Captures| {
+                format!("<a href=\"{0}\">{0}</a>", &caps[0])
+            })
+            .to_string(),
+        Err(_) => escaped,
+    };
+    format!("<p>{}</p>", linked.replace('\n', "<br>"))
+}
+
+/// Строка вида "1 reply, 2 boosts, 3 favourites, via Tusky"
+fn stats_footer(post: &NormalizedPost) -> String {
+    let count =
+        |n: u64, one: &str, many: &str| format!("{} {}", n, if n == 1 { one } else { many });
+    let mut footer = [
+        count(post.replies_count, "reply", "replies"),
+        count(post.boosts_count, "boost", "boosts"),
+        count(post.favourites_count, "favourite", "favourites"),
+    ]
+    .join(", ");
+    if let Some(application) = &post.application {
+        footer = format!("{}, via {}", footer, application);
+    }
+    footer
+}
+
+/// Message-ID письма с постом: postid@instance. Одинаков при каждом опросе,
+/// поэтому клиенты правильно собирают треды и отбрасывают повторы.
+/// Схема и порт инстанции в Message-ID недопустимы и отбрасываются.
+fn message_id(post_id: &str, account_addr: &str) -> String {
+    let instance = instance_domain(account_addr);
+    let host = instance.trim_start_matches("https://");
+    let host = host.split([':', '/']).next().unwrap_or(host);
+    format!("{}@{}", post_id, host)
+}
+
+/// Домен инстанции из адреса пользователя user@instance
+fn instance_domain(account_addr: &str) -> &str {
+    account_addr
+        .rsplit_once('@')
+        .map_or(account_addr, |(_, instance)| instance)
+}
+
+/// Формирует значение From по --from-format.
+/// Локальные аккаунты (acct без домена) дополняются доменом инстанции пользователя.
+/// Хэндл Bluesky не является почтовым адресом, поэтому для него в From
+/// всегда адрес шлюза.
+fn from_address(account: &PostAuthor, account_addr: &str, config: &Config) -> String {
+    let acct = full_acct(&account.acct, account_addr);
+    let name = if account.display_name.trim().is_empty() {
+        &account.username
+    } else {
+        &account.display_name
+    };
+
+    let charset = config.charset;
+    match config.from_format {
+        FromFormat::Acct if acct.contains('@') => charset.encode_address(name, &acct),
+        FromFormat::Acct | FromFormat::Gateway => {
+            charset.encode_address(name, &gateway_address(&acct, config))
+        }
+        FromFormat::Name => {
+            let name = charset.encode_header(name);
+            if name.starts_with("=?") {
+                format!("{}:;", name)
+            } else {
+                format!("\"{}\":;", name.replace(['"', '\\'], ""))
+            }
+        }
+    }
+}
+
+/// acct с доменом: локальные аккаунты Mastodon приходят без него. Хэндл
+/// Bluesky (alice.bsky.social) сам является доменом и остаётся как есть.
+pub(crate) fn full_acct(acct: &str, account_addr: &str) -> String {
+    if acct.contains(['@', '.']) {
+        acct.to_string()
+    } else {
+        format!("{}@{}", acct, instance_domain(account_addr))
+    }
+}
+
+/// Адрес шлюза user=instance@gateway-domain: письмо на него SMTP сервер
+/// mop3 превращает в упоминание @user@instance
+pub(crate) fn gateway_address(acct: &str, config: &Config) -> String {
+    format!("{}@{}", acct.replace('@', "="), config.gateway_domain)
+}
+
+/// Локальная часть адреса ответа на пост: reply+<id>
+const REPLY_PREFIX: &str = "reply+";
+
+/// Адрес ответа на пост reply+<id>@gateway-domain. Символы ID, которым не
+/// место в адресе (`:` в URI Bluesky), кодируются как =XX.
+pub(crate) fn reply_address(post_id: &str, config: &Config) -> String {
+    let mut local = String::from(REPLY_PREFIX);
+    for byte in post_id.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'.' | b'-' | b'_' | b'/') {
+            local.push(byte as char);
+        } else {
+            local.push_str(&format!("={:02X}", byte));
+        }
+    }
+    format!("{}@{}", local, config.gateway_domain)
+}
+
+/// ID поста из локальной части адреса ответа, обратное к `reply_address`
+pub(crate) fn reply_address_id(local: &str) -> Option<String> {
+    let prefix = local.get(..REPLY_PREFIX.len())?;
+    if !prefix.eq_ignore_ascii_case(REPLY_PREFIX) {
+        return None;
+    }
+
+    let mut bytes = Vec::new();
+    let mut rest = local[REPLY_PREFIX.len()..].bytes();
+    while let Some(byte) = rest.next() {
+        if byte == b'=' {
+            let hex = [rest.next()?, rest.next()?];
+            let hex = std::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok().filter(|id| !id.is_empty())
+}
+
+/// Заменяет упоминания @user и @user@instance в тексте на адреса шлюза,
+/// чтобы на них можно было написать из почтового клиента
+fn rewrite_mentions(text: &str, mentions: &[(String, String)]) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+
+    'scan: while let Some(start) = rest.find('@') {
+        output.push_str(&rest[..start]);
+        let candidate = &rest[start + 1..];
+        let preceded_by_word = output
+            .chars()
+            .next_back()
+            .is_some_and(|ch| ch.is_alphanumeric() || ch == '@');
+
+        if !preceded_by_word {
+            // Полная форма проверяется раньше короткой
+            for (acct, address) in mentions {
+                let username = acct.split('@').next().unwrap_or(acct);
+                for form in [acct.as_str(), username] {
+                    let Some(after) = candidate.strip_prefix(form) else {
+                        continue;
+                    };
+                    let ends_word = !after
+                        .chars()
+                        .next()
+                        .is_some_and(|ch| ch.is_alphanumeric() || ch == '_' || ch == '@');
+                    if ends_word {
+                        output.push_str(&format!("@{} <{}>", username, address));
+                        rest = after;
+                        continue 'scan;
+                    }
+                }
+            }
+        }
+
+        output.push('@');
+        rest = candidate;
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// Разбивает длинное значение заголовка пробелами, чтобы его можно было перенести
+fn fold(value: &str) -> String {
+    value
+        .as_bytes()
+        .chunks(72)
+        .map(String::from_utf8_lossy)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Конвертирует HTML в обычный текст
+pub(crate) fn html_to_text(html: &str) -> String {
+    // Простое удаление HTML тегов
+    let re = Regex::new(r"<[^>]*>").unwrap();
+    let text = re.replace_all(html, "").to_string();
+
+    // Декодируем HTML entities
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#39;", "'")
+        .replace("<br>", "\n")
+        .replace("<br/>", "\n")
+        .replace("<br />", "\n")
+        .replace("<p>", "")
+        .replace("https://", "\nhttps://")
+        .replace("#", " #")
+        .replace("</p>", "\n")
+}
+
+/// Переносит строки текста по словам, не разрывая слова длиннее ширины (например URL)
+fn wrap_text(text: &str, width: usize) -> String {
+    let mut wrapped = String::with_capacity(text.len());
+
+    for line in text.lines() {
+        let mut line_len = 0;
+
+        for word in line.split_whitespace() {
+            let word_len = word.chars().count();
+
+            if line_len > 0 && line_len + 1 + word_len > width {
+                wrapped.push('\n');
+                line_len = 0;
+            } else if line_len > 0 {
+                wrapped.push(' ');
+                line_len += 1;
+            }
+
+            wrapped.push_str(word);
+            line_len += word_len;
+        }
+
+        wrapped.push('\n');
+    }
+
+    wrapped
+}
+
+/// Ссылка из подписи письма через --proxy, если он задан
+fn proxied(url: &str, config: &Config) -> String {
+    format!("{}{}", config.proxy.as_deref().unwrap_or_default(), url)
+}
+
+/// Применяет proxy к ссылкам в тексте
+fn apply_proxy_to_links(content: &str, proxy: &str) -> String {
+    // Найти и заменить HTTP ссылки
+    match Regex::new(r"https?://[^\s\]<>]+") {
+        Ok(re) => re
+            .replace_all(content, |caps: &fancy_regex::Captures| {
+                let url = &caps[0];
+                format!("{}{}\n", proxy, url)
+            })
+            .to_string(),
+        Err(_) => content.to_string(),
+    }
+}
+
+/// Заменяет ссылки в тексте номерами [1], [2] и дописывает их списком в
+/// конце, с proxy: на узком экране длинные URL не разрывают текст.
+/// Повторная ссылка получает тот же номер.
+fn footnote_links(content: &str, proxy: &str) -> String {
+    // html_to_text ставит перед ссылкой перенос строки, он не нужен; точка
+    // или скобка после ссылки относятся к тексту
+    let Ok(re) = Regex::new(r"\n?(https?://[^\s\]<>]*[^\s\]<>.,;:!?)])") else {
+        return content.to_string();
+    };
+
+    let mut links: Vec<&str> = Vec::new();
+    let mut text = String::with_capacity(content.len());
+    let mut last = 0;
+    for caps in re.captures_iter(content).flatten() {
+        let (Some(found), Some(url)) = (caps.get(0), caps.get(1)) else {
+            continue;
+        };
+        text.push_str(&content[last..found.start()]);
+        last = found.end();
+
+        let n = match links.iter().position(|link| *link == url.as_str()) {
+            Some(n) => n + 1,
+            None => {
+                links.push(url.as_str());
+                links.len()
+            }
+        };
+        if !text.is_empty() && !text.ends_with(char::is_whitespace) {
+            text.push(' ');
+        }
+        text.push_str(&format!("[{}]", n));
+    }
+    text.push_str(&content[last..]);
+    if links.is_empty() {
+        return text;
+    }
+
+    let mut text = format!("{}\n\nLinks:\n", text.trim_end());
+    for (n, url) in links.iter().enumerate() {
+        text.push_str(&format!("[{}] {}{}\n", n + 1, proxy, url));
+    }
+    text
+}
+
+/// Парсит дату поста Mastodon/Bluesky в Unix timestamp: RFC 3339 с любым смещением
+/// и точностью долей секунды, а также дату без часового пояса (считается UTC).
+/// Нераспознанная дата заменяется текущим временем, а не 1970 годом.
+fn parse_timestamp(date_str: &str) -> i64 {
+    let date_str = date_str.trim();
+    if let Ok(dt) = DateTime::parse_from_rfc3339(date_str) {
+        return dt.timestamp();
+    }
+
+    for format in ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"] {
+        if let Ok(dt) = NaiveDateTime::parse_from_str(date_str, format) {
+            return dt.and_utc().timestamp();
+        }
+    }
+
+    warn!(
+        "Cannot parse post timestamp '{}', using current time",
+        date_str
+    );
+    Utc::now().timestamp()
+}
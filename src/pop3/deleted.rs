@@ -0,0 +1,43 @@
+use crate::error::AppResult;
+use crate::models::NormalizedPost;
+use crate::state::AppState;
+use std::sync::Mutex;
+
+/// Посты, удалённые командой DELE: аккаунт -> ID, от старых к новым
+const DELETED_TABLE: &str = "deleted";
+
+/// Сколько ID помнить: с запасом больше ленты, которую отдаёт API
+const DELETED_CAPACITY: usize = 1000;
+
+/// Список читается и перезаписывается целиком
+static DELETED_LOCK: Mutex<()> = Mutex::new(());
+
+/// Запоминает письма, удалённые в сессии ящика `account_addr`. Вызывается
+/// при QUIT, как требует RFC 1939: до него удаление можно отменить RSET.
+pub fn record(state: &AppState, account_addr: &str, ids: &[String]) -> AppResult<()> {
+    let _guard = DELETED_LOCK.lock();
+    let mut deleted: Vec<String> = state
+        .store
+        .get(DELETED_TABLE, account_addr)
+        .unwrap_or_default();
+    for id in ids {
+        if !deleted.contains(id) {
+            deleted.push(id.clone());
+        }
+    }
+    let excess = deleted.len().saturating_sub(DELETED_CAPACITY);
+    deleted.drain(..excess);
+    state.store.insert(DELETED_TABLE, account_addr, &deleted)
+}
+
+/// Убирает из загруженных постов удалённые раньше, чтобы они не
+/// возвращались в ящик при следующей загрузке
+pub fn exclude(state: &AppState, account_addr: &str, posts: &mut Vec<NormalizedPost>) {
+    let deleted: Vec<String> = state
+        .store
+        .get(DELETED_TABLE, account_addr)
+        .unwrap_or_default();
+    if !deleted.is_empty() {
+        posts.retain(|post| !deleted.contains(&post.id));
+    }
+}
@@ -0,0 +1,54 @@
+use crate::models::NormalizedPost;
+use crate::state::AppState;
+use std::sync::Mutex;
+use tracing::warn;
+
+/// Версии постов, пришедших в ящик: аккаунт -> (ID, edited_at первой
+/// загруженной версии), в порядке первой загрузки
+const VERSIONS_TABLE: &str = "versions";
+
+/// Сколько постов помнить: с запасом больше ленты, которую отдаёт API
+const VERSIONS_CAPACITY: usize = 1000;
+
+/// Список читается и перезаписывается целиком
+static VERSIONS_LOCK: Mutex<()> = Mutex::new(());
+
+/// Оставляет пометку правки только постам, которые уже приходили в ящик в
+/// другой версии. Пост, впервые загруженный после правки, приходит обычным
+/// письмом: исходной версии у клиента нет, и ответу на неё не на что
+/// ссылаться. Запоминает первую версию каждого поста, чтобы при следующих
+/// загрузках письмо оставалось тем же.
+pub fn mark(state: &AppState, account_addr: &str, posts: &mut [NormalizedPost]) {
+    let _guard = VERSIONS_LOCK.lock();
+    let mut versions: Vec<(String, Option<String>)> = state
+        .store
+        .get(VERSIONS_TABLE, account_addr)
+        .unwrap_or_default();
+    let known = versions.len();
+
+    for post in posts.iter_mut() {
+        // У буста правится исходный пост
+        let edited_at = match &mut post.reblog {
+            Some(reblog) => &mut reblog.edited_at,
+            None => &mut post.edited_at,
+        };
+        let first = match versions.iter().find(|(id, _)| *id == post.id) {
+            Some((_, first)) => first.clone(),
+            None => {
+                versions.push((post.id.clone(), edited_at.clone()));
+                edited_at.clone()
+            }
+        };
+        if *edited_at == first {
+            *edited_at = None;
+        }
+    }
+
+    if versions.len() > known {
+        let excess = versions.len().saturating_sub(VERSIONS_CAPACITY);
+        versions.drain(..excess);
+        if let Err(e) = state.store.insert(VERSIONS_TABLE, account_addr, &versions) {
+            warn!("Cannot record post versions of {}: {}", account_addr, e);
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use deunicode::{deunicode, deunicode_char};
+
+/// Модификаторы, которые не несут смысла без картинки: оттенки кожи,
+/// zero width joiner и селекторы вариантов
+const MODIFIERS: [std::ops::RangeInclusive<char>; 3] = [
+    '\u{1F3FB}'..='\u{1F3FF}',
+    '\u{200D}'..='\u{200D}',
+    '\u{FE00}'..='\u{FE0F}',
+];
+
+/// Флаги состоят из пары regional indicator символов
+const REGIONAL_INDICATORS: std::ops::RangeInclusive<char> = '\u{1F1E6}'..='\u{1F1FF}';
+
+/// Транслитерирует текст в ASCII для --ascii. Эмодзи заменяются на шорткоды
+/// вида `:thinking:`, флаги - на `:flag_ua:`. Кастомные эмодзи Mastodon уже
+/// приходят в тексте шорткодами и остаются как есть.
+pub fn to_ascii(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut plain = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        let shortcode = if REGIONAL_INDICATORS.contains(&ch) {
+            chars
+                .next_if(|next| REGIONAL_INDICATORS.contains(next))
+                .map(|next| format!(":flag_{}{}:", indicator_letter(ch), indicator_letter(next)))
+        } else if MODIFIERS.iter().any(|range| range.contains(&ch)) {
+            Some(String::new())
+        } else if is_emoji(ch) {
+            deunicode_char(ch)
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(|name| format!(":{}:", name.replace(' ', "_")))
+        } else {
+            None
+        };
+
+        match shortcode {
+            Some(shortcode) => {
+                output.push_str(&deunicode(&plain));
+                plain.clear();
+                output.push_str(&shortcode);
+            }
+            None => plain.push(ch),
+        }
+    }
+
+    output.push_str(&deunicode(&plain));
+    output
+}
+
+/// Пиктограммы и символы из блоков эмодзи Unicode
+fn is_emoji(ch: char) -> bool {
+    matches!(ch, '\u{1F000}'..='\u{1FAFF}' | '\u{2600}'..='\u{27BF}' | '\u{2B00}'..='\u{2BFF}')
+}
+
+fn indicator_letter(ch: char) -> char {
+    char::from_u32(ch as u32 - 0x1F1E6 + 'a' as u32).unwrap_or('?')
+}
@@ -0,0 +1,236 @@
+use crate::models::NormalizedPost;
+use crate::state::AppState;
+use chrono::{DateTime, Utc};
+use reqwest::header::ACCEPT;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+use tracing::debug;
+
+/// Accept для запроса объекта ActivityPub
+const ACTIVITY_JSON: &str =
+    "application/activity+json, application/ld+json; profile=\"https://www.w3.org/ns/activitystreams\"";
+
+/// Пути, по которым серверы отдают обычные заметки: Mastodon, GoToSocial,
+/// Misskey. Событий среди них не бывает, запрашивать их незачем.
+const NOTE_PATHS: [&str; 2] = ["/statuses/", "/notes/"];
+
+/// Сколько объектов держать в памяти
+const CACHE_CAPACITY: usize = 1024;
+
+/// Длина строки iCalendar в октетах, дальше она переносится
+const ICS_LINE_OCTETS: usize = 75;
+
+/// Событие ActivityPub (Mobilizon, Friendica), из которого получился пост
+#[derive(Debug)]
+pub struct Event {
+    /// ID объекта: UID события в календаре
+    pub uid: String,
+    pub name: String,
+    pub start: DateTime<Utc>,
+    pub end: Option<DateTime<Utc>>,
+    pub location: Option<String>,
+    pub url: String,
+}
+
+/// `None` - объект не событие или запрос не удался
+type CachedEvent = Option<Arc<Event>>;
+
+/// События по ID объекта; неудачи тоже кешируются, чтобы не запрашивать
+/// их при каждой проверке почты
+static CACHE: LazyLock<Mutex<HashMap<String, CachedEvent>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Событие, если пост - федеративное событие. Mastodon показывает события
+/// обычными постами, поэтому время и место берутся из исходного объекта
+/// ActivityPub на сервере автора.
+pub async fn fetch_event(post: &NormalizedPost, state: &AppState) -> CachedEvent {
+    let uri = post
+        .uri
+        .as_deref()
+        .filter(|uri| uri.starts_with("https://") || uri.starts_with("http://"))
+        .filter(|uri| !NOTE_PATHS.iter().any(|path| uri.contains(path)))?;
+    if let Some(cached) = CACHE.lock().ok()?.get(uri) {
+        return cached.clone();
+    }
+
+    let event = fetch_object(uri, state)
+        .await
+        .and_then(|object| parse_event(&object, post))
+        .map(Arc::new);
+
+    let mut cache = CACHE.lock().ok()?;
+    if cache.len() >= CACHE_CAPACITY {
+        cache.clear();
+    }
+    cache.insert(uri.to_string(), event.clone());
+    event
+}
+
+async fn fetch_object(uri: &str, state: &AppState) -> Option<Value> {
+    let response = state
+        .http_client
+        .get(uri)
+        .header(ACCEPT, ACTIVITY_JSON)
+        .timeout(Duration::from_secs(state.config.media_timeout))
+        .send()
+        .await
+        .map_err(|e| debug!("Cannot fetch ActivityPub object {}: {}", uri, e))
+        .ok()?;
+    if !response.status().is_success() {
+        // Серверы с authorized fetch отвечают 401 на запрос без подписи
+        debug!(
+            "ActivityPub object {} returned status: {}",
+            uri,
+            response.status()
+        );
+        return None;
+    }
+    response
+        .json()
+        .await
+        .map_err(|e| debug!("Cannot parse ActivityPub object {}: {}", uri, e))
+        .ok()
+}
+
+fn parse_event(object: &Value, post: &NormalizedPost) -> Option<Event> {
+    let is_event = match &object["type"] {
+        Value::String(kind) => kind == "Event",
+        Value::Array(kinds) => kinds.iter().any(|kind| kind == "Event"),
+        _ => false,
+    };
+    if !is_event {
+        return None;
+    }
+
+    let time = |field: &str| {
+        object[field]
+            .as_str()
+            .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+            .map(|time| time.with_timezone(&Utc))
+    };
+    let uid = object["id"].as_str().or(post.uri.as_deref())?.to_string();
+    let url = link(&object["url"])
+        .or(post.url.clone())
+        .unwrap_or_else(|| uid.clone());
+    Some(Event {
+        name: object["name"]
+            .as_str()
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+        start: time("startTime")?,
+        end: time("endTime"),
+        location: location(&object["location"]),
+        url,
+        uid,
+    })
+}
+
+/// `url` объекта: строка, объект Link или список из них
+fn link(url: &Value) -> Option<String> {
+    match url {
+        Value::String(url) => Some(url.clone()),
+        Value::Object(link) => link.get("href")?.as_str().map(str::to_string),
+        Value::Array(urls) => urls.iter().find_map(link),
+        _ => None,
+    }
+}
+
+/// Место события: название и адрес через запятую
+fn location(place: &Value) -> Option<String> {
+    let place = match place {
+        Value::Array(places) => places.first()?,
+        Value::String(name) => return Some(name.clone()).filter(|name| !name.is_empty()),
+        place => place,
+    };
+    let address = &place["address"];
+    let mut parts: Vec<&str> = vec![place["name"].as_str().unwrap_or_default()];
+    match address {
+        Value::String(address) => parts.push(address),
+        Value::Object(_) => parts.extend(
+            [
+                "streetAddress",
+                "postalCode",
+                "addressLocality",
+                "addressCountry",
+            ]
+            .iter()
+            .filter_map(|field| address[*field].as_str()),
+        ),
+        _ => {}
+    }
+
+    let mut seen = Vec::new();
+    for part in parts.into_iter().map(str::trim) {
+        if !part.is_empty() && !seen.contains(&part) {
+            seen.push(part);
+        }
+    }
+    Some(seen.join(", ")).filter(|location| !location.is_empty())
+}
+
+/// Файл iCalendar (RFC 5545) с одним событием
+pub fn to_ics(event: &Event, post: &NormalizedPost) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//mop3//EN".to_string(),
+        "METHOD:PUBLISH".to_string(),
+        "BEGIN:VEVENT".to_string(),
+        format!("UID:{}", event.uid),
+        format!("DTSTAMP:{}", ics_time(&parse_created(post))),
+        format!("DTSTART:{}", ics_time(&event.start)),
+    ];
+    if let Some(end) = event.end.filter(|end| *end > event.start) {
+        lines.push(format!("DTEND:{}", ics_time(&end)));
+    }
+    if !event.name.is_empty() {
+        lines.push(format!("SUMMARY:{}", ics_text(&event.name)));
+    }
+    if let Some(location) = &event.location {
+        lines.push(format!("LOCATION:{}", ics_text(location)));
+    }
+    lines.push(format!("URL:{}", event.url));
+    lines.push(format!("DESCRIPTION:{}", ics_text(&event.url)));
+    lines.push("END:VEVENT".to_string());
+    lines.push("END:VCALENDAR".to_string());
+
+    lines.iter().map(|line| fold(line)).collect()
+}
+
+fn parse_created(post: &NormalizedPost) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(&post.created_at)
+        .map(|time| time.with_timezone(&Utc))
+        .unwrap_or_else(|_| Utc::now())
+}
+
+fn ics_time(time: &DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn ics_text(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace(';', "\\;")
+        .replace('\n', "\\n")
+        .replace('\r', "")
+}
+
+/// Переносит строку по 75 октетов, не разрывая символы UTF-8
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 8);
+    let mut width = 0;
+    for ch in line.chars() {
+        if width + ch.len_utf8() > ICS_LINE_OCTETS {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(ch);
+        width += ch.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
@@ -0,0 +1,137 @@
+use crate::api::SocialNetworkApi;
+use crate::config::Config;
+use crate::models::{Credentials, NormalizedPost, PostAuthor};
+use crate::pop3::converter::html_to_text;
+use std::collections::HashSet;
+use tracing::{debug, warn};
+
+/// Отбирает посты для почтового ящика согласно --no-boosts, --no-replies
+/// и спискам muted_words / muted_accounts
+pub async fn filter_posts(
+    posts: Vec<NormalizedPost>,
+    api_client: &dyn SocialNetworkApi,
+    cred: &Credentials,
+    config: &Config,
+) -> Vec<NormalizedPost> {
+    let total = posts.len();
+    let mute = MuteList::new(config, &cred.username);
+    let mut posts: Vec<NormalizedPost> = posts
+        .into_iter()
+        .filter(|post| !(config.no_boosts && is_boost(post)))
+        .filter(|post| !mute.matches(post))
+        .collect();
+
+    if config.no_replies {
+        posts = filter_replies(posts, api_client, cred).await;
+    }
+
+    if posts.len() != total {
+        debug!("Filtered out {} of {} posts", total - posts.len(), total);
+    }
+    posts
+}
+
+fn is_boost(post: &NormalizedPost) -> bool {
+    post.reblog.is_some()
+}
+
+/// Убирает ответы людям, на которых пользователь не подписан.
+/// Ответы автора самому себе (треды) остаются.
+async fn filter_replies(
+    posts: Vec<NormalizedPost>,
+    api_client: &dyn SocialNetworkApi,
+    cred: &Credentials,
+) -> Vec<NormalizedPost> {
+    let mut reply_targets: Vec<String> = posts
+        .iter()
+        .filter_map(|post| post.reply.as_ref()?.account_id.clone())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    reply_targets.sort();
+
+    let followed = match api_client.followed_accounts(cred, &reply_targets).await {
+        Ok(followed) => followed,
+        Err(e) => {
+            warn!("Cannot fetch followed accounts, keeping replies: {}", e);
+            return posts;
+        }
+    };
+
+    posts
+        .into_iter()
+        .filter(
+            |post| match post.reply.as_ref().and_then(|r| r.account_id.as_ref()) {
+                Some(target) => *target == post.author.id || followed.contains(target),
+                None => true,
+            },
+        )
+        .collect()
+}
+
+/// Список скрытых слов и аккаунтов, приведённых к нижнему регистру
+struct MuteList {
+    words: Vec<String>,
+    accounts: Vec<String>,
+    /// Домен инстанции пользователя: локальные аккаунты приходят без него
+    domain: String,
+}
+
+impl MuteList {
+    fn new(config: &Config, username: &str) -> Self {
+        let normalize = |items: &[String]| -> Vec<String> {
+            items
+                .iter()
+                .map(|item| item.trim().trim_start_matches('@').to_lowercase())
+                .filter(|item| !item.is_empty())
+                .collect()
+        };
+
+        MuteList {
+            words: normalize(&config.muted_words),
+            accounts: normalize(&config.muted_accounts),
+            domain: username
+                .rsplit_once('@')
+                .map(|(_, domain)| domain.to_lowercase())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn matches(&self, post: &NormalizedPost) -> bool {
+        if self.words.is_empty() && self.accounts.is_empty() {
+            return false;
+        }
+
+        let original = post.reblog.as_deref().unwrap_or(post);
+        self.is_muted_account(&post.author)
+            || self.is_muted_account(&original.author)
+            || self.contains_muted_word(&html_to_text(&original.html))
+    }
+
+    /// Хэндл Bluesky (alice.bsky.social) уже полный и домена не требует
+    fn is_muted_account(&self, account: &PostAuthor) -> bool {
+        let acct = account.acct.to_lowercase();
+        let full = if acct.contains(['@', '.']) {
+            acct.clone()
+        } else {
+            format!("{}@{}", acct, self.domain)
+        };
+        self.accounts
+            .iter()
+            .any(|muted| *muted == acct || *muted == full)
+    }
+
+    fn contains_muted_word(&self, text: &str) -> bool {
+        let text = text.to_lowercase();
+        self.words.iter().any(|word| contains_word(&text, word))
+    }
+}
+
+/// Ищет вхождение `word`, окружённое не буквенно-цифровыми символами
+fn contains_word(text: &str, word: &str) -> bool {
+    text.match_indices(word).any(|(start, _)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + word.len()..].chars().next();
+        !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+    })
+}
@@ -0,0 +1,37 @@
+/// Ящик сессии POP3. По умолчанию это домашняя лента; другой ящик
+/// выбирается суффиксом логина: `user@mastodon.social+scheduled`,
+/// `user.bsky.social+discover`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Folder {
+    /// Домашняя лента
+    #[default]
+    Timeline,
+    /// Отложенные посты пользователя, ещё не опубликованные
+    Scheduled,
+    /// Сохранённая лента по имени из `SavedFeed::folder_name`
+    Feed(String),
+}
+
+impl Folder {
+    /// Отделяет суффикс ящика от логина. Всё, что не `scheduled`, считается
+    /// именем сохранённой ленты: есть ли такая, выясняется при загрузке.
+    pub fn split_login(login: &str) -> (&str, Folder) {
+        let Some((user, suffix)) = login.rsplit_once('+') else {
+            return (login, Folder::Timeline);
+        };
+        match suffix.to_lowercase().as_str() {
+            "" | "timeline" => (user, Folder::Timeline),
+            "scheduled" => (user, Folder::Scheduled),
+            feed => (user, Folder::Feed(feed.to_string())),
+        }
+    }
+
+    /// Имя ящика в суффиксе логина и ответе XSTATUS
+    pub fn name(&self) -> &str {
+        match self {
+            Folder::Timeline => "timeline",
+            Folder::Scheduled => "scheduled",
+            Folder::Feed(name) => name,
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use crate::config::Config;
+use crate::error::{AppError, AppResult};
+use crate::models::{self, Credentials, Email};
+use crate::pop3::converter::{self, Draft, Threads};
+use crate::pop3::folder::Folder;
+use crate::pop3::media;
+use crate::state::AppState;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::{LazyLock, Mutex};
+use std::time::Instant;
+use tokio::sync::OnceCell;
+use tracing::{debug, info, warn};
+
+/// Сколько размеров собранных писем помнить между сессиями
+const SIZES_CAPACITY: usize = 4096;
+
+/// Размеры уже собранных писем по ID поста: следующая сессия показывает
+/// в STAT и LIST точный размер ещё до сборки
+static SIZES: LazyLock<Mutex<HashMap<String, usize>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Ящик одной загрузки ленты. Письмо собирается при первом RETR или TOP,
+/// а до этого STAT и LIST отвечают известным или примерным размером, так
+/// что клиент, который только проверяет почту, не ждёт загрузки медиа.
+pub struct Mailbox {
+    entries: Vec<Entry>,
+    cred: Credentials,
+    account_addr: String,
+    folder: Folder,
+    threads: Threads,
+    pub fetched_at: Instant,
+}
+
+/// Письмо ящика: пост ленты или уже собранный отчёт шлюза без поста
+struct Entry {
+    draft: Option<Draft>,
+    email: OnceCell<Email>,
+}
+
+impl Entry {
+    fn id(&self) -> &str {
+        match (&self.draft, self.email.get()) {
+            (Some(draft), _) => draft.id(),
+            (None, Some(email)) => &email.id,
+            (None, None) => "",
+        }
+    }
+}
+
+impl Mailbox {
+    pub fn new(
+        drafts: Vec<Draft>,
+        cred: Credentials,
+        account_addr: String,
+        folder: Folder,
+        config: &Config,
+    ) -> Self {
+        let mut mailbox = Mailbox {
+            entries: drafts
+                .into_iter()
+                .map(|draft| Entry {
+                    draft: Some(draft),
+                    email: OnceCell::new(),
+                })
+                .collect(),
+            cred,
+            account_addr,
+            folder,
+            threads: Threads::default(),
+            fetched_at: Instant::now(),
+        };
+
+        if let Some(max) = config.max_mailbox_size {
+            mailbox.trim(max, config);
+        }
+        mailbox
+    }
+
+    /// Кладёт готовое письмо первым, как самое новое
+    pub fn prepend(&mut self, email: Email) {
+        self.entries.insert(
+            0,
+            Entry {
+                draft: None,
+                email: OnceCell::new_with(Some(email)),
+            },
+        );
+    }
+
+    /// Отбрасывает самые старые посты, пока ящик больше `max` байт.
+    /// Самое новое письмо остаётся всегда: его размер ограничивает
+    /// --max-message-size.
+    fn trim(&mut self, max: usize, config: &Config) {
+        let mut total = 0;
+        let keep = (0..self.len())
+            .take_while(|&index| {
+                total += self.size(index, config);
+                index == 0 || total <= max
+            })
+            .count();
+
+        if keep < self.len() {
+            info!(
+                "Mailbox exceeds --max-mailbox-size {}: dropping {} oldest posts",
+                max,
+                self.len() - keep
+            );
+            self.entries.truncate(keep);
+        }
+    }
+
+    /// Собирает все письма сразу, загружая медиа всех постов параллельно.
+    /// Письма, которые собрать не удалось, соберутся повторно при RETR;
+    /// уже собранные не трогаются.
+    pub async fn build_all(&self, state: &AppState) {
+        let config = &state.config;
+        let downloads = if config.attachment || config.inline {
+            let urls: HashSet<String> = self
+                .entries
+                .iter()
+                .filter(|entry| entry.email.get().is_none())
+                .flat_map(|entry| entry.draft.iter().flat_map(Draft::preview_urls))
+                .collect();
+            media::download_all(urls, state).await
+        } else {
+            media::Downloads::new()
+        };
+
+        for entry in &self.entries {
+            if let Err(e) = self.build(entry, Some(&downloads), state).await {
+                warn!("Cannot build message for {}: {}", entry.id(), e);
+            }
+        }
+    }
+
+    /// Учётные данные, с которыми загружена лента
+    pub fn cred(&self) -> &Credentials {
+        &self.cred
+    }
+
+    /// Адрес аккаунта, которому принадлежит ящик
+    pub fn account_addr(&self) -> &str {
+        &self.account_addr
+    }
+
+    pub fn folder(&self) -> &Folder {
+        &self.folder
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Размер письма `index` (с 0): точный, если письмо уже собрано
+    pub fn size(&self, index: usize, config: &Config) -> usize {
+        let entry = &self.entries[index];
+        if let Some(email) = entry.email.get() {
+            return email.size;
+        }
+        SIZES
+            .lock()
+            .ok()
+            .and_then(|sizes| sizes.get(entry.id()).copied())
+            .or_else(|| {
+                entry
+                    .draft
+                    .as_ref()
+                    .map(|draft| draft.estimated_size(config))
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn total_size(&self, config: &Config) -> usize {
+        (0..self.len()).map(|index| self.size(index, config)).sum()
+    }
+
+    /// ID поста или служебного письма `index` (с 0)
+    pub fn id(&self, index: usize) -> &str {
+        self.entries[index].id()
+    }
+
+    /// UIDL письма `index` (с 0); письмо для этого не собирается
+    pub fn uidl(&self, index: usize) -> Cow<'_, str> {
+        let entry = &self.entries[index];
+        match (entry.email.get(), &entry.draft) {
+            (Some(email), _) => Cow::Borrowed(email.uidl.as_str()),
+            (None, Some(draft)) => Cow::Owned(models::uidl(&draft.email_id()).into_owned()),
+            (None, None) => models::uidl(entry.id()),
+        }
+    }
+
+    /// Письмо `index` (с 0), при первом обращении собирается
+    pub async fn email(&self, index: usize, state: &AppState) -> AppResult<&Email> {
+        self.build(&self.entries[index], None, state).await
+    }
+
+    async fn build<'a>(
+        &self,
+        entry: &'a Entry,
+        downloads: Option<&media::Downloads>,
+        state: &AppState,
+    ) -> AppResult<&'a Email> {
+        entry
+            .email
+            .get_or_try_init(|| async {
+                // Письма без поста кладутся в ящик уже собранными
+                let draft = entry.draft.as_ref().ok_or_else(|| {
+                    AppError::ServerError(format!("Message {} has no post", entry.id()))
+                })?;
+                debug!("Building message for post {}", draft.id());
+                let email = converter::convert_draft(
+                    draft,
+                    downloads,
+                    &self.threads,
+                    state,
+                    &self.cred,
+                    &self.account_addr,
+                )
+                .await?;
+
+                if let Ok(mut sizes) = SIZES.lock() {
+                    if sizes.len() >= SIZES_CAPACITY {
+                        sizes.clear();
+                    }
+                    sizes.insert(draft.id().to_string(), email.size);
+                }
+                Ok(email)
+            })
+            .await
+    }
+}
@@ -0,0 +1,340 @@
+use crate::config::{Config, ImageTranscode};
+use crate::error::{AppError, AppResult};
+use crate::models::{MediaAttachment, MediaType};
+use crate::pop3::cache::Validators;
+use crate::state::AppState;
+use futures_util::{stream, StreamExt};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use reqwest::{Response, StatusCode};
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Качество JPEG при перекодировании
+const JPEG_QUALITY: u8 = 85;
+
+/// Ниже этого качества JPEG не пережимается - вместо этого уменьшается размер
+const MIN_JPEG_QUALITY: u8 = 40;
+
+/// Меньше этой стороны изображение не уменьшается даже ради лимита байт
+const MIN_IMAGE_DIMENSION: u32 = 64;
+
+/// Форматы, которые показывают старые клиенты; их не перекодируем
+const LEGACY_IMAGE_TYPES: [&str; 3] = ["image/jpeg", "image/png", "image/gif"];
+
+/// Сколько вложений загружать одновременно
+const CONCURRENT_DOWNLOADS: usize = 8;
+
+/// Загруженные и подготовленные вложения по URL; `None` - загрузка не удалась
+pub type Downloads = HashMap<String, Option<(Arc<Vec<u8>>, String)>>;
+
+/// Загружает и подготавливает вложения всех постов параллельно, не более
+/// CONCURRENT_DOWNLOADS одновременно. Каждая загрузка ограничена
+/// --media-timeout, так что одно зависшее медиа не задерживает весь ящик.
+pub async fn download_all(urls: impl IntoIterator<Item = String>, state: &AppState) -> Downloads {
+    let config = &state.config;
+    let timeout = Duration::from_secs(config.media_timeout);
+
+    stream::iter(urls)
+        .map(|url| async move {
+            let media = match tokio::time::timeout(timeout, download_media(&url, state)).await {
+                Ok(Ok((data, mime))) => adapt_image(data, mime, config).await,
+                Ok(Err(e)) => {
+                    debug!("Cannot download media {}: {}", url, e);
+                    None
+                }
+                Err(_) => {
+                    warn!("Media download timed out after {:?}: {}", timeout, url);
+                    None
+                }
+            };
+            (url, media.map(|(data, mime)| (Arc::new(data), mime)))
+        })
+        .buffer_unordered(CONCURRENT_DOWNLOADS)
+        .collect()
+        .await
+}
+
+/// Загружает медиа файл по URL общим HTTP клиентом, с --media-cache - через
+/// дисковый кеш. Устаревшее медиа из кеша перепроверяется условным запросом.
+pub async fn download_media(url: &str, state: &AppState) -> AppResult<(Vec<u8>, String)> {
+    let cached = match &state.media_cache {
+        Some(cache) => cache.get(url).await,
+        None => None,
+    };
+
+    let Some(cached) = cached else {
+        let response = state.http_client.get(url).send().await?;
+        return receive(url, response, state).await;
+    };
+    // Лимит мог появиться уже после того, как медиа попало в кеш
+    if let Some(max) = state
+        .config
+        .max_attachment_size
+        .filter(|max| cached.data.len() > *max)
+    {
+        return Err(AppError::Media(format!(
+            "Cached media {} exceeds --max-attachment-size {}",
+            url, max
+        )));
+    }
+    if cached.validators.is_fresh() {
+        return Ok((cached.data, cached.mime));
+    }
+
+    let response = cached
+        .validators
+        .apply(state.http_client.get(url))
+        .send()
+        .await?;
+    if response.status() != StatusCode::NOT_MODIFIED {
+        return receive(url, response, state).await;
+    }
+
+    debug!("Media not modified: {}", url);
+    let validators = cached
+        .validators
+        .refresh(Validators::from_headers(response.headers()));
+    if validators != cached.validators {
+        if let Some(cache) = &state.media_cache {
+            cache
+                .insert(url, &cached.data, &cached.mime, &validators)
+                .await;
+        }
+    }
+    Ok((cached.data, cached.mime))
+}
+
+/// Читает полный ответ и сохраняет медиа в кеш вместе с валидаторами
+async fn receive(url: &str, response: Response, state: &AppState) -> AppResult<(Vec<u8>, String)> {
+    if !response.status().is_success() {
+        return Err(AppError::Media(format!(
+            "Failed to download media: {}",
+            response.status()
+        )));
+    }
+
+    let mime = response
+        .headers()
+        .get("Content-Type")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("image/jpeg")
+        .to_string();
+    let validators = Validators::from_headers(response.headers());
+    let data = read_limited(url, response, state.config.max_attachment_size).await?;
+
+    if let Some(cache) = &state.media_cache {
+        cache.insert(url, &data, &mime, &validators).await;
+    }
+    Ok((data, mime))
+}
+
+/// Читает тело ответа, прерывая загрузку сверх --max-attachment-size,
+/// чтобы огромное медиа не заняло память целиком
+async fn read_limited(url: &str, mut response: Response, max: Option<usize>) -> AppResult<Vec<u8>> {
+    let Some(max) = max else {
+        return Ok(response.bytes().await?.to_vec());
+    };
+
+    let too_large = || {
+        AppError::Media(format!(
+            "Media {} exceeds --max-attachment-size {}",
+            url, max
+        ))
+    };
+    if response
+        .content_length()
+        .is_some_and(|length| length > max as u64)
+    {
+        return Err(too_large());
+    }
+
+    let mut data = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if data.len() + chunk.len() > max {
+            return Err(too_large());
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+/// Приводит изображение к виду, понятному и посильному старым клиентам:
+/// перекодирует по --transcode-images, уменьшает по --max-image-dimension и
+/// пережимает по --max-image-bytes. Работа с пикселями идёт в blocking пуле.
+/// `None` - обработка аварийно завершилась и вложение нужно пропустить.
+pub async fn adapt_image(
+    data: Vec<u8>,
+    mime: String,
+    config: &Arc<Config>,
+) -> Option<(Vec<u8>, String)> {
+    let config = Arc::clone(config);
+    tokio::task::spawn_blocking(move || adapt_image_blocking(data, mime, &config))
+        .await
+        .map_err(|e| warn!("Image processing failed: {}", e))
+        .ok()
+}
+
+/// Декодеры собраны только для JPEG, PNG, GIF и WebP; остальные форматы
+/// (AVIF, HEIC) и изображения, которые не удалось декодировать, остаются
+/// как есть
+fn adapt_image_blocking(data: Vec<u8>, mime: String, config: &Config) -> (Vec<u8>, String) {
+    let essence = mime.split(';').next().unwrap_or_default().trim();
+    if !essence.starts_with("image/") {
+        return (data, mime);
+    }
+
+    let decodable = image::guess_format(&data).is_ok_and(|f| f.reading_enabled());
+    let transcode = config
+        .transcode_images
+        .filter(|_| !LEGACY_IMAGE_TYPES.contains(&essence));
+    if !decodable {
+        if transcode.is_some() {
+            debug!("No decoder for {} in this build, attaching as is", mime);
+        }
+        return (data, mime);
+    }
+    let too_heavy = config.max_image_bytes.is_some_and(|max| data.len() > max);
+    if transcode.is_none() && !too_heavy && config.max_image_dimension.is_none() {
+        return (data, mime);
+    }
+
+    let mut image = match image::load_from_memory(&data) {
+        Ok(image) => image,
+        Err(e) => {
+            debug!("Cannot decode {} for processing: {}", mime, e);
+            return (data, mime);
+        }
+    };
+
+    let too_large = config
+        .max_image_dimension
+        .is_some_and(|max| image.width() > max || image.height() > max);
+    if transcode.is_none() && !too_heavy && !too_large {
+        return (data, mime);
+    }
+
+    if let (true, Some(max)) = (too_large, config.max_image_dimension) {
+        image = image.resize(max, max, FilterType::Triangle);
+    }
+
+    // Без явного формата сохраняем исходный: JPEG остаётся JPEG, PNG и GIF - PNG
+    let mut target = transcode.unwrap_or(match essence {
+        "image/png" | "image/gif" => ImageTranscode::Png,
+        _ => ImageTranscode::Jpeg,
+    });
+    let mut quality = JPEG_QUALITY;
+    let Some(mut encoded) = encode(&image, target, quality) else {
+        return (data, mime);
+    };
+
+    // Лимит байт: PNG заменяется на JPEG, затем снижается качество, затем размер
+    if let Some(max) = config.max_image_bytes {
+        while encoded.len() > max {
+            if target == ImageTranscode::Png {
+                target = ImageTranscode::Jpeg;
+            } else if quality > MIN_JPEG_QUALITY {
+                quality = quality.saturating_sub(15).max(MIN_JPEG_QUALITY);
+            } else if image.width().max(image.height()) > MIN_IMAGE_DIMENSION {
+                image = image.resize(
+                    image.width() * 3 / 4,
+                    image.height() * 3 / 4,
+                    FilterType::Triangle,
+                );
+            } else {
+                break;
+            }
+
+            match encode(&image, target, quality) {
+                Some(smaller) => encoded = smaller,
+                None => break,
+            }
+        }
+    }
+
+    debug!(
+        "Processed {} ({} bytes) into {:?} {}x{} ({} bytes)",
+        mime,
+        data.len(),
+        target,
+        image.width(),
+        image.height(),
+        encoded.len()
+    );
+    (encoded, target.mime().to_string())
+}
+
+/// Подпись ссылки на оригинал вложения: "Fullsize" для картинок,
+/// "Video 1:23", "Audio 3:05:10" для видео и аудио
+pub fn link_label(attachment: &MediaAttachment) -> String {
+    let kind = match attachment.kind {
+        MediaType::Video => "Video",
+        MediaType::Gifv => "Animation",
+        MediaType::Audio => "Audio",
+        MediaType::Image | MediaType::Unknown => return "Fullsize".to_string(),
+    };
+
+    let duration = attachment.meta.original.as_ref().and_then(|o| o.duration);
+    match duration {
+        Some(duration) if duration > 0.0 => format!("{} {}", kind, format_duration(duration)),
+        _ => kind.to_string(),
+    }
+}
+
+/// Длительность в виде m:ss или h:mm:ss
+fn format_duration(seconds: f64) -> String {
+    let total = seconds.round() as u64;
+    let (hours, minutes, seconds) = (total / 3600, total / 60 % 60, total % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+/// Имя файла вложения из URL с расширением, соответствующим MIME типу
+pub fn file_name(url: &str, mime: &str) -> String {
+    let name = url
+        .split(['?', '#'])
+        .next()
+        .and_then(|path| path.split('/').next_back())
+        .filter(|name| !name.is_empty())
+        .unwrap_or("image.jpg");
+
+    let extension = match mime {
+        "image/jpeg" => "jpg",
+        "image/png" => "png",
+        _ => return name.to_string(),
+    };
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    format!("{}.{}", stem, extension)
+}
+
+fn encode(image: &DynamicImage, target: ImageTranscode, quality: u8) -> Option<Vec<u8>> {
+    let mut output = Vec::new();
+    let result = match target {
+        // JPEG не поддерживает прозрачность
+        ImageTranscode::Jpeg => {
+            JpegEncoder::new_with_quality(&mut output, quality).encode_image(&image.to_rgb8())
+        }
+        ImageTranscode::Png => image.write_to(&mut Cursor::new(&mut output), ImageFormat::Png),
+    };
+
+    result
+        .map_err(|e| debug!("Cannot encode image as {:?}: {}", target, e))
+        .ok()
+        .map(|_| output)
+}
+
+impl ImageTranscode {
+    fn mime(self) -> &'static str {
+        match self {
+            ImageTranscode::Jpeg => "image/jpeg",
+            ImageTranscode::Png => "image/png",
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use crate::error::{AppError, AppResult};
+use mail_builder::encoders::Base64Encoder;
+use std::borrow::Cow;
+use std::sync::Arc;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// Сырые байты одной строки base64 (76 символов, как у mail-builder)
+const LINE_BYTES: usize = 57;
+
+/// Сколько строк base64 кодируется за одну запись в сокет (~14 КиБ данных)
+const CHUNK_LINES: usize = 256;
+
+/// Письмо для отдачи по POP3. Вложения хранятся в исходном виде и кодируются
+/// в base64 только при отдаче, кусками: в памяти нет закодированной копии,
+/// которая на треть больше самого вложения. Текст хранится со строками через
+/// CRLF, как он уходит клиенту.
+pub struct Message {
+    segments: Vec<Segment>,
+    size: usize,
+}
+
+enum Segment {
+    Text(String),
+    Base64(Arc<Vec<u8>>),
+}
+
+/// Вложения письма, которые при сборке заменяются метками
+#[derive(Default)]
+pub struct Attachments {
+    placeholders: Vec<(Vec<u8>, Arc<Vec<u8>>)>,
+}
+
+impl Attachments {
+    /// Возвращает метку, которую нужно передать в mail-builder вместо данных
+    pub fn placeholder(&mut self, data: Arc<Vec<u8>>) -> Vec<u8> {
+        let marker = format!(
+            "\0mop3-attachment-{}-{:p}\0",
+            self.placeholders.len(),
+            Arc::as_ptr(&data)
+        )
+        .into_bytes();
+        self.placeholders.push((marker.clone(), data));
+        marker
+    }
+}
+
+impl Message {
+    /// Разбивает сериализованное письмо по base64 меткам вложений
+    pub fn assemble(text: String, attachments: Attachments) -> AppResult<Self> {
+        let mut placed = Vec::with_capacity(attachments.placeholders.len());
+        for (marker, data) in attachments.placeholders {
+            let mut encoded = Vec::new();
+            Base64Encoder::new()
+                .wrap_lines()
+                .encode_to_writer(&marker, &mut encoded)?;
+            let encoded = String::from_utf8_lossy(&encoded).into_owned();
+            let start = text.find(&encoded).ok_or_else(|| {
+                AppError::ServerError("Attachment placeholder not found in message".to_string())
+            })?;
+            placed.push((start, start + encoded.len(), data));
+        }
+        placed.sort_by_key(|(start, _, _)| *start);
+
+        let mut segments = Vec::with_capacity(placed.len() * 2 + 1);
+        let mut pos = 0;
+        for (start, end, data) in placed {
+            segments.push(Segment::Text(to_crlf(&text[pos..start])));
+            segments.push(Segment::Base64(data));
+            pos = end;
+        }
+        // Ответ RETR завершается строкой ".", поэтому письмо должно
+        // заканчиваться переводом строки
+        let mut last = to_crlf(&text[pos..]);
+        if !last.is_empty() && !last.ends_with("\r\n") {
+            last.push_str("\r\n");
+        }
+        segments.push(Segment::Text(last));
+
+        // Размер считается так, как письмо уходит по RETR: с удвоенными
+        // точками в начале строк. Строки base64 с точки не начинаются.
+        let size = segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Text(text) => text.len() + stuffed_dots(text),
+                Segment::Base64(data) => encoded_size(data.len()),
+            })
+            .sum();
+        Ok(Message { segments, size })
+    }
+
+    /// Размер письма в октетах, как его передаёт RETR, без завершающей
+    /// строки "."
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Записывает письмо как есть, кодируя вложения по ходу записи
+    pub async fn write_to<W: AsyncWrite + Unpin>(&self, writer: &mut W) -> AppResult<()> {
+        self.write(writer, false).await
+    }
+
+    /// Отправляет письмо в ответ на RETR: точка в начале строки удваивается
+    /// (RFC 1939), ровно [`Message::size`] октетов
+    pub async fn write_stuffed<W: AsyncWrite + Unpin>(&self, writer: &mut W) -> AppResult<()> {
+        self.write(writer, true).await
+    }
+
+    async fn write<W: AsyncWrite + Unpin>(&self, writer: &mut W, stuff: bool) -> AppResult<()> {
+        let mut buf = Vec::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) if stuff => {
+                    writer.write_all(stuff_dots(text).as_bytes()).await?
+                }
+                Segment::Text(text) => writer.write_all(text.as_bytes()).await?,
+                Segment::Base64(data) => {
+                    // Куски кратны строке, поэтому переносы совпадают с кодированием целиком
+                    for chunk in data.chunks(LINE_BYTES * CHUNK_LINES) {
+                        buf.clear();
+                        Base64Encoder::new()
+                            .wrap_lines()
+                            .encode_to_writer(chunk, &mut buf)?;
+                        writer.write_all(&buf).await?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Письмо целиком, как его записывает [`Message::write_to`]
+    pub fn to_bytes(&self) -> AppResult<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(self.size);
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => bytes.extend_from_slice(text.as_bytes()),
+                Segment::Base64(data) => {
+                    Base64Encoder::new()
+                        .wrap_lines()
+                        .encode_to_writer(data, &mut bytes)?;
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Строки письма без переводов строк; строки вложений кодируются по запросу
+    pub fn lines(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        self.segments
+            .iter()
+            .flat_map(|segment| -> Box<dyn Iterator<Item = Cow<'_, str>>> {
+                match segment {
+                    Segment::Text(text) => Box::new(text.lines().map(Cow::Borrowed)),
+                    Segment::Base64(data) => Box::new(data.chunks(LINE_BYTES).map(|line| {
+                        // Запись в Vec не завершается ошибкой
+                        let encoded = Base64Encoder::new().encode(line).unwrap_or_default();
+                        Cow::Owned(String::from_utf8_lossy(&encoded).into_owned())
+                    })),
+                }
+            })
+    }
+}
+
+/// Переводы строк в CRLF, в том числе одиночные LF из текста постов
+fn to_crlf(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\n', "\r\n")
+}
+
+/// Сколько строк текста начинается с точки. Сегмент всегда начинается с
+/// начала строки: вложения вставляются после пустой строки заголовков.
+fn stuffed_dots(text: &str) -> usize {
+    usize::from(text.starts_with('.')) + text.matches("\r\n.").count()
+}
+
+fn stuff_dots(text: &str) -> Cow<'_, str> {
+    if stuffed_dots(text) == 0 {
+        return Cow::Borrowed(text);
+    }
+    let stuffed = text.replace("\r\n.", "\r\n..");
+    if stuffed.starts_with('.') {
+        Cow::Owned(format!(".{}", stuffed))
+    } else {
+        Cow::Owned(stuffed)
+    }
+}
+
+/// Размер base64 с переносом строк по 76 символов и CRLF в конце
+fn encoded_size(len: usize) -> usize {
+    let full_lines = len / LINE_BYTES;
+    let last_line = match len % LINE_BYTES {
+        0 => 0,
+        rest => rest.div_ceil(3) * 4 + 2,
+    };
+    full_lines * 78 + last_line
+}
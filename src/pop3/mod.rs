@@ -1 +1,17 @@
+pub mod avatar;
+pub mod cache;
+pub mod charset;
+pub mod converter;
+pub mod deleted;
+pub mod edited;
+pub mod emoji;
+pub mod event;
+pub mod filter;
+pub mod folder;
+pub mod mailbox;
+pub mod media;
+pub mod message;
+pub mod moved;
+pub mod prefetch;
 pub mod server;
+pub mod translate;
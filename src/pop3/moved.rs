@@ -0,0 +1,110 @@
+use crate::error::{AppError, AppResult};
+use crate::models::{Email, NormalizedPost, PostAuthor};
+use crate::pop3::converter::{full_acct, gateway_address};
+use crate::pop3::message::{Attachments, Message};
+use crate::sign;
+use crate::state::AppState;
+use chrono::Utc;
+use mail_builder::headers::raw::Raw;
+use mail_builder::MessageBuilder;
+use std::collections::HashSet;
+use tracing::{info, warn};
+
+/// О каких переездах ящик уже знает: "ящик старый-acct" -> новый acct
+const NOTICES_TABLE: &str = "moved_notices";
+
+/// Письма о переехавших авторах ленты, о которых ящику `account_addr` ещё
+/// не сообщалось (--moved-notices). Автор буста учитывается вместе с бустером.
+pub fn due_notices(state: &AppState, account_addr: &str, posts: &[NormalizedPost]) -> Vec<Email> {
+    if !state.config.moved_notices {
+        return Vec::new();
+    }
+
+    let mut seen = HashSet::new();
+    let mut notices = Vec::new();
+    let authors = posts.iter().flat_map(|post| {
+        std::iter::once(&post.author).chain(post.reblog.as_ref().map(|r| &r.author))
+    });
+    for author in authors {
+        let Some(moved_to) = &author.moved_to else {
+            continue;
+        };
+        let old = full_acct(&author.acct, account_addr);
+        let moved_to = full_acct(moved_to, account_addr);
+        let key = format!("{} {}", account_addr, old);
+        if !seen.insert(old.clone())
+            || state.store.get::<String>(NOTICES_TABLE, &key).as_ref() == Some(&moved_to)
+        {
+            continue;
+        }
+
+        match build_notice(state, account_addr, author, &old, &moved_to) {
+            Ok(notice) => {
+                // Без записи письмо приходило бы при каждой загрузке ленты
+                if let Err(e) = state.store.insert(NOTICES_TABLE, &key, &moved_to) {
+                    warn!("Cannot record moved account notice for {}: {}", old, e);
+                    continue;
+                }
+                info!("Delivering moved account notice: {} -> {}", old, moved_to);
+                notices.push(notice);
+            }
+            Err(e) => warn!("Cannot build moved account notice for {}: {}", old, e),
+        }
+    }
+    notices
+}
+
+fn build_notice(
+    state: &AppState,
+    account_addr: &str,
+    author: &PostAuthor,
+    old: &str,
+    moved_to: &str,
+) -> AppResult<Email> {
+    let config = &state.config;
+    let charset = config.charset;
+    let name = if author.display_name.trim().is_empty() {
+        &author.username
+    } else {
+        &author.display_name
+    };
+
+    let text = format!(
+        "Аккаунт {} (@{}) переехал на @{}.\n\
+         \n\
+         Посты этого автора в ленте помечены строкой \"moved to\". Подписчики\n\
+         обычно переносятся на новый аккаунт автоматически, но списки, фильтры\n\
+         и адресная книга - нет.\n\
+         \n\
+         Адрес шлюза нового аккаунта: {}\n",
+        name,
+        old,
+        moved_to,
+        gateway_address(moved_to, config),
+    );
+
+    let now = Utc::now();
+    let subject = format!("mop3: @{} переехал на @{}", old, moved_to);
+    let gateway = format!("mop3@{}", config.gateway_domain);
+    let id = format!("moved-{}", old.replace('@', "="));
+    let mut message = MessageBuilder::new()
+        .to(account_addr)
+        .date(now.timestamp())
+        .message_id(format!("{}@{}", id, config.gateway_domain))
+        .header("From", Raw::new(charset.encode_address("mop3", &gateway)))
+        .header("X-Mop3-Moved-To", Raw::new(moved_to.to_string()));
+    if charset.is_utf8() {
+        message = message.subject(subject.as_str());
+    } else {
+        message = message.header("Subject", Raw::new(charset.encode_header(&subject)));
+    }
+
+    let email_string = message
+        .body(charset.text_part("text/plain", &text, config.transfer_encoding))
+        .write_to_string()
+        .map_err(|e| AppError::ServerError(format!("Failed to build email: {}", e)))?;
+
+    let body = Message::assemble(email_string, Attachments::default())?;
+    let body = sign::sign(state, body)?;
+    Ok(Email::new(id, subject, body))
+}
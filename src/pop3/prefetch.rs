@@ -0,0 +1,128 @@
+use crate::error::AppResult;
+use crate::models::Credentials;
+use crate::pop3::folder::Folder;
+use crate::pop3::mailbox::Mailbox;
+use crate::pop3::server;
+use crate::state::{AppState, SharedState};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Последний загруженный ящик; живёт в AppState и сбрасывается при перезагрузке
+pub type MailboxCache = Mutex<Option<Arc<Mailbox>>>;
+
+/// Запускает фоновое обновление ленты для аккаунта из конфигурации.
+/// Интервал берётся из --prefetch текущего состояния: после SIGHUP ящик
+/// собирается заново сразу, а без --prefetch задача просто ждёт перезагрузки.
+/// Если API попросил подождать дольше интервала (Retry-After), следующая
+/// попытка откладывается до этого срока.
+pub fn spawn_prefetch(mut shared_state: SharedState) {
+    tokio::spawn(async move {
+        loop {
+            let state = Arc::clone(&shared_state.borrow_and_update());
+
+            if let Some(interval) = state.config.prefetch {
+                let mut delay = Duration::from_secs(interval);
+                match refresh(&state).await {
+                    Ok(mailbox) => {
+                        store(&state, mailbox);
+                    }
+                    Err(e) => {
+                        warn!("Timeline prefetch failed: {}", e);
+                        delay = delay.max(e.retry_after().unwrap_or_default());
+                    }
+                }
+
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => continue,
+                    changed = shared_state.changed() => if changed.is_err() { return },
+                }
+            } else if shared_state.changed().await.is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/// Прогретый при запуске ящик старше этого уже не отдаётся
+const WARM_CACHE_TTL: Duration = Duration::from_secs(600);
+
+/// Собирает ящик один раз после запуска (--warm-cache). С --prefetch это
+/// делает фоновая задача, поэтому прогрев пропускается.
+pub fn spawn_warm_cache(state: Arc<AppState>) {
+    if !state.config.warm_cache || state.config.prefetch.is_some() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        match refresh(&state).await {
+            Ok(mailbox) => {
+                if let Ok(mut cache) = state.mailbox.lock() {
+                    *cache = Some(Arc::new(mailbox));
+                }
+            }
+            Err(e) => warn!("Cache warm-up failed: {}", e),
+        }
+    });
+}
+
+/// Готовый ящик, если фоновая задача уже успела его собрать. Прогретый
+/// при запуске ящик без --prefetch достаётся только первой сессии.
+pub fn cached(state: &AppState) -> Option<Arc<Mailbox>> {
+    let mailbox = {
+        let mut cache = state.mailbox.lock().ok()?;
+        if state.config.prefetch.is_some() {
+            cache.clone()?
+        } else {
+            cache
+                .take()
+                .filter(|mailbox| mailbox.fetched_at.elapsed() < WARM_CACHE_TTL)?
+        }
+    };
+    debug!(
+        "Serving {} prefetched messages from {}s ago",
+        mailbox.len(),
+        mailbox.fetched_at.elapsed().as_secs()
+    );
+    Some(mailbox)
+}
+
+/// Сохраняет ящик, если включён --prefetch; так сессия до первой фоновой
+/// загрузки тоже наполняет ящик, а собранные ей письма не собираются снова
+pub fn store(state: &AppState, mailbox: Mailbox) -> Arc<Mailbox> {
+    let mailbox = Arc::new(mailbox);
+    if state.config.prefetch.is_some() {
+        if let Ok(mut cache) = state.mailbox.lock() {
+            *cache = Some(Arc::clone(&mailbox));
+        }
+    }
+    mailbox
+}
+
+/// Сбрасывает ящик фоновой загрузки, если это `mailbox`: после DELE
+/// следующая сессия загрузит ленту заново, уже без удалённых писем
+pub fn forget(state: &AppState, mailbox: &Arc<Mailbox>) {
+    if let Ok(mut cache) = state.mailbox.lock() {
+        if cache
+            .as_ref()
+            .is_some_and(|cached| Arc::ptr_eq(cached, mailbox))
+        {
+            *cache = None;
+        }
+    }
+}
+
+async fn refresh(state: &AppState) -> AppResult<Mailbox> {
+    let config = &state.config;
+    let cred = Credentials {
+        username: config.account.clone().unwrap_or_default(),
+        password: config.token.clone().unwrap_or_default(),
+    };
+
+    let account_addr = state.api_client.verify_credentials(&cred).await?;
+    let mailbox = server::fetch_mailbox(state, &cred, &account_addr, &Folder::Timeline).await?;
+    mailbox.build_all(state).await;
+
+    info!("Prefetched {} messages for {}", mailbox.len(), account_addr);
+    Ok(mailbox)
+}
@@ -1,39 +1,247 @@
-use crate::api;
+use crate::acme::Acme;
+use crate::activity::{self, Counter};
+use crate::audit::{self, LoginResult};
 use crate::config::Config;
-use crate::error::AppResult;
-use crate::models::{Credentials, Post};
-use chrono::{DateTime, NaiveDateTime, Utc};
-use deunicode::deunicode;
-use fancy_regex::Regex;
-use mail_builder::MessageBuilder;
-use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use crate::error::{AppError, AppResult};
+use crate::lockout;
+use crate::logging;
+use crate::models::{Credentials, Secret};
+use crate::net::{self, Throttled};
+use crate::outbox;
+use crate::password;
+use crate::pop3::deleted;
+use crate::pop3::edited;
+use crate::pop3::folder::Folder;
+use crate::pop3::mailbox::Mailbox;
+use crate::pop3::moved;
+use crate::pop3::prefetch;
+use crate::pop3::{converter, filter};
+use crate::state::{AppState, SharedState};
+use crate::webhook;
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::net::{TcpListener, TcpStream};
-use tracing::{debug, error, info, warn};
+use tokio::task::JoinSet;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error, field, info, info_span, warn, Instrument, Span};
+use zeroize::Zeroizing;
 
 const POP3_BANNER: &[u8] = b"+OK MOP3 ready\r\n";
 const POP3_OK_MESSAGES_FETCHED: &[u8] = b"+OK MOP3 READY, MESSAGES FETCHED\r\n";
+/// RESP-CODES и AUTH-RESP-CODE: ответы -ERR несут коды [AUTH], [IN-USE] и [SYS/...]
+/// PIPELINING: команды читаются построчно, клиент может слать их пачкой
+/// XREFRESH и XSTATUS - собственные команды mop3
+const POP3_CAPABILITIES: &[u8] = b"+OK Capability list follows\r\n\
+    USER\r\nTOP\r\nUIDL\r\nRESP-CODES\r\nAUTH-RESP-CODE\r\nPIPELINING\r\n\
+    XREFRESH\r\nXSTATUS\r\n.\r\n";
+const POP3_ERR_LOCKED: &str = "[AUTH] Too many failed logins, try again later";
+
+/// Самая длинная строка команды вместе с CRLF. RFC 2449 допускает 255
+/// октетов, запас - для длинных токенов в PASS.
+const MAX_COMMAND_LINE: u64 = 1024;
+
+/// Соединение с клиентом POP3 поверх TCP или TLS: чтение буферизовано по
+/// строкам, запись ограничена --throttle-kbps
+type Pop3Stream<S> = BufReader<Throttled<S>>;
+
+/// Через сколько закрывается сессия, от которой нет команд: RFC 1939
+/// требует не меньше 10 минут. Иначе зависший клиент держал бы ящик.
+const POP3_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Сколько ждать рукопожатия TLS на порту POP3S
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Таблица хранилища: аккаунт -> ID самого нового поста прошлой загрузки
+const SINCE_ID_TABLE: &str = "since_ids";
+
+/// Ящики, открытые сессиями: "логин ящик"
+static MAILDROP_LOCKS: LazyLock<Mutex<HashSet<String>>> =
+    LazyLock::new(|| Mutex::new(HashSet::new()));
+
+/// Исключительная блокировка ящика на время сессии (RFC 1939): пометки DELE
+/// двух сессий не перемешиваются. Снимается при закрытии соединения.
+struct MaildropLock(String);
+
+impl MaildropLock {
+    fn acquire(login: &str, folder: &Folder) -> AppResult<Self> {
+        let key = format!("{} {}", login, folder.name());
+        let mut locks = MAILDROP_LOCKS
+            .lock()
+            .map_err(|_| AppError::ServerError("Maildrop locks poisoned".to_string()))?;
+        if !locks.insert(key.clone()) {
+            return Err(AppError::MailboxInUse);
+        }
+        Ok(MaildropLock(key))
+    }
+}
+
+impl Drop for MaildropLock {
+    fn drop(&mut self) {
+        if let Ok(mut locks) = MAILDROP_LOCKS.lock() {
+            locks.remove(&self.0);
+        }
+    }
+}
+
+/// POP3 сервер шлюза. Каждое соединение берёт снимок текущего состояния,
+/// так что новое состояние, отправленное в `SharedState`, подхватывается
+/// без перезапуска сервера.
+pub struct Pop3Server {
+    state: SharedState,
+    listeners: Vec<TcpListener>,
+    /// Сокеты POP3S: TLS начинается сразу после accept
+    tls_listeners: Vec<TcpListener>,
+    /// TLS слушателей POP3S: сертификат из --tls-cert и --tls-key или от
+    /// Let's Encrypt для --acme-domain
+    tls_acceptor: Option<TlsAcceptor>,
+}
+
+impl Pop3Server {
+    /// Сервер на уже открытых сокетах: mop3 открывает их до сброса привилегий
+    pub fn new(state: SharedState, listeners: Vec<TcpListener>) -> Self {
+        Pop3Server {
+            state,
+            listeners,
+            tls_listeners: Vec::new(),
+            tls_acceptor: None,
+        }
+    }
+
+    /// Добавляет уже открытые сокеты POP3S. Сертификат загружается до сброса
+    /// привилегий, как и сокеты, и не перечитывается по SIGHUP: ключ обычно
+    /// доступен только root.
+    pub fn with_tls_listeners(
+        mut self,
+        listeners: Vec<TcpListener>,
+        acceptor: TlsAcceptor,
+    ) -> Self {
+        self.tls_listeners = listeners;
+        self.tls_acceptor = Some(acceptor);
+        self
+    }
+
+    /// Открывает сокеты на --address и --pop3port из текущей конфигурации,
+    /// а с --tls-cert или --acme-domain и на --pop3s-port
+    pub async fn bind(state: SharedState) -> AppResult<Self> {
+        let config = Arc::clone(&state.borrow().config);
+        let listeners = net::bind_listeners(&config.address, config.pop3port).await?;
+        let server = Self::new(state, listeners);
+        if !config.pop3s() {
+            return Ok(server);
+        }
+
+        let acme = config.acme().then(|| Acme::new(&config)).transpose()?;
+        let acceptor = net::pop3s_acceptor(&config, acme.as_ref())?;
+        let tls_listeners = net::bind_listeners(&config.address, config.pop3s_port).await?;
+        if let Some(acme) = acme {
+            acme.spawn(net::bind_listeners(&config.address, config.acme_port).await?);
+        }
+        Ok(server.with_tls_listeners(tls_listeners, acceptor))
+    }
 
-pub async fn run_pop3_server(config: Arc<Config>) -> AppResult<()> {
-    let bind_addr = format!("{}:{}", config.address, config.pop3port);
+    /// Адреса, на которых сервер принимает соединения, сначала POP3, затем POP3S
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .chain(&self.tls_listeners)
+            .filter_map(|listener| listener.local_addr().ok())
+            .collect()
+    }
 
-    let listener = TcpListener::bind(&bind_addr).await?;
-    info!("POP3 server listening on: {}", bind_addr);
+    /// Принимает соединения; завершается только с ошибкой одного из сокетов
+    pub async fn run(self) -> AppResult<()> {
+        prefetch::spawn_warm_cache(Arc::clone(&self.state.borrow()));
 
-    let recent_id = String::new();
+        // Каждый адрес обслуживается независимым циклом accept
+        let mut accept_loops = JoinSet::new();
+        for listener in self.listeners {
+            accept_loops.spawn(accept_pop3_connections(listener, self.state.clone(), None));
+        }
+        for listener in self.tls_listeners {
+            accept_loops.spawn(accept_pop3_connections(
+                listener,
+                self.state.clone(),
+                self.tls_acceptor.clone(),
+            ));
+        }
+
+        match accept_loops.join_next().await {
+            Some(Ok(res)) => res,
+            Some(Err(e)) => Err(AppError::ServerError(format!(
+                "POP3 accept loop failed: {}",
+                e
+            ))),
+            None => Err(AppError::ServerError("No POP3 listeners bound".to_string())),
+        }
+    }
+}
+
+async fn accept_pop3_connections(
+    listener: TcpListener,
+    shared_state: SharedState,
+    tls_acceptor: Option<TlsAcceptor>,
+) -> AppResult<()> {
+    let protocol = if tls_acceptor.is_some() {
+        "POP3S"
+    } else {
+        "POP3"
+    };
+    info!(
+        "{} server listening on: {}",
+        protocol,
+        listener.local_addr()?
+    );
 
     loop {
         match listener.accept().await {
             Ok((stream, peer_addr)) => {
                 debug!("New POP3 connection from: {}", peer_addr);
-                let config = Arc::clone(&config);
-                let recent = recent_id.clone();
+                let state = Arc::clone(&shared_state.borrow());
+
+                if !net::is_peer_allowed(&state.config, peer_addr.ip()) {
+                    warn!(
+                        "POP3 connection from {} rejected by address filter",
+                        peer_addr
+                    );
+                    continue;
+                }
 
-                tokio::spawn(async move {
-                    if let Err(e) = handle_pop3_connection(stream, config, recent).await {
-                        warn!("POP3 connection error from {}: {}", peer_addr, e);
+                let session = logging::session_id();
+                let span = info_span!(
+                    "pop3_session",
+                    otel.kind = "server",
+                    session = %session,
+                    peer = %peer_addr,
+                    account = field::Empty,
+                    backend = ?state.config.api_mode,
+                );
+                let tls_acceptor = tls_acceptor.clone();
+                tokio::spawn(
+                    async move {
+                        let result = match tls_acceptor {
+                            Some(acceptor) => {
+                                accept_tls(acceptor, stream, state, peer_addr.ip(), &session).await
+                            }
+                            None => {
+                                handle_pop3_connection(
+                                    stream,
+                                    state,
+                                    peer_addr.ip(),
+                                    &session,
+                                    None,
+                                )
+                                .await
+                            }
+                        };
+                        if let Err(e) = result {
+                            warn!("{} connection error from {}: {}", protocol, peer_addr, e);
+                        }
                     }
-                });
+                    .instrument(span),
+                );
             }
             Err(e) => {
                 error!("Failed to accept POP3 connection: {}", e);
@@ -42,278 +250,461 @@ pub async fn run_pop3_server(config: Arc<Config>) -> AppResult<()> {
     }
 }
 
-async fn handle_pop3_connection(
-    mut stream: TcpStream,
-    config: Arc<Config>,
-    _recent_id: String,
+/// Рукопожатие TLS на порту POP3S, дальше - обычная сессия POP3
+async fn accept_tls(
+    acceptor: TlsAcceptor,
+    stream: TcpStream,
+    state: Arc<AppState>,
+    peer_ip: IpAddr,
+    session: &str,
 ) -> AppResult<()> {
-    stream.write_all(POP3_BANNER).await?;
+    let stream = tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+        .await
+        .map_err(|_| AppError::Disconnected("TLS handshake timed out"))?
+        .map_err(|e| AppError::ServerError(format!("TLS handshake failed: {}", e)))?;
+
+    // С --tls-client-ca рукопожатие без сертификата не проходит, и остаётся
+    // найти логин по CN
+    let client_login = match &state.config.tls_client_ca {
+        Some(_) => {
+            let cn = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(net::certificate_cn)
+                .unwrap_or_default();
+            let Some(login) = state.config.tls_client_accounts.get(&cn) else {
+                warn!(
+                    target: "security",
+                    "POP3S client certificate '{}' from {} is not mapped to an account",
+                    cn, peer_ip
+                );
+                return Ok(());
+            };
+            Some(login.clone())
+        }
+        None => None,
+    };
+    handle_pop3_connection(stream, state, peer_ip, session, client_login).await
+}
 
-    // Получаем учётные данные
-    let cred = get_pop3_login(&mut stream).await?;
+async fn handle_pop3_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    state: Arc<AppState>,
+    peer_ip: IpAddr,
+    session: &str,
+    client_login: Option<String>,
+) -> AppResult<()> {
+    let mut stream = BufReader::new(Throttled::new(stream, state.config.throttle_kbps));
+    let result = pop3_session(&mut stream, &state, peer_ip, session, client_login).await;
 
-    // Берём аккаунт и токен из конфига или из логина
-    let mut final_cred = cred;
-    if let Some(account) = &config.account {
-        final_cred.username = account.clone();
+    // Досылает хвост последнего ответа и закрывает TLS уведомлением close_notify
+    if let Err(e) = stream.shutdown().await {
+        debug!("POP3 connection shutdown failed: {}", e);
     }
-    if let Some(token) = &config.token {
-        final_cred.password = token.clone();
+    result
+}
+
+async fn pop3_session<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut Pop3Stream<S>,
+    state: &Arc<AppState>,
+    peer_ip: IpAddr,
+    session: &str,
+    client_login: Option<String>,
+) -> AppResult<()> {
+    let config = &state.config;
+    if lockout::banned(peer_ip, None).is_some() {
+        write_err(stream, session, POP3_ERR_LOCKED).await?;
+        return Ok(());
     }
+    stream.write_all(POP3_BANNER).await?;
+
+    // Получаем учётные данные; суффикс логина выбирает ящик
+    let mut cred = get_pop3_login(stream).await?;
+    let (login, folder) = Folder::split_login(&cred.username);
+    let login = login.to_string();
+    cred.username = login.clone();
+    if let Some(left) = lockout::banned(peer_ip, Some(&login)) {
+        debug!(
+            "POP3 login for {} from {} locked for {:?}",
+            login, peer_ip, left
+        );
+        audit::login(config, peer_ip, "POP3", &login, LoginResult::Locked);
+        write_err(stream, session, POP3_ERR_LOCKED).await?;
+        return Ok(());
+    }
+
+    // Клиентский сертификат уже подтвердил логин, пользователь --users-file
+    // или пароль шлюза проверяются локально, а токен API клиент не передаёт
+    let local_cred = match (&client_login, &state.users, &config.gateway_password) {
+        (Some(cert_login), _, _) if *cert_login != login => Some(None),
+        (Some(_), Some(users), _) => Some(users.credentials(&login, config.token.as_ref())),
+        (Some(_), None, _) => Some(Some(config_credentials(config, cred.clone()))),
+        (None, Some(users), _) => Some(
+            users
+                .authenticate(&login, &cred.password, config.token.as_ref())
+                .await,
+        ),
+        (None, None, Some(hash)) => Some(
+            password::check(&cred.password, hash)
+                .await
+                .then(|| config_credentials(config, cred.clone())),
+        ),
+        (None, None, None) => None,
+    };
+    let checked_locally = local_cred.is_some();
+
+    let final_cred = match local_cred {
+        Some(Some(local_cred)) => {
+            lockout::record_success(peer_ip, &login);
+            audit::login(config, peer_ip, "POP3", &login, LoginResult::Success);
+            local_cred
+        }
+        Some(None) => {
+            lockout::record_failure(config, peer_ip, &login, "POP3");
+            audit::login(config, peer_ip, "POP3", &login, LoginResult::Failure);
+            let reply = AppError::InvalidCredentials.pop3_reply();
+            write_err(stream, session, &reply).await?;
+            return Ok(());
+        }
+        None => config_credentials(config, cred),
+    };
 
     debug!("POP3 login successful for user: {}", final_cred.username);
+    Span::current().record("account", final_cred.username.as_str());
+
+    // Ящик из фоновой загрузки отдаётся сразу, без обращения к API. Он
+    // собран для --account, поэтому другим пользователям не достаётся.
+    let prefetched_account = folder == Folder::Timeline
+        && config.account.as_deref() == Some(final_cred.username.as_str());
+    if prefetched_account {
+        if let Some(mailbox) = prefetch::cached(state) {
+            let Some(_maildrop_lock) =
+                lock_maildrop(stream, session, &final_cred.username, &folder).await?
+            else {
+                return Ok(());
+            };
+            return serve_mailbox(stream, mailbox, state, session).await;
+        }
+    }
 
-    // Создаём API клиент
-    let api_client = api::create_api_client(&config)?;
+    let api_client = state.api_client.as_ref();
 
     // АСИНХРОННО проверяем учётные данные
     match api_client.verify_credentials(&final_cred).await {
         Ok(account_addr) => {
             info!("Verified account: {}", account_addr);
+            lockout::record_success(peer_ip, &login);
+            // При локальной проверке успешный вход уже записан выше
+            if !checked_locally {
+                audit::login(config, peer_ip, "POP3", &login, LoginResult::Success);
+            }
 
-            // Получаем ленту постов
-            match api_client.get_timeline(&final_cred, 40, "").await {
-                Ok(posts) => {
-                    debug!("Fetched {} posts from timeline", posts.len());
-
-                    // Конвертируем посты в письма
-                    let emails = convert_posts_to_emails(posts, &account_addr, &config).await?;
-
-                    let post_size: usize = emails.iter().map(|e| e.len()).sum();
-
-                    stream.write_all(POP3_OK_MESSAGES_FETCHED).await?;
-
-                    // Обрабатываем команды от клиента
-                    handle_pop3_commands(&mut stream, &emails, &post_size).await?;
+            let Some(_maildrop_lock) =
+                lock_maildrop(stream, session, &final_cred.username, &folder).await?
+            else {
+                return Ok(());
+            };
+
+            // Получаем ленту постов; письма собираются по мере запросов клиента
+            match fetch_mailbox(state, &final_cred, &account_addr, &folder).await {
+                Ok(mailbox) if prefetched_account => {
+                    let mailbox = prefetch::store(state, mailbox);
+                    serve_mailbox(stream, mailbox, state, session).await?;
+                }
+                Ok(mailbox) => {
+                    serve_mailbox(stream, Arc::new(mailbox), state, session).await?;
                 }
                 Err(e) => {
                     error!("Failed to get timeline 0: {}", e);
-                    stream
-                        .write_all(b"-ERR Failed to fetch messages\r\n")
-                        .await?;
+                    write_err(stream, session, &e.pop3_reply()).await?;
                 }
             }
         }
         Err(e) => {
             error!("Failed to verify credentials: {}", e);
-            stream.write_all(b"-ERR Invalid credentials\r\n").await?;
+            if e.is_auth() {
+                lockout::record_failure(config, peer_ip, &login, "POP3");
+                audit::login(config, peer_ip, "POP3", &login, LoginResult::Failure);
+            }
+            write_err(stream, session, &e.pop3_reply()).await?;
         }
     }
 
     Ok(())
 }
 
-/// Конвертирует посты Mastodon/Bluesky в RFC822 письма
-async fn convert_posts_to_emails(
-    posts: Vec<Post>,
-    account_addr: &str,
-    config: &Arc<Config>,
-) -> AppResult<Vec<String>> {
-    let mut emails = Vec::new();
-    //let domain = account_addr.split('@').last().unwrap_or("mastodon.local");
-
-    for post in posts {
-        match post {
-            Post::Mastodon(mastodon_post) => {
-                if let Ok(email) =
-                    convert_mastodon_post_to_email(&mastodon_post, account_addr, config).await
-                {
-                    emails.push(email);
-                }
-            }
-            Post::Bluesky(_bluesky_post) => {
-                debug!("Bluesky post conversion not fully implemented yet");
-            }
+/// Блокирует ящик уже проверенного пользователя (RFC 1939): до проверки
+/// ответ [IN-USE] выдавал бы, что ящик открыт. Занятый ящик - `-ERR [IN-USE]`
+/// и `None`.
+async fn lock_maildrop<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut Pop3Stream<S>,
+    session: &str,
+    login: &str,
+    folder: &Folder,
+) -> AppResult<Option<MaildropLock>> {
+    match MaildropLock::acquire(login, folder) {
+        Ok(lock) => Ok(Some(lock)),
+        Err(e) => {
+            debug!("Mailbox of {} is already open", login);
+            write_err(stream, session, &e.pop3_reply()).await?;
+            Ok(None)
         }
     }
-
-    Ok(emails)
 }
 
-/// Конвертирует один пост Mastodon в RFC822 письмо
-async fn convert_mastodon_post_to_email(
-    post: &crate::models::MastodonStatus,
-    account_addr: &str,
-    config: &Arc<Config>,
-) -> AppResult<String> {
-    let subject: String;
-    let attachments: Vec<serde_json::Value>;
-    let mut content: String;
-
-    // Определяем тему письма
-    if let Some(reblog) = &post.reblog {
-        subject = format!("mop3 Boost from {}", post.account.display_name);
-        content = reblog.content.to_string();
-        attachments = reblog.media_attachments.clone();
-    } else {
-        subject = format!("mop3 Post");
-        content = post.content.clone();
-        attachments = post.media_attachments.clone();
-    };
-
-    // Удаляем HTML теги если нужно конвертировать в текст
-    if !config.html {
-        content = html_to_text(&content);
-    }
-
-    // Применяем ASCII преобразование если нужно
-    if config.ascii {
-        content = deunicode(&content);
+/// Учётные данные без --users-file: аккаунт и токен из конфига, если заданы,
+/// иначе из логина клиента
+fn config_credentials(config: &Config, mut cred: Credentials) -> Credentials {
+    if let Some(account) = &config.account {
+        cred.username = account.clone();
     }
-
-    // Применяем proxy для ссылок если нужно
-    if let Some(proxy) = &config.proxy {
-        content = apply_proxy_to_links(&content, proxy);
-    } else {
-        content = apply_proxy_to_links(&content, "");
+    if let Some(token) = &config.token {
+        cred.password = token.clone();
     }
+    cred
+}
 
-    // Парсим дату
-    let created_at = parse_timestamp(&post.created_at);
-
-    // Создаём сообщение
-    let mut message = MessageBuilder::new()
-        .from((post.account.display_name.clone(), post.account.acct.clone()))
-        .to(account_addr)
-        .subject(subject)
-        .date(created_at)
-        .message_id(format!("{}@{}", post.id, account_addr));
+/// Загружает ящик `folder`; письма собираются сразу только с --exact-sizes
+pub async fn fetch_mailbox(
+    state: &AppState,
+    cred: &Credentials,
+    account_addr: &str,
+    folder: &Folder,
+) -> AppResult<Mailbox> {
+    let mailbox = match folder {
+        Folder::Timeline => fetch_timeline(state, cred, account_addr).await?,
+        Folder::Scheduled => {
+            let mut posts = state.api_client.get_scheduled_posts(cred).await?;
+            debug!("Fetched {} scheduled posts", posts.len());
+            deleted::exclude(state, account_addr, &mut posts);
+            let drafts = converter::prepare_posts(posts, &state.config);
+            Mailbox::new(
+                drafts,
+                cred.clone(),
+                account_addr.to_string(),
+                folder.clone(),
+                &state.config,
+            )
+        }
+        Folder::Feed(name) => {
+            let api_client = state.api_client.as_ref();
+            let feeds = api_client.saved_feeds(cred).await?;
+            let Some(feed) = feeds.iter().find(|feed| feed.folder_name() == *name) else {
+                let available: Vec<String> = feeds.iter().map(|feed| feed.folder_name()).collect();
+                return Err(AppError::ApiError(format!(
+                    "Saved feed {} not found (available: {})",
+                    name,
+                    available.join(", ")
+                )));
+            };
+
+            let mut posts = api_client.get_feed(cred, &feed.id, 40).await?;
+            debug!("Fetched {} posts from feed {}", posts.len(), feed.name);
+            deleted::exclude(state, account_addr, &mut posts);
+            let mut posts = filter::filter_posts(posts, api_client, cred, &state.config).await;
+            edited::mark(state, account_addr, &mut posts);
+            let drafts = converter::prepare_posts(posts, &state.config);
+            Mailbox::new(
+                drafts,
+                cred.clone(),
+                account_addr.to_string(),
+                folder.clone(),
+                &state.config,
+            )
+        }
+    };
 
-    // Добавляем reply if header если это ответ
-    if let Some(reply_id) = &post.in_reply_to_id {
-        message = message.in_reply_to(format!("{}@{}", reply_id, account_addr));
+    // Fetchmail и строгие клиенты сверяют размер из LIST с полученным письмом
+    if state.config.exact_sizes {
+        mailbox.build_all(state).await;
     }
+    Ok(mailbox)
+}
 
-    // Обрабатываем медиа вложения
-    if config.attachment || config.inline {
-        for attachment in attachments {
-            let url = attachment
-                .get("url")
-                .and_then(|v| v.as_str())
-                .unwrap_or("no_url")
-                .to_string();
-            let preview_url = attachment
-                .get("preview_url")
-                .and_then(|v| v.as_str())
-                .unwrap_or("no_url")
-                .to_string();
-
-            if preview_url != "no_url" {
-                // Загружаем медиа
-                if let Ok((data, mime)) = download_media(&preview_url).await {
-                    let filename = preview_url
-                        .split('/')
-                        .next_back()
-                        .unwrap_or("image.jpg")
-                        .to_string();
-                    if config.attachment {
-                        message = message.binary_attachment(mime, filename, data);
-                    } else if config.inline {
-                        message = message.binary_inline(mime, filename, data);
-                    }
-                }
-            }
-            // Добавляем ссылку на оригинальный аттачмент
-            if url != "no_url" {
-                content = format!("{}\n> Fullsize: {}\n", content, url);
-            }
+/// Загружает ленту, фильтрует её и готовит ящик вместе со служебными
+/// письмами шлюза
+async fn fetch_timeline(
+    state: &AppState,
+    cred: &Credentials,
+    account_addr: &str,
+) -> AppResult<Mailbox> {
+    let api_client = state.api_client.as_ref();
+    let (posts, ()) = tokio::join!(
+        api_client.get_timeline(cred, 40, ""),
+        webhook::notify(state, cred, account_addr)
+    );
+    let mut posts = posts?;
+
+    // Лента отсортирована от новых постов к старым
+    let since_id: Option<String> = state.store.get(SINCE_ID_TABLE, account_addr);
+    let new_posts = since_id
+        .and_then(|since_id| posts.iter().position(|post| post.id == since_id))
+        .unwrap_or(posts.len());
+    debug!(
+        "Fetched {} posts from timeline, {} new since last fetch",
+        posts.len(),
+        new_posts
+    );
+    activity::count(Counter::PostsFetched, new_posts as u64);
+    if let Some(newest) = posts.first() {
+        if let Err(e) = state.store.insert(SINCE_ID_TABLE, account_addr, &newest.id) {
+            warn!("Cannot record since_id for {}: {}", account_addr, e);
         }
     }
-
-    // Добавляем тело
-    if config.html {
-        message = message.html_body(&content);
-    } else {
-        message = message.text_body(&content);
+    deleted::exclude(state, account_addr, &mut posts);
+    let mut posts = filter::filter_posts(posts, api_client, cred, &state.config).await;
+    edited::mark(state, account_addr, &mut posts);
+    let notices = moved::due_notices(state, account_addr, &posts);
+
+    let drafts = converter::prepare_posts(posts, &state.config);
+
+    let mut mailbox = Mailbox::new(
+        drafts,
+        cred.clone(),
+        account_addr.to_string(),
+        Folder::Timeline,
+        &state.config,
+    );
+    if let Some(report) = activity::due_report(state, account_addr) {
+        mailbox.prepend(report);
     }
+    for email in outbox::take(state, account_addr).into_iter().chain(notices) {
+        mailbox.prepend(email);
+    }
+    Ok(mailbox)
+}
 
-    // Сериализуем в RFC822
-    let email_string = message
-        .write_to_string()
-        .map_err(|e| format!("Failed to build email: {}", e))?;
+async fn serve_mailbox<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut Pop3Stream<S>,
+    mailbox: Arc<Mailbox>,
+    state: &AppState,
+    session: &str,
+) -> AppResult<()> {
+    stream.write_all(POP3_OK_MESSAGES_FETCHED).await?;
 
-    Ok(email_string)
+    // Обрабатываем команды от клиента
+    handle_pop3_commands(stream, mailbox, state, session).await
 }
 
-/// Загружает медиа файл по URL
-async fn download_media(url: &str) -> AppResult<(Vec<u8>, String)> {
-    let client = reqwest::Client::new();
-    let response = client.get(url).send().await?;
-
-    if !response.status().is_success() {
-        return Err(format!("Failed to download media: {}", &response.status()).into());
+/// XREFRESH: загружает ленту заново посреди сессии. Ящик аккаунта из
+/// --account заменяет и ящик фоновой загрузки, так что свежую ленту
+/// получают и следующие сессии.
+async fn refresh_mailbox(mailbox: &Mailbox, state: &AppState) -> AppResult<Arc<Mailbox>> {
+    let cred = mailbox.cred();
+    let refreshed = fetch_mailbox(state, cred, mailbox.account_addr(), mailbox.folder()).await?;
+    info!(
+        "Mailbox refreshed on client request: {} messages",
+        refreshed.len()
+    );
+
+    if *mailbox.folder() == Folder::Timeline
+        && state.config.account.as_deref() == Some(cred.username.as_str())
+    {
+        Ok(prefetch::store(state, refreshed))
+    } else {
+        Ok(Arc::new(refreshed))
     }
-
-    let mime = response
-        .headers()
-        .get("Content-Type")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("image/jpeg")
-        .to_string();
-    let data = response.bytes().await?;
-    Ok((data.to_vec(), mime))
 }
 
-/// Конвертирует HTML в обычный текст
-fn html_to_text(html: &str) -> String {
-    // Простое удаление HTML тегов
-    let re = Regex::new(r"<[^>]*>").unwrap();
-    let text = re.replace_all(html, "").to_string();
-
-    // Декодируем HTML entities
-    text.replace("&lt;", "<")
-        .replace("&gt;", ">")
-        .replace("&amp;", "&")
-        .replace("&quot;", "\"")
-        .replace("&apos;", "'")
-        .replace("&#39;", "'")
-        .replace("<br>", "\n")
-        .replace("<br/>", "\n")
-        .replace("<br />", "\n")
-        .replace("<p>", "")
-        .replace("https://", "\nhttps://")
-        .replace("#", " #")
-        .replace("</p>", "\n")
+/// XSTATUS: состояние шлюза и ящика сессии, по параметру на строку
+fn status_report(mailbox: &Mailbox, state: &AppState) -> String {
+    let config = &state.config;
+    let prefetch = config.prefetch.map_or_else(
+        || "off".to_string(),
+        |interval| format!("every {}s", interval),
+    );
+
+    format!(
+        "+OK status follows\r\n\
+         version {}\r\n\
+         backend {}\r\n\
+         account {}\r\n\
+         folder {}\r\n\
+         messages {}\r\n\
+         size {}\r\n\
+         fetched {}s ago\r\n\
+         prefetch {}\r\n\
+         .\r\n",
+        env!("CARGO_PKG_VERSION"),
+        config.api_mode.name(),
+        mailbox.account_addr(),
+        mailbox.folder().name(),
+        mailbox.len(),
+        mailbox.total_size(config),
+        mailbox.fetched_at.elapsed().as_secs(),
+        prefetch,
+    )
 }
 
-/// Применяет proxy к ссылкам в тексте
-fn apply_proxy_to_links(content: &str, proxy: &str) -> String {
-    // Найти и заменить HTTP ссылки
-    match Regex::new(r"https?://[^\s\]<>]+") {
-        Ok(re) => re
-            .replace_all(content, |caps: &fancy_regex::Captures| {
-                let url = &caps[0];
-                format!("{}{}\n", proxy, url)
-            })
-            .to_string(),
-        Err(_) => content.to_string(),
-    }
+/// Ответ -ERR на сбой шлюза или входа с ID сессии, по которому причина
+/// находится в логе. Текст с кодом ответа даёт `AppError::pop3_reply`.
+/// Ошибки синтаксиса команд отвечаются без него.
+async fn write_err<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut Pop3Stream<S>,
+    session: &str,
+    text: &str,
+) -> AppResult<()> {
+    let response = format!("-ERR {} (session {})\r\n", text, session);
+    stream.write_all(response.as_bytes()).await?;
+    Ok(())
 }
 
-/// Парсит дату Mastodon в Unix timestamp
-fn parse_timestamp(date_str: &str) -> i64 {
-    if let Ok(dt) = NaiveDateTime::parse_from_str(date_str, "%Y-%m-%dT%H:%M:%S%.3fZ") {
-        DateTime::<Utc>::from_naive_utc_and_offset(dt, Utc).timestamp()
-    } else {
-        0
+/// Читает одну команду до перевода строки. Команда может прийти в
+/// нескольких сегментах TCP, а несколько команд - в одном: остаток ждёт в
+/// буфере следующего вызова. На слишком длинную строку отвечает -ERR и
+/// пропускает её. `None` - клиент закрыл соединение.
+async fn read_command<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut Pop3Stream<S>,
+) -> AppResult<Option<Zeroizing<String>>> {
+    loop {
+        // Поверх TLS записанное может ждать в буфере rustls: без сброса клиент
+        // не получил бы конец ответа и не прислал бы следующую команду
+        stream.flush().await?;
+        // В строке может быть пароль
+        let mut line = Zeroizing::new(Vec::new());
+        (&mut *stream)
+            .take(MAX_COMMAND_LINE)
+            .read_until(b'\n', &mut line)
+            .await?;
+        if line.ends_with(b"\n") {
+            let command = String::from_utf8_lossy(&line).trim_end().to_string();
+            return Ok(Some(Zeroizing::new(command)));
+        }
+        // Соединение закрыто, в том числе посреди строки
+        if (line.len() as u64) < MAX_COMMAND_LINE {
+            return Ok(None);
+        }
+
+        let mut rest = Zeroizing::new(Vec::new());
+        while !rest.ends_with(b"\n") {
+            rest.clear();
+            if (&mut *stream)
+                .take(MAX_COMMAND_LINE)
+                .read_until(b'\n', &mut rest)
+                .await?
+                == 0
+            {
+                return Ok(None);
+            }
+        }
+        stream.write_all(b"-ERR command line too long\r\n").await?;
     }
 }
 
-async fn get_pop3_login(stream: &mut TcpStream) -> AppResult<Credentials> {
+async fn get_pop3_login<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut Pop3Stream<S>,
+) -> AppResult<Credentials> {
     let mut cred = Credentials {
         username: String::new(),
-        password: String::new(),
+        password: Secret::default(),
     };
 
     loop {
-        let mut buf = vec![0u8; 1024];
-        let n = stream.read(&mut buf).await?;
-
-        if n == 0 {
-            return Err("Connection closed".into());
-        }
-
-        let command = String::from_utf8_lossy(&buf[..n]);
+        let Some(command) = read_command(stream).await? else {
+            return Err(AppError::Disconnected("connection closed"));
+        };
         let mut parts = command.split_whitespace();
 
         match parts.next() {
@@ -325,7 +716,7 @@ async fn get_pop3_login(stream: &mut TcpStream) -> AppResult<Credentials> {
             }
             Some("PASS") => {
                 if let Some(password) = parts.next() {
-                    cred.password = password.to_string();
+                    cred.password = password.into();
                     if !cred.username.is_empty() && !cred.password.is_empty() {
                         return Ok(cred);
                     }
@@ -333,7 +724,14 @@ async fn get_pop3_login(stream: &mut TcpStream) -> AppResult<Credentials> {
             }
             Some("QUIT") => {
                 stream.write_all(b"+OK bye\r\n").await?;
-                return Err("User quit".into());
+                return Err(AppError::Disconnected("user quit"));
+            }
+            // RFC 2449: клиенты спрашивают CAPA до входа, чтобы знать о RESP-CODES
+            Some("CAPA") => {
+                stream.write_all(POP3_CAPABILITIES).await?;
+            }
+            Some("NOOP") => {
+                stream.write_all(b"+OK\r\n").await?;
             }
             _ => {
                 stream.write_all(b"-ERR unknown command\r\n").await?;
@@ -342,33 +740,48 @@ async fn get_pop3_login(stream: &mut TcpStream) -> AppResult<Credentials> {
     }
 }
 
-async fn handle_pop3_commands(
-    stream: &mut TcpStream,
-    emails: &[String],
-    post_size: &usize,
+async fn handle_pop3_commands<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut Pop3Stream<S>,
+    mut mailbox: Arc<Mailbox>,
+    state: &AppState,
+    session: &str,
 ) -> AppResult<()> {
-    let mut buf = vec![0u8; 1024];
+    let config = &state.config;
+    // ID писем, помеченных DELE: номера писем после XREFRESH меняются
+    let mut marked: HashSet<String> = HashSet::new();
+    // Наибольший номер письма, отданного RETR, для LAST (RFC 1460)
+    let mut last_retrieved = 0;
 
     loop {
-        let n = stream.read(&mut buf).await?;
-
-        if n == 0 {
+        // Без QUIT состояние UPDATE не наступает, пометки DELE пропадают
+        let Ok(command) = tokio::time::timeout(POP3_IDLE_TIMEOUT, read_command(stream)).await
+        else {
+            info!("Closing idle POP3 session");
             break;
-        }
-
-        let command = String::from_utf8_lossy(&buf[..n]);
+        };
+        let Some(command) = command? else {
+            break;
+        };
         let mut parts = command.split_whitespace();
 
         match parts.next() {
             Some("STAT") => {
-                let response = format!("+OK {} {}\r\n", emails.len(), post_size);
+                let kept: Vec<usize> = (0..mailbox.len())
+                    .filter(|&i| !marked.contains(mailbox.id(i)))
+                    .collect();
+                let size: usize = kept.iter().map(|&i| mailbox.size(i, config)).sum();
+                let response = format!("+OK {} {}\r\n", kept.len(), size);
                 stream.write_all(response.as_bytes()).await?;
             }
             Some("LIST") => {
                 if let Some(index_str) = parts.next() {
                     if let Ok(index) = index_str.parse::<usize>() {
-                        if index > 0 && index <= emails.len() {
-                            let response = format!("+OK {} {}\r\n", index, emails[index - 1].len());
+                        if index > 0
+                            && index <= mailbox.len()
+                            && !marked.contains(mailbox.id(index - 1))
+                        {
+                            let response =
+                                format!("+OK {} {}\r\n", index, mailbox.size(index - 1, config));
                             stream.write_all(response.as_bytes()).await?;
                         } else {
                             stream.write_all(b"-ERR no such message\r\n").await?;
@@ -377,13 +790,18 @@ async fn handle_pop3_commands(
                         stream.write_all(b"-ERR invalid message number\r\n").await?;
                     }
                 } else {
-                    // LIST без параметра - выводим список всех
+                    // LIST без параметра - выводим список всех, кроме удалённых
+                    let kept: Vec<usize> = (0..mailbox.len())
+                        .filter(|&i| !marked.contains(mailbox.id(i)))
+                        .collect();
                     stream
-                        .write_all(format!("+OK {} messages\r\n", emails.len()).as_bytes())
+                        .write_all(format!("+OK {} messages\r\n", kept.len()).as_bytes())
                         .await?;
-                    for (i, email) in emails.iter().enumerate() {
+                    for i in kept {
                         stream
-                            .write_all(format!("{} {}\r\n", i + 1, email.len()).as_bytes())
+                            .write_all(
+                                format!("{} {}\r\n", i + 1, mailbox.size(i, config)).as_bytes(),
+                            )
                             .await?;
                     }
                     stream.write_all(b".\r\n").await?;
@@ -392,13 +810,28 @@ async fn handle_pop3_commands(
             Some("RETR") => {
                 if let Some(index_str) = parts.next() {
                     if let Ok(index) = index_str.parse::<usize>() {
-                        if index > 0 && index <= emails.len() {
-                            let email = &emails[index - 1];
-                            stream
-                                .write_all(format!("+OK {} octets\r\n", email.len()).as_bytes())
-                                .await?;
-                            stream.write_all(email.as_bytes()).await?;
-                            stream.write_all(b"\r\n.\r\n").await?;
+                        if index > 0
+                            && index <= mailbox.len()
+                            && !marked.contains(mailbox.id(index - 1))
+                        {
+                            match mailbox.email(index - 1, state).await {
+                                Ok(email) => {
+                                    debug!("Serving message {}: {}", email.id, email.subject);
+                                    stream
+                                        .write_all(
+                                            format!("+OK {} octets\r\n", email.size).as_bytes(),
+                                        )
+                                        .await?;
+                                    email.body.write_stuffed(stream).await?;
+                                    stream.write_all(b".\r\n").await?;
+                                    activity::count(Counter::EmailsServed, 1);
+                                    last_retrieved = last_retrieved.max(index);
+                                }
+                                Err(e) => {
+                                    warn!("Cannot build message {}: {}", index, e);
+                                    write_err(stream, session, &e.pop3_reply()).await?;
+                                }
+                            }
                         } else {
                             stream.write_all(b"-ERR no such message\r\n").await?;
                         }
@@ -410,22 +843,54 @@ async fn handle_pop3_commands(
                 }
             }
             Some("DELE") => {
-                // Мы не удаляем письма, просто отправляем OK
-                stream.write_all(b"+OK\r\n").await?;
+                // Письмо только помечается, удаляется оно при QUIT
+                match parts.next().map(str::parse::<usize>) {
+                    Some(Ok(index)) if index > 0 && index <= mailbox.len() => {
+                        if marked.insert(mailbox.id(index - 1).to_string()) {
+                            let response = format!("+OK message {} deleted\r\n", index);
+                            stream.write_all(response.as_bytes()).await?;
+                        } else {
+                            let response = format!("-ERR message {} already deleted\r\n", index);
+                            stream.write_all(response.as_bytes()).await?;
+                        }
+                    }
+                    Some(Ok(_)) => stream.write_all(b"-ERR no such message\r\n").await?,
+                    Some(Err(_)) => stream.write_all(b"-ERR invalid message number\r\n").await?,
+                    None => stream.write_all(b"-ERR no message specified\r\n").await?,
+                }
             }
             Some("QUIT") => {
+                // Состояние UPDATE (RFC 1939): удаление вступает в силу
+                if !marked.is_empty() {
+                    let ids: Vec<String> = marked.drain().collect();
+                    if let Err(e) = deleted::record(state, mailbox.account_addr(), &ids) {
+                        warn!("Cannot record deleted messages: {}", e);
+                        write_err(stream, session, &e.pop3_reply()).await?;
+                        break;
+                    }
+                    prefetch::forget(state, &mailbox);
+                    info!(
+                        "Deleted {} messages of {}",
+                        ids.len(),
+                        mailbox.account_addr()
+                    );
+                }
                 stream.write_all(b"+OK bye\r\n").await?;
                 break;
             }
             Some("CAPA") => {
-                stream
-                    .write_all(b"+OK Capability list follows\r\nUSER\r\nTOP\r\nUIDL\r\n.\r\n")
-                    .await?;
+                stream.write_all(POP3_CAPABILITIES).await?;
             }
             Some("NOOP") => {
                 stream.write_all(b"+OK\r\n").await?;
             }
+            Some("LAST") => {
+                let response = format!("+OK {}\r\n", last_retrieved);
+                stream.write_all(response.as_bytes()).await?;
+            }
             Some("RSET") => {
+                marked.clear();
+                last_retrieved = 0;
                 stream.write_all(b"+OK\r\n").await?;
             }
             Some("TOP") => {
@@ -433,13 +898,21 @@ async fn handle_pop3_commands(
                     if let (Ok(msg), Ok(lines)) =
                         (msg_str.parse::<usize>(), lines_str.parse::<usize>())
                     {
-                        if msg > 0 && msg <= emails.len() {
-                            let email = &emails[msg - 1];
+                        if msg > 0 && msg <= mailbox.len() && !marked.contains(mailbox.id(msg - 1))
+                        {
+                            let email = match mailbox.email(msg - 1, state).await {
+                                Ok(email) => email,
+                                Err(e) => {
+                                    warn!("Cannot build message {}: {}", msg, e);
+                                    write_err(stream, session, &e.pop3_reply()).await?;
+                                    continue;
+                                }
+                            };
                             let mut line_count = 0;
                             let mut output = String::new();
                             let mut in_body = false;
 
-                            for line in email.lines() {
+                            for line in email.body.lines() {
                                 if line.is_empty() {
                                     in_body = true;
                                 }
@@ -451,7 +924,10 @@ async fn handle_pop3_commands(
                                     line_count += 1;
                                 }
 
-                                output.push_str(line);
+                                if line.starts_with('.') {
+                                    output.push('.');
+                                }
+                                output.push_str(&line);
                                 output.push_str("\r\n");
                             }
 
@@ -470,13 +946,32 @@ async fn handle_pop3_commands(
                     stream.write_all(b"-ERR missing parameters\r\n").await?;
                 }
             }
+            Some("XREFRESH") => match refresh_mailbox(&mailbox, state).await {
+                Ok(refreshed) => {
+                    mailbox = refreshed;
+                    // Номера писем в новом ящике другие
+                    last_retrieved = 0;
+                    let response = format!("+OK {} messages\r\n", mailbox.len());
+                    stream.write_all(response.as_bytes()).await?;
+                }
+                Err(e) => {
+                    warn!("Cannot refresh mailbox: {}", e);
+                    write_err(stream, session, &e.pop3_reply()).await?;
+                }
+            },
+            Some("XSTATUS") => {
+                let response = status_report(&mailbox, state);
+                stream.write_all(response.as_bytes()).await?;
+            }
             Some("UIDL") => {
                 if let Some(index_str) = parts.next() {
                     if let Ok(index) = index_str.parse::<usize>() {
-                        if index > 0 && index <= emails.len() {
-                            stream
-                                .write_all(format!("+OK {} msg-{}\r\n", index, index).as_bytes())
-                                .await?;
+                        if index > 0
+                            && index <= mailbox.len()
+                            && !marked.contains(mailbox.id(index - 1))
+                        {
+                            let response = format!("+OK {} {}\r\n", index, mailbox.uidl(index - 1));
+                            stream.write_all(response.as_bytes()).await?;
                         } else {
                             stream.write_all(b"-ERR no such message\r\n").await?;
                         }
@@ -484,11 +979,14 @@ async fn handle_pop3_commands(
                         stream.write_all(b"-ERR invalid message number\r\n").await?;
                     }
                 } else {
-                    // UIDL без параметра - выводим список всех
+                    // UIDL без параметра - выводим список всех, кроме удалённых
                     stream.write_all(b"+OK\r\n").await?;
-                    for i in 1..=emails.len() {
+                    for i in 0..mailbox.len() {
+                        if marked.contains(mailbox.id(i)) {
+                            continue;
+                        }
                         stream
-                            .write_all(format!("{} msg-{}\r\n", i, i).as_bytes())
+                            .write_all(format!("{} {}\r\n", i + 1, mailbox.uidl(i)).as_bytes())
                             .await?;
                     }
                     stream.write_all(b".\r\n").await?;
@@ -0,0 +1,108 @@
+use crate::config::{Config, TranslateApi};
+use crate::error::{AppError, AppResult};
+use reqwest::header::HeaderValue;
+use reqwest::Client;
+use serde_json::{json, Value};
+use tracing::{debug, warn};
+
+const DEEPL_URL: &str = "https://api-free.deepl.com";
+
+/// Переводит HTML поста на язык --translate-to, если пост написан на другом.
+/// Оригинал сохраняется под переводом. Посты без указанного языка и ошибки
+/// сервиса перевода оставляют текст как есть.
+pub async fn translate_post(
+    html: &str,
+    language: Option<&str>,
+    http_client: &Client,
+    config: &Config,
+) -> String {
+    let (Some(target), Some(source)) = (config.translate_to.as_deref(), language) else {
+        return html.to_string();
+    };
+    if primary_subtag(source) == primary_subtag(target) || html.trim().is_empty() {
+        return html.to_string();
+    }
+
+    match translate(html, source, target, http_client, config).await {
+        Ok(translated) => {
+            debug!("Translated post from {} to {}", source, target);
+            format!(
+                "{}\n<p>---- Original ({}) ----</p>\n{}",
+                translated, source, html
+            )
+        }
+        Err(e) => {
+            warn!("Translation from {} to {} failed: {}", source, target, e);
+            html.to_string()
+        }
+    }
+}
+
+async fn translate(
+    html: &str,
+    source: &str,
+    target: &str,
+    client: &Client,
+    config: &Config,
+) -> AppResult<String> {
+    let response: Value = match config.translate_api {
+        TranslateApi::LibreTranslate => {
+            let url = config.translate_url.as_deref().unwrap_or_default();
+            let mut body = json!({
+                "q": html,
+                "source": primary_subtag(source),
+                "target": primary_subtag(target),
+                "format": "html",
+            });
+            if let Some(key) = &config.translate_key {
+                body["api_key"] = Value::String(key.to_string());
+            }
+            client
+                .post(format!("{}/translate", url.trim_end_matches('/')))
+                .json(&body)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?
+        }
+        TranslateApi::Deepl => {
+            let url = config.translate_url.as_deref().unwrap_or(DEEPL_URL);
+            let key = config.translate_key.as_deref().unwrap_or_default();
+            let mut auth = HeaderValue::try_from(format!("DeepL-Auth-Key {}", key))
+                .map_err(|e| AppError::Config(format!("Некорректный --translate-key: {}", e)))?;
+            auth.set_sensitive(true);
+            client
+                .post(format!("{}/v2/translate", url.trim_end_matches('/')))
+                .header("Authorization", auth)
+                .json(&json!({
+                    "text": [html],
+                    "source_lang": primary_subtag(source).to_uppercase(),
+                    "target_lang": target.to_uppercase(),
+                    "tag_handling": "html",
+                }))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?
+        }
+    };
+
+    let translated = match config.translate_api {
+        TranslateApi::LibreTranslate => response["translatedText"].as_str(),
+        TranslateApi::Deepl => response["translations"][0]["text"].as_str(),
+    };
+    translated
+        .map(str::to_string)
+        .ok_or_else(|| AppError::ApiError("No translation in response".to_string()))
+}
+
+/// Основной тег языка: "pt-BR" -> "pt"
+fn primary_subtag(language: &str) -> String {
+    language
+        .split(['-', '_'])
+        .next()
+        .unwrap_or(language)
+        .to_lowercase()
+}
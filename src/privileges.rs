@@ -0,0 +1,119 @@
+use crate::config::Config;
+use crate::error::AppResult;
+
+/// Сменяет пользователя и группу процесса на --user и --group. Вызывается
+/// после открытия портов: сокеты на 110 и 25 остаются открытыми, а сетевые
+/// соединения обслуживаются уже без прав root.
+#[cfg(unix)]
+pub fn drop_privileges(config: &Config) -> AppResult<()> {
+    use crate::error::AppError;
+    use std::io;
+    use tracing::info;
+
+    if config.user.is_none() && config.group.is_none() {
+        return Ok(());
+    }
+
+    let user = config.user.as_deref().map(lookup_user).transpose()?;
+    let gid = match (config.group.as_deref(), &user) {
+        (Some(group), _) => Some(lookup_group(group)?),
+        (None, Some(user)) => Some(user.gid),
+        (None, None) => None,
+    };
+    let failed = |call: &str| {
+        AppError::ServerError(format!(
+            "Не удалось сбросить привилегии ({}): {}",
+            call,
+            io::Error::last_os_error()
+        ))
+    };
+
+    if let Some(gid) = gid {
+        // Дополнительные группы root заменяются группами нового пользователя
+        let res = match &user {
+            Some(user) => unsafe { libc::initgroups(user.name.as_ptr(), gid as _) },
+            None => unsafe { libc::setgroups(1, &gid) },
+        };
+        if res != 0 {
+            return Err(failed("setgroups"));
+        }
+        if unsafe { libc::setgid(gid) } != 0 {
+            return Err(failed("setgid"));
+        }
+    }
+
+    if let Some(user) = &user {
+        if unsafe { libc::setuid(user.uid) } != 0 {
+            return Err(failed("setuid"));
+        }
+        // Вернуть root после смены uid не должно получаться
+        if user.uid != 0 && unsafe { libc::setuid(0) } == 0 {
+            return Err(AppError::ServerError(
+                "Права root восстанавливаются после setuid, привилегии не сброшены".to_string(),
+            ));
+        }
+    }
+
+    info!(
+        "Dropped privileges to uid {} gid {}",
+        unsafe { libc::getuid() },
+        unsafe { libc::getgid() }
+    );
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn drop_privileges(_config: &Config) -> AppResult<()> {
+    // --user и --group отклоняются при валидации конфигурации
+    Ok(())
+}
+
+#[cfg(unix)]
+struct User {
+    name: std::ffi::CString,
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+}
+
+/// Ищет пользователя по имени или числовому uid
+#[cfg(unix)]
+fn lookup_user(user: &str) -> AppResult<User> {
+    use crate::error::AppError;
+    use std::ffi::{CStr, CString};
+
+    let not_found = || AppError::Config(format!("Пользователь '{}' не найден", user));
+    let name = CString::new(user).map_err(|_| not_found())?;
+    let passwd = match user.parse::<libc::uid_t>() {
+        Ok(uid) => unsafe { libc::getpwuid(uid) },
+        Err(_) => unsafe { libc::getpwnam(name.as_ptr()) },
+    };
+    if passwd.is_null() {
+        return Err(not_found());
+    }
+
+    let passwd = unsafe { &*passwd };
+    Ok(User {
+        name: unsafe { CStr::from_ptr(passwd.pw_name) }.to_owned(),
+        uid: passwd.pw_uid,
+        gid: passwd.pw_gid,
+    })
+}
+
+/// Ищет группу по имени или числовому gid
+#[cfg(unix)]
+fn lookup_group(group: &str) -> AppResult<libc::gid_t> {
+    use crate::error::AppError;
+    use std::ffi::CString;
+
+    if let Ok(gid) = group.parse::<libc::gid_t>() {
+        return Ok(gid);
+    }
+
+    let not_found = || AppError::Config(format!("Группа '{}' не найдена", group));
+    let name = CString::new(group).map_err(|_| not_found())?;
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    if entry.is_null() {
+        return Err(not_found());
+    }
+    Ok(unsafe { (*entry).gr_gid })
+}
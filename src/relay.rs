@@ -0,0 +1,116 @@
+use crate::config::{Config, RelayTls};
+use crate::error::{AppError, AppResult};
+use crate::models::Email;
+use mail_builder::encoders::Base64Encoder;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_native_tls::{native_tls, TlsConnector};
+use tracing::debug;
+
+/// Отправляет письмо через --relay-smtp на --relay-to: почтовый сервер
+/// провайдера доставит ленту в ящик, который уже читается по IMAP
+pub async fn send(config: &Config, email: &Email) -> AppResult<()> {
+    let Some(address) = &config.relay_smtp else {
+        return Ok(());
+    };
+    let host = address
+        .rsplit_once(':')
+        .map_or(address.as_str(), |(host, _)| host)
+        .trim_matches(['[', ']']);
+
+    let tcp = TcpStream::connect(address).await?;
+    if config.relay_tls == RelayTls::Tls {
+        let mut stream = BufReader::new(connect_tls(host, tcp).await?);
+        expect(&mut stream, '2').await?;
+        command(&mut stream, &format!("EHLO {}", config.gateway_domain), '2').await?;
+        return transaction(&mut stream, config, email).await;
+    }
+
+    let mut stream = BufReader::new(tcp);
+    expect(&mut stream, '2').await?;
+    command(&mut stream, &format!("EHLO {}", config.gateway_domain), '2').await?;
+    if config.relay_tls == RelayTls::None {
+        return transaction(&mut stream, config, email).await;
+    }
+
+    command(&mut stream, "STARTTLS", '2').await?;
+    let tls = connect_tls(host, stream.into_inner()).await?;
+    let mut stream = BufReader::new(tls);
+    // После STARTTLS сервер забывает всё, что было сказано до него
+    command(&mut stream, &format!("EHLO {}", config.gateway_domain), '2').await?;
+    transaction(&mut stream, config, email).await
+}
+
+async fn connect_tls<S: AsyncRead + AsyncWrite + Unpin>(
+    host: &str,
+    stream: S,
+) -> AppResult<tokio_native_tls::TlsStream<S>> {
+    let connector = native_tls::TlsConnector::new()
+        .map_err(|e| AppError::ServerError(format!("Cannot initialize TLS: {}", e)))?;
+    TlsConnector::from(connector)
+        .connect(host, stream)
+        .await
+        .map_err(|e| AppError::ServerError(format!("TLS handshake with {} failed: {}", host, e)))
+}
+
+/// AUTH, если задан --relay-user, и отправка письма
+async fn transaction<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+    config: &Config,
+    email: &Email,
+) -> AppResult<()> {
+    let to = config.relay_to.as_deref().unwrap_or_default();
+    let from = config
+        .relay_from
+        .clone()
+        .unwrap_or_else(|| format!("mop3@{}", config.gateway_domain));
+
+    if let Some(user) = &config.relay_user {
+        let password = config.relay_password.as_deref().unwrap_or_default();
+        let plain = Base64Encoder::new().encode(format!("\0{}\0{}", user, password).as_bytes())?;
+        let auth = format!("AUTH PLAIN {}", String::from_utf8_lossy(&plain));
+        command(stream, &auth, '2').await?;
+    }
+    command(stream, &format!("MAIL FROM:<{}>", from), '2').await?;
+    command(stream, &format!("RCPT TO:<{}>", to), '2').await?;
+    command(stream, "DATA", '3').await?;
+    email.body.write_stuffed(stream).await?;
+    command(stream, ".", '2').await?;
+    stream.write_all(b"QUIT\r\n").await?;
+    stream.flush().await?;
+    debug!("Relayed {} to {}", email.id, to);
+    Ok(())
+}
+
+async fn command<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+    line: &str,
+    class: char,
+) -> AppResult<()> {
+    stream.write_all(format!("{}\r\n", line).as_bytes()).await?;
+    expect(stream, class).await
+}
+
+/// Читает ответ сервера SMTP или LMTP, в том числе многострочный, и
+/// проверяет первую цифру кода
+pub(crate) async fn expect<S: AsyncRead + Unpin>(
+    stream: &mut BufReader<S>,
+    class: char,
+) -> AppResult<()> {
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await? == 0 {
+            return Err(AppError::Disconnected("Mail server closed the connection"));
+        }
+        if !line.starts_with(class) {
+            return Err(AppError::ServerError(format!(
+                "Mail server replied: {}",
+                line.trim_end()
+            )));
+        }
+        // "250-..." продолжается, "250 ..." - последняя строка
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use crate::error::{AppError, AppResult};
+use crate::pop3::message::{Attachments, Message};
+use crate::state::AppState;
+use mail_builder::encoders::Base64Encoder;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::path::Path;
+
+/// Сертификат и ключ подписи из --sign-cert и --sign-key
+#[cfg(feature = "smime")]
+pub struct Signer {
+    cert: openssl::x509::X509,
+    key: openssl::pkey::PKey<openssl::pkey::Private>,
+    /// Промежуточные сертификаты из того же файла: кладутся в подпись,
+    /// чтобы клиент мог построить цепочку до корня
+    chain: openssl::stack::Stack<openssl::x509::X509>,
+}
+
+/// Без feature smime подписывать нечем, и значение не создаётся
+#[cfg(not(feature = "smime"))]
+pub enum Signer {}
+
+#[cfg(feature = "smime")]
+impl Signer {
+    pub fn load(cert_path: &Path, key_path: &Path) -> AppResult<Self> {
+        let read = |path: &Path| {
+            std::fs::read(path).map_err(|e| {
+                AppError::Config(format!("Не удалось прочитать {}: {}", path.display(), e))
+            })
+        };
+        let mut certs = openssl::x509::X509::stack_from_pem(&read(cert_path)?)
+            .map_err(|e| {
+                AppError::Config(format!(
+                    "Некорректный сертификат {}: {}",
+                    cert_path.display(),
+                    e
+                ))
+            })?
+            .into_iter();
+        let cert = certs.next().ok_or_else(|| {
+            AppError::Config(format!("В {} нет сертификата", cert_path.display()))
+        })?;
+        let key = openssl::pkey::PKey::private_key_from_pem(&read(key_path)?).map_err(|e| {
+            AppError::Config(format!("Некорректный ключ {}: {}", key_path.display(), e))
+        })?;
+        let matches = cert
+            .public_key()
+            .map(|public| public.public_eq(&key))
+            .unwrap_or(false);
+        if !matches {
+            return Err(AppError::Config(format!(
+                "Ключ {} не подходит к сертификату {}",
+                key_path.display(),
+                cert_path.display()
+            )));
+        }
+
+        let mut chain = openssl::stack::Stack::new().map_err(openssl_error)?;
+        for cert in certs {
+            chain.push(cert).map_err(openssl_error)?;
+        }
+        Ok(Signer { cert, key, chain })
+    }
+
+    /// Отсоединённая подпись PKCS#7 в DER. Данные уже в каноническом виде
+    /// (строки через CRLF), поэтому подписываются как есть.
+    fn signature(&self, data: &[u8]) -> AppResult<Vec<u8>> {
+        use openssl::pkcs7::{Pkcs7, Pkcs7Flags};
+
+        Pkcs7::sign(
+            &self.cert,
+            &self.key,
+            &self.chain,
+            data,
+            Pkcs7Flags::DETACHED | Pkcs7Flags::BINARY,
+        )
+        .and_then(|pkcs7| pkcs7.to_der())
+        .map_err(openssl_error)
+    }
+}
+
+#[cfg(feature = "smime")]
+fn openssl_error(e: openssl::error::ErrorStack) -> AppError {
+    AppError::ServerError(format!("Cannot sign message: {}", e))
+}
+
+#[cfg(not(feature = "smime"))]
+impl Signer {
+    pub fn load(_cert_path: &Path, _key_path: &Path) -> AppResult<Self> {
+        Err(AppError::Config(
+            "mop3 собран без поддержки S/MIME (cargo build --features smime)".to_string(),
+        ))
+    }
+
+    fn signature(&self, _data: &[u8]) -> AppResult<Vec<u8>> {
+        match *self {}
+    }
+}
+
+/// Подписывает письмо шлюза S/MIME (RFC 8551), если заданы --sign-cert и
+/// --sign-key: заголовки Content-* переносятся в подписанную часть, а письмо
+/// становится multipart/signed с подписью smime.p7s. Уже подписанное письмо
+/// возвращается как есть. Вложения подписанного письма хранятся
+/// закодированными: подпись считается по тексту целиком.
+pub fn sign(state: &AppState, message: Message) -> AppResult<Message> {
+    match &state.signer {
+        Some(signer) => signed(signer, message),
+        None => Ok(message),
+    }
+}
+
+fn signed(signer: &Signer, message: Message) -> AppResult<Message> {
+    let bytes = message.to_bytes()?;
+    let text = String::from_utf8_lossy(&bytes);
+    let (head, body) = text.split_once("\r\n\r\n").unwrap_or((&text, ""));
+
+    // Заголовок вместе со строками продолжения
+    let mut headers: Vec<String> = Vec::new();
+    for line in head.split("\r\n") {
+        match headers.last_mut() {
+            Some(last) if line.starts_with([' ', '\t']) => {
+                last.push_str("\r\n");
+                last.push_str(line);
+            }
+            _ => headers.push(line.to_string()),
+        }
+    }
+    let is_content = |header: &String| header.to_ascii_lowercase().starts_with("content-");
+    if headers.iter().any(|header| {
+        is_content(header)
+            && header
+                .to_ascii_lowercase()
+                .starts_with("content-type: multipart/signed")
+    }) {
+        return Ok(message);
+    }
+
+    let (content, outer): (Vec<String>, Vec<String>) = headers.into_iter().partition(is_content);
+    let entity = format!("{}\r\n\r\n{}", content.join("\r\n"), body);
+    let mut signature = Vec::new();
+    Base64Encoder::new()
+        .wrap_lines()
+        .encode_to_writer(&signer.signature(entity.as_bytes())?, &mut signature)?;
+
+    let boundary = format!(
+        "mop3-signed-{:016x}",
+        RandomState::new().build_hasher().finish()
+    );
+    let signed = format!(
+        "{outer}\r\n\
+         Content-Type: multipart/signed; protocol=\"application/pkcs7-signature\";\r\n \
+         micalg=sha-256; boundary=\"{boundary}\"\r\n\
+         \r\n\
+         --{boundary}\r\n\
+         {entity}\r\n\
+         --{boundary}\r\n\
+         Content-Type: application/pkcs7-signature; name=\"smime.p7s\"\r\n\
+         Content-Transfer-Encoding: base64\r\n\
+         Content-Disposition: attachment; filename=\"smime.p7s\"\r\n\
+         \r\n\
+         {signature}\
+         --{boundary}--\r\n",
+        outer = outer.join("\r\n"),
+        signature = String::from_utf8_lossy(&signature),
+    );
+    Message::assemble(signed, Attachments::default())
+}
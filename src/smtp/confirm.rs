@@ -0,0 +1,212 @@
+use super::crosspost;
+use super::submission::{self, Submission};
+use crate::error::{AppError, AppResult};
+use crate::models::Credentials;
+use crate::outbox;
+use crate::state::AppState;
+use chrono::Utc;
+use mail_builder::encoders::Base64Encoder;
+use mail_builder::headers::raw::Raw;
+use mail_builder::MessageBuilder;
+use mail_parser::decoders::base64::base64_decode;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+/// Письма, ждущие подтверждения: аккаунт -> письма
+const HELD_TABLE: &str = "held";
+
+/// Локальная часть Reply-To письма с превью: confirm+<код>@gateway-domain
+pub const CONFIRM_PREFIX: &str = "confirm+";
+
+/// Сколько цифр в коде подтверждения
+const CODE_DIGITS: usize = 6;
+
+/// Сколько письмо ждёт подтверждения, секунд
+const HOLD_TTL: i64 = 24 * 60 * 60;
+
+/// Список читается и перезаписывается целиком
+static HELD_LOCK: Mutex<()> = Mutex::new(());
+
+/// Задержанное письмо. Хранится исходным текстом в base64: при
+/// подтверждении оно разбирается заново, как только что полученное.
+#[derive(Debug, Serialize, Deserialize)]
+struct Held {
+    code: String,
+    /// Адрес MAIL FROM: по нему выбираются бэкенды кросспостинга
+    from: String,
+    created: i64,
+    raw: String,
+}
+
+/// Письмо, отпущенное кодом подтверждения
+pub struct Released {
+    pub from: String,
+    pub raw: Vec<u8>,
+}
+
+/// Режим --confirm-posts: вместо публикации кладёт письмо в очередь
+/// подтверждения и отправляет в ящик превью постов с кодом. Пост уйдёт,
+/// когда на превью ответят, см. [`release`].
+pub async fn hold(
+    state: &AppState,
+    cred: &Credentials,
+    from: &str,
+    raw: &[u8],
+    submission: &Submission,
+) -> AppResult<()> {
+    let config = &state.config;
+    if submission.text.is_empty()
+        && submission.attachments.is_empty()
+        && submission.attach_urls.is_empty()
+    {
+        return Err(AppError::InvalidEmail("Empty message body".to_string()));
+    }
+    // Ошибки в выборе бэкендов видны сразу, а не после подтверждения
+    let targets = crosspost::route(state, submission, from, cred)?;
+    let account_addr = state.api_client.verify_credentials(cred).await?;
+
+    let code = {
+        let _guard = HELD_LOCK.lock();
+        let mut held = pending(state, &cred.username);
+        let code = loop {
+            let code = new_code();
+            if !held.iter().any(|h| h.code == code) {
+                break code;
+            }
+        };
+        let encoded = Base64Encoder::new()
+            .encode(raw)
+            .map_err(|e| AppError::ServerError(format!("Cannot encode message: {}", e)))?;
+        held.push(Held {
+            code: code.clone(),
+            from: from.to_string(),
+            created: Utc::now().timestamp(),
+            raw: String::from_utf8_lossy(&encoded).into_owned(),
+        });
+        state.store.insert(HELD_TABLE, &cred.username, &held)?;
+        code
+    };
+
+    let mut text = String::from(
+        "Письмо задержано до подтверждения. Чтобы опубликовать, ответьте на\n\
+         это письмо: ответ уйдёт на адрес с кодом. Текст ответа не важен.\n\
+         Если публиковать не нужно, просто удалите письмо.\n",
+    );
+    for target in &targets {
+        let parts = submission::render(target.api_client, &target.cred, submission, config);
+        text.push_str(&format!(
+            "\n=== {} ({}) ===\n",
+            target.name, target.cred.username
+        ));
+        if let Some(cw) = &submission.content_warning {
+            text.push_str(&format!("\nCW: {}\n", cw));
+        }
+        for part in parts {
+            text.push_str(&format!("\n{}\n", part));
+        }
+    }
+    let media: Vec<&str> = submission
+        .attachments
+        .iter()
+        .map(|attachment| attachment.filename.as_str())
+        .chain(submission.attach_urls.iter().map(String::as_str))
+        .collect();
+    if !media.is_empty() {
+        text.push_str(&format!("\nМедиа: {}\n", media.join(", ")));
+    }
+    text.push_str(&format!(
+        "\nКод подтверждения: {} (действует {} ч)\n",
+        code,
+        HOLD_TTL / 3600
+    ));
+
+    let now = Utc::now();
+    let charset = config.charset;
+    let subject = format!("mop3: подтвердите пост {}", code);
+    let gateway = format!("mop3@{}", config.gateway_domain);
+    let reply_to = format!("{}{}@{}", CONFIRM_PREFIX, code, config.gateway_domain);
+    let id = format!("confirm-{}", code);
+    let mut message = MessageBuilder::new()
+        .to(account_addr.as_str())
+        .reply_to(reply_to.as_str())
+        .date(now.timestamp())
+        .message_id(format!("{}@{}", id, config.gateway_domain))
+        .header("From", Raw::new(charset.encode_address("mop3", &gateway)));
+    if charset.is_utf8() {
+        message = message.subject(subject.as_str());
+    } else {
+        message = message.header("Subject", Raw::new(charset.encode_header(&subject)));
+    }
+    let email_string = message
+        .body(charset.text_part("text/plain", &text, config.transfer_encoding))
+        .write_to_string()
+        .map_err(|e| AppError::ServerError(format!("Failed to build email: {}", e)))?;
+
+    outbox::push(state, &account_addr, id, subject, email_string)?;
+    info!("Submission from {} held for confirmation {}", from, code);
+    Ok(())
+}
+
+/// Команда confirm@: отпускает задержанное письмо по коду из адреса
+/// confirm+<код> или, если письмо пришло на confirm@, из его текста
+pub fn release(
+    state: &AppState,
+    cred: &Credentials,
+    submission: &Submission,
+) -> AppResult<Released> {
+    let code = submission
+        .confirm_code
+        .clone()
+        .or_else(|| find_code(&submission.text))
+        .ok_or_else(|| {
+            AppError::InvalidEmail("Reply to the confirmation email to publish".to_string())
+        })?;
+
+    let _guard = HELD_LOCK.lock();
+    let mut held = pending(state, &cred.username);
+    let Some(n) = held.iter().position(|h| h.code == code) else {
+        warn!("Unknown confirmation code {} from {}", code, cred.username);
+        return Err(AppError::InvalidEmail(format!(
+            "Confirmation code {} is unknown or expired",
+            code
+        )));
+    };
+    let released = held.remove(n);
+    state.store.insert(HELD_TABLE, &cred.username, &held)?;
+
+    let raw = base64_decode(released.raw.as_bytes())
+        .ok_or_else(|| AppError::ServerError("Held message is corrupted".to_string()))?;
+    info!("Confirmation {} released for {}", code, cred.username);
+    Ok(Released {
+        from: released.from,
+        raw,
+    })
+}
+
+/// Письма аккаунта, срок подтверждения которых не истёк
+fn pending(state: &AppState, account: &str) -> Vec<Held> {
+    let now = Utc::now().timestamp();
+    let held: Vec<Held> = state.store.get(HELD_TABLE, account).unwrap_or_default();
+    held.into_iter()
+        .filter(|h| now - h.created < HOLD_TTL)
+        .collect()
+}
+
+fn new_code() -> String {
+    let random = RandomState::new().build_hasher().finish();
+    format!(
+        "{:0width$}",
+        random % 10u64.pow(CODE_DIGITS as u32),
+        width = CODE_DIGITS
+    )
+}
+
+/// Код в тексте письма: первое слово из шести цифр
+fn find_code(text: &str) -> Option<String> {
+    text.split(|c: char| !c.is_ascii_digit())
+        .find(|word| word.len() == CODE_DIGITS)
+        .map(str::to_string)
+}
@@ -0,0 +1,104 @@
+use crate::api::SocialNetworkApi;
+use crate::error::{AppError, AppResult};
+use crate::models::Credentials;
+use crate::smtp::submission::{Submission, BACKENDS_HEADER};
+use crate::state::AppState;
+use tracing::info;
+
+/// Бэкенд, в который публикуется письмо, и аккаунт на нём
+pub struct Target<'a> {
+    pub name: &'static str,
+    pub api_client: &'a dyn SocialNetworkApi,
+    pub cred: Credentials,
+    /// Аккаунт --api-mode, а не из [[crosspost]]
+    pub primary: bool,
+}
+
+/// Куда публиковать письмо: бэкенды из X-Mop3-Backends, иначе из
+/// crosspost_routes по домену MAIL FROM, иначе только основной аккаунт
+/// `cred`. Ответ публикуется только в основной: пост, на который отвечают,
+/// пришёл из его ленты.
+pub fn route<'a>(
+    state: &'a AppState,
+    submission: &Submission,
+    from: &str,
+    cred: &Credentials,
+) -> AppResult<Vec<Target<'a>>> {
+    let config = &state.config;
+    let primary = config.api_mode.name();
+
+    let domain = from
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.to_lowercase());
+    let routed = domain.as_ref().and_then(|domain| {
+        config
+            .crosspost_routes
+            .iter()
+            .find(|(route, _)| route.eq_ignore_ascii_case(domain))
+            .map(|(_, names)| names)
+    });
+    let names: Vec<String> = match (&submission.backends, routed) {
+        (Some(names), _) => names.clone(),
+        (None, Some(names)) => names.clone(),
+        (None, None) => vec![primary.to_string()],
+    };
+
+    let mut targets: Vec<Target> = Vec::with_capacity(names.len());
+    for name in &names {
+        if targets.iter().any(|target| target.name == name) {
+            continue;
+        }
+        if name == primary {
+            targets.push(Target {
+                name: primary,
+                api_client: state.api_client.as_ref(),
+                cred: cred.clone(),
+                primary: true,
+            });
+            continue;
+        }
+
+        let found = config
+            .crosspost
+            .iter()
+            .zip(&state.crosspost_clients)
+            .find(|(account, _)| account.api_mode.name() == name);
+        let Some((account, api_client)) = found else {
+            let available: Vec<&str> = std::iter::once(primary)
+                .chain(config.crosspost.iter().map(|a| a.api_mode.name()))
+                .collect();
+            return Err(AppError::InvalidEmail(format!(
+                "Unknown backend {} (available: {})",
+                name,
+                available.join(", ")
+            )));
+        };
+        targets.push(Target {
+            name: account.api_mode.name(),
+            api_client: api_client.as_ref(),
+            cred: Credentials {
+                username: account.account.clone(),
+                password: account.token.clone(),
+            },
+            primary: false,
+        });
+    }
+
+    if submission.in_reply_to_id.is_some() && targets.len() > 1 {
+        targets.retain(|target| target.primary);
+        info!("Reply from {} is published to {} only", from, primary);
+    }
+    if submission.in_reply_to_id.is_some() && !targets.iter().any(|target| target.primary) {
+        return Err(AppError::InvalidEmail(format!(
+            "Replies can only be published to {}",
+            primary
+        )));
+    }
+    if targets.is_empty() {
+        return Err(AppError::InvalidEmail(format!(
+            "{} lists no backends",
+            BACKENDS_HEADER
+        )));
+    }
+    Ok(targets)
+}
@@ -0,0 +1,144 @@
+use crate::error::{AppError, AppResult};
+use image::codecs::jpeg::JpegEncoder;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use std::io::Cursor;
+use tracing::debug;
+
+/// Качество JPEG, если фото приходится поворачивать
+const JPEG_QUALITY: u8 = 90;
+
+/// Чанки PNG с метаданными: EXIF, текстовые поля (в них бывает XMP) и время
+const PNG_METADATA_CHUNKS: [&[u8; 4]; 5] = [b"eXIf", b"tEXt", b"zTXt", b"iTXt", b"tIME"];
+
+/// Удаляет из JPEG и PNG метаданные EXIF/XMP, в том числе координаты GPS.
+/// Без поворота изображение не перекодируется. Фото с поворотом из EXIF
+/// поворачивается заранее, иначе без метаданных оно покажется лёжа.
+/// Файл, который не удалось разобрать, перекодируется целиком, а если его
+/// не читает и декодер - отклоняется: загружать его с GPS нельзя.
+/// Остальные форматы возвращаются без изменений.
+pub fn strip_metadata(data: Vec<u8>, mime: &str) -> AppResult<Vec<u8>> {
+    let stripped = match mime {
+        "image/jpeg" | "image/jpg" => {
+            strip_jpeg(&data).or_else(|| reencode(&data, ImageFormat::Jpeg))
+        }
+        "image/png" => strip_png(&data).or_else(|| reencode(&data, ImageFormat::Png)),
+        _ => return Ok(data),
+    };
+
+    let stripped = stripped.ok_or_else(|| {
+        AppError::InvalidEmail(format!(
+            "Cannot remove metadata from a damaged {} attachment",
+            mime
+        ))
+    })?;
+    debug!(
+        "Stripped metadata from {}: {} -> {} bytes",
+        mime,
+        data.len(),
+        stripped.len()
+    );
+    Ok(stripped)
+}
+
+/// Декодирует и заново кодирует изображение: кодеры image метаданных не
+/// пишут. Поворот из EXIF применяется, как и в [`strip_jpeg`].
+fn reencode(data: &[u8], format: ImageFormat) -> Option<Vec<u8>> {
+    let mut decoder = ImageReader::with_format(Cursor::new(data), format)
+        .into_decoder()
+        .ok()?;
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut image = DynamicImage::from_decoder(decoder).ok()?;
+    image.apply_orientation(orientation);
+
+    let mut output = Vec::new();
+    match format {
+        ImageFormat::Jpeg => JpegEncoder::new_with_quality(&mut output, JPEG_QUALITY)
+            .encode_image(&image.to_rgb8())
+            .ok()?,
+        _ => image.write_to(&mut Cursor::new(&mut output), format).ok()?,
+    }
+    Some(output)
+}
+
+fn strip_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(&[0xFF, 0xD8]);
+    let mut orientation = Orientation::NoTransforms;
+    let mut pos = 2;
+
+    loop {
+        if data.get(pos) != Some(&0xFF) {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+
+        // Начало данных изображения: дальше метаданных нет
+        if marker == 0xDA {
+            output.extend_from_slice(&data[pos..]);
+            break;
+        }
+
+        let length = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+        if length < 2 {
+            return None;
+        }
+        let segment = data.get(pos..pos + 2 + length)?;
+        let payload = &segment[4..];
+
+        match marker {
+            // APP1: EXIF и XMP
+            0xE1 => {
+                if let Some(tiff) = payload.strip_prefix(b"Exif\0\0") {
+                    orientation = Orientation::from_exif_chunk(tiff).unwrap_or(orientation);
+                }
+            }
+            // APP13: IPTC из Photoshop
+            0xED => {}
+            _ => output.extend_from_slice(segment),
+        }
+        pos += 2 + length;
+    }
+
+    if orientation == Orientation::NoTransforms {
+        return Some(output);
+    }
+
+    let mut image = image::load_from_memory(&output).ok()?;
+    image.apply_orientation(orientation);
+    let mut rotated = Vec::new();
+    JpegEncoder::new_with_quality(&mut rotated, JPEG_QUALITY)
+        .encode_image(&image.to_rgb8())
+        .ok()?;
+    Some(rotated)
+}
+
+fn strip_png(data: &[u8]) -> Option<Vec<u8>> {
+    const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    if !data.starts_with(&SIGNATURE) {
+        return None;
+    }
+
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(&SIGNATURE);
+    let mut pos = SIGNATURE.len();
+
+    while pos < data.len() {
+        let length = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        // Длина, тип, данные и CRC
+        let chunk = data.get(pos..pos + 12 + length)?;
+        if !PNG_METADATA_CHUNKS
+            .iter()
+            .any(|name| chunk[4..8] == name[..])
+        {
+            output.extend_from_slice(chunk);
+        }
+        pos += 12 + length;
+    }
+
+    Some(output)
+}
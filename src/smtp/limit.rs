@@ -0,0 +1,86 @@
+use crate::error::{AppError, AppResult};
+use crate::state::AppState;
+use chrono::Utc;
+use std::sync::Mutex;
+use tracing::warn;
+
+/// Время публикации постов по аккаунтам за последние сутки: аккаунт ->
+/// Unix-время каждого поста
+const POSTS_TABLE: &str = "post_times";
+
+const HOUR: i64 = 60 * 60;
+const DAY: i64 = 24 * HOUR;
+
+/// Список читается и перезаписывается целиком
+static POSTS_LOCK: Mutex<()> = Mutex::new(());
+
+/// Отказывает, если аккаунт уже опубликовал --max-posts-per-hour постов за
+/// последний час или --max-posts-per-day за сутки. Ошибка временная: SMTP
+/// клиент оставит письмо в очереди и повторит отправку позже.
+pub fn check(state: &AppState, account: &str) -> AppResult<()> {
+    let config = &state.config;
+    if config.max_posts_per_hour.is_none() && config.max_posts_per_day.is_none() {
+        return Ok(());
+    }
+
+    let times = recent(state, account);
+    let now = Utc::now().timestamp();
+    let limits = [
+        (config.max_posts_per_hour, HOUR, "hour"),
+        (config.max_posts_per_day, DAY, "day"),
+    ];
+    for (limit, window, name) in limits {
+        let Some(limit) = limit else {
+            continue;
+        };
+        let posted: Vec<i64> = times
+            .iter()
+            .copied()
+            .filter(|time| now - time < window)
+            .collect();
+        if posted.len() >= limit as usize {
+            // Место освободится, когда из окна выйдет самый старый пост
+            let wait = posted
+                .iter()
+                .min()
+                .map_or(0, |oldest| oldest + window - now);
+            warn!(
+                "Post limit reached for {}: {} posts in the last {}",
+                account,
+                posted.len(),
+                name
+            );
+            return Err(AppError::PostLimit(format!(
+                "{} posts per {} for {}, retry in {} min",
+                limit,
+                name,
+                account,
+                (wait + 59) / 60
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Учитывает `count` опубликованных постов аккаунта
+pub fn record(state: &AppState, account: &str, count: usize) {
+    let config = &state.config;
+    if config.max_posts_per_hour.is_none() && config.max_posts_per_day.is_none() {
+        return;
+    }
+
+    let _guard = POSTS_LOCK.lock();
+    let now = Utc::now().timestamp();
+    let mut times = recent(state, account);
+    times.extend(std::iter::repeat_n(now, count));
+    if let Err(e) = state.store.insert(POSTS_TABLE, account, &times) {
+        warn!("Cannot record published posts of {}: {}", account, e);
+    }
+}
+
+/// Посты аккаунта за последние сутки
+fn recent(state: &AppState, account: &str) -> Vec<i64> {
+    let now = Utc::now().timestamp();
+    let times: Vec<i64> = state.store.get(POSTS_TABLE, account).unwrap_or_default();
+    times.into_iter().filter(|time| now - time < DAY).collect()
+}
@@ -1 +1,6 @@
+pub mod confirm;
+pub mod crosspost;
+pub mod exif;
+pub mod limit;
 pub mod server;
+pub mod submission;
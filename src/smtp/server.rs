@@ -1,28 +1,122 @@
-use crate::config::Config;
-use crate::error::AppResult;
+use super::confirm;
+use super::crosspost;
+use super::limit;
+use super::submission::{self, MailCommand, Submission};
+use crate::activity::{self, Counter};
+use crate::audit::{self, LoginResult};
+use crate::contacts;
+use crate::error::{AppError, AppResult};
+use crate::fetch;
+use crate::lockout;
+use crate::logging;
+use crate::models::Credentials;
+use crate::net;
+use crate::password;
+use crate::state::{AppState, SharedState};
+use mail_parser::decoders::base64::base64_decode;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
-use tokio::net::{TcpListener, TcpStream};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tracing::{error, info, warn, debug};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tracing::{debug, error, field, info, info_span, warn, Instrument, Span};
+use zeroize::Zeroizing;
+
+/// Таблица хранилища: Message-ID отправленного письма -> ID созданных постов
+const PUBLISHED_TABLE: &str = "published";
+
+/// Наибольшее письмо после DATA, объявляется в EHLO как SIZE (RFC 1870)
+const MAX_MESSAGE_SIZE: usize = 5_000_000;
+
+const SMTP_ERR_TOO_LARGE: &[u8] = b"552 Message size exceeds fixed maximum message size\r\n";
+
+/// SMTP сервер шлюза: публикует полученные письма постами. Как и
+/// `Pop3Server`, берёт снимок состояния на каждое соединение.
+pub struct SmtpServer {
+    state: SharedState,
+    listeners: Vec<TcpListener>,
+}
+
+impl SmtpServer {
+    /// Сервер на уже открытых сокетах: mop3 открывает их до сброса привилегий
+    pub fn new(state: SharedState, listeners: Vec<TcpListener>) -> Self {
+        SmtpServer { state, listeners }
+    }
+
+    /// Открывает сокеты на --address и --smtp-port из текущей конфигурации
+    pub async fn bind(state: SharedState) -> AppResult<Self> {
+        let config = Arc::clone(&state.borrow().config);
+        let listeners = net::bind_listeners(&config.address, config.smtp_port).await?;
+        Ok(Self::new(state, listeners))
+    }
+
+    /// Адреса, на которых сервер принимает соединения
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .filter_map(|listener| listener.local_addr().ok())
+            .collect()
+    }
 
-pub async fn run_smtp_server(config: Arc<Config>) -> AppResult<()> {
-    let bind_addr = format!("{}:{}", config.address, config.smtp_port);
-    
-    let listener = TcpListener::bind(&bind_addr).await?;
-    info!("SMTP server listening on: {}", bind_addr);
+    /// Принимает соединения; завершается только с ошибкой одного из сокетов
+    pub async fn run(self) -> AppResult<()> {
+        // Каждый адрес обслуживается независимым циклом accept
+        let mut accept_loops = JoinSet::new();
+        for listener in self.listeners {
+            accept_loops.spawn(accept_smtp_connections(listener, self.state.clone()));
+        }
+
+        match accept_loops.join_next().await {
+            Some(Ok(res)) => res,
+            Some(Err(e)) => Err(AppError::ServerError(format!(
+                "SMTP accept loop failed: {}",
+                e
+            ))),
+            None => Err(AppError::ServerError("No SMTP listeners bound".to_string())),
+        }
+    }
+}
+
+async fn accept_smtp_connections(
+    listener: TcpListener,
+    shared_state: SharedState,
+) -> AppResult<()> {
+    info!("SMTP server listening on: {}", listener.local_addr()?);
 
     loop {
         match listener.accept().await {
             Ok((stream, peer_addr)) => {
                 debug!("New SMTP connection from: {}", peer_addr);
-                let config = Arc::clone(&config);
-                
+                let state = Arc::clone(&shared_state.borrow());
+
+                if !net::is_peer_allowed(&state.config, peer_addr.ip()) {
+                    warn!(
+                        "SMTP connection from {} rejected by address filter",
+                        peer_addr
+                    );
+                    continue;
+                }
+
                 // Каждое соединение обрабатывается в отдельной задаче
-                tokio::spawn(async move {
-                    if let Err(e) = handle_smtp_connection(stream, config).await {
-                        warn!("SMTP connection error from {}: {}", peer_addr, e);
+                let session = logging::session_id();
+                let span = info_span!(
+                    "smtp_session",
+                    otel.kind = "server",
+                    session = %session,
+                    peer = %peer_addr,
+                    account = field::Empty,
+                    backend = ?state.config.api_mode,
+                );
+                tokio::spawn(
+                    async move {
+                        if let Err(e) =
+                            handle_smtp_connection(stream, state, peer_addr.ip(), &session).await
+                        {
+                            warn!("SMTP connection error from {}: {}", peer_addr, e);
+                        }
                     }
-                });
+                    .instrument(span),
+                );
             }
             Err(e) => {
                 error!("Failed to accept SMTP connection: {}", e);
@@ -33,12 +127,20 @@ pub async fn run_smtp_server(config: Arc<Config>) -> AppResult<()> {
 
 async fn handle_smtp_connection(
     mut stream: TcpStream,
-    config: Arc<Config>,
+    state: Arc<AppState>,
+    peer_ip: IpAddr,
+    session: &str,
 ) -> AppResult<()> {
     stream.write_all(b"220 MOP3 SMTP ready\r\n").await?;
 
+    // Без пароля шлюза и --users-file отправка открыта, как и раньше
+    let requires_auth = state.config.gateway_password.is_some() || state.users.is_some();
+    let mut authenticated = !requires_auth;
+    // Логин AUTH и учётные данные аккаунта, от имени которого публикуются письма
+    let mut login: Option<(String, Credentials)> = None;
     let mut from = String::new();
-    let mut buf = vec![0u8; 4096];
+    // В буфер попадает и начальный ответ AUTH PLAIN с паролем
+    let mut buf = Zeroizing::new(vec![0u8; 4096]);
 
     loop {
         match stream.read(&mut buf).await {
@@ -47,14 +149,105 @@ async fn handle_smtp_connection(
                 let command = String::from_utf8_lossy(&buf[..n]);
                 let mut parts = command.split_whitespace();
 
-                match parts.next() {
+                match parts.next().map(str::to_ascii_uppercase).as_deref() {
                     Some("HELO") => {
                         stream.write_all(b"250 MOP3 ready\r\n").await?;
                     }
                     Some("EHLO") => {
-                        stream.write_all(b"250-MOP3\r\n250-SIZE 5000000\r\n250 OK\r\n").await?;
+                        let auth = if requires_auth {
+                            "250-AUTH PLAIN LOGIN\r\n"
+                        } else {
+                            ""
+                        };
+                        let response = format!(
+                            "250-MOP3\r\n250-SIZE {}\r\n{}250 OK\r\n",
+                            MAX_MESSAGE_SIZE, auth
+                        );
+                        stream.write_all(response.as_bytes()).await?;
+                    }
+                    Some("AUTH") => {
+                        if !requires_auth {
+                            stream.write_all(b"502 command not implemented\r\n").await?;
+                            continue;
+                        }
+                        if authenticated {
+                            stream.write_all(b"503 Already authenticated\r\n").await?;
+                            continue;
+                        }
+                        let mechanism = parts.next().map(str::to_ascii_uppercase);
+                        let initial = parts.next().map(|s| Zeroizing::new(s.to_string()));
+                        let Some(cred) =
+                            read_auth(&mut stream, mechanism.as_deref(), initial).await?
+                        else {
+                            stream
+                                .write_all(b"501 Malformed authentication\r\n")
+                                .await?;
+                            continue;
+                        };
+
+                        if lockout::banned(peer_ip, Some(&cred.username)).is_some() {
+                            audit::login(
+                                &state.config,
+                                peer_ip,
+                                "SMTP",
+                                &cred.username,
+                                LoginResult::Locked,
+                            );
+                            let response = format!(
+                                "454 Too many failed logins, try later (session {})\r\n",
+                                session
+                            );
+                            stream.write_all(response.as_bytes()).await?;
+                            break;
+                        }
+                        if let Some(account) = check_login(&state, &cred).await {
+                            lockout::record_success(peer_ip, &cred.username);
+                            audit::login(
+                                &state.config,
+                                peer_ip,
+                                "SMTP",
+                                &cred.username,
+                                LoginResult::Success,
+                            );
+                            authenticated = true;
+                            debug!("SMTP login successful for user: {}", cred.username);
+                            Span::current().record("account", account.username.as_str());
+                            login = Some((cred.username.clone(), account));
+                            stream
+                                .write_all(b"235 Authentication successful\r\n")
+                                .await?;
+                        } else {
+                            lockout::record_failure(&state.config, peer_ip, &cred.username, "SMTP");
+                            audit::login(
+                                &state.config,
+                                peer_ip,
+                                "SMTP",
+                                &cred.username,
+                                LoginResult::Failure,
+                            );
+                            let response = format!(
+                                "535 Authentication credentials invalid (session {})\r\n",
+                                session
+                            );
+                            stream.write_all(response.as_bytes()).await?;
+                        }
+                    }
+                    Some("MAIL" | "RCPT" | "DATA") if !authenticated => {
+                        stream.write_all(b"530 Authentication required\r\n").await?;
                     }
                     Some("MAIL") => {
+                        // Размер, объявленный в MAIL FROM, проверяется до DATA
+                        let declared = parts.find_map(|param| {
+                            param
+                                .to_ascii_uppercase()
+                                .strip_prefix("SIZE=")
+                                .and_then(|size| size.parse::<usize>().ok())
+                        });
+                        if declared.is_some_and(|size| size > MAX_MESSAGE_SIZE) {
+                            stream.write_all(SMTP_ERR_TOO_LARGE).await?;
+                            continue;
+                        }
+
                         // MAIL FROM: <user@example.com>
                         if let Some(from_addr) = extract_email_addr(&command) {
                             from = from_addr;
@@ -66,28 +259,32 @@ async fn handle_smtp_connection(
                     }
                     Some("DATA") => {
                         stream.write_all(b"354 Send message\r\n").await?;
-                        
-                        // TODO: получить email данные и отправить в социальную сеть
-                        debug!("Received email from: {}", from);
-                        
-                        // Читаем данные письма до ".\r\n"
-                        let mut email_data = String::new();
-                        loop {
-                            let mut line_buf = vec![0u8; 1024];
-                            match stream.read(&mut line_buf).await {
-                                Ok(0) => break,
-                                Ok(n) => {
-                                    let line = String::from_utf8_lossy(&line_buf[..n]);
-                                    if line.trim() == "." {
-                                        break;
-                                    }
-                                    email_data.push_str(&line);
-                                }
-                                Err(_) => break,
+
+                        let Some(email_data) = read_data(&mut stream).await? else {
+                            warn!(
+                                "Rejected email from {} larger than {} bytes",
+                                from, MAX_MESSAGE_SIZE
+                            );
+                            stream.write_all(SMTP_ERR_TOO_LARGE).await?;
+                            continue;
+                        };
+                        debug!("Received email from: {} ({} bytes)", from, email_data.len());
+
+                        match submit(&email_data, &from, &state, peer_ip, login.as_ref()).await {
+                            Ok(post_ids) if post_ids.is_empty() => {
+                                stream.write_all(b"250 OK\r\n").await?;
+                            }
+                            Ok(post_ids) => {
+                                let response = format!("250 OK {}\r\n", post_ids.join(" "));
+                                stream.write_all(response.as_bytes()).await?;
+                            }
+                            Err(e) => {
+                                error!("Failed to publish email from {}: {}", from, e);
+                                let response =
+                                    format!("{} (session {})\r\n", e.smtp_reply(), session);
+                                stream.write_all(response.as_bytes()).await?;
                             }
                         }
-                        
-                        stream.write_all(b"250 OK\r\n").await?;
                     }
                     Some("RSET") => {
                         from.clear();
@@ -115,11 +312,324 @@ async fn handle_smtp_connection(
     Ok(())
 }
 
+/// Принимает учётные данные AUTH PLAIN или AUTH LOGIN. `None` - если
+/// механизм не поддерживается или ответ клиента не разобрать.
+async fn read_auth(
+    stream: &mut TcpStream,
+    mechanism: Option<&str>,
+    initial: Option<Zeroizing<String>>,
+) -> AppResult<Option<Credentials>> {
+    match mechanism {
+        Some("PLAIN") => {
+            let response = match initial {
+                Some(response) => response,
+                None => read_auth_line(stream, b"334 \r\n").await?,
+            };
+            // authzid \0 authcid \0 passwd
+            let Some(decoded) = decode_auth(&response) else {
+                return Ok(None);
+            };
+            let mut fields = decoded.splitn(3, '\0').skip(1);
+            Ok(fields
+                .next()
+                .zip(fields.next())
+                .map(|(username, password)| Credentials {
+                    username: username.to_string(),
+                    password: password.into(),
+                }))
+        }
+        Some("LOGIN") => {
+            let username = match initial {
+                Some(response) => response,
+                // "Username:" в base64
+                None => read_auth_line(stream, b"334 VXNlcm5hbWU6\r\n").await?,
+            };
+            let Some(username) = decode_auth(&username) else {
+                return Ok(None);
+            };
+            // "Password:" в base64
+            let password = read_auth_line(stream, b"334 UGFzc3dvcmQ6\r\n").await?;
+            Ok(decode_auth(&password).map(|password| Credentials {
+                username: username.to_string(),
+                password: password.as_str().into(),
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Отправляет запрос 334 и читает строку ответа клиента
+async fn read_auth_line(stream: &mut TcpStream, prompt: &[u8]) -> AppResult<Zeroizing<String>> {
+    stream.write_all(prompt).await?;
+    let mut buf = Zeroizing::new(vec![0u8; 1024]);
+    let n = stream.read(&mut buf).await?;
+    if n == 0 {
+        return Err(AppError::Disconnected("connection closed during AUTH"));
+    }
+    Ok(Zeroizing::new(
+        String::from_utf8_lossy(&buf[..n]).trim().to_string(),
+    ))
+}
+
+/// "*" - клиент отменил AUTH
+fn decode_auth(response: &str) -> Option<Zeroizing<String>> {
+    if response == "*" {
+        return None;
+    }
+    let decoded = Zeroizing::new(base64_decode(response.as_bytes())?);
+    std::str::from_utf8(&decoded)
+        .ok()
+        .map(|decoded| Zeroizing::new(decoded.to_string()))
+}
+
+/// Читает письмо после DATA до строки из одной точки и снимает dot-stuffing.
+/// Письмо больше `MAX_MESSAGE_SIZE` дочитывается без сохранения, чтобы
+/// клиент получил ответ 552 и сессия продолжилась, и возвращается `None`.
+async fn read_data(stream: &mut TcpStream) -> AppResult<Option<Vec<u8>>> {
+    let mut data = Vec::new();
+    let mut buf = vec![0u8; 4096];
+    let mut oversized = false;
+
+    loop {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Err(AppError::Disconnected("connection closed during DATA"));
+        }
+        data.extend_from_slice(&buf[..n]);
+
+        if data == b".\r\n" || data.ends_with(b"\r\n.\r\n") {
+            data.truncate(data.len() - 3);
+            break;
+        }
+        if data.len() > MAX_MESSAGE_SIZE {
+            // Четырёх байт хвоста хватает, чтобы заметить конец письма,
+            // разрезанный между чтениями
+            oversized = true;
+            data.drain(..data.len() - 4);
+        }
+    }
+    if oversized || data.len() > MAX_MESSAGE_SIZE {
+        return Ok(None);
+    }
+
+    let mut unstuffed = Vec::with_capacity(data.len());
+    for line in data.split_inclusive(|&b| b == b'\n') {
+        unstuffed.extend_from_slice(line.strip_prefix(b".").unwrap_or(line));
+    }
+
+    Ok(Some(unstuffed))
+}
+
+/// Проверяет AUTH по --users-file или паролю шлюза и возвращает учётные
+/// данные аккаунта для публикации
+async fn check_login(state: &AppState, cred: &Credentials) -> Option<Credentials> {
+    let config = &state.config;
+    if let Some(users) = &state.users {
+        return users
+            .authenticate(&cred.username, &cred.password, config.token.as_ref())
+            .await;
+    }
+
+    let hash = config.gateway_password.as_deref()?;
+    password::check(&cred.password, hash)
+        .await
+        .then(|| Credentials {
+            username: config.account.clone().unwrap_or_default(),
+            password: config.token.clone().unwrap_or_default(),
+        })
+}
+
+/// Разбирает письмо и публикует его в социальной сети. `login` - имя,
+/// под которым клиент прошёл AUTH, и аккаунт, на который он вошёл.
+async fn submit(
+    email_data: &[u8],
+    from: &str,
+    state: &AppState,
+    peer_ip: IpAddr,
+    login: Option<&(String, Credentials)>,
+) -> AppResult<Vec<String>> {
+    let config = &state.config;
+    let mut submission = submission::parse_submission(email_data, &config.gateway_domain)?;
+
+    let cred = match login {
+        Some((_, account)) => account.clone(),
+        None => Credentials {
+            username: config.account.clone().unwrap_or_else(|| from.to_string()),
+            password: config.token.clone().unwrap_or_default(),
+        },
+    };
+    Span::current().record("account", cred.username.as_str());
+
+    if let Some(command) = submission.command {
+        match command {
+            MailCommand::ExportContacts => contacts::deliver_export(state, &cred).await?,
+            MailCommand::CancelScheduled => {
+                let id = submission.forwarded_id.as_deref().ok_or_else(|| {
+                    AppError::InvalidEmail(
+                        "Forward the scheduled post email to cancel it".to_string(),
+                    )
+                })?;
+                if config.dry_run {
+                    info!("[dry-run] Would cancel scheduled post {}", id);
+                } else {
+                    state.api_client.cancel_scheduled_post(&cred, id).await?;
+                }
+                audit::record(
+                    config,
+                    peer_ip,
+                    audit::Event::Cancel {
+                        user: login.map_or(&cred.username, |(login, _)| login),
+                        from,
+                        scheduled_id: id,
+                        dry_run: config.dry_run,
+                    },
+                );
+            }
+            MailCommand::DeletePost => {
+                let id = submission.forwarded_id.as_deref().ok_or_else(|| {
+                    AppError::InvalidEmail("Forward the post email to delete it".to_string())
+                })?;
+                if config.dry_run {
+                    info!("[dry-run] Would delete post {}", id);
+                } else {
+                    state.api_client.delete_post(&cred, id).await?;
+                    info!("Deleted post {} on request from {}", id, from);
+                }
+                audit::record(
+                    config,
+                    peer_ip,
+                    audit::Event::Delete {
+                        user: login.map_or(&cred.username, |(login, _)| login),
+                        from,
+                        post_id: id,
+                        dry_run: config.dry_run,
+                    },
+                );
+            }
+            MailCommand::FetchThread => {
+                fetch::deliver_thread(state, &cred, &submission.text).await?
+            }
+            MailCommand::ConfirmPost => {
+                let held = confirm::release(state, &cred, &submission)?;
+                let submission = submission::parse_submission(&held.raw, &config.gateway_domain)?;
+                return publish(submission, &held.from, state, peer_ip, login, &cred).await;
+            }
+        }
+        info!("Mail command {:?} from {} done", command, from);
+        return Ok(Vec::new());
+    }
+
+    if config.confirm_posts {
+        submission::apply_cw_rules(&mut submission, config);
+        submission::check_alt_text(&submission, config)?;
+        confirm::hold(state, &cred, from, email_data, &submission).await?;
+        return Ok(Vec::new());
+    }
+    publish(submission, from, state, peer_ip, login, &cred).await
+}
+
+/// Публикует письмо в бэкенды, выбранные для него [`crosspost::route`]
+async fn publish(
+    mut submission: Submission,
+    from: &str,
+    state: &AppState,
+    peer_ip: IpAddr,
+    login: Option<&(String, Credentials)>,
+    cred: &Credentials,
+) -> AppResult<Vec<String>> {
+    let config = &state.config;
+    submission::apply_cw_rules(&mut submission, config);
+
+    // Клиент, не дождавшийся ответа на DATA, повторяет отправку того же
+    // письма. При кросспостинге каждый бэкенд запоминается отдельно: повтор
+    // после сбоя одного из них публикует только в него.
+    let message_id = submission.message_id.clone();
+    let published_key = |target: &crosspost::Target| {
+        message_id.as_ref().map(|id| {
+            if target.primary {
+                id.clone()
+            } else {
+                format!("{} {}", id, target.name)
+            }
+        })
+    };
+    let mut post_ids = Vec::new();
+    let mut pending = Vec::new();
+    for target in crosspost::route(state, &submission, from, cred)? {
+        match published_key(&target)
+            .and_then(|key| state.store.get::<Vec<String>>(PUBLISHED_TABLE, &key))
+        {
+            Some(ids) => {
+                info!(
+                    "Submission {} from {} already published to {}: {:?}",
+                    message_id.as_deref().unwrap_or_default(),
+                    from,
+                    target.name,
+                    ids
+                );
+                post_ids.extend(ids);
+            }
+            None => pending.push(target),
+        }
+    }
+    if pending.is_empty() {
+        return Ok(post_ids);
+    }
+    for target in &pending {
+        limit::check(state, &target.cred.username)?;
+    }
+
+    submission::fetch_linked_media(&mut submission, state).await?;
+    submission::check_alt_text(&submission, config)?;
+    let mut published = Vec::new();
+    for target in pending {
+        let ids = submission::publish(target.api_client, &target.cred, submission.clone(), config)
+            .await?;
+        if !config.dry_run {
+            limit::record(state, &target.cred.username, ids.len());
+            if let Some(key) = published_key(&target) {
+                if let Err(e) = state.store.insert(PUBLISHED_TABLE, &key, &ids) {
+                    warn!("Cannot record published submission {}: {}", key, e);
+                }
+            }
+        }
+        info!(
+            "Submission from {} posted to {}: {:?}",
+            from, target.name, ids
+        );
+        published.extend(ids);
+    }
+    let post_ids: Vec<String> = post_ids.into_iter().chain(published.clone()).collect();
+    audit::record(
+        config,
+        peer_ip,
+        audit::Event::Publish {
+            user: login.map_or(&cred.username, |(login, _)| login),
+            from,
+            message_id: message_id.as_deref(),
+            post_ids: &published,
+            dry_run: config.dry_run,
+        },
+    );
+
+    if config.dry_run {
+        info!(
+            "[dry-run] Submission from {} processed: {:?}",
+            from, post_ids
+        );
+    } else {
+        info!("Published submission from {}: {:?}", from, post_ids);
+        activity::count(Counter::PostsPublished, published.len() as u64);
+    }
+
+    Ok(post_ids)
+}
+
 fn extract_email_addr(command: &str) -> Option<String> {
     // Извлекаем email из MAIL FROM: <user@example.com>
     let start = command.find('<')?;
     let end = command.find('>')?;
-    
+
     if start < end {
         Some(command[start + 1..end].to_string())
     } else {
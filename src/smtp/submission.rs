@@ -0,0 +1,612 @@
+use super::confirm::CONFIRM_PREFIX;
+use super::exif;
+use crate::api::SocialNetworkApi;
+use crate::config::Config;
+use crate::error::{AppError, AppResult};
+use crate::models::{Attachment, Credentials};
+use crate::pop3::converter::reply_address_id;
+use crate::pop3::media;
+use crate::state::AppState;
+use mail_parser::{MessageParser, MimeHeaders};
+use std::collections::HashSet;
+use std::time::Duration;
+use tracing::{debug, info};
+
+/// Суффикс нумерации частей треда: " (12/34)"
+const THREAD_COUNTER_RESERVE: usize = 8;
+
+/// Строка тела `!attach <url>` прикладывает медиа по ссылке
+const ATTACH_DIRECTIVE: &str = "!attach";
+
+/// Строка тела `alt: <описание>` задаёт alt текст очередного вложения
+const ALT_DIRECTIVE: &str = "alt:";
+
+/// Команда шлюзу: письмо на служебный адрес вместо публикации поста
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MailCommand {
+    /// export@: подписчики и подписки вложениями CSV и vCard
+    ExportContacts,
+    /// cancel@: отмена отложенного поста, пересланного из ящика +scheduled
+    CancelScheduled,
+    /// fetch@: тред поста по ссылке из тела письма
+    FetchThread,
+    /// confirm@: публикация письма, задержанного --confirm-posts
+    ConfirmPost,
+    /// Тема DELETE: удаление своего поста, пересланного из ящика
+    DeletePost,
+}
+
+impl MailCommand {
+    /// Команда по локальной части служебного адреса шлюза
+    fn from_local(local: &str) -> Option<Self> {
+        match local {
+            "export" => Some(MailCommand::ExportContacts),
+            "cancel" => Some(MailCommand::CancelScheduled),
+            "fetch" => Some(MailCommand::FetchThread),
+            "confirm" => Some(MailCommand::ConfirmPost),
+            _ => None,
+        }
+    }
+}
+
+/// Тема письма, которое удаляет пересланный пост вместо публикации
+const DELETE_SUBJECT: &str = "DELETE";
+
+/// Заголовок со списком бэкендов для публикации: "mastodon, bluesky"
+pub const BACKENDS_HEADER: &str = "X-Mop3-Backends";
+
+/// Письмо, разобранное для публикации
+#[derive(Debug, Clone)]
+pub struct Submission {
+    /// Message-ID письма: по нему повторная отправка не публикуется дважды
+    pub message_id: Option<String>,
+    pub text: String,
+    pub in_reply_to_id: Option<String>,
+    /// Упоминания @user@instance из адресов шлюза в To и Cc
+    pub mentions: Vec<String>,
+    pub attachments: Vec<Attachment>,
+    /// Медиа по ссылкам из строк `!attach`; загружается перед публикацией
+    pub attach_urls: Vec<String>,
+    /// Строки `alt:`, оставшиеся после вложений письма: достаются медиа
+    /// из `attach_urls` по порядку
+    pub alt_texts: Vec<String>,
+    /// Письмо адресовано служебному адресу шлюза и не публикуется
+    pub command: Option<MailCommand>,
+    /// ID поста из пересланного письма шлюза, к которому относится команда
+    pub forwarded_id: Option<String>,
+    /// Бэкенды из X-Mop3-Backends в нижнем регистре
+    pub backends: Option<Vec<String>>,
+    /// Код из адреса confirm+<код>, на который отвечают письмом с превью
+    pub confirm_code: Option<String>,
+    /// Предупреждение о содержимом из cw_rules, см. [`apply_cw_rules`]
+    pub content_warning: Option<String>,
+}
+
+/// Разбирает RFC822 письмо, полученное через SMTP DATA
+pub fn parse_submission(raw: &[u8], gateway_domain: &str) -> AppResult<Submission> {
+    let message = MessageParser::default()
+        .parse(raw)
+        .ok_or_else(|| AppError::InvalidEmail("Cannot parse message".to_string()))?;
+
+    // Текст поста - тело письма без цитат и подписи; тема используется,
+    // если тело пустое
+    let body = message
+        .body_text(0)
+        .map(|body| clean_body(&body))
+        .unwrap_or_default();
+    let (body, attach_urls) = take_attach_directives(&body);
+    let (mut text, mut alt_texts) = take_alt_lines(&body);
+    if text.is_empty() {
+        text = message.subject().unwrap_or_default().trim().to_string();
+    }
+
+    // Message-ID наших писем имеет вид <postid@...>
+    let mut in_reply_to_id = message.in_reply_to().as_text().and_then(post_id);
+
+    // Адреса шлюза user=instance@gateway-domain становятся упоминаниями
+    let mut mentions: Vec<String> = Vec::new();
+    let mut command = None;
+    let mut confirm_code = None;
+    for address in [message.to(), message.cc()]
+        .into_iter()
+        .flatten()
+        .flat_map(|address| address.iter())
+        .filter_map(|addr| addr.address())
+    {
+        let Some((local, domain)) = address.rsplit_once('@') else {
+            continue;
+        };
+        if !domain.eq_ignore_ascii_case(gateway_domain) {
+            continue;
+        }
+        // Адрес reply+<id> из Reply-To надёжнее In-Reply-To, который
+        // некоторые клиенты теряют или переписывают
+        if let Some(id) = reply_address_id(local) {
+            in_reply_to_id = Some(id);
+            continue;
+        }
+        let local = local.to_lowercase();
+        let local = local.as_str();
+        if let Some(code) = local.strip_prefix(CONFIRM_PREFIX) {
+            command = Some(MailCommand::ConfirmPost);
+            confirm_code = Some(code.to_string());
+            continue;
+        }
+        if let Some(found) = MailCommand::from_local(local) {
+            command = Some(found);
+            continue;
+        }
+        if let Some((user, instance)) = local.split_once('=') {
+            let mention = format!("@{}@{}", user, instance);
+            if !mentions.contains(&mention) {
+                mentions.push(mention);
+            }
+        }
+    }
+
+    // Тема DELETE - команда на любой адрес: пересланный пост не должен
+    // опубликоваться копией, даже если ID в нём не нашёлся
+    if message
+        .subject()
+        .is_some_and(|subject| subject.trim().eq_ignore_ascii_case(DELETE_SUBJECT))
+    {
+        command = Some(MailCommand::DeletePost);
+    }
+
+    let mut attachments: Vec<Attachment> = message
+        .attachments()
+        .filter_map(|part| {
+            let content_type = part.content_type()?;
+            let mime = match content_type.subtype() {
+                Some(subtype) => format!("{}/{}", content_type.ctype(), subtype),
+                None => content_type.ctype().to_string(),
+            };
+            if !matches!(content_type.ctype(), "image" | "video" | "audio") {
+                debug!("Skipping non-media attachment: {}", mime);
+                return None;
+            }
+
+            Some(Attachment {
+                filename: part.attachment_name().unwrap_or("attachment").to_string(),
+                content_type: mime,
+                data: part.contents().to_vec(),
+                description: part
+                    .content_description()
+                    .map(str::trim)
+                    .filter(|description| !description.is_empty())
+                    .map(str::to_string),
+            })
+        })
+        .collect();
+
+    // Строки alt: по порядку достаются вложениям без Content-Description
+    for attachment in attachments
+        .iter_mut()
+        .filter(|attachment| attachment.description.is_none())
+    {
+        if alt_texts.is_empty() {
+            break;
+        }
+        attachment.description = Some(alt_texts.remove(0));
+    }
+
+    Ok(Submission {
+        message_id: message.message_id().map(str::to_string),
+        text,
+        in_reply_to_id,
+        mentions,
+        attachments,
+        attach_urls,
+        alt_texts,
+        command,
+        forwarded_id: forwarded_post_id(&message),
+        backends: message.header_raw(BACKENDS_HEADER).map(|value| {
+            value
+                .split([',', ' ', '\t', '\r', '\n'])
+                .filter(|name| !name.is_empty())
+                .map(str::to_lowercase)
+                .collect()
+        }),
+        confirm_code,
+        content_warning: None,
+    })
+}
+
+/// Скрывает пост за предупреждением, если текст письма подходит под
+/// правила cw_rules. Предупреждения всех подошедших правил объединяются.
+pub fn apply_cw_rules(submission: &mut Submission, config: &Config) {
+    let mut warnings: Vec<&str> = Vec::new();
+    for rule in &config.cw_rules {
+        // Выражения проверены при загрузке конфигурации
+        let matched = rule
+            .regex()
+            .ok()
+            .and_then(|regex| regex.is_match(&submission.text).ok())
+            .unwrap_or(false);
+        let cw = rule.cw.trim();
+        if matched && !warnings.contains(&cw) {
+            warnings.push(cw);
+        }
+    }
+    if !warnings.is_empty() {
+        debug!("Content warning by cw_rules: {}", warnings.join(", "));
+        submission.content_warning = Some(warnings.join(", "));
+    }
+}
+
+/// Убирает из текста строки `!attach <url>` и возвращает их ссылки. Так
+/// медиа прикладывают клиенты, не умеющие MIME вложения.
+fn take_attach_directives(body: &str) -> (String, Vec<String>) {
+    let mut urls = Vec::new();
+    let mut lines = Vec::new();
+    for line in body.lines() {
+        let directive = line
+            .trim()
+            .split_once(char::is_whitespace)
+            .filter(|(name, _)| name.eq_ignore_ascii_case(ATTACH_DIRECTIVE));
+        match directive {
+            Some((_, url)) => urls.push(url.trim().to_string()),
+            None => lines.push(line),
+        }
+    }
+    (lines.join("\n").trim().to_string(), urls)
+}
+
+/// Убирает из текста строки `alt: <описание>` и возвращает описания по
+/// порядку. Клиент без заголовков MIME частей задаёт так alt текст.
+fn take_alt_lines(body: &str) -> (String, Vec<String>) {
+    let mut alts = Vec::new();
+    let mut lines = Vec::new();
+    for line in body.lines() {
+        let trimmed = line.trim();
+        let alt = trimmed
+            .get(..ALT_DIRECTIVE.len())
+            .filter(|name| name.eq_ignore_ascii_case(ALT_DIRECTIVE))
+            .map(|_| trimmed[ALT_DIRECTIVE.len()..].trim())
+            .filter(|alt| !alt.is_empty());
+        match alt {
+            Some(alt) => alts.push(alt.to_string()),
+            None => lines.push(line),
+        }
+    }
+    (lines.join("\n").trim().to_string(), alts)
+}
+
+/// Режим --require-alt-text: отклоняет письмо, в котором у картинки нет
+/// alt текста, с ответом, как его добавить
+pub fn check_alt_text(submission: &Submission, config: &Config) -> AppResult<()> {
+    if !config.require_alt_text {
+        return Ok(());
+    }
+    match submission.attachments.iter().find(|attachment| {
+        attachment.content_type.starts_with("image/") && attachment.description.is_none()
+    }) {
+        Some(attachment) => Err(AppError::MissingAltText(format!(
+            "image {} has no description; add a Content-Description header to the \
+             attachment or a line \"{} <description>\" to the message body, one per image",
+            attachment.filename, ALT_DIRECTIVE
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Загружает медиа из строк `!attach` и добавляет его к вложениям письма.
+/// Ссылка, которую не удалось загрузить, отклоняет письмо целиком: пост
+/// без обещанной картинки хуже повторной отправки.
+pub async fn fetch_linked_media(submission: &mut Submission, state: &AppState) -> AppResult<()> {
+    let timeout = Duration::from_secs(state.config.media_timeout);
+    for url in std::mem::take(&mut submission.attach_urls) {
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            return Err(AppError::InvalidEmail(format!(
+                "{} {}: only http and https links can be attached",
+                ATTACH_DIRECTIVE, url
+            )));
+        }
+
+        let (data, content_type) =
+            match tokio::time::timeout(timeout, media::download_media(&url, state)).await {
+                Ok(Ok(media)) => media,
+                Ok(Err(e)) => {
+                    return Err(AppError::InvalidEmail(format!(
+                        "Cannot download {}: {}",
+                        url, e
+                    )))
+                }
+                Err(_) => {
+                    return Err(AppError::InvalidEmail(format!(
+                        "Download of {} timed out",
+                        url
+                    )))
+                }
+            };
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+        if !["image/", "video/", "audio/"]
+            .iter()
+            .any(|kind| mime.starts_with(kind))
+        {
+            return Err(AppError::InvalidEmail(format!(
+                "{} is {}, not an image, video or audio",
+                url, mime
+            )));
+        }
+
+        debug!("Attached {} ({}, {} bytes)", url, mime, data.len());
+        let description =
+            (!submission.alt_texts.is_empty()).then(|| submission.alt_texts.remove(0));
+        submission.attachments.push(Attachment {
+            filename: media::file_name(&url, &mime),
+            content_type: mime,
+            data,
+            description,
+        });
+    }
+    Ok(())
+}
+
+/// ID поста из Message-ID письма шлюза <postid@instance>
+fn post_id(message_id: &str) -> Option<String> {
+    message_id
+        .trim()
+        .trim_matches(['<', '>'])
+        .split('@')
+        .next()
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+}
+
+/// ID поста письма шлюза, пересланного обратно. Пересылка вложением несёт
+/// заголовки исходного письма целиком; при пересылке в тексте Thunderbird
+/// добавляет X-Forwarded-Message-Id, а остальные клиенты - разве что строки
+/// заголовков в начале цитаты.
+fn forwarded_post_id(message: &mail_parser::Message) -> Option<String> {
+    let attached = message.attachments().find_map(|part| part.message());
+    if let Some(original) = attached {
+        if let Some(id) = original.header_raw("X-Mop3-Post-Id") {
+            return Some(id.trim().to_string());
+        }
+        if let Some(id) = original.message_id().and_then(post_id) {
+            return Some(id);
+        }
+    }
+
+    if let Some(id) = message
+        .header_raw("X-Forwarded-Message-Id")
+        .and_then(post_id)
+    {
+        return Some(id);
+    }
+
+    let body = message.body_text(0)?;
+    body.lines().find_map(|line| {
+        let (name, value) = line.trim_start_matches(['>', ' ']).split_once(':')?;
+        if name.eq_ignore_ascii_case("X-Mop3-Post-Id") {
+            Some(value.trim().to_string()).filter(|id| !id.is_empty())
+        } else if name.eq_ignore_ascii_case("Message-ID") {
+            post_id(value)
+        } else {
+            None
+        }
+    })
+}
+
+/// Публикует письмо: проверяет текст, делит на тред, загружает медиа и постит.
+/// Возвращает ID созданных постов (в режиме --dry-run - синтетические).
+pub async fn publish(
+    api_client: &dyn SocialNetworkApi,
+    cred: &Credentials,
+    submission: Submission,
+    config: &Config,
+) -> AppResult<Vec<String>> {
+    if submission.text.is_empty() && submission.attachments.is_empty() {
+        return Err(AppError::InvalidEmail("Empty message body".to_string()));
+    }
+
+    let parts = render(api_client, cred, &submission, config);
+    debug!(
+        "Publishing submission: {} part(s), {} attachment(s), reply_to: {:?}",
+        parts.len(),
+        submission.attachments.len(),
+        submission.in_reply_to_id
+    );
+
+    // Медиа прикрепляются к первой части треда
+    let mut media_ids = Vec::with_capacity(submission.attachments.len());
+    for (n, mut attachment) in submission.attachments.into_iter().enumerate() {
+        // Почтовые клиенты прикладывают оригиналы фото вместе с GPS координатами
+        if !config.keep_exif {
+            let mime = attachment.content_type.clone();
+            attachment.data =
+                tokio::task::spawn_blocking(move || exif::strip_metadata(attachment.data, &mime))
+                    .await
+                    .map_err(|e| {
+                        AppError::ServerError(format!("Metadata stripping failed: {}", e))
+                    })??;
+        }
+
+        if config.dry_run {
+            info!(
+                "[dry-run] Would upload media: {} ({}, {} bytes)",
+                attachment.filename,
+                attachment.content_type,
+                attachment.data.len()
+            );
+            media_ids.push(format!("dry-run-media-{}", n + 1));
+        } else {
+            let media_id = api_client
+                .upload_media(
+                    cred,
+                    attachment.data,
+                    attachment.filename,
+                    attachment.content_type,
+                    attachment.description,
+                )
+                .await?;
+            media_ids.push(media_id);
+        }
+    }
+
+    let mut post_ids = Vec::with_capacity(parts.len());
+    let mut reply_to = submission.in_reply_to_id;
+    for (n, part) in parts.into_iter().enumerate() {
+        let part_media = if n == 0 {
+            std::mem::take(&mut media_ids)
+        } else {
+            Vec::new()
+        };
+
+        let post_id = if config.dry_run {
+            info!(
+                "[dry-run] Would post status (reply_to: {:?}, media: {:?}, cw: {:?}): {}",
+                reply_to, part_media, submission.content_warning, part
+            );
+            format!("dry-run-{}", n + 1)
+        } else {
+            // Предупреждение ставится на каждую часть треда
+            api_client
+                .post_status(
+                    cred,
+                    part,
+                    reply_to.clone(),
+                    part_media,
+                    submission.content_warning.clone(),
+                )
+                .await?
+        };
+
+        reply_to = Some(post_id.clone());
+        post_ids.push(post_id);
+    }
+
+    Ok(post_ids)
+}
+
+/// Тексты постов, которые опубликует письмо: с упоминаниями и тегами,
+/// разделённые на тред по лимиту бэкенда. Предупреждение о содержимом
+/// входит в лимит, как его считает Mastodon.
+pub fn render(
+    api_client: &dyn SocialNetworkApi,
+    cred: &Credentials,
+    submission: &Submission,
+    config: &Config,
+) -> Vec<String> {
+    let text = with_mentions(&submission.text, &submission.mentions, &cred.username);
+    let text = with_tags(&text, &config.append_tags);
+    let limit = api_client.max_post_length().saturating_sub(
+        submission
+            .content_warning
+            .as_deref()
+            .map_or(0, |cw| api_client.post_length(cw)),
+    );
+    split_post(&text, limit, |text| api_client.post_length(text))
+}
+
+/// Добавляет в начало поста упоминания, которых ещё нет в тексте.
+/// Собственный аккаунт не упоминается.
+fn with_mentions(text: &str, mentions: &[String], username: &str) -> String {
+    let own = format!("@{}", username.to_lowercase());
+    let lowercase = text.to_lowercase();
+    let missing: Vec<&str> = mentions
+        .iter()
+        .filter(|mention| **mention != own && !lowercase.contains(mention.as_str()))
+        .map(String::as_str)
+        .collect();
+
+    if missing.is_empty() {
+        text.to_string()
+    } else {
+        format!("{} {}", missing.join(" "), text)
+            .trim_end()
+            .to_string()
+    }
+}
+
+/// Добавляет в конец поста хэштеги --append-tags, которых ещё нет в тексте.
+/// Теги сравниваются без учёта регистра и только целиком.
+fn with_tags(text: &str, tags: &[String]) -> String {
+    let present: HashSet<String> = text
+        .split(|c: char| !(c.is_alphanumeric() || c == '#' || c == '_'))
+        .filter(|word| word.starts_with('#'))
+        .map(str::to_lowercase)
+        .collect();
+
+    let mut missing: Vec<String> = Vec::new();
+    for tag in tags.iter().flat_map(|tags| tags.split_whitespace()) {
+        let tag = format!("#{}", tag.trim_start_matches('#'));
+        let lowercase = tag.to_lowercase();
+        if tag.len() > 1
+            && !present.contains(&lowercase)
+            && !missing.iter().any(|m| m.to_lowercase() == lowercase)
+        {
+            missing.push(tag);
+        }
+    }
+
+    match (missing.is_empty(), text.is_empty()) {
+        (true, _) => text.to_string(),
+        (false, true) => missing.join(" "),
+        (false, false) => format!("{}\n\n{}", text, missing.join(" ")),
+    }
+}
+
+/// Убирает из тела письма цитаты ответа и подпись
+fn clean_body(body: &str) -> String {
+    body.lines()
+        .take_while(|line| *line != "-- ")
+        .filter(|line| !line.starts_with('>'))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+/// Делит текст на части не длиннее `limit` символов по границам слов.
+/// Длина считается по правилам бэкенда (`length`), поэтому длинная ссылка
+/// не разбивает пост, если бэкенд считает её короткой. Если частей
+/// несколько, каждая получает суффикс " (i/n)".
+fn split_post(text: &str, limit: usize, length: impl Fn(&str) -> usize) -> Vec<String> {
+    if length(text) <= limit {
+        return vec![text.to_string()];
+    }
+
+    let chunk_limit = limit.saturating_sub(THREAD_COUNTER_RESERVE).max(1);
+    let mut chunks: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut current_len = 0;
+
+    for word in text.split_inclusive(char::is_whitespace) {
+        let word_len = length(word);
+
+        if current_len + word_len > chunk_limit && !current.is_empty() {
+            chunks.push(current.trim_end().to_string());
+            current.clear();
+            current_len = 0;
+        }
+
+        // Слово длиннее лимита режется по символам
+        if word_len > chunk_limit {
+            let chars: Vec<char> = word.chars().collect();
+            for piece in chars.chunks(chunk_limit) {
+                chunks.push(piece.iter().collect::<String>().trim_end().to_string());
+            }
+            continue;
+        }
+
+        current.push_str(word);
+        current_len += word_len;
+    }
+    if !current.trim().is_empty() {
+        chunks.push(current.trim_end().to_string());
+    }
+
+    let total = chunks.len();
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(n, chunk)| format!("{} ({}/{})", chunk, n + 1, total))
+        .collect()
+}
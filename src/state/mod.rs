@@ -0,0 +1,85 @@
+pub mod store;
+
+use crate::api::{self, SocialNetworkApi};
+use crate::config::Config;
+use crate::error::{AppError, AppResult};
+use crate::pop3::cache::MediaCache;
+use crate::pop3::prefetch::MailboxCache;
+use crate::sign::Signer;
+use crate::users::Users;
+use reqwest::Client;
+use std::sync::{Arc, Mutex};
+use store::Store;
+use tokio::sync::watch;
+
+/// Текущее состояние приложения, обновляемое при перезагрузке (SIGHUP).
+/// Каждое соединение берёт снимок при подключении и работает с ним до конца.
+pub type SharedState = watch::Receiver<Arc<AppState>>;
+
+/// Конфигурация и клиенты, общие для всех соединений обоих серверов.
+/// HTTP клиент один на приложение, поэтому соединения с инстанцией
+/// и серверами медиа переиспользуются между опросами.
+pub struct AppState {
+    pub config: Arc<Config>,
+    pub http_client: Client,
+    pub api_client: Box<dyn SocialNetworkApi>,
+    /// Клиенты аккаунтов `config.crosspost`, в том же порядке
+    pub crosspost_clients: Vec<Box<dyn SocialNetworkApi>>,
+    pub media_cache: Option<MediaCache>,
+    pub mailbox: MailboxCache,
+    /// Пользователи из --users-file, перечитываются при перезагрузке
+    pub users: Option<Users>,
+    /// Ключ подписи писем шлюза из --sign-cert и --sign-key
+    pub signer: Option<Signer>,
+    /// Постоянное хранилище переживает перезагрузку конфигурации
+    pub store: Arc<Store>,
+}
+
+impl AppState {
+    pub fn new(config: Config, store: Arc<Store>) -> AppResult<Self> {
+        let http_client = api::http_client(&config)?;
+        let api_client = api::create_api_client(&config, http_client.clone());
+        let crosspost_clients = config
+            .crosspost
+            .iter()
+            .map(|account| {
+                let config = Config {
+                    api_mode: account.api_mode,
+                    ..config.clone()
+                };
+                api::create_api_client(&config, http_client.clone())
+            })
+            .collect();
+        let media_cache = config
+            .media_cache
+            .as_deref()
+            .map(|dir| MediaCache::open(dir, config.media_cache_size))
+            .transpose()?;
+        let users = config.users_file.as_deref().map(Users::load).transpose()?;
+        if let Some(login) = users.as_ref().and_then(Users::missing_token) {
+            if config.token.is_none() {
+                return Err(AppError::Config(format!(
+                    "У пользователя {} нет токена API, а общий --token не задан",
+                    login
+                )));
+            }
+        }
+
+        let signer = match (&config.sign_cert, &config.sign_key) {
+            (Some(cert), Some(key)) => Some(Signer::load(cert, key)?),
+            _ => None,
+        };
+
+        Ok(AppState {
+            config: Arc::new(config),
+            http_client,
+            api_client,
+            crosspost_clients,
+            media_cache,
+            mailbox: Mutex::new(None),
+            users,
+            signer,
+            store,
+        })
+    }
+}
@@ -0,0 +1,97 @@
+use crate::error::{AppError, AppResult};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{debug, info};
+
+/// Таблицы хранилища: имя таблицы -> ключ -> значение
+type Tables = BTreeMap<String, BTreeMap<String, Value>>;
+
+/// Постоянное хранилище состояния шлюза в JSON файле --state-file.
+/// Каждое изменение сразу записывается на диск через временный файл,
+/// так что после падения файл остаётся целым. Без --state-file
+/// состояние живёт в памяти до перезапуска.
+pub struct Store {
+    path: Option<PathBuf>,
+    tables: Mutex<Tables>,
+}
+
+impl Store {
+    pub fn open(path: Option<&Path>) -> AppResult<Self> {
+        let tables = match path {
+            Some(path) if path.exists() => {
+                let data = std::fs::read(path).map_err(|e| {
+                    AppError::Config(format!(
+                        "Не удалось прочитать --state-file {}: {}",
+                        path.display(),
+                        e
+                    ))
+                })?;
+                serde_json::from_slice(&data).map_err(|e| {
+                    AppError::Config(format!("Повреждён --state-file {}: {}", path.display(), e))
+                })?
+            }
+            _ => Tables::new(),
+        };
+
+        if let Some(path) = path {
+            info!("State store: {} ({} tables)", path.display(), tables.len());
+        }
+
+        Ok(Store {
+            path: path.map(Path::to_path_buf),
+            tables: Mutex::new(tables),
+        })
+    }
+
+    /// Значение по ключу; `None`, если его нет или оно другого типа
+    pub fn get<T: DeserializeOwned>(&self, table: &str, key: &str) -> Option<T> {
+        let tables = self.tables.lock().ok()?;
+        let value = tables.get(table)?.get(key)?.clone();
+        serde_json::from_value(value).ok()
+    }
+
+    /// Сохраняет значение и записывает хранилище на диск
+    pub fn insert<T: Serialize>(&self, table: &str, key: &str, value: &T) -> AppResult<()> {
+        let value = serde_json::to_value(value)?;
+        let mut tables = self
+            .tables
+            .lock()
+            .map_err(|_| AppError::ServerError("State store lock poisoned".to_string()))?;
+
+        tables
+            .entry(table.to_string())
+            .or_default()
+            .insert(key.to_string(), value);
+        self.persist(&tables)
+    }
+
+    /// Удаляет значение и возвращает его; `None`, если его не было
+    pub fn take<T: DeserializeOwned>(&self, table: &str, key: &str) -> AppResult<Option<T>> {
+        let mut tables = self
+            .tables
+            .lock()
+            .map_err(|_| AppError::ServerError("State store lock poisoned".to_string()))?;
+
+        let Some(value) = tables.get_mut(table).and_then(|table| table.remove(key)) else {
+            return Ok(None);
+        };
+        self.persist(&tables)?;
+        Ok(serde_json::from_value(value).ok())
+    }
+
+    fn persist(&self, tables: &Tables) -> AppResult<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(tables)?)?;
+        std::fs::rename(&tmp, path)?;
+        debug!("State store saved to {}", path.display());
+        Ok(())
+    }
+}
@@ -0,0 +1,265 @@
+use crate::config::Config;
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{warn, Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Столько завершённых спанов ждут отправки; лишние отбрасываются
+const QUEUE_SIZE: usize = 2048;
+
+/// Спаны отправляются пачками не больше этой
+const MAX_BATCH: usize = 512;
+
+/// Накопленные спаны отправляются не реже этого
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Слой tracing, отправляющий завершённые спаны (сессии POP3 и SMTP,
+/// запросы к API) в коллектор OpenTelemetry по OTLP/HTTP в формате JSON.
+pub struct OtlpLayer {
+    sender: mpsc::Sender<SpanData>,
+}
+
+/// Слой экспорта, если задан --otlp-endpoint. Запускает фоновую отправку,
+/// поэтому вызывается внутри runtime tokio.
+pub fn layer(config: &Config) -> Option<OtlpLayer> {
+    let endpoint = config.otlp_endpoint.as_deref()?;
+    let url = format!("{}/v1/traces", endpoint.trim_end_matches('/'));
+    let client = Client::builder()
+        .timeout(EXPORT_TIMEOUT)
+        .build()
+        .unwrap_or_default();
+
+    let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+    tokio::spawn(export(receiver, client, url));
+    Some(OtlpLayer { sender })
+}
+
+/// Данные спана, накапливаемые в extensions до его закрытия
+struct SpanData {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_span_id: Option<[u8; 8]>,
+    name: &'static str,
+    kind: u8,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(&'static str, Value)>,
+    error: bool,
+}
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let parent = span.parent().and_then(|parent| {
+            parent
+                .extensions()
+                .get::<SpanData>()
+                .map(|p| (p.trace_id, p.span_id))
+        });
+
+        let mut data = SpanData {
+            trace_id: parent.map_or_else(random_id, |(trace_id, _)| trace_id),
+            span_id: random_id(),
+            parent_span_id: parent.map(|(_, span_id)| span_id),
+            name: attrs.metadata().name(),
+            // SPAN_KIND_INTERNAL, если не задан otel.kind
+            kind: 1,
+            start: SystemTime::now(),
+            end: UNIX_EPOCH,
+            attributes: Vec::new(),
+            error: false,
+        };
+        attrs.record(&mut data);
+        span.extensions_mut().insert(data);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(data) = span.extensions_mut().get_mut::<SpanData>() {
+                values.record(data);
+            }
+        }
+    }
+
+    /// Ошибка в логе внутри спана помечает спан как неудачный
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        if *event.metadata().level() != Level::ERROR {
+            return;
+        }
+        if let Some(span) = ctx.event_span(event) {
+            if let Some(data) = span.extensions_mut().get_mut::<SpanData>() {
+                data.error = true;
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(mut data) = span.extensions_mut().remove::<SpanData>() else {
+            return;
+        };
+        data.end = SystemTime::now();
+        // Коллектор не успевает - теряем спан, но не тормозим сессии
+        let _ = self.sender.try_send(data);
+    }
+}
+
+impl Visit for SpanData {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "otel.kind" {
+            self.kind = match value {
+                "server" => 2,
+                "client" => 3,
+                _ => 1,
+            };
+        } else {
+            // error.type по соглашениям OpenTelemetry означает неудачу
+            self.error |= field.name() == "error.type";
+            self.attributes
+                .push((field.name(), json!({ "stringValue": value })));
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        // int64 в JSON-представлении OTLP передаётся строкой
+        self.attributes
+            .push((field.name(), json!({ "intValue": value.to_string() })));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.attributes
+            .push((field.name(), json!({ "intValue": value.to_string() })));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.attributes
+            .push((field.name(), json!({ "doubleValue": value })));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.attributes
+            .push((field.name(), json!({ "boolValue": value })));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value));
+    }
+}
+
+/// Собирает закрытые спаны в пачки и отправляет их коллектору
+async fn export(mut receiver: mpsc::Receiver<SpanData>, client: Client, url: String) {
+    let mut batch = Vec::new();
+    let mut interval = tokio::time::interval(EXPORT_INTERVAL);
+
+    loop {
+        tokio::select! {
+            span = receiver.recv() => match span {
+                Some(span) => {
+                    batch.push(span);
+                    if batch.len() < MAX_BATCH {
+                        continue;
+                    }
+                }
+                None => return,
+            },
+            _ = interval.tick() => {
+                if batch.is_empty() {
+                    continue;
+                }
+            }
+        }
+
+        let body = encode(&batch);
+        batch.clear();
+        let result = client
+            .post(&url)
+            .json(&body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = result {
+            warn!("Cannot export traces to {}: {}", url, e);
+        }
+    }
+}
+
+/// ExportTraceServiceRequest в JSON-представлении OTLP
+fn encode(batch: &[SpanData]) -> Value {
+    let spans: Vec<Value> = batch
+        .iter()
+        .map(|span| {
+            let attributes: Vec<Value> = span
+                .attributes
+                .iter()
+                .map(|(key, value)| json!({ "key": key, "value": value }))
+                .collect();
+            let mut encoded = json!({
+                "traceId": to_hex(&span.trace_id),
+                "spanId": to_hex(&span.span_id),
+                "name": span.name,
+                "kind": span.kind,
+                "startTimeUnixNano": unix_nanos(span.start),
+                "endTimeUnixNano": unix_nanos(span.end),
+                "attributes": attributes,
+                // STATUS_CODE_ERROR или STATUS_CODE_UNSET
+                "status": { "code": if span.error { 2 } else { 0 } },
+            });
+            if let Some(parent) = &span.parent_span_id {
+                encoded["parentSpanId"] = Value::String(to_hex(parent));
+            }
+            encoded
+        })
+        .collect();
+
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    { "key": "service.name", "value": { "stringValue": "mop3" } },
+                    { "key": "service.version", "value": { "stringValue": env!("CARGO_PKG_VERSION") } },
+                ],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "mop3" },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+/// Случайный ID трассы или спана: ключи SipHash случайны для каждого RandomState
+fn random_id<const N: usize>() -> [u8; N] {
+    let mut id = [0u8; N];
+    for chunk in id.chunks_mut(8) {
+        let random = RandomState::new().build_hasher().finish().to_le_bytes();
+        chunk.copy_from_slice(&random[..chunk.len()]);
+    }
+    id
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,158 @@
+use crate::config::{Config, UserCommand};
+use crate::error::{AppError, AppResult};
+use crate::models::{Credentials, Secret};
+use crate::password;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use zeroize::Zeroizing;
+
+/// Пользователь шлюза из --users-file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    /// Хеш пароля входа в шлюз: строка PHC argon2id (`password::hash_argon2`)
+    pub password: String,
+    /// Аккаунт социальной сети, на который входит пользователь
+    pub account: String,
+    /// Токен API этого аккаунта; без него используется --token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<Secret>,
+}
+
+/// Пользователи для многопользовательского режима: логин шлюза -> аккаунт.
+/// Пароли шлюза не зависят от токенов API и хранятся только в виде хешей.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Users {
+    #[serde(default)]
+    users: BTreeMap<String, User>,
+}
+
+impl Users {
+    pub fn load(path: &Path) -> AppResult<Users> {
+        let text = fs::read_to_string(path).map_err(|e| {
+            AppError::Config(format!("Не удалось прочитать {}: {}", path.display(), e))
+        })?;
+        let users: Users = toml::from_str(&text)
+            .map_err(|e| AppError::Config(format!("Ошибка в {}: {}", path.display(), e)))?;
+
+        if let Some((login, _)) = users
+            .users
+            .iter()
+            .find(|(_, user)| !password::is_argon2_hash(&user.password))
+        {
+            return Err(AppError::Config(format!(
+                "Пароль пользователя {} в {} не является хешем argon2, задайте его заново: mop3 user add",
+                login,
+                path.display()
+            )));
+        }
+        Ok(users)
+    }
+
+    /// Логин первого пользователя без собственного токена API
+    pub fn missing_token(&self) -> Option<&str> {
+        self.users
+            .iter()
+            .find(|(_, user)| user.token.is_none())
+            .map(|(login, _)| login.as_str())
+    }
+
+    /// Сохраняет файл через временный, чтобы работающий шлюз не прочитал
+    /// его наполовину записанным. Файл содержит токены, поэтому права 600.
+    fn save(&self, path: &Path) -> AppResult<()> {
+        let text = toml::to_string(self)
+            .map_err(|e| AppError::Config(format!("Ошибка сериализации пользователей: {}", e)))?;
+
+        let tmp = path.with_extension("tmp");
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        options.open(&tmp)?.write_all(text.as_bytes())?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Проверяет пароль шлюза и возвращает учётные данные аккаунта
+    /// пользователя. `fallback_token` - общий --token для записей без своего.
+    pub async fn authenticate(
+        &self,
+        login: &str,
+        password: &str,
+        fallback_token: Option<&Secret>,
+    ) -> Option<Credentials> {
+        let Some(user) = self.users.get(login) else {
+            password::check_dummy(password).await;
+            return None;
+        };
+        if !password::check(password, &user.password).await {
+            return None;
+        }
+        self.credentials(login, fallback_token)
+    }
+
+    /// Учётные данные аккаунта пользователя без проверки пароля: вход уже
+    /// подтверждён, например, клиентским сертификатом
+    pub fn credentials(&self, login: &str, fallback_token: Option<&Secret>) -> Option<Credentials> {
+        let user = self.users.get(login)?;
+        Some(Credentials {
+            username: user.account.clone(),
+            password: user.token.clone().or(fallback_token.cloned())?,
+        })
+    }
+}
+
+/// Выполняет `mop3 user add|remove` над --users-file
+pub fn run_command(config: &Config, command: &UserCommand) -> AppResult<()> {
+    let path = config.users_file.as_deref().ok_or_else(|| {
+        AppError::Config("Для управления пользователями задайте --users-file".to_string())
+    })?;
+    let mut users = if path.exists() {
+        Users::load(path)?
+    } else {
+        Users::default()
+    };
+
+    match command {
+        UserCommand::Add { login, account } => {
+            let mut lines = std::io::stdin().lines();
+            let password = Zeroizing::new(lines.next().transpose()?.unwrap_or_default());
+            if password.is_empty() {
+                return Err(AppError::Config(
+                    "Пароль пользователя ожидается первой строкой stdin".to_string(),
+                ));
+            }
+            let token = lines
+                .next()
+                .transpose()?
+                .filter(|token| !token.trim().is_empty())
+                .map(|token| Secret::from(token.trim()));
+
+            users.users.insert(
+                login.clone(),
+                User {
+                    password: password::hash_argon2(&password)?,
+                    account: account.clone(),
+                    token,
+                },
+            );
+            users.save(path)?;
+            println!("User {} -> {} saved to {}", login, account, path.display());
+        }
+        UserCommand::Remove { login } => {
+            if users.users.remove(login).is_none() {
+                return Err(AppError::Config(format!(
+                    "Пользователь {} не найден в {}",
+                    login,
+                    path.display()
+                )));
+            }
+            users.save(path)?;
+            println!("User {} removed from {}", login, path.display());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,127 @@
+use crate::error::AppError;
+use crate::models::{Credentials, NotificationKind};
+use crate::pop3::converter::full_acct;
+use crate::state::AppState;
+use serde::Serialize;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Сколько ждать ответа вебхука, секунд
+const WEBHOOK_TIMEOUT: u64 = 10;
+
+/// Почему пост вызвал уведомление
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Kind {
+    Mention,
+    Direct,
+}
+
+/// Тело запроса вебхука. Текста поста в нём нет: он ждёт в ящике.
+#[derive(Debug, Serialize)]
+struct Payload {
+    event: Kind,
+    account: String,
+    post_id: String,
+    author: String,
+    url: Option<String>,
+    created_at: String,
+}
+
+/// Таблица хранилища: аккаунт -> ID самого нового уведомления прошлой проверки
+const SINCE_ID_TABLE: &str = "webhook_since_ids";
+
+/// Сколько уведомлений запрашивать за раз
+const NOTIFICATIONS_LIMIT: u32 = 40;
+
+/// Отправляет на --webhook-url по запросу на каждое новое упоминание
+/// аккаунта и личное сообщение. Они берутся из уведомлений API, а не из
+/// ленты: упоминание от аккаунта без подписки в ленту не попадает. Новыми
+/// считаются уведомления после прошлой проверки; первая проверка только
+/// запоминает позицию. Запросы уходят в фоне, ошибки только пишутся в лог.
+pub async fn notify(state: &AppState, cred: &Credentials, account_addr: &str) {
+    let Some(url) = &state.config.webhook_url else {
+        return;
+    };
+
+    let notifications = match state
+        .api_client
+        .get_notifications(cred, NOTIFICATIONS_LIMIT)
+        .await
+    {
+        Ok(notifications) => notifications,
+        Err(AppError::Unsupported(_)) => {
+            debug!("Backend has no notifications, webhook not sent");
+            return;
+        }
+        Err(e) => {
+            warn!("Cannot fetch notifications for webhook: {}", e);
+            return;
+        }
+    };
+
+    // Уведомления отсортированы от новых к старым
+    let since_id: Option<String> = state.store.get(SINCE_ID_TABLE, account_addr);
+    if let Some(newest) = notifications.first() {
+        if let Err(e) = state.store.insert(SINCE_ID_TABLE, account_addr, &newest.id) {
+            warn!("Cannot record webhook since_id for {}: {}", account_addr, e);
+        }
+    }
+    let Some(since_id) = since_id else {
+        return;
+    };
+    let new_notifications = notifications
+        .iter()
+        .position(|notification| notification.id == since_id)
+        .unwrap_or(notifications.len());
+
+    let payloads: Vec<Payload> = notifications[..new_notifications]
+        .iter()
+        .filter(|notification| {
+            matches!(
+                notification.kind,
+                NotificationKind::Mention | NotificationKind::Reply
+            )
+        })
+        .filter_map(|notification| {
+            let post = notification.post.as_ref()?;
+            let kind = if post.visibility.as_deref() == Some("direct") {
+                Kind::Direct
+            } else {
+                Kind::Mention
+            };
+            Some(Payload {
+                event: kind,
+                account: account_addr.to_string(),
+                post_id: post.id.clone(),
+                author: full_acct(&notification.account.acct, account_addr),
+                url: post.url.clone(),
+                created_at: post.created_at.clone(),
+            })
+        })
+        .collect();
+    if payloads.is_empty() {
+        return;
+    }
+
+    let http_client = state.http_client.clone();
+    let url = url.clone();
+    tokio::spawn(async move {
+        for payload in payloads {
+            let sent = http_client
+                .post(&url)
+                .json(&payload)
+                .timeout(Duration::from_secs(WEBHOOK_TIMEOUT))
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            match sent {
+                Ok(_) => debug!("Webhook sent for {:?} {}", payload.event, payload.post_id),
+                Err(e) => warn!(
+                    "Webhook for {:?} {} failed: {}",
+                    payload.event, payload.post_id, e
+                ),
+            }
+        }
+    });
+}